@@ -96,7 +96,9 @@ impl BBox {
             })
     }
     
-    pub fn intersects(&self, ray: &Ray) -> bool {
+    /// Slab test returning where the ray enters and leaves the box, which
+    /// may be behind the ray origin.
+    pub fn intersect_interval(&self, ray: &Ray) -> Option<(f64, f64)> {
         // Using ray.inverse_dir is an optimisation. Normally, for simplicity we would do
         //
         //     let d = -ray.direction;
@@ -133,7 +135,7 @@ impl BBox {
         let ty_max = (max_y_bound.y - o.y) * ray.inverse_dir.y;
 
         if t_min > ty_max || ty_min > t_max {
-            return false
+            return None
         }
         if ty_min > t_min {
             t_min = ty_min;
@@ -151,7 +153,7 @@ impl BBox {
         let tz_max = (max_z_bound.z - o.z) * ray.inverse_dir.z;
 
         if t_min > tz_max || tz_min > t_max {
-            return false
+            return None
         }
         if tz_min > t_min {
             t_min = tz_min;
@@ -160,8 +162,15 @@ impl BBox {
             t_max = tz_max;
         }
 
-        // tmin < t1 && tmax > t0
-        t_min < ::core::f64::INFINITY && t_max > 0.0
+        Some((t_min, t_max))
+    }
+
+    pub fn intersects(&self, ray: &Ray) -> bool {
+        match self.intersect_interval(ray) {
+            // tmin < t1 && tmax > t0
+            Some((t_min, t_max)) => t_min < ::core::f64::INFINITY && t_max > 0.0,
+            None => false
+        }
     }
 
     pub fn overlaps(&self, other: &BBox) -> bool {
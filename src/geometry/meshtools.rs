@@ -0,0 +1,265 @@
+use std::collections::{HashMap, VecDeque};
+use crate::vec3::Vec3;
+
+/// Triangles as indices into shared positions, for cleaning up meshes that
+/// came without normals: welding split vertices, making the winding
+/// consistent and computing smooth normals.
+pub struct IndexedTriangles {
+    pub positions: Vec<Vec3>,
+    pub faces: Vec<[u32; 3]>,
+    /// Where each face corner came from, as `3 * face + corner` of the
+    /// faces as first given. Follows the faces as they're dropped or flipped.
+    pub corners: Vec<[usize; 3]>
+}
+
+impl IndexedTriangles {
+    pub fn new(positions: Vec<Vec3>, faces: Vec<[u32; 3]>) -> IndexedTriangles {
+        let corners = (0..faces.len()).map(|face| [3 * face, 3 * face + 1, 3 * face + 2]).collect();
+        IndexedTriangles { positions: positions, faces: faces, corners: corners }
+    }
+
+    /// The face, as first given, that `face` came from.
+    pub fn origin(&self, face: usize) -> usize {
+        self.corners[face][0] / 3
+    }
+
+    /// Merges vertices closer than `tolerance`, or exactly equal for a
+    /// tolerance of 0, and drops faces that collapse as a result. Returns
+    /// how many vertices were merged away.
+    pub fn weld(&mut self, tolerance: f64) -> usize {
+        let cell_size = if tolerance > 0.0 { tolerance } else { 1.0 };
+        let cell = |v: &Vec3| [(v.x / cell_size).floor() as i64, (v.y / cell_size).floor() as i64, (v.z / cell_size).floor() as i64];
+
+        let mut grid: HashMap<[i64; 3], Vec<u32>> = HashMap::new();
+        let mut positions: Vec<Vec3> = Vec::new();
+        let mut remap = Vec::with_capacity(self.positions.len());
+
+        for v in self.positions.iter() {
+            let c = cell(v);
+            let mut found = None;
+            // A match within the tolerance is at most one cell away
+            'search: for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        for &other in grid.get(&[c[0] + dx, c[1] + dy, c[2] + dz]).into_iter().flatten() {
+                            let d = positions[other as usize] - *v;
+                            if d.dot(&d) <= tolerance * tolerance {
+                                found = Some(other);
+                                break 'search;
+                            }
+                        }
+                    }
+                }
+            }
+
+            remap.push(found.unwrap_or_else(|| {
+                positions.push(*v);
+                let index = (positions.len() - 1) as u32;
+                grid.entry(c).or_default().push(index);
+                index
+            }));
+        }
+
+        let merged = self.positions.len() - positions.len();
+        self.positions = positions;
+
+        let faces = self.faces.iter().map(|face| [remap[face[0] as usize], remap[face[1] as usize], remap[face[2] as usize]]);
+        let (faces, corners) = faces.zip(self.corners.iter())
+            .filter(|&(face, _)| face[0] != face[1] && face[1] != face[2] && face[2] != face[0])
+            .map(|(face, &corners)| (face, corners))
+            .unzip();
+        self.faces = faces;
+        self.corners = corners;
+
+        merged
+    }
+
+    /// Flips faces so that neighbours across every edge shared by exactly
+    /// two faces agree on their winding. Each connected piece takes the
+    /// winding of its first face, except that closed pieces are turned to
+    /// face outwards. Returns how many faces were flipped.
+    pub fn orient(&mut self) -> usize {
+        let mut edges: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+        for (index, face) in self.faces.iter().enumerate() {
+            for k in 0..3 {
+                let (a, b) = (face[k], face[(k + 1) % 3]);
+                edges.entry((a.min(b), a.max(b))).or_default().push(index);
+            }
+        }
+
+        // Whether `face` runs along the edge from `a` to `b`, as given
+        let runs_forward = |face: &[u32; 3], a: u32, b: u32| (0..3).any(|k| face[k] == a && face[(k + 1) % 3] == b);
+
+        let mut flip = vec![false; self.faces.len()];
+        let mut visited = vec![false; self.faces.len()];
+
+        for seed in 0..self.faces.len() {
+            if visited[seed] {
+                continue;
+            }
+            visited[seed] = true;
+            let mut piece = vec![seed];
+            let mut closed = true;
+            let mut queue = VecDeque::new();
+            queue.push_back(seed);
+
+            while let Some(index) = queue.pop_front() {
+                let face = self.faces[index];
+                for k in 0..3 {
+                    let (a, b) = (face[k], face[(k + 1) % 3]);
+                    let sharing = &edges[&(a.min(b), a.max(b))];
+                    if sharing.len() != 2 {
+                        closed = false;
+                        continue;
+                    }
+
+                    let other = if sharing[0] == index { sharing[1] } else { sharing[0] };
+                    if visited[other] {
+                        continue;
+                    }
+                    // Neighbours agree when they run along the edge in
+                    // opposite directions
+                    visited[other] = true;
+                    flip[other] = flip[index] != runs_forward(&self.faces[other], a, b);
+                    piece.push(other);
+                    queue.push_back(other);
+                }
+            }
+
+            if closed && self.signed_volume(&piece, &flip) < 0.0 {
+                for &index in piece.iter() {
+                    flip[index] = !flip[index];
+                }
+            }
+        }
+
+        for (index, &flip) in flip.iter().enumerate() {
+            if flip {
+                self.faces[index].swap(1, 2);
+                self.corners[index].swap(1, 2);
+            }
+        }
+        flip.iter().filter(|&&flip| flip).count()
+    }
+
+    /// Six times the volume enclosed by `faces`, positive when they wind
+    /// counter-clockwise seen from outside.
+    fn signed_volume(&self, faces: &[usize], flip: &[bool]) -> f64 {
+        faces.iter().map(|&index| {
+            let face = self.faces[index];
+            let (a, b, c) = (self.positions[face[0] as usize], self.positions[face[1] as usize], self.positions[face[2] as usize]);
+            let volume = a.dot(&b.cross(&c));
+            if flip[index] { -volume } else { volume }
+        }).sum()
+    }
+
+    /// A normal for each face corner (`3 * face + corner`).
+    pub fn smooth_normals(&self, crease_angle: f64) -> Vec<Vec3> {
+        smooth_normals(&self.positions, &self.faces, crease_angle)
+    }
+}
+
+/// Computes a normal for each face corner (`3 * face + corner`) by averaging
+/// the normals of the faces around the vertex, weighted by their angle at
+/// the vertex, and skipping faces that meet this one at more than
+/// `crease_angle` degrees. Weighting by angle keeps the result the same
+/// however the faces around a vertex happen to be split up.
+pub fn smooth_normals(positions: &[Vec3], faces: &[[u32; 3]], crease_angle: f64) -> Vec<Vec3> {
+    let face_normals: Vec<Vec3> = faces.iter().map(|face| {
+        let (a, b, c) = (positions[face[0] as usize], positions[face[1] as usize], positions[face[2] as usize]);
+        let n = (b - a).cross(&(c - a));
+        if n.len() > 0.0 { n.unit() } else { Vec3::zero() }
+    }).collect();
+
+    // The faces around each vertex, with their angle there
+    let mut vertex_faces: Vec<Vec<(usize, f64)>> = vec![Vec::new(); positions.len()];
+    for (index, face) in faces.iter().enumerate() {
+        for k in 0..3 {
+            let p = positions[face[k] as usize];
+            let (e1, e2) = (positions[face[(k + 1) % 3] as usize] - p, positions[face[(k + 2) % 3] as usize] - p);
+            let angle = if e1.len() > 0.0 && e2.len() > 0.0 {
+                e1.unit().dot(&e2.unit()).clamp(-1.0, 1.0).acos()
+            } else {
+                0.0
+            };
+            vertex_faces[face[k] as usize].push((index, angle));
+        }
+    }
+
+    let cos_crease = crease_angle.to_radians().cos();
+    let mut normals = Vec::with_capacity(faces.len() * 3);
+
+    for (index, face) in faces.iter().enumerate() {
+        let unit = face_normals[index];
+        for &v in face.iter() {
+            let sum = vertex_faces[v as usize].iter()
+                .filter(|&&(other, _)| face_normals[other].dot(&unit) >= cos_crease)
+                .fold(Vec3::zero(), |sum, &(other, angle)| sum + face_normals[other].scale(angle));
+            normals.push(if sum.len() > 0.0 { sum.unit() } else { unit });
+        }
+    }
+
+    normals
+}
+
+#[cfg(test)]
+fn cube_faces() -> (Vec<Vec3>, Vec<[u32; 3]>) {
+    let positions = (0..8).map(|i| Vec3 { x: (i & 1) as f64, y: ((i >> 1) & 1) as f64, z: ((i >> 2) & 1) as f64 }).collect();
+    // Wound counter-clockwise seen from outside
+    let faces = vec![
+        [0, 2, 1], [1, 2, 3], [4, 5, 6], [5, 7, 6],
+        [0, 1, 4], [1, 5, 4], [2, 6, 3], [3, 6, 7],
+        [0, 4, 2], [2, 4, 6], [1, 3, 5], [3, 7, 5]
+    ];
+    (positions, faces)
+}
+
+#[test]
+fn it_welds_within_the_tolerance() {
+    let mut mesh = IndexedTriangles::new(vec![
+        Vec3::zero(), Vec3 { x: 1.0, y: 0.0, z: 0.0 }, Vec3 { x: 0.0, y: 1.0, z: 0.0 },
+        Vec3 { x: 1.0 + 1e-7, y: 0.0, z: 0.0 }, Vec3 { x: 0.0, y: 1.0, z: -1e-7 }, Vec3 { x: 1.0, y: 1.0, z: 0.0 },
+        Vec3 { x: 0.0, y: 0.0, z: 1e-7 }
+    ], vec![[0, 1, 2], [3, 5, 4], [0, 6, 1]]);
+
+    assert_eq!(0, IndexedTriangles::new(mesh.positions.clone(), mesh.faces.clone()).weld(0.0));
+    assert_eq!(3, mesh.weld(1e-6));
+    assert_eq!(4, mesh.positions.len());
+    // The sliver collapses and goes
+    assert_eq!(vec![[0, 1, 2], [1, 3, 2]], mesh.faces);
+    assert_eq!(1, mesh.origin(1));
+}
+
+#[test]
+fn it_orients_closed_meshes_outwards() {
+    let (positions, mut faces) = cube_faces();
+    let expected = faces.clone();
+    // Turn the whole cube inside out, except for a couple of faces
+    for face in faces.iter_mut().skip(2) {
+        face.swap(1, 2);
+    }
+
+    let mut mesh = IndexedTriangles::new(positions, faces);
+    assert_eq!(10, mesh.orient());
+    for (face, expected) in mesh.faces.iter().zip(expected.iter()) {
+        let turns = (0..3).any(|k| (0..3).all(|i| face[i] == expected[(i + k) % 3]));
+        assert!(turns);
+    }
+}
+
+#[test]
+fn it_smooths_within_the_crease_angle() {
+    let (positions, faces) = cube_faces();
+    let mesh = IndexedTriangles::new(positions, faces);
+
+    // Each cube face is flat, even though it's split in two
+    let sharp = mesh.smooth_normals(30.0);
+    assert_eq!(Vec3 { x: 0.0, y: 0.0, z: -1.0 }, sharp[0]);
+    assert_eq!(Vec3 { x: 0.0, y: 0.0, z: -1.0 }, sharp[3]);
+
+    // Corners point out along the diagonal, however the faces were split:
+    // two triangles meet at this corner on the bottom and front, one on the side
+    let smooth = mesh.smooth_normals(100.0);
+    let diagonal = Vec3 { x: 1.0, y: -1.0, z: -1.0 }.unit();
+    assert!((smooth[30] - diagonal).len() < 1e-9);
+}
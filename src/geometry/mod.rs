@@ -2,14 +2,52 @@ pub use self::prim::Prim;
 pub use self::bbox::{BBox, PartialBoundingBox};
 
 pub mod bbox;
+pub mod meshtools;
 pub mod prim;
+pub mod subdivision;
 
 pub mod prims {
+    #[allow(unused_imports)]
+    pub use self::cone::{Cone, ConeOptions};
+    #[allow(unused_imports)]
+    pub use self::csg::{Csg, CsgOp};
+    pub use self::cuboid::Cuboid;
+    #[allow(unused_imports)]
+    pub use self::curve::{Curves, CurvesOptions, CurveBasis, CurveShape};
+    #[allow(unused_imports)]
+    pub use self::cylinder::{Cylinder, CylinderOptions};
+    #[allow(unused_imports)]
+    pub use self::disk::{Disk, DiskOptions};
+    #[allow(unused_imports)]
+    pub use self::displacement::{Displacement, DisplacementOptions};
+    #[allow(unused_imports)]
+    pub use self::heightfield::{Heightfield, HeightfieldOptions};
+    pub use self::mesh::{Mesh, MeshFace, MeshOptions};
+    #[cfg(test)]
+    pub use self::mesh::unit_quad;
+    pub use self::moving::Moving;
     pub use self::plane::Plane;
+    #[allow(unused_imports)]
+    pub use self::sdf::{Sdf, SdfNode, SdfOptions};
     pub use self::sphere::Sphere;
+    #[allow(unused_imports)]
+    pub use self::torus::Torus;
     pub use self::triangle::{Triangle, TriangleOptions};
 
+    mod cone;
+    mod csg;
+    mod cuboid;
+    mod curve;
+    mod cylinder;
+    mod disk;
+    mod displacement;
+    mod heightfield;
+    mod mesh;
+    mod moving;
     mod plane;
+    mod quadric;
+    mod sdf;
     mod sphere;
+    mod torus;
     mod triangle;
 }
@@ -1,11 +1,76 @@
 use crate::prelude::*;
 use crate::geometry::{BBox, PartialBoundingBox};
-use crate::raytracer::{Ray, Intersection};
+use crate::light::lights::AreaLight;
+use crate::raytracer::{Ray, Intersection, TraversalStats};
 use crate::mat4::Transform;
 
+/// Crossings closer together than this are treated as one when walking
+/// along a ray, as in `Ray::get_nearest_hit`.
+pub static SPAN_EPSILON: f64 = 0.000001;
+
+/// Upper limit on surfaces crossed when walking a ray through a prim, so a
+/// prim that keeps reporting the same hit can't hang the render.
+pub static MAX_CROSSINGS: usize = 64;
+
+/// A stretch of ray spent inside a solid. `enter` is `None` if the ray was
+/// already inside at `t_min`, and `exit` is `None` if it's still inside at
+/// `t_max`.
+pub struct Span<'a> {
+    pub enter: Option<Intersection<'a>>,
+    pub exit: Option<Intersection<'a>>
+}
+
 pub trait Prim: PartialBoundingBox {
     fn intersects<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>>;
-    
+
+    /// As `intersects`, also counting the work done for the traversal heatmap.
+    /// Prims with their own acceleration structure should count their insides.
+    fn intersects_counted<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64,
+                              stats: &mut TraversalStats) -> Option<Intersection<'a>> {
+        stats.prim_tests += 1;
+        self.intersects(ray, t_min, t_max)
+    }
+
+    /// Every stretch of `[t_min, t_max]` the ray spends inside the prim,
+    /// nearest first, for constructive solid geometry.
+    ///
+    /// By default this walks each surface crossing with `intersects`, taking
+    /// one along the normal to be leaving. That suits any closed prim with
+    /// outward normals, and planes as half-spaces; open surfaces like
+    /// triangles and disks give meaningless spans.
+    fn spans<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Vec<Span<'a>> {
+        let mut spans = Vec::new();
+        let mut enter = None;
+        let mut t = t_min;
+
+        for crossing in 0..MAX_CROSSINGS {
+            let hit = match self.intersects(ray, t, t_max) {
+                Some(hit) if hit.t >= t => hit,
+                _ => break
+            };
+            t = hit.t + SPAN_EPSILON;
+
+            if hit.front_face {
+                enter = Some(hit);
+            } else if enter.is_some() || crossing == 0 {
+                // Only the first crossing can leave without having entered
+                spans.push(Span { enter: enter.take(), exit: Some(hit) });
+            }
+        }
+
+        if enter.is_some() {
+            spans.push(Span { enter: enter, exit: None });
+        }
+        spans
+    }
+
+    /// Area lights for the parts of the prim made of glowing materials, for
+    /// `Scene::new` to light the scene with. Prims that can't pick points
+    /// on their surface light nothing, however much they glow.
+    fn area_lights(&self) -> Vec<AreaLight> {
+        Vec::new()
+    }
+
     // fn transform(&self, transform: &Transform) -> Box<Prim+Send+Sync>;
     fn mut_transform(&mut self, transform: &Transform);
 }
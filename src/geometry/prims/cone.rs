@@ -0,0 +1,188 @@
+use crate::prelude::*;
+use crate::geometry::bbox::{BBox, PartialBoundingBox};
+use crate::geometry::prim::Prim;
+use crate::light::lights::{AreaLight, EmitterShape};
+use crate::material::Material;
+use crate::material::materials::FlatMaterial;
+use crate::mat4::{Mat4, Transform};
+use crate::poly::solve_quadratic;
+use crate::raytracer::{Ray, Intersection};
+use crate::vec3::Vec3;
+use core::f64::consts::PI;
+
+use super::quadric::{intersect_ring, nearest, phi, ring_emitter, LocalHit, EMITTER_CELLS};
+
+pub struct ConeOptions {
+    radius: f64,
+    height: f64,
+    capped: bool,
+    sweep: f64,
+    material: Option<Box<dyn Material+Send+Sync>>,
+}
+
+#[allow(dead_code)]
+impl ConeOptions {
+    /// A cone with its base of `radius` on the origin and its apex `height`
+    /// up the +Y axis. Use `mut_transform` on the result to place it.
+    pub fn new(radius: f64, height: f64) -> ConeOptions {
+        ConeOptions {
+            radius: radius,
+            height: height,
+            capped: false,
+            sweep: 360.0,
+            material: None,
+        }
+    }
+
+    /// Close the base with a disk.
+    pub fn capped(&mut self, capped: bool) -> &mut Self {
+        self.capped = capped;
+        self
+    }
+
+    /// Degrees around the axis to keep, from +X towards +Z. The cut edges
+    /// are left open.
+    pub fn sweep(&mut self, degrees: f64) -> &mut Self {
+        self.sweep = degrees;
+        self
+    }
+
+    pub fn material(&mut self, material: Box<dyn Material+Send+Sync>) -> &mut Self {
+        self.material = Some(material);
+        self
+    }
+
+    pub fn build(self) -> Cone {
+        Cone {
+            radius: self.radius,
+            height: self.height,
+            phi_max: self.sweep.clamp(0.0, 360.0) * PI / 180.0,
+            capped: self.capped,
+            transform: Transform::new(Mat4::identity()),
+            material: self.material.unwrap_or_else(|| Box::new(FlatMaterial { color: Vec3::one() }))
+        }
+    }
+}
+
+pub struct Cone {
+    pub radius: f64,
+    pub height: f64,
+    pub phi_max: f64,
+    pub capped: bool,
+    pub transform: Transform,
+    pub material: Box<dyn Material+Send+Sync>
+}
+
+impl Cone {
+    fn intersect_side(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<LocalHit> {
+        // x^2 + z^2 = k * (height - y)^2, measured down from the apex
+        let (o, d) = (ray.origin, ray.direction);
+        let k = (self.radius / self.height) * (self.radius / self.height);
+        let apex_dy = self.height - o.y;
+        let a = d.x * d.x + d.z * d.z - k * d.y * d.y;
+        let b = 2.0 * (o.x * d.x + o.z * d.z + k * d.y * apex_dy);
+        let c = o.x * o.x + o.z * o.z - k * apex_dy * apex_dy;
+        let (t0, t1) = solve_quadratic(a, b, c)?;
+
+        for &t in [t0, t1].iter() {
+            if t < t_min || t > t_max {
+                continue;
+            }
+            // The quadric is a double cone, so this also rejects the mirror
+            // image above the apex
+            let p = o + d.scale(t);
+            let phi = phi(&p);
+            if p.y < 0.0 || p.y > self.height || phi > self.phi_max {
+                continue;
+            }
+            return Some(LocalHit {
+                t: t,
+                n: Vec3 { x: p.x, y: k * (self.height - p.y), z: p.z },
+                u: phi / self.phi_max,
+                v: p.y / self.height,
+                dpdu: Vec3 { x: -p.z, y: 0.0, z: p.x }.scale(self.phi_max),
+                dpdv: Vec3 { x: -self.radius * phi.cos(), y: self.height, z: -self.radius * phi.sin() }
+            });
+        }
+        None
+    }
+}
+
+impl PartialBoundingBox for Cone {
+    fn partial_bounding_box(&self) -> Option<BBox> {
+        let r = self.radius;
+        Some(self.transform.bbox_to_world(&BBox {
+            min: Vec3 { x: -r, y: 0.0, z: -r },
+            max: Vec3 { x: r, y: self.height, z: r }
+        }))
+    }
+}
+
+impl Prim for Cone {
+    fn intersects<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        let local_ray = self.transform.ray_to_object(ray);
+        let mut hit = self.intersect_side(&local_ray, t_min, t_max);
+
+        if self.capped {
+            let base = intersect_ring(&local_ray, 0.0, -1.0, 0.0, self.radius, self.phi_max)
+                .filter(|hit| hit.t >= t_min && hit.t <= t_max);
+            hit = nearest(hit, base);
+        }
+
+        hit.map(|hit| hit.into_world(ray, &self.transform, &self.material))
+    }
+
+    fn mut_transform(&mut self, transform: &Transform) {
+        self.transform = Transform::new(Mat4::mult_m(&transform.m, &self.transform.m));
+    }
+    /// One light for the side and one for the base.
+    fn area_lights(&self) -> Vec<AreaLight> {
+        let emission = match self.material.emitter() {
+            Some(emission) => emission,
+            None => return Vec::new()
+        };
+
+        let (radius, height, phi_max) = (self.radius, self.height, self.phi_max);
+        let mut shapes = vec![EmitterShape::Surface {
+            surface: Box::new(move |u, v| {
+                let phi = u * phi_max;
+                let r = radius * (1.0 - v);
+                // The normal only leans with the slope, so it stays put up
+                // to the apex
+                let n = Vec3 { x: height * phi.cos(), y: radius, z: height * phi.sin() }.unit();
+                (Vec3 { x: r * phi.cos(), y: v * height, z: r * phi.sin() }, n)
+            }),
+            columns: EMITTER_CELLS,
+            rows: EMITTER_CELLS
+        }];
+        if self.capped {
+            shapes.push(ring_emitter(0.0, -1.0, 0.0, radius, phi_max));
+        }
+
+        shapes.into_iter().map(|shape| AreaLight::new(shape.transformed(&self.transform), emission.clone())).collect()
+    }
+}
+
+#[test]
+fn it_intersects_the_side_and_base() {
+    let mut options = ConeOptions::new(1.0, 1.0);
+    options.capped(true);
+    let cone = options.build();
+
+    // Halfway up, the cone's radius is 0.5 and its normal is tilted 45 degrees
+    let ray = Ray::new(Vec3 { x: -2.0, y: 0.5, z: 0.0 }, Vec3 { x: 1.0, y: 0.0, z: 0.0 });
+    let hit = cone.intersects(&ray, 0.0, 10.0).unwrap();
+    assert!((hit.t - 1.5).abs() < 1e-9);
+    assert!((hit.n.x + 0.5f64.sqrt()).abs() < 1e-9);
+    assert!((hit.n.y - 0.5f64.sqrt()).abs() < 1e-9);
+    assert!((hit.v - 0.5).abs() < 1e-9);
+
+    let ray = Ray::new(Vec3 { x: 0.5, y: -1.0, z: 0.0 }, Vec3 { x: 0.0, y: 1.0, z: 0.0 });
+    let hit = cone.intersects(&ray, 0.0, 10.0).unwrap();
+    assert_eq!(1.0, hit.t);
+    assert_eq!(Vec3 { x: 0.0, y: -1.0, z: 0.0 }, hit.n);
+
+    // Passing above the apex only meets the mirrored half of the quadric
+    let ray = Ray::new(Vec3 { x: -2.0, y: 1.5, z: 0.0 }, Vec3 { x: 1.0, y: 0.0, z: 0.0 });
+    assert!(cone.intersects(&ray, 0.0, 10.0).is_none());
+}
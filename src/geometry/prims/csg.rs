@@ -0,0 +1,210 @@
+use crate::prelude::*;
+use crate::geometry::bbox::{union_bbox, BBox, PartialBoundingBox};
+use crate::geometry::prim::{Prim, Span};
+use crate::mat4::Transform;
+use crate::raytracer::{Ray, Intersection};
+use crate::vec3::Vec3;
+
+#[cfg(test)]
+use crate::geometry::prims::Sphere;
+#[cfg(test)]
+use crate::material::materials::FlatMaterial;
+#[cfg(test)]
+use core::f64::consts::PI;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    /// Left with right carved out of it
+    Difference
+}
+
+impl CsgOp {
+    fn inside(self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOp::Union => in_left || in_right,
+            CsgOp::Intersection => in_left && in_right,
+            CsgOp::Difference => in_left && !in_right
+        }
+    }
+}
+
+/// A solid built from two others, which can themselves be `Csg`s. Both need
+/// to be closed prims; see `Prim::spans`. Each surface keeps the material of
+/// the child it came from.
+pub struct Csg {
+    pub op: CsgOp,
+    pub left: Box<dyn Prim+Send+Sync>,
+    pub right: Box<dyn Prim+Send+Sync>
+}
+
+struct Event<'a> {
+    t: f64,
+    entering: bool,
+    from_left: bool,
+    hit: Option<Intersection<'a>>
+}
+
+fn push_events<'a>(events: &mut Vec<Event<'a>>, spans: Vec<Span<'a>>, from_left: bool) {
+    for span in spans {
+        events.push(Event {
+            t: span.enter.as_ref().map_or(f64::NEG_INFINITY, |hit| hit.t),
+            entering: true,
+            from_left: from_left,
+            hit: span.enter
+        });
+        events.push(Event {
+            t: span.exit.as_ref().map_or(f64::INFINITY, |hit| hit.t),
+            entering: false,
+            from_left: from_left,
+            hit: span.exit
+        });
+    }
+}
+
+impl Csg {
+    #[allow(dead_code)]
+    pub fn new(op: CsgOp, left: Box<dyn Prim+Send+Sync>, right: Box<dyn Prim+Send+Sync>) -> Csg {
+        Csg {
+            op: op,
+            left: left,
+            right: right
+        }
+    }
+}
+
+impl PartialBoundingBox for Csg {
+    fn partial_bounding_box(&self) -> Option<BBox> {
+        let left = self.left.partial_bounding_box();
+        let right = self.right.partial_bounding_box();
+
+        match self.op {
+            CsgOp::Union => match (left, right) {
+                (Some(left), Some(right)) => Some(union_bbox(&left, &right)),
+                _ => None
+            },
+            CsgOp::Intersection => match (left, right) {
+                (Some(left), Some(right)) => Some(BBox {
+                    min: Vec3 { x: left.min.x.max(right.min.x), y: left.min.y.max(right.min.y), z: left.min.z.max(right.min.z) },
+                    max: Vec3 { x: left.max.x.min(right.max.x), y: left.max.y.min(right.max.y), z: left.max.z.min(right.max.z) }
+                }),
+                (left, right) => left.or(right)
+            },
+            CsgOp::Difference => left
+        }
+    }
+}
+
+impl Prim for Csg {
+    fn intersects<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        // Starting inside the solid, the first surface met is where it leaves
+        self.spans(ray, t_min, t_max).into_iter()
+            .filter_map(|span| span.enter.or(span.exit))
+            .next()
+    }
+
+    /// Merges the children's spans in order along the ray, tracking whether
+    /// the ray is inside each. Wherever that changes whether it is inside
+    /// the result, the child's hit becomes a boundary of the result.
+    fn spans<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Vec<Span<'a>> {
+        let mut events = Vec::new();
+        push_events(&mut events, self.left.spans(ray, t_min, t_max), true);
+        push_events(&mut events, self.right.spans(ray, t_min, t_max), false);
+        events.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap_or(::core::cmp::Ordering::Equal));
+
+        let mut spans = Vec::new();
+        let (mut in_left, mut in_right, mut inside) = (false, false, false);
+        let mut enter = None;
+
+        for event in events {
+            if event.from_left { in_left = event.entering; } else { in_right = event.entering; }
+            if self.op.inside(in_left, in_right) == inside {
+                continue;
+            }
+            inside = !inside;
+
+            // The carved-out surface faces into what's left
+            let mut hit = event.hit;
+            if self.op == CsgOp::Difference && !event.from_left {
+                if let Some(ref mut hit) = hit {
+                    hit.n = -hit.n;
+                    hit.geometric_n = -hit.geometric_n;
+                    hit.front_face = !hit.front_face;
+                }
+            }
+
+            if inside {
+                enter = Some(hit);
+            } else {
+                spans.push(Span { enter: enter.take().and_then(|hit| hit), exit: hit });
+            }
+        }
+
+        if let Some(hit) = enter {
+            spans.push(Span { enter: hit, exit: None });
+        }
+        spans
+    }
+
+    fn mut_transform(&mut self, transform: &Transform) {
+        self.left.mut_transform(transform);
+        self.right.mut_transform(transform);
+    }
+}
+
+#[cfg(test)]
+fn sphere(x: f64, radius: f64, color: f64) -> Box<dyn Prim+Send+Sync> {
+    Box::new(Sphere {
+        center: Vec3 { x: x, y: 0.0, z: 0.0 },
+        radius: radius,
+        material: Box::new(FlatMaterial { color: Vec3::one().scale(color) })
+    })
+}
+
+#[test]
+fn it_combines_spheres() {
+    let ray = Ray::new(Vec3 { x: -5.0, y: 0.0, z: 0.0 }, Vec3 { x: 1.0, y: 0.0, z: 0.0 });
+    let up = Vec3 { x: 0.0, y: 0.0, z: 1.0 };
+
+    // Two overlapping spheres spanning x = -2.5..0.5 and -0.5..2.5
+    let union = Csg::new(CsgOp::Union, sphere(-1.0, 1.5, 0.5), sphere(1.0, 1.5, 1.0));
+    let spans = union.spans(&ray, 0.0, 20.0);
+    assert_eq!(1, spans.len());
+    assert_eq!(2.5, spans[0].enter.as_ref().unwrap().t);
+    assert_eq!(7.5, spans[0].exit.as_ref().unwrap().t);
+
+    // A lens
+    let lens = Csg::new(CsgOp::Intersection, sphere(-1.0, 2.0, 0.5), sphere(1.0, 2.0, 1.0));
+    let hit = lens.intersects(&ray, 0.0, 20.0).unwrap();
+    assert_eq!(4.0, hit.t);
+    assert_eq!(Vec3 { x: -1.0, y: 0.0, z: 0.0 }, hit.n);
+    assert_eq!(Vec3::one().scale(1.0 / PI), hit.material.bsdf(0.0, 0.0).eval(up, up));
+
+    // From inside the lens, the first surface is where the ray leaves
+    let hit = lens.intersects(&ray, 5.0, 20.0).unwrap();
+    assert_eq!(6.0, hit.t);
+}
+
+#[test]
+fn it_flips_normals_on_carved_surfaces() {
+    let ray = Ray::new(Vec3 { x: -5.0, y: 0.0, z: 0.0 }, Vec3 { x: 1.0, y: 0.0, z: 0.0 });
+    let up = Vec3 { x: 0.0, y: 0.0, z: 1.0 };
+
+    // A big sphere with a small one bitten out of its near side
+    let bitten = Csg::new(CsgOp::Difference, sphere(0.0, 2.0, 1.0), sphere(-2.0, 1.0, 0.5));
+    let hit = bitten.intersects(&ray, 0.0, 20.0).unwrap();
+    assert_eq!(4.0, hit.t);
+    assert_eq!(Vec3 { x: -1.0, y: 0.0, z: 0.0 }, hit.n);
+    assert_eq!(Vec3::one().scale(0.5).scale(1.0 / PI), hit.material.bsdf(0.0, 0.0).eval(up, up));
+
+    // Carving out a sphere's middle leaves a shell, entered twice
+    let shell = Csg::new(CsgOp::Difference, sphere(0.0, 2.0, 1.0), sphere(0.0, 1.0, 0.5));
+    let spans = shell.spans(&ray, 0.0, 20.0);
+    assert_eq!(2, spans.len());
+    assert_eq!(6.0, spans[1].enter.as_ref().unwrap().t);
+    assert_eq!(Vec3 { x: -1.0, y: 0.0, z: 0.0 }, spans[1].enter.as_ref().unwrap().n);
+
+    assert!(bitten.partial_bounding_box().unwrap() == BBox { min: Vec3::one().scale(-2.0), max: Vec3::one().scale(2.0) });
+}
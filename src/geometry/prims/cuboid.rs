@@ -0,0 +1,201 @@
+use crate::prelude::*;
+use crate::geometry::bbox::{BBox, PartialBoundingBox};
+use crate::geometry::prim::Prim;
+use crate::light::lights::{AreaLight, EmitterShape, EmitterTriangle};
+use crate::material::Material;
+use crate::mat4::{Mat4, Transform};
+use crate::raytracer::{Ray, Intersection};
+use crate::vec3::Vec3;
+
+#[cfg(test)]
+use crate::material::materials::FlatMaterial;
+
+/// A box, axis-aligned in its own space and placed in the world by
+/// `transform`, so rotating it gives an oriented box.
+#[allow(dead_code)]
+pub struct Cuboid {
+    pub bbox: BBox,
+    pub transform: Transform,
+    pub material: Box<dyn Material+Send+Sync>
+}
+
+impl Cuboid {
+    pub fn new(min: Vec3, max: Vec3, material: Box<dyn Material+Send+Sync>) -> Cuboid {
+        Cuboid {
+            bbox: BBox { min: min, max: max },
+            transform: Transform::new(Mat4::identity()),
+            material: material
+        }
+    }
+}
+
+/// Each face is textured across its full extent, oriented so that it reads
+/// the right way round from outside the box.
+fn face_uv(axis: usize, positive: bool, offset: &Vec3) -> (f64, f64) {
+    let flip = |t: f64| if positive { t } else { 1.0 - t };
+    match axis {
+        0 => (flip(1.0 - offset.z), offset.y),
+        1 => (offset.x, flip(1.0 - offset.z)),
+        _ => (flip(offset.x), offset.y)
+    }
+}
+
+/// How a face's UVs from `face_uv` run across a box of `size`.
+fn face_derivatives(axis: usize, positive: bool, size: &Vec3) -> (Vec3, Vec3) {
+    let sign = if positive { 1.0 } else { -1.0 };
+    let x = Vec3 { x: size.x, y: 0.0, z: 0.0 };
+    let y = Vec3 { x: 0.0, y: size.y, z: 0.0 };
+    let z = Vec3 { x: 0.0, y: 0.0, z: size.z };
+    match axis {
+        0 => (z.scale(-sign), y),
+        1 => (x, z.scale(-sign)),
+        _ => (x.scale(sign), y)
+    }
+}
+
+impl PartialBoundingBox for Cuboid {
+    fn partial_bounding_box(&self) -> Option<BBox> {
+        Some(self.transform.bbox_to_world(&self.bbox))
+    }
+}
+
+impl Prim for Cuboid {
+    fn intersects<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        let local_ray = self.transform.ray_to_object(ray);
+
+        let (t_near, t_far) = self.bbox.intersect_interval(&local_ray)?;
+        let t = if t_near >= t_min { t_near } else { t_far };
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        // The face hit is the one the local hit point is furthest towards
+        let local_point = local_ray.origin + local_ray.direction.scale(t);
+        let offset = self.bbox.offset(&local_point);
+        let centered = [offset.x - 0.5, offset.y - 0.5, offset.z - 0.5];
+        let axis = (0..3).fold(0, |best, axis| if centered[axis].abs() > centered[best].abs() { axis } else { best });
+        let positive = centered[axis] > 0.0;
+
+        let mut local_n = [0.0; 3];
+        local_n[axis] = if positive { 1.0 } else { -1.0 };
+        let local_n = Vec3 { x: local_n[0], y: local_n[1], z: local_n[2] };
+        let (u, v) = face_uv(axis, positive, &offset);
+        let (dpdu, dpdv) = face_derivatives(axis, positive, &(self.bbox.max - self.bbox.min));
+
+        let n = self.transform.normal_to_world(&local_n);
+
+        Some(Intersection {
+            n: n,
+            geometric_n: n,
+            front_face: n.dot(&ray.direction) < 0.0,
+            prim_id: 0,
+            t: t,
+            u: u,
+            v: v,
+            position: ray.origin + ray.direction.scale(t),
+            dpdu: Mat4::mult_v(&self.transform.m, &dpdu),
+            dpdv: Mat4::mult_v(&self.transform.m, &dpdv),
+            material: &self.material
+        })
+    }
+
+    fn mut_transform(&mut self, transform: &Transform) {
+        self.transform = Transform::new(Mat4::mult_m(&transform.m, &self.transform.m));
+    }
+    fn area_lights(&self) -> Vec<AreaLight> {
+        self.material.emitter().into_iter().map(|emission| {
+            // Two triangles per face, with the uvs `face_uv` gives them
+            let mut triangles = Vec::with_capacity(12);
+            for axis in 0..3 {
+                for &positive in [false, true].iter() {
+                    let corner = |s: f64, t: f64| {
+                        let mut offset = [0.0; 3];
+                        offset[axis] = if positive { 1.0 } else { 0.0 };
+                        offset[(axis + 1) % 3] = s;
+                        offset[(axis + 2) % 3] = t;
+                        let offset = Vec3 { x: offset[0], y: offset[1], z: offset[2] };
+                        (self.bbox.lerp(offset.x, offset.y, offset.z), face_uv(axis, positive, &offset))
+                    };
+                    let (a, b, c, d) = (corner(0.0, 0.0), corner(1.0, 0.0), corner(1.0, 1.0), corner(0.0, 1.0));
+                    for &(p, q, r) in [(a, b, c), (a, c, d)].iter() {
+                        triangles.push(EmitterTriangle { vertices: [p.0, q.0, r.0], texinfo: [p.1, q.1, r.1] });
+                    }
+                }
+            }
+            AreaLight::new(EmitterShape::Triangles(triangles).transformed(&self.transform), emission)
+        }).collect()
+    }
+}
+
+#[test]
+fn it_intersects_each_face() {
+    let cuboid = Cuboid::new(Vec3::zero(), Vec3 { x: 2.0, y: 1.0, z: 1.0 }, Box::new(FlatMaterial { color: Vec3::one() }));
+
+    let ray = Ray::new(Vec3 { x: 0.5, y: 0.5, z: -1.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    let hit = cuboid.intersects(&ray, 0.0, 10.0).unwrap();
+    assert_eq!(1.0, hit.t);
+    assert_eq!(Vec3 { x: 0.0, y: 0.0, z: -1.0 }, hit.n);
+    assert_eq!((0.75, 0.5), (hit.u, hit.v));
+
+    let ray = Ray::new(Vec3 { x: 3.0, y: 0.5, z: 0.25 }, Vec3 { x: -1.0, y: 0.0, z: 0.0 });
+    let hit = cuboid.intersects(&ray, 0.0, 10.0).unwrap();
+    assert_eq!(1.0, hit.t);
+    assert_eq!(Vec3 { x: 1.0, y: 0.0, z: 0.0 }, hit.n);
+    assert_eq!((0.75, 0.5), (hit.u, hit.v));
+
+    // From inside, the far face is hit
+    let ray = Ray::new(Vec3 { x: 1.0, y: 0.5, z: 0.5 }, Vec3 { x: 0.0, y: 1.0, z: 0.0 });
+    let hit = cuboid.intersects(&ray, 0.0, 10.0).unwrap();
+    assert_eq!(0.5, hit.t);
+    assert_eq!(Vec3 { x: 0.0, y: 1.0, z: 0.0 }, hit.n);
+
+    // Outside of tmin/tmax, and missing the box
+    assert!(cuboid.intersects(&ray, 0.0, 0.25).is_none());
+    let ray = Ray::new(Vec3 { x: 0.5, y: 1.5, z: -1.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    assert!(cuboid.intersects(&ray, 0.0, 10.0).is_none());
+}
+
+#[test]
+fn it_transforms_into_an_oriented_box() {
+    let mut cuboid = Cuboid::new(-Vec3::one(), Vec3::one(), Box::new(FlatMaterial { color: Vec3::one() }));
+    cuboid.mut_transform(&Transform::new(Mat4::rotate_y_deg_matrix(45.0)));
+    cuboid.mut_transform(&Transform::new(Mat4::translate_matrix(&Vec3 { x: 0.0, y: 0.0, z: 5.0 })));
+
+    let bbox = cuboid.partial_bounding_box().unwrap();
+    let half_diagonal = 2.0f64.sqrt();
+    assert!((bbox.max.x - half_diagonal).abs() < 1e-9);
+    assert!((bbox.min.z - (5.0 - half_diagonal)).abs() < 1e-9);
+    assert!((bbox.max.y - 1.0).abs() < 1e-9);
+
+    // Straight on, the ray meets the edge between two faces
+    let ray = Ray::new(Vec3 { x: 0.0, y: 0.0, z: 0.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    let hit = cuboid.intersects(&ray, 0.0, 10.0).unwrap();
+    assert!((hit.t - (5.0 - half_diagonal)).abs() < 1e-9);
+
+    // Off to the side, it meets a face turned 45 degrees
+    let ray = Ray::new(Vec3 { x: 0.5, y: 0.0, z: 0.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    let hit = cuboid.intersects(&ray, 0.0, 10.0).unwrap();
+    assert!((hit.n.x.abs() - 0.5f64.sqrt()).abs() < 1e-9);
+    assert!((hit.n.z + 0.5f64.sqrt()).abs() < 1e-9);
+}
+
+#[test]
+fn it_lights_from_all_six_faces() {
+    use crate::light::Light;
+    use crate::material::materials::EmissiveMaterial;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    let mut cuboid = Cuboid::new(Vec3::zero(), Vec3 { x: 2.0, y: 1.0, z: 1.0 }, Box::new(EmissiveMaterial::default()));
+    cuboid.mut_transform(&Transform::new(Mat4::translate_matrix(&Vec3 { x: 0.0, y: 0.0, z: 5.0 })));
+    let lights = cuboid.area_lights();
+    assert_eq!(1, lights.len());
+    assert!((lights[0].center() - Vec3 { x: 1.0, y: 0.5, z: 5.5 }).len() < 1e-9);
+
+    let mut rng: Box<dyn rand::RngCore> = Box::new(StdRng::seed_from_u64(27));
+    for _ in 0..100 {
+        let p = lights[0].sample(&mut rng, &Vec3::zero()).position - Vec3 { x: 0.0, y: 0.0, z: 5.0 };
+        let on_face = |t: f64, max: f64| t.abs() < 1e-9 || (t - max).abs() < 1e-9;
+        assert!(on_face(p.x, 2.0) || on_face(p.y, 1.0) || on_face(p.z, 1.0), "{:?}", p);
+    }
+}
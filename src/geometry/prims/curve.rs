@@ -0,0 +1,480 @@
+use crate::prelude::*;
+use crate::geometry::bbox::{union_point, BBox, PartialBoundingBox};
+use crate::geometry::prim::Prim;
+use crate::material::Material;
+use crate::mat4::{Mat4, Transform};
+use crate::raytracer::{Octree, Ray, Intersection, TraversalStats};
+use crate::vec3::Vec3;
+
+use crate::material::materials::FlatMaterial;
+
+/// How a strand's control points define its cubic segments.
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CurveBasis {
+    /// 3n + 1 points: segments share their end points and pass through them
+    Bezier,
+    /// n + 3 points: segments overlap and stay smooth where they join, but
+    /// only pass near the points
+    BSpline
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CurveShape {
+    /// A flat strip always facing the ray, for strands too thin to see
+    /// round, like grass seen from afar
+    Flat,
+    /// As `Flat`, but shaded as if round, for hair and fur
+    Tube,
+    /// A flat strip facing along the normals given with each strand, like
+    /// a blade of grass. Seen edge on it vanishes.
+    Ribbon
+}
+
+/// The most times a segment is halved when intersecting it.
+pub static MAX_CURVE_DEPTH: i32 = 10;
+
+/// One cubic Bézier piece of a strand.
+#[derive(Clone, Copy)]
+struct CurveSegment {
+    points: [Vec3; 4],
+    widths: [f64; 2],
+    normals: Option<[Vec3; 2]>,
+    /// Where the segment starts and ends along the whole strand
+    strand_u: [f64; 2],
+    material: u32
+}
+
+fn bezier(p: &[Vec3; 4], u: f64) -> Vec3 {
+    let s = 1.0 - u;
+    p[0].scale(s * s * s) + p[1].scale(3.0 * s * s * u) + p[2].scale(3.0 * s * u * u) + p[3].scale(u * u * u)
+}
+
+fn bezier_derivative(p: &[Vec3; 4], u: f64) -> Vec3 {
+    let s = 1.0 - u;
+    ((p[1] - p[0]).scale(s * s) + (p[2] - p[1]).scale(2.0 * s * u) + (p[3] - p[2]).scale(u * u)).scale(3.0)
+}
+
+/// Splits a Bézier segment in half by de Casteljau's algorithm.
+fn split_bezier(p: &[Vec3; 4]) -> ([Vec3; 4], [Vec3; 4]) {
+    let mid = |a: Vec3, b: Vec3| (a + b).scale(0.5);
+    let (p01, p12, p23) = (mid(p[0], p[1]), mid(p[1], p[2]), mid(p[2], p[3]));
+    let (p012, p123) = (mid(p01, p12), mid(p12, p23));
+    let center = mid(p012, p123);
+    ([p[0], p01, p012, center], [center, p123, p23, p[3]])
+}
+
+/// Bézier points of the uniform cubic B-spline segment over `p`.
+fn bspline_to_bezier(p: &[Vec3]) -> [Vec3; 4] {
+    [
+        (p[0] + p[1].scale(4.0) + p[2]).scale(1.0 / 6.0),
+        (p[1].scale(2.0) + p[2]).scale(1.0 / 3.0),
+        (p[1] + p[2].scale(2.0)).scale(1.0 / 3.0),
+        (p[1] + p[2].scale(4.0) + p[3]).scale(1.0 / 6.0)
+    ]
+}
+
+/// Orthonormal axes with `z` along the ray and `x` across `across` where
+/// possible, so that segments lined up with `across` get tight bounds.
+fn ray_frame(direction: &Vec3, across: &Vec3) -> (Vec3, Vec3, Vec3) {
+    let z = direction.unit();
+    let mut x = z.cross(across);
+    if x.len() < 1e-12 {
+        let other = if z.x.abs() < 0.9 { Vec3 { x: 1.0, y: 0.0, z: 0.0 } } else { Vec3 { x: 0.0, y: 1.0, z: 0.0 } };
+        x = z.cross(&other);
+    }
+    let x = x.unit();
+    (x, z.cross(&x), z)
+}
+
+struct RayHit {
+    z: f64,
+    u: f64
+}
+
+impl CurveSegment {
+    fn max_width(&self) -> f64 {
+        self.widths[0].max(self.widths[1])
+    }
+
+    fn bbox(&self) -> BBox {
+        let mut bbox = BBox { min: self.points[0], max: self.points[0] };
+        for p in self.points[1..].iter() {
+            bbox = union_point(&bbox, p);
+        }
+        let half = self.max_width() / 2.0;
+        BBox { min: bbox.min - half, max: bbox.max + half }
+    }
+
+    fn width_at(&self, u: f64, direction: &Vec3) -> f64 {
+        let width = self.widths[0] + (self.widths[1] - self.widths[0]) * u;
+        match self.normals {
+            // A ribbon is narrower seen at an angle
+            Some(ref normals) => width * self.normal_at(normals, u).dot(&direction.unit()).abs(),
+            None => width
+        }
+    }
+
+    fn normal_at(&self, normals: &[Vec3; 2], u: f64) -> Vec3 {
+        Vec3::lerp(&normals[0], &normals[1], u).unit()
+    }
+
+    /// Halves the segment until its pieces are close to straight, and tests
+    /// the ray against those, as in pbrt's curve intersection. Points are
+    /// in the ray's frame, with the ray running up the Z axis.
+    #[allow(clippy::too_many_arguments)]
+    fn intersect_pieces(&self, local: &[Vec3; 4], piece: &[Vec3; 4], u0: f64, u1: f64, depth: i32,
+                        direction: &Vec3, z_min: f64, z_max: f64) -> Option<RayHit> {
+        let half = self.max_width() / 2.0;
+        let (mut min, mut max) = (piece[0], piece[0]);
+        for p in piece[1..].iter() {
+            min = Vec3 { x: min.x.min(p.x), y: min.y.min(p.y), z: min.z.min(p.z) };
+            max = Vec3 { x: max.x.max(p.x), y: max.y.max(p.y), z: max.z.max(p.z) };
+        }
+        if max.x + half < 0.0 || min.x - half > 0.0 || max.y + half < 0.0 || min.y - half > 0.0 ||
+           max.z + half < z_min || min.z - half > z_max {
+            return None;
+        }
+
+        if depth > 0 {
+            let (first, second) = split_bezier(piece);
+            let mid = (u0 + u1) / 2.0;
+            let near = self.intersect_pieces(local, &first, u0, mid, depth - 1, direction, z_min, z_max);
+            let z_max = near.as_ref().map_or(z_max, |hit| hit.z);
+            let far = self.intersect_pieces(local, &second, mid, u1, depth - 1, direction, z_min, z_max);
+            return far.or(near);
+        }
+
+        // The ray has to pass between the planes square to the piece at
+        // each end, or neighbouring pieces would both claim it
+        let flat = |v: Vec3| Vec3 { x: v.x, y: v.y, z: 0.0 };
+        if (flat(piece[1]) - flat(piece[0])).dot(&-flat(piece[0])) < 0.0 ||
+           (flat(piece[2]) - flat(piece[3])).dot(&-flat(piece[3])) < 0.0 {
+            return None;
+        }
+
+        // Nearest point along the piece, taken as a straight line
+        let along = flat(piece[3]) - flat(piece[0]);
+        let length2 = along.dot(&along);
+        if length2 == 0.0 {
+            return None;
+        }
+        let w = (-flat(piece[0])).dot(&along) / length2;
+        let u = (u0 + (u1 - u0) * w).clamp(u0, u1);
+
+        let p = bezier(local, u);
+        let width = self.width_at(u, direction);
+        if p.x * p.x + p.y * p.y > width * width / 4.0 || p.z < z_min || p.z > z_max {
+            return None;
+        }
+        Some(RayHit { z: p.z, u: u })
+    }
+
+    fn intersect(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+        let (x, y, z) = ray_frame(&ray.direction, &(self.points[3] - self.points[0]));
+        let to_local = |p: &Vec3| {
+            let d = *p - ray.origin;
+            Vec3 { x: d.dot(&x), y: d.dot(&y), z: d.dot(&z) }
+        };
+        let local = [to_local(&self.points[0]), to_local(&self.points[1]), to_local(&self.points[2]), to_local(&self.points[3])];
+
+        // Enough halvings that the pieces are within a twentieth of the
+        // width of straight
+        let bend = (0..2).fold(0.0f64, |bend, i| {
+            let d = local[i] - local[i + 1].scale(2.0) + local[i + 2];
+            bend.max(d.x.abs()).max(d.y.abs()).max(d.z.abs())
+        });
+        let eps = self.max_width() * 0.05;
+        let depth = if bend > 0.0 && eps > 0.0 {
+            (((2.0f64.sqrt() * 6.0 * bend / (8.0 * eps)).log2() / 2.0).round() as i32).clamp(0, MAX_CURVE_DEPTH)
+        } else {
+            0
+        };
+
+        let scale = ray.direction.len();
+        let hit = self.intersect_pieces(&local, &local, 0.0, 1.0, depth, &ray.direction, t_min * scale, t_max * scale)?;
+        Some((hit.z / scale, hit.u))
+    }
+}
+
+pub struct CurvesOptions {
+    basis: CurveBasis,
+    shape: CurveShape,
+    segments: Vec<CurveSegment>,
+    materials: Vec<Box<dyn Material+Send+Sync>>
+}
+
+impl CurvesOptions {
+    pub fn new(basis: CurveBasis, shape: CurveShape) -> CurvesOptions {
+        CurvesOptions {
+            basis: basis,
+            shape: shape,
+            segments: Vec::new(),
+            materials: Vec::new()
+        }
+    }
+
+    /// Strands added after this use `material`, as for `MeshOptions`.
+    #[allow(dead_code)]
+    pub fn material(&mut self, material: Box<dyn Material+Send+Sync>) -> &mut Self {
+        self.materials.push(material);
+        self
+    }
+
+    /// Adds a strand with a width at each control point. `Ribbon` strands
+    /// also need a normal at each control point; other shapes ignore them.
+    pub fn strand(&mut self, points: &[Vec3], widths: &[f64], normals: Option<&[Vec3]>) -> &mut Self {
+        assert_eq!(points.len(), widths.len(), "a strand needs a width for every control point");
+        if let Some(normals) = normals {
+            assert_eq!(points.len(), normals.len(), "a strand needs a normal for every control point");
+        }
+        assert!(self.shape != CurveShape::Ribbon || normals.is_some(), "ribbon strands need normals");
+
+        if self.materials.is_empty() {
+            self.materials.push(Box::new(FlatMaterial { color: Vec3::one() }));
+        }
+        let material = (self.materials.len() - 1) as u32;
+
+        // Each segment's first and last control point, and its Bézier points
+        let pieces: Vec<(usize, usize, [Vec3; 4])> = match self.basis {
+            CurveBasis::Bezier => {
+                assert!(points.len() >= 4 && (points.len() - 1).is_multiple_of(3),
+                        "a Bézier strand needs 3n + 1 control points, got {}", points.len());
+                (0..(points.len() - 1) / 3)
+                    .map(|i| (3 * i, 3 * i + 3, [points[3 * i], points[3 * i + 1], points[3 * i + 2], points[3 * i + 3]]))
+                    .collect()
+            },
+            CurveBasis::BSpline => {
+                assert!(points.len() >= 4, "a B-spline strand needs at least 4 control points, got {}", points.len());
+                (0..points.len() - 3).map(|i| (i + 1, i + 2, bspline_to_bezier(&points[i..i + 4]))).collect()
+            }
+        };
+
+        let count = pieces.len() as f64;
+        let shape = self.shape;
+        self.segments.extend(pieces.into_iter().enumerate().map(|(index, (first, last, bezier))| {
+            CurveSegment {
+                points: bezier,
+                widths: [widths[first], widths[last]],
+                normals: match shape {
+                    CurveShape::Ribbon => normals.map(|normals| [normals[first].unit(), normals[last].unit()]),
+                    _ => None
+                },
+                strand_u: [index as f64 / count, (index + 1) as f64 / count],
+                material: material
+            }
+        }));
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn build(self) -> Curves {
+        let mut curves = Curves {
+            shape: self.shape,
+            segments: self.segments,
+            materials: self.materials,
+            bbox: None,
+            octree: Octree::with_bounds(Vec::new(), |_| None)
+        };
+        curves.rebuild_octree();
+        curves
+    }
+}
+
+/// Strands of hair, fur or grass as cubic curves with varying width. Like
+/// `Mesh`, all the strands are one prim with their own octree.
+///
+/// `u` runs from 0 at the root to 1 at the tip of each strand and `v`
+/// across it, so the tangent frame of a hit runs along the strand.
+pub struct Curves {
+    shape: CurveShape,
+    segments: Vec<CurveSegment>,
+    materials: Vec<Box<dyn Material+Send+Sync>>,
+    bbox: Option<BBox>,
+    octree: Octree<usize>
+}
+
+impl Curves {
+    fn rebuild_octree(&mut self) {
+        self.bbox = BBox::from_union(self.segments.iter().map(|segment| Some(segment.bbox())));
+
+        let octree = {
+            let segments = &self.segments;
+            Octree::with_bounds((0..segments.len()).collect(), |&segment| Some(segments[segment].bbox()))
+        };
+        self.octree = octree;
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    fn hit<'a>(&'a self, segment: &CurveSegment, ray: &Ray, t: f64, u: f64) -> Intersection<'a> {
+        let position = ray.origin + ray.direction.scale(t);
+        let derivative = bezier_derivative(&segment.points, u);
+        let tangent = derivative.unit();
+        let center = bezier(&segment.points, u);
+        let view = (-ray.direction).unit();
+
+        // Square to the strand, facing back along the ray
+        let facing = (view - tangent.scale(tangent.dot(&view))).unit();
+        let side = tangent.cross(&facing);
+        let half_width = (segment.width_at(u, &ray.direction) / 2.0).max(1e-12);
+        let across = ((position - center).dot(&side) / half_width).clamp(-1.0, 1.0);
+
+        let n = match (self.shape, segment.normals) {
+            (CurveShape::Tube, _) => facing.scale((1.0 - across * across).sqrt()) + side.scale(across),
+            (CurveShape::Ribbon, Some(ref normals)) => {
+                let n = segment.normal_at(normals, u);
+                if n.dot(&view) < 0.0 { -n } else { n }
+            },
+            _ => facing
+        };
+
+        // Always seen from the front: there's no inside to a strand
+        Intersection {
+            n: n,
+            geometric_n: facing,
+            front_face: true,
+            prim_id: 0,
+            t: t,
+            u: segment.strand_u[0] + (segment.strand_u[1] - segment.strand_u[0]) * u,
+            v: (across + 1.0) / 2.0,
+            position: position,
+            dpdu: derivative.scale(1.0 / (segment.strand_u[1] - segment.strand_u[0])),
+            dpdv: side.scale(half_width * 2.0),
+            material: &self.materials[segment.material as usize]
+        }
+    }
+}
+
+impl PartialBoundingBox for Curves {
+    fn partial_bounding_box(&self) -> Option<BBox> {
+        self.bbox
+    }
+}
+
+impl Prim for Curves {
+    fn intersects<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        self.intersects_counted(ray, t_min, t_max, &mut TraversalStats::default())
+    }
+
+    fn intersects_counted<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64,
+                              stats: &mut TraversalStats) -> Option<Intersection<'a>> {
+        let mut nearest = None;
+        let mut nearest_t = t_max;
+
+        let mut segments = self.octree.intersect_iter(ray);
+        for &segment in &mut segments {
+            stats.prim_tests += 1;
+            if let Some((t, u)) = self.segments[segment].intersect(ray, t_min, nearest_t) {
+                nearest_t = t;
+                nearest = Some((segment, t, u));
+            }
+        }
+        stats.node_visits += segments.node_visits();
+
+        nearest.map(|(segment, t, u)| self.hit(&self.segments[segment], ray, t, u))
+    }
+
+    /// Widths scale by the average the transform stretches each axis by.
+    fn mut_transform(&mut self, transform: &Transform) {
+        let m = &transform.m;
+        let axis = |v: Vec3| Mat4::mult_v(m, &v).len();
+        let stretch = (axis(Vec3 { x: 1.0, y: 0.0, z: 0.0 }) + axis(Vec3 { x: 0.0, y: 1.0, z: 0.0 }) +
+                       axis(Vec3 { x: 0.0, y: 0.0, z: 1.0 })) / 3.0;
+
+        for segment in self.segments.iter_mut() {
+            for p in segment.points.iter_mut() {
+                *p = Mat4::mult_p(m, p);
+            }
+            for w in segment.widths.iter_mut() {
+                *w *= stretch;
+            }
+            if let Some(ref mut normals) = segment.normals {
+                for n in normals.iter_mut() {
+                    *n = Mat4::transform_normal(n, m).unit();
+                }
+            }
+        }
+
+        self.rebuild_octree();
+    }
+}
+
+#[cfg(test)]
+fn straight_strand(shape: CurveShape, normals: Option<&[Vec3]>) -> Curves {
+    // Up the Y axis from 0 to 3, narrowing from 0.2 to 0.1
+    let points: Vec<Vec3> = (0..4).map(|i| Vec3 { x: 0.0, y: i as f64, z: 0.0 }).collect();
+    let mut options = CurvesOptions::new(CurveBasis::Bezier, shape);
+    options.strand(&points, &[0.2, 0.2, 0.1, 0.1], normals);
+    options.build()
+}
+
+#[test]
+fn it_intersects_a_strand_across_its_width() {
+    let curves = straight_strand(CurveShape::Flat, None);
+
+    let ray = Ray::new(Vec3 { x: 0.05, y: 1.5, z: -5.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    let hit = curves.intersects(&ray, 0.0, 10.0).unwrap();
+    assert!((hit.t - 5.0).abs() < 1e-9);
+    assert!((hit.u - 0.5).abs() < 1e-9);
+    assert!((hit.n.z + 1.0).abs() < 1e-9);
+    assert!((hit.tangent_frame().0.y - 1.0).abs() < 1e-9);
+
+    // Half width is 0.075 halfway up, so this is two thirds of the way
+    // from the middle to the edge
+    assert!(((hit.v - 0.5).abs() - 1.0 / 3.0).abs() < 1e-9);
+
+    // Just outside, and past either end
+    let miss = |x: f64, y: f64| curves.intersects(&Ray::new(Vec3 { x: x, y: y, z: -5.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 }), 0.0, 10.0).is_none();
+    assert!(miss(0.08, 1.5));
+    assert!(miss(0.0, -0.01));
+    assert!(miss(0.0, 3.01));
+    assert!(!miss(0.09, 0.3));
+}
+
+#[test]
+fn it_shades_tubes_round_and_ribbons_flat() {
+    let ray = Ray::new(Vec3 { x: 0.07, y: 1.5, z: -5.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+
+    let tube = straight_strand(CurveShape::Tube, None);
+    let n = tube.intersects(&ray, 0.0, 10.0).unwrap().n;
+    assert!(n.x.abs() > 0.8 && n.z < 0.0);
+
+    // A ribbon facing the ray, then turned edge on
+    let facing = [Vec3 { x: 0.0, y: 0.0, z: 1.0 }; 4];
+    let ribbon = straight_strand(CurveShape::Ribbon, Some(&facing));
+    let hit = ribbon.intersects(&ray, 0.0, 10.0).unwrap();
+    assert_eq!(Vec3 { x: 0.0, y: 0.0, z: -1.0 }, hit.n);
+
+    let edge_on = [Vec3 { x: 1.0, y: 0.0, z: 0.0 }; 4];
+    let ribbon = straight_strand(CurveShape::Ribbon, Some(&edge_on));
+    assert!(ribbon.intersects(&ray, 0.0, 10.0).is_none());
+}
+
+#[test]
+fn it_follows_a_bent_bspline() {
+    // A quarter circle-ish arc in the XY plane
+    let points = [
+        Vec3 { x: 0.0, y: 0.0, z: 0.0 }, Vec3 { x: 0.0, y: 2.0, z: 0.0 },
+        Vec3 { x: 1.0, y: 3.0, z: 0.0 }, Vec3 { x: 3.0, y: 3.0, z: 0.0 },
+        Vec3 { x: 5.0, y: 3.0, z: 0.0 }
+    ];
+    let mut options = CurvesOptions::new(CurveBasis::BSpline, CurveShape::Flat);
+    options.strand(&points, &[0.05; 5], None);
+    let curves = options.build();
+    assert_eq!(2, curves.len());
+
+    // Aim at a point on the curve; the hit is on the curve with the tangent there
+    let segment = &curves.segments[0];
+    let target = bezier(&segment.points, 0.7);
+    let ray = Ray::new(target + Vec3 { x: 0.0, y: 0.0, z: -4.0 }, Vec3 { x: 0.0, y: 0.0, z: 2.0 });
+    let hit = curves.intersects(&ray, 0.0, 10.0).unwrap();
+    assert!((hit.t - 2.0).abs() < 1e-9);
+    assert!((hit.u - 0.35).abs() < 1e-3);
+    let tangent = bezier_derivative(&segment.points, 0.7).unit();
+    assert!(hit.tangent_frame().0.dot(&tangent) > 0.9999);
+}
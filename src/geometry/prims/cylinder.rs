@@ -0,0 +1,230 @@
+use crate::prelude::*;
+use crate::geometry::bbox::{BBox, PartialBoundingBox};
+use crate::geometry::prim::Prim;
+use crate::light::lights::{AreaLight, EmitterShape};
+use crate::material::Material;
+use crate::material::materials::FlatMaterial;
+use crate::mat4::{Mat4, Transform};
+use crate::poly::solve_quadratic;
+use crate::raytracer::{Ray, Intersection};
+use crate::vec3::Vec3;
+use core::f64::consts::PI;
+
+use super::quadric::{intersect_ring, nearest, phi, ring_emitter, LocalHit, EMITTER_CELLS};
+
+pub struct CylinderOptions {
+    radius: f64,
+    height: f64,
+    capped: bool,
+    sweep: f64,
+    material: Option<Box<dyn Material+Send+Sync>>,
+}
+
+#[allow(dead_code)]
+impl CylinderOptions {
+    /// A cylinder standing on the origin with its axis along +Y. Use
+    /// `mut_transform` on the result to place it.
+    pub fn new(radius: f64, height: f64) -> CylinderOptions {
+        CylinderOptions {
+            radius: radius,
+            height: height,
+            capped: false,
+            sweep: 360.0,
+            material: None,
+        }
+    }
+
+    /// Close both ends with disks. Uncapped cylinders are open tubes.
+    pub fn capped(&mut self, capped: bool) -> &mut Self {
+        self.capped = capped;
+        self
+    }
+
+    /// Degrees around the axis to keep, from +X towards +Z. The cut edges
+    /// are left open.
+    pub fn sweep(&mut self, degrees: f64) -> &mut Self {
+        self.sweep = degrees;
+        self
+    }
+
+    pub fn material(&mut self, material: Box<dyn Material+Send+Sync>) -> &mut Self {
+        self.material = Some(material);
+        self
+    }
+
+    pub fn build(self) -> Cylinder {
+        Cylinder {
+            radius: self.radius,
+            height: self.height,
+            phi_max: self.sweep.clamp(0.0, 360.0) * PI / 180.0,
+            capped: self.capped,
+            transform: Transform::new(Mat4::identity()),
+            material: self.material.unwrap_or_else(|| Box::new(FlatMaterial { color: Vec3::one() }))
+        }
+    }
+}
+
+pub struct Cylinder {
+    pub radius: f64,
+    pub height: f64,
+    pub phi_max: f64,
+    pub capped: bool,
+    pub transform: Transform,
+    pub material: Box<dyn Material+Send+Sync>
+}
+
+impl Cylinder {
+    fn intersect_side(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<LocalHit> {
+        let (o, d) = (ray.origin, ray.direction);
+        let a = d.x * d.x + d.z * d.z;
+        if a == 0.0 {
+            // Parallel to the axis, so only the caps can be hit
+            return None;
+        }
+        let b = 2.0 * (o.x * d.x + o.z * d.z);
+        let c = o.x * o.x + o.z * o.z - self.radius * self.radius;
+        let (t0, t1) = solve_quadratic(a, b, c)?;
+
+        for &t in [t0, t1].iter() {
+            if t < t_min || t > t_max {
+                continue;
+            }
+            let p = o + d.scale(t);
+            let phi = phi(&p);
+            if p.y < 0.0 || p.y > self.height || phi > self.phi_max {
+                continue;
+            }
+            return Some(LocalHit {
+                t: t,
+                n: Vec3 { x: p.x, y: 0.0, z: p.z },
+                u: phi / self.phi_max,
+                v: p.y / self.height,
+                dpdu: Vec3 { x: -p.z, y: 0.0, z: p.x }.scale(self.phi_max),
+                dpdv: Vec3 { x: 0.0, y: self.height, z: 0.0 }
+            });
+        }
+        None
+    }
+}
+
+impl PartialBoundingBox for Cylinder {
+    fn partial_bounding_box(&self) -> Option<BBox> {
+        let r = self.radius;
+        Some(self.transform.bbox_to_world(&BBox {
+            min: Vec3 { x: -r, y: 0.0, z: -r },
+            max: Vec3 { x: r, y: self.height, z: r }
+        }))
+    }
+}
+
+impl Prim for Cylinder {
+    fn intersects<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        let local_ray = self.transform.ray_to_object(ray);
+        let mut hit = self.intersect_side(&local_ray, t_min, t_max);
+
+        if self.capped {
+            let in_range = |hit: &LocalHit| hit.t >= t_min && hit.t <= t_max;
+            let bottom = intersect_ring(&local_ray, 0.0, -1.0, 0.0, self.radius, self.phi_max).filter(in_range);
+            let top = intersect_ring(&local_ray, self.height, 1.0, 0.0, self.radius, self.phi_max).filter(in_range);
+            hit = nearest(hit, nearest(bottom, top));
+        }
+
+        hit.map(|hit| hit.into_world(ray, &self.transform, &self.material))
+    }
+
+    fn mut_transform(&mut self, transform: &Transform) {
+        self.transform = Transform::new(Mat4::mult_m(&transform.m, &self.transform.m));
+    }
+    /// One light for the side and one for each cap.
+    fn area_lights(&self) -> Vec<AreaLight> {
+        let emission = match self.material.emitter() {
+            Some(emission) => emission,
+            None => return Vec::new()
+        };
+
+        let (radius, height, phi_max) = (self.radius, self.height, self.phi_max);
+        let mut shapes = vec![EmitterShape::Surface {
+            surface: Box::new(move |u, v| {
+                let phi = u * phi_max;
+                let n = Vec3 { x: phi.cos(), y: 0.0, z: phi.sin() };
+                (n.scale(radius) + Vec3 { x: 0.0, y: v * height, z: 0.0 }, n)
+            }),
+            columns: EMITTER_CELLS,
+            rows: EMITTER_CELLS
+        }];
+        if self.capped {
+            shapes.push(ring_emitter(0.0, -1.0, 0.0, radius, phi_max));
+            shapes.push(ring_emitter(height, 1.0, 0.0, radius, phi_max));
+        }
+
+        shapes.into_iter().map(|shape| AreaLight::new(shape.transformed(&self.transform), emission.clone())).collect()
+    }
+}
+
+#[test]
+fn it_intersects_the_side_and_caps() {
+    let mut options = CylinderOptions::new(1.0, 2.0);
+    options.capped(true);
+    let cylinder = options.build();
+
+    let ray = Ray::new(Vec3 { x: -3.0, y: 1.0, z: 0.0 }, Vec3 { x: 1.0, y: 0.0, z: 0.0 });
+    let hit = cylinder.intersects(&ray, 0.0, 10.0).unwrap();
+    assert_eq!(2.0, hit.t);
+    assert_eq!(Vec3 { x: -1.0, y: 0.0, z: 0.0 }, hit.n);
+    assert_eq!((0.5, 0.5), (hit.u, hit.v));
+
+    let ray = Ray::new(Vec3 { x: 0.5, y: 5.0, z: 0.0 }, Vec3 { x: 0.0, y: -1.0, z: 0.0 });
+    let hit = cylinder.intersects(&ray, 0.0, 10.0).unwrap();
+    assert_eq!(3.0, hit.t);
+    assert_eq!(Vec3 { x: 0.0, y: 1.0, z: 0.0 }, hit.n);
+
+    // Without caps the same ray passes straight down the tube
+    let open = CylinderOptions::new(1.0, 2.0).build();
+    assert!(open.intersects(&ray, 0.0, 10.0).is_none());
+}
+
+#[test]
+fn it_sweeps_part_of_a_turn() {
+    let mut options = CylinderOptions::new(1.0, 2.0);
+    options.sweep(90.0);
+    let mut cylinder = options.build();
+    cylinder.mut_transform(&Transform::new(Mat4::translate_matrix(&Vec3 { x: 0.0, y: 0.0, z: 5.0 })));
+
+    let bbox = cylinder.partial_bounding_box().unwrap();
+    assert_eq!(Vec3 { x: -1.0, y: 0.0, z: 4.0 }, bbox.min);
+
+    // The near side is cut away, so the ray meets the inside of the far wall
+    let ray = Ray::new(Vec3 { x: 0.5, y: 1.0, z: 0.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    let hit = cylinder.intersects(&ray, 0.0, 10.0).unwrap();
+    assert!((hit.t - (5.0 + 0.75f64.sqrt())).abs() < 1e-9);
+    assert!(hit.n.z > 0.0);
+
+    let ray = Ray::new(Vec3 { x: -0.5, y: 1.0, z: 0.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    assert!(cylinder.intersects(&ray, 0.0, 10.0).is_none());
+}
+
+#[test]
+fn it_lights_from_its_side_and_caps() {
+    use crate::light::Light;
+    use crate::material::materials::EmissiveMaterial;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    let mut options = CylinderOptions::new(1.0, 2.0);
+    options.capped(true).material(Box::new(EmissiveMaterial::default()));
+    let mut cylinder = options.build();
+    cylinder.mut_transform(&Transform::new(Mat4::translate_matrix(&Vec3 { x: 0.0, y: 0.0, z: 5.0 })));
+
+    let lights = cylinder.area_lights();
+    assert_eq!(3, lights.len());
+
+    // Points on the side lie on the curve itself, not on a chord inside it
+    let mut rng: Box<dyn rand::RngCore> = Box::new(StdRng::seed_from_u64(34));
+    for _ in 0..100 {
+        let sample = lights[0].sample(&mut rng, &Vec3::zero());
+        let p = sample.position - Vec3 { x: 0.0, y: 0.0, z: 5.0 };
+        assert!(((p.x * p.x + p.z * p.z).sqrt() - 1.0).abs() < 1e-9);
+        assert!(p.y >= 0.0 && p.y <= 2.0);
+    }
+    assert!((lights[2].center() - Vec3 { x: 0.0, y: 2.0, z: 5.0 }).len() < 1e-9);
+}
@@ -0,0 +1,126 @@
+use crate::prelude::*;
+use crate::geometry::bbox::{BBox, PartialBoundingBox};
+use crate::geometry::prim::Prim;
+use crate::light::lights::AreaLight;
+use crate::material::Material;
+use crate::material::materials::FlatMaterial;
+use crate::mat4::{Mat4, Transform};
+use crate::raytracer::{Ray, Intersection};
+use crate::vec3::Vec3;
+use core::f64::consts::PI;
+
+use super::quadric::{intersect_ring, ring_emitter};
+
+pub struct DiskOptions {
+    radius: f64,
+    inner_radius: f64,
+    sweep: f64,
+    material: Option<Box<dyn Material+Send+Sync>>,
+}
+
+#[allow(dead_code)]
+impl DiskOptions {
+    /// A disk around the origin in the XZ plane, facing +Y. Use
+    /// `mut_transform` on the result to place it.
+    pub fn new(radius: f64) -> DiskOptions {
+        DiskOptions {
+            radius: radius,
+            inner_radius: 0.0,
+            sweep: 360.0,
+            material: None,
+        }
+    }
+
+    /// Cut a hole out of the middle, making an annulus.
+    pub fn inner_radius(&mut self, inner_radius: f64) -> &mut Self {
+        self.inner_radius = inner_radius;
+        self
+    }
+
+    /// Degrees around the centre to keep, from +X towards +Z.
+    pub fn sweep(&mut self, degrees: f64) -> &mut Self {
+        self.sweep = degrees;
+        self
+    }
+
+    pub fn material(&mut self, material: Box<dyn Material+Send+Sync>) -> &mut Self {
+        self.material = Some(material);
+        self
+    }
+
+    pub fn build(self) -> Disk {
+        Disk {
+            radius: self.radius,
+            inner_radius: self.inner_radius,
+            phi_max: self.sweep.clamp(0.0, 360.0) * PI / 180.0,
+            transform: Transform::new(Mat4::identity()),
+            material: self.material.unwrap_or_else(|| Box::new(FlatMaterial { color: Vec3::one() }))
+        }
+    }
+}
+
+/// A disk or annulus. `u` runs around it and `v` inwards from the rim.
+pub struct Disk {
+    pub radius: f64,
+    pub inner_radius: f64,
+    pub phi_max: f64,
+    pub transform: Transform,
+    pub material: Box<dyn Material+Send+Sync>
+}
+
+impl PartialBoundingBox for Disk {
+    fn partial_bounding_box(&self) -> Option<BBox> {
+        let r = self.radius;
+        Some(self.transform.bbox_to_world(&BBox {
+            min: Vec3 { x: -r, y: 0.0, z: -r },
+            max: Vec3 { x: r, y: 0.0, z: r }
+        }))
+    }
+}
+
+impl Prim for Disk {
+    fn intersects<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        let local_ray = self.transform.ray_to_object(ray);
+        intersect_ring(&local_ray, 0.0, 1.0, self.inner_radius, self.radius, self.phi_max)
+            .filter(|hit| hit.t >= t_min && hit.t <= t_max)
+            .map(|hit| hit.into_world(ray, &self.transform, &self.material))
+    }
+
+    fn mut_transform(&mut self, transform: &Transform) {
+        self.transform = Transform::new(Mat4::mult_m(&transform.m, &self.transform.m));
+    }
+    fn area_lights(&self) -> Vec<AreaLight> {
+        self.material.emitter().into_iter().map(|emission| {
+            let shape = ring_emitter(0.0, 1.0, self.inner_radius, self.radius, self.phi_max);
+            AreaLight::new(shape.transformed(&self.transform), emission)
+        }).collect()
+    }
+}
+
+#[test]
+fn it_intersects_an_annulus() {
+    let mut options = DiskOptions::new(2.0);
+    options.inner_radius(1.0);
+    let mut disk = options.build();
+    // Stand it up facing -Z, 5 units away
+    disk.mut_transform(&Transform::new(Mat4::rotate_x_deg_matrix(-90.0)));
+    disk.mut_transform(&Transform::new(Mat4::translate_matrix(&Vec3 { x: 0.0, y: 0.0, z: 5.0 })));
+
+    let bbox = disk.partial_bounding_box().unwrap();
+    assert!((bbox.min.z - 5.0).abs() < 1e-9 && (bbox.max.z - 5.0).abs() < 1e-9);
+    assert!((bbox.max.y - 2.0).abs() < 1e-9);
+
+    let ray = Ray::new(Vec3 { x: 1.5, y: 0.0, z: 0.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    let hit = disk.intersects(&ray, 0.0, 10.0).unwrap();
+    assert!((hit.t - 5.0).abs() < 1e-9);
+    assert!((hit.n.z + 1.0).abs() < 1e-9);
+    assert!((hit.v - 0.5).abs() < 1e-9);
+
+    // Through the hole, past the rim, and out of range
+    let ray = Ray::new(Vec3 { x: 0.5, y: 0.0, z: 0.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    assert!(disk.intersects(&ray, 0.0, 10.0).is_none());
+    let ray = Ray::new(Vec3 { x: 2.5, y: 0.0, z: 0.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    assert!(disk.intersects(&ray, 0.0, 10.0).is_none());
+    let ray = Ray::new(Vec3 { x: 1.5, y: 0.0, z: 0.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    assert!(disk.intersects(&ray, 0.0, 4.0).is_none());
+}
@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+use crate::material::Texture;
+use crate::vec3::Vec3;
+
+use super::mesh::MeshFace;
+use super::triangle::UvValue;
+
+#[cfg(test)]
+use crate::geometry::prim::Prim;
+#[cfg(test)]
+use crate::geometry::prims::MeshOptions;
+#[cfg(test)]
+use crate::raytracer::compositor::ColorRGBA;
+#[cfg(test)]
+use crate::raytracer::Ray;
+
+/// Edges are never split more than this many times over, whatever the
+/// texture does.
+pub static MAX_SPLIT_DEPTH: u32 = 16;
+
+pub struct DisplacementOptions {
+    texture: Box<dyn Texture+Send+Sync>,
+    scale: f64,
+    bounds: (f64, f64),
+    max_edge_length: f64,
+    min_edge_length: f64,
+    tolerance: f64
+}
+
+#[allow(dead_code)]
+impl DisplacementOptions {
+    /// No edge of the displaced mesh will be longer than `max_edge_length`
+    /// before displacement.
+    pub fn new(texture: Box<dyn Texture+Send+Sync>, max_edge_length: f64) -> DisplacementOptions {
+        assert!(max_edge_length > 0.0, "max_edge_length must be positive, got {}", max_edge_length);
+        DisplacementOptions {
+            texture: texture,
+            scale: 1.0,
+            bounds: (f64::NEG_INFINITY, f64::INFINITY),
+            max_edge_length: max_edge_length,
+            min_edge_length: max_edge_length / 16.0,
+            tolerance: max_edge_length / 16.0
+        }
+    }
+
+    /// Distance moved along the normal where the texture is white.
+    pub fn scale(&mut self, scale: f64) -> &mut Self {
+        self.scale = scale;
+        self
+    }
+
+    /// Limits on the distance moved, after scaling.
+    pub fn bounds(&mut self, min: f64, max: f64) -> &mut Self {
+        self.bounds = (min, max);
+        self
+    }
+
+    /// Edges get split further, down to this length, wherever the height
+    /// halfway along them is more than `tolerance` off a straight line.
+    pub fn min_edge_length(&mut self, min_edge_length: f64) -> &mut Self {
+        self.min_edge_length = min_edge_length;
+        self
+    }
+
+    pub fn tolerance(&mut self, tolerance: f64) -> &mut Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn build(self) -> Displacement {
+        Displacement {
+            texture: self.texture,
+            scale: self.scale,
+            bounds: self.bounds,
+            max_edge_length: self.max_edge_length,
+            min_edge_length: self.min_edge_length.min(self.max_edge_length),
+            tolerance: self.tolerance
+        }
+    }
+}
+
+/// Moves a mesh's surface along its normals by a height read from a
+/// texture. The mesh is tessellated first, finely enough to show the
+/// detail, so the result is real geometry: silhouettes and shadows follow
+/// it, unlike a bump map's.
+pub struct Displacement {
+    pub texture: Box<dyn Texture+Send+Sync>,
+    pub scale: f64,
+    pub bounds: (f64, f64),
+    pub max_edge_length: f64,
+    pub min_edge_length: f64,
+    pub tolerance: f64
+}
+
+/// A mesh's buffers after displacement. Every face indexes all three
+/// buffers with its vertex indices.
+pub struct Tessellation {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub texinfo: Vec<UvValue>,
+    pub faces: Vec<MeshFace>
+}
+
+#[derive(Clone, Copy)]
+struct Vertex {
+    position: Vec3,
+    normal: Vec3,
+    uv: UvValue,
+    height: f64
+}
+
+struct Tessellator<'a> {
+    displacement: &'a Displacement,
+    vertices: Vec<Vertex>,
+    midpoints: HashMap<(u32, u32), u32>,
+    faces: Vec<MeshFace>
+}
+
+impl<'a> Tessellator<'a> {
+    fn vertex(&mut self, position: Vec3, normal: Vec3, uv: UvValue) -> u32 {
+        self.vertices.push(Vertex {
+            position: position,
+            normal: normal,
+            uv: uv,
+            height: self.displacement.height(uv.u, uv.v)
+        });
+        (self.vertices.len() - 1) as u32
+    }
+
+    /// Shared by both faces along an edge, so they split it the same way.
+    fn midpoint(&mut self, a: u32, b: u32) -> u32 {
+        let key = if a < b { (a, b) } else { (b, a) };
+        if let Some(&mid) = self.midpoints.get(&key) {
+            return mid;
+        }
+
+        let (va, vb) = (self.vertices[key.0 as usize], self.vertices[key.1 as usize]);
+        let uv = UvValue { u: (va.uv.u + vb.uv.u) * 0.5, v: (va.uv.v + vb.uv.v) * 0.5 };
+        let mid = self.vertex((va.position + vb.position).scale(0.5), (va.normal + vb.normal).unit(), uv);
+        self.midpoints.insert(key, mid);
+        mid
+    }
+
+    /// Depends only on the edge, never on the face, so neighbours agree
+    /// and no cracks open between them.
+    fn should_split(&mut self, a: u32, b: u32) -> bool {
+        let (va, vb) = (self.vertices[a as usize], self.vertices[b as usize]);
+        let length = (vb.position - va.position).len();
+        if length <= self.displacement.min_edge_length {
+            return false;
+        }
+        if length > self.displacement.max_edge_length {
+            return true;
+        }
+
+        let mid = self.midpoint(a, b);
+        let height = self.vertices[mid as usize].height;
+        (height - (va.height + vb.height) * 0.5).abs() > self.displacement.tolerance
+    }
+
+    /// Splits every edge that needs it at once: all three gives four
+    /// triangles, fewer gives a fan from the new midpoints.
+    fn split(&mut self, face: [u32; 3], material: u32, depth: u32) {
+        let split: Vec<bool> = (0..3).map(|i| depth < MAX_SPLIT_DEPTH && self.should_split(face[i], face[(i + 1) % 3])).collect();
+
+        let children = match split.iter().filter(|&&s| s).count() {
+            0 => {
+                self.faces.push(MeshFace { vertices: face, normals: Some(face), texinfo: Some(face), material: material });
+                return;
+            },
+            3 => {
+                let (a, b, c) = (face[0], face[1], face[2]);
+                let (ab, bc, ca) = (self.midpoint(a, b), self.midpoint(b, c), self.midpoint(c, a));
+                vec![[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]
+            },
+            1 => {
+                // Rotate the split edge to a-b
+                let i = split.iter().position(|&s| s).unwrap();
+                let (a, b, c) = (face[i], face[(i + 1) % 3], face[(i + 2) % 3]);
+                let ab = self.midpoint(a, b);
+                vec![[a, ab, c], [ab, b, c]]
+            },
+            _ => {
+                // Rotate the edge left whole to c-a
+                let i = (split.iter().position(|&s| !s).unwrap() + 1) % 3;
+                let (a, b, c) = (face[i], face[(i + 1) % 3], face[(i + 2) % 3]);
+                let (ab, bc) = (self.midpoint(a, b), self.midpoint(b, c));
+                vec![[ab, b, bc], [a, ab, bc], [a, bc, c]]
+            }
+        };
+
+        for child in children {
+            self.split(child, material, depth + 1);
+        }
+    }
+}
+
+impl Displacement {
+    /// Distance to move along the normal at `(u, v)`: the mean of the
+    /// texture's color channels, scaled and clamped to the bounds.
+    pub fn height(&self, u: f64, v: f64) -> f64 {
+        let color = self.texture.color(u, v);
+        let height = (color.r + color.g + color.b) / 3.0 * self.scale;
+        height.clamp(self.bounds.0, self.bounds.1)
+    }
+
+    /// Faces without normals use their flat normal, and faces without UVs
+    /// the `Triangle` defaults. Where faces don't share a vertex's normal
+    /// and UV across an edge, the edge can open up once displaced.
+    pub fn tessellate(&self, positions: &[Vec3], normals: &[Vec3], texinfo: &[UvValue], faces: &[MeshFace]) -> Tessellation {
+        let mut tessellator = Tessellator {
+            displacement: self,
+            vertices: Vec::new(),
+            midpoints: HashMap::new(),
+            faces: Vec::new()
+        };
+
+        // Corners with the same attributes become one vertex. Missing
+        // attributes are keyed by face, as they differ between faces.
+        let mut corners: HashMap<(u32, i64, i64), u32> = HashMap::new();
+
+        for (index, face) in faces.iter().enumerate() {
+            let p = [positions[face.vertices[0] as usize], positions[face.vertices[1] as usize], positions[face.vertices[2] as usize]];
+            let flat = (p[1] - p[0]).cross(&(p[2] - p[0])).unit();
+            let default_uvs = UvValue::default3();
+
+            let mut vertices = [0u32; 3];
+            for corner in 0..3 {
+                let n = face.normals.map(|n| n[corner]);
+                let t = face.texinfo.map(|t| t[corner]);
+                let key = (
+                    face.vertices[corner],
+                    n.map_or(-(index as i64) - 1, |n| n as i64),
+                    t.map_or(-(index as i64 * 3 + corner as i64) - 1, |t| t as i64)
+                );
+
+                vertices[corner] = match corners.get(&key) {
+                    Some(&vertex) => vertex,
+                    None => {
+                        let normal = n.map_or(flat, |n| normals[n as usize].unit());
+                        let uv = t.map_or(default_uvs[corner], |t| texinfo[t as usize]);
+                        let vertex = tessellator.vertex(p[corner], normal, uv);
+                        corners.insert(key, vertex);
+                        vertex
+                    }
+                };
+            }
+
+            tessellator.split(vertices, face.material, 0);
+        }
+
+        // Midpoints only needed to test an edge are dropped here too
+        let mut used = vec![None; tessellator.vertices.len()];
+        let mut displaced = Vec::new();
+        let mut texinfo = Vec::new();
+        for face in tessellator.faces.iter_mut() {
+            for vertex in face.vertices.iter_mut() {
+                let v = tessellator.vertices[*vertex as usize];
+                *vertex = *used[*vertex as usize].get_or_insert_with(|| {
+                    displaced.push(v.position + v.normal.scale(v.height));
+                    texinfo.push(v.uv);
+                    (displaced.len() - 1) as u32
+                });
+            }
+            face.normals = Some(face.vertices);
+            face.texinfo = Some(face.vertices);
+        }
+
+        // Shade with the displaced surface, not the original normals
+        let mut normals = vec![Vec3::zero(); displaced.len()];
+        for face in tessellator.faces.iter() {
+            let [a, b, c] = face.vertices;
+            let n = (displaced[b as usize] - displaced[a as usize]).cross(&(displaced[c as usize] - displaced[a as usize]));
+            for &v in face.vertices.iter() {
+                normals[v as usize] = normals[v as usize] + n;
+            }
+        }
+
+        Tessellation {
+            positions: displaced,
+            normals: normals.iter().map(|n| n.unit()).collect(),
+            texinfo: texinfo,
+            faces: tessellator.faces
+        }
+    }
+}
+
+/// Heights rising from 0 at u = 0 to 1 at u = 1
+#[cfg(test)]
+#[derive(Clone)]
+struct Ramp;
+
+#[cfg(test)]
+impl Texture for Ramp {
+    fn color(&self, u: f64, _v: f64) -> ColorRGBA<f64> {
+        ColorRGBA::new_rgb(u, u, u)
+    }
+
+    fn clone_self(&self) -> Box<dyn Texture+Send+Sync> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+fn flat_square() -> MeshOptions {
+    flat_square_of(Box::new(crate::material::materials::FlatMaterial { color: Vec3::one() }))
+}
+
+#[cfg(test)]
+fn flat_square_of(material: Box<dyn crate::material::Material+Send+Sync>) -> MeshOptions {
+    let mut meshopts = MeshOptions::new(vec![
+        Vec3 { x: 0.0, y: 0.0, z: 0.0 },
+        Vec3 { x: 1.0, y: 0.0, z: 0.0 },
+        Vec3 { x: 1.0, y: 1.0, z: 0.0 },
+        Vec3 { x: 0.0, y: 1.0, z: 0.0 }]);
+    meshopts.normals(vec![Vec3 { x: 0.0, y: 0.0, z: 1.0 }]);
+    meshopts.texinfo(vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]);
+    meshopts.material(material);
+    meshopts.face([0, 1, 2], Some([0, 0, 0]), Some([0, 1, 2]));
+    meshopts.face([0, 2, 3], Some([0, 0, 0]), Some([0, 2, 3]));
+    meshopts
+}
+
+#[test]
+fn it_displaces_along_the_normal() {
+    let mut options = DisplacementOptions::new(Box::new(Ramp), 0.25);
+    options.scale(2.0).bounds(0.0, 1.5);
+    let displacement = options.build();
+
+    let mut meshopts = flat_square();
+    meshopts.displace(&displacement);
+    let mesh = meshopts.build();
+    assert!(mesh.triangles().all(|triangle| {
+        let v = triangle.vertices();
+        (0..3).all(|i| (v[i].x - v[(i + 1) % 3].x).abs() <= 0.25 && (v[i].y - v[(i + 1) % 3].y).abs() <= 0.25)
+    }));
+
+    // Partway up the ramp, tilted away from it
+    let ray = Ray::new(Vec3 { x: 0.4, y: 0.3, z: 5.0 }, Vec3 { x: 0.0, y: 0.0, z: -1.0 });
+    let hit = mesh.intersects(&ray, 0.0, 10.0).unwrap();
+    assert!((hit.t - 4.2).abs() < 1e-9);
+    assert!(hit.n.x < -0.8 && hit.n.z > 0.0);
+
+    // Clamped to the upper bound near the end
+    let ray = Ray::new(Vec3 { x: 0.9, y: 0.3, z: 5.0 }, Vec3 { x: 0.0, y: 0.0, z: -1.0 });
+    assert!((mesh.intersects(&ray, 0.0, 10.0).unwrap().t - 3.5).abs() < 1e-9);
+}
+
+#[test]
+fn it_refines_only_where_the_surface_bends() {
+    // A straight ramp needs nothing beyond the original two triangles
+    let displacement = DisplacementOptions::new(Box::new(Ramp), 10.0).build();
+    let mut meshopts = flat_square();
+    meshopts.displace(&displacement);
+    assert_eq!(2, meshopts.build().len());
+
+    // Clamping bends it at x = 0.5
+    let mut options = DisplacementOptions::new(Box::new(Ramp), 10.0);
+    options.bounds(0.0, 0.5).tolerance(0.01).min_edge_length(0.1);
+    let mut meshopts = flat_square();
+    meshopts.displace(&options.build());
+    assert!(meshopts.build().len() > 2);
+}
+
+#[test]
+fn it_lights_from_the_displaced_surface() {
+    use crate::light::Light;
+    use crate::material::materials::EmissiveMaterial;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    let mut options = DisplacementOptions::new(Box::new(Ramp), 0.25);
+    options.scale(2.0).bounds(0.0, 1.5);
+    let mut meshopts = flat_square_of(Box::new(EmissiveMaterial::default()));
+    meshopts.displace(&options.build());
+    let lights = meshopts.build().area_lights();
+    assert_eq!(1, lights.len());
+
+    // Up the ramp and along the flat top, never back on the square
+    let mut rng: Box<dyn rand::RngCore> = Box::new(StdRng::seed_from_u64(40));
+    for _ in 0..100 {
+        let p = lights[0].sample(&mut rng, &Vec3 { x: 0.5, y: 0.5, z: 5.0 }).position;
+        assert!((p.z - (2.0 * p.x).min(1.5)).abs() < 0.1, "{:?}", p);
+    }
+}
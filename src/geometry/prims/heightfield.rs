@@ -0,0 +1,347 @@
+use crate::prelude::*;
+use crate::geometry::bbox::{BBox, PartialBoundingBox};
+use crate::geometry::prim::Prim;
+use crate::light::lights::{AreaLight, EmitterShape, EmitterTriangle};
+use crate::material::Material;
+use crate::material::materials::FlatMaterial;
+use crate::mat4::{Mat4, Transform};
+use crate::raytracer::{Ray, Intersection};
+use crate::vec3::Vec3;
+
+pub struct HeightfieldOptions {
+    columns: usize,
+    rows: usize,
+    heights: Vec<f64>,
+    size: Vec3,
+    material: Option<Box<dyn Material+Send+Sync>>,
+}
+
+#[allow(dead_code)]
+impl HeightfieldOptions {
+    /// A grid of `columns` samples along X by `rows` along Z, row by row.
+    /// Heights are nominally from 0 to 1 and scaled by `size`.
+    pub fn new(columns: usize, rows: usize, heights: Vec<f64>) -> HeightfieldOptions {
+        assert!(columns >= 2 && rows >= 2, "a heightfield needs at least 2x2 samples");
+        assert_eq!(columns * rows, heights.len(), "heightfield sample count doesn't match its grid");
+
+        HeightfieldOptions {
+            columns: columns,
+            rows: rows,
+            heights: heights,
+            size: Vec3::one(),
+            material: None,
+        }
+    }
+
+    /// Samples `height(x, z)` over the unit square, for procedural terrain.
+    pub fn from_fn<F>(columns: usize, rows: usize, height: F) -> HeightfieldOptions
+            where F: Fn(f64, f64) -> f64 {
+        let mut heights = Vec::with_capacity(columns * rows);
+        for row in 0..rows {
+            for column in 0..columns {
+                heights.push(height(column as f64 / (columns - 1) as f64, row as f64 / (rows - 1) as f64));
+            }
+        }
+        HeightfieldOptions::new(columns, rows, heights)
+    }
+
+    /// The terrain covers 0 to `size.x` along X and 0 to `size.z` along Z,
+    /// with heights multiplied by `size.y`.
+    pub fn size(&mut self, size: Vec3) -> &mut Self {
+        self.size = size;
+        self
+    }
+
+    pub fn material(&mut self, material: Box<dyn Material+Send+Sync>) -> &mut Self {
+        self.material = Some(material);
+        self
+    }
+
+    pub fn build(self) -> Heightfield {
+        let (columns, rows) = (self.columns, self.rows);
+        let cell = Vec3 { x: self.size.x / (columns - 1) as f64, y: self.size.y, z: self.size.z / (rows - 1) as f64 };
+        let heights: Vec<f64> = self.heights.iter().map(|h| h * self.size.y).collect();
+        let height = |column: usize, row: usize| heights[row * columns + column];
+
+        // Vertex normals from central differences, one-sided at the edges
+        let mut normals = Vec::with_capacity(columns * rows);
+        for row in 0..rows {
+            for column in 0..columns {
+                let (left, right) = (column.saturating_sub(1), (column + 1).min(columns - 1));
+                let (back, front) = (row.saturating_sub(1), (row + 1).min(rows - 1));
+                let dh_dx = (height(right, row) - height(left, row)) / ((right - left) as f64 * cell.x);
+                let dh_dz = (height(column, front) - height(column, back)) / ((front - back) as f64 * cell.z);
+                normals.push(Vec3 { x: -dh_dx, y: 1.0, z: -dh_dz }.unit());
+            }
+        }
+
+        // Height range of each cell, so rays passing over or under skip it
+        let mut cell_bounds = Vec::with_capacity((columns - 1) * (rows - 1));
+        for row in 0..rows - 1 {
+            for column in 0..columns - 1 {
+                let corners = [height(column, row), height(column + 1, row), height(column, row + 1), height(column + 1, row + 1)];
+                cell_bounds.push(corners.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &h| (lo.min(h), hi.max(h))));
+            }
+        }
+
+        let (min_height, max_height) = cell_bounds.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &(cell_lo, cell_hi)| (lo.min(cell_lo), hi.max(cell_hi)));
+
+        Heightfield {
+            columns: columns,
+            rows: rows,
+            cell: cell,
+            heights: heights,
+            normals: normals,
+            cell_bounds: cell_bounds,
+            bbox: BBox {
+                min: Vec3 { x: 0.0, y: min_height, z: 0.0 },
+                max: Vec3 { x: self.size.x, y: max_height, z: self.size.z }
+            },
+            transform: Transform::new(Mat4::identity()),
+            material: self.material.unwrap_or_else(|| Box::new(FlatMaterial { color: Vec3::one() }))
+        }
+    }
+}
+
+/// Terrain from a grid of heights, intersected by walking the ray across
+/// the grid cell by cell. Each cell is two triangles with interpolated
+/// normals, and UVs run from 0 to 1 across the whole terrain.
+pub struct Heightfield {
+    pub columns: usize,
+    pub rows: usize,
+    pub cell: Vec3,
+    pub heights: Vec<f64>,
+    pub normals: Vec<Vec3>,
+    pub cell_bounds: Vec<(f64, f64)>,
+    pub bbox: BBox,
+    pub transform: Transform,
+    pub material: Box<dyn Material+Send+Sync>
+}
+
+/// Möller-Trumbore, giving t and the barycentric weights of `b` and `c`
+fn intersect_triangle(ray: &Ray, a: &Vec3, b: &Vec3, c: &Vec3, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+    let (e1, e2) = (*b - *a, *c - *a);
+    let p = ray.direction.cross(&e2);
+    let det = e1.dot(&p);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+
+    let s = ray.origin - *a;
+    let beta = s.dot(&p) / det;
+    if !(0.0..=1.0).contains(&beta) {
+        return None;
+    }
+    let q = s.cross(&e1);
+    let gamma = ray.direction.dot(&q) / det;
+    if gamma < 0.0 || beta + gamma > 1.0 {
+        return None;
+    }
+
+    let t = e2.dot(&q) / det;
+    if t < t_min || t > t_max { None } else { Some((t, beta, gamma)) }
+}
+
+impl Heightfield {
+    fn vertex(&self, column: usize, row: usize) -> Vec3 {
+        Vec3 { x: column as f64 * self.cell.x, y: self.heights[row * self.columns + column], z: row as f64 * self.cell.z }
+    }
+
+    /// Tests the two triangles of one cell, returning t, the smooth normal
+    /// and the triangle's own normal
+    fn intersect_cell(&self, ray: &Ray, column: usize, row: usize, t_min: f64, t_max: f64) -> Option<(f64, Vec3, Vec3)> {
+        let corners = [(column, row), (column, row + 1), (column + 1, row), (column + 1, row + 1)];
+        let triangles = [[corners[0], corners[1], corners[2]], [corners[3], corners[2], corners[1]]];
+
+        let mut nearest: Option<(f64, Vec3, Vec3)> = None;
+        for triangle in triangles.iter() {
+            let [a, b, c] = *triangle;
+            let limit = nearest.as_ref().map_or(t_max, |hit| hit.0);
+            let (va, vb, vc) = (self.vertex(a.0, a.1), self.vertex(b.0, b.1), self.vertex(c.0, c.1));
+            if let Some((t, beta, gamma)) = intersect_triangle(ray, &va, &vb, &vc, t_min, limit) {
+                let normal = |(column, row): (usize, usize)| self.normals[row * self.columns + column];
+                let n = normal(a).scale(1.0 - beta - gamma) + normal(b).scale(beta) + normal(c).scale(gamma);
+                // Both triangles wind so their normal points up
+                nearest = Some((t, n, (vb - va).cross(&(vc - va))));
+            }
+        }
+        nearest
+    }
+}
+
+impl PartialBoundingBox for Heightfield {
+    fn partial_bounding_box(&self) -> Option<BBox> {
+        Some(self.transform.bbox_to_world(&self.bbox))
+    }
+}
+
+impl Prim for Heightfield {
+    fn intersects<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        let local_ray = self.transform.ray_to_object(ray);
+        let (t_near, t_far) = self.bbox.intersect_interval(&local_ray)?;
+        let (t_start, t_end) = (t_near.max(t_min), t_far.min(t_max));
+        if t_start > t_end {
+            return None;
+        }
+
+        let (o, d) = (local_ray.origin, local_ray.direction);
+        let entry = o + d.scale(t_start);
+        let (last_column, last_row) = (self.columns as isize - 2, self.rows as isize - 2);
+        let mut column = ((entry.x / self.cell.x).floor() as isize).clamp(0, last_column);
+        let mut row = ((entry.z / self.cell.z).floor() as isize).clamp(0, last_row);
+
+        // Parametric distance to the next cell boundary along X and Z, and
+        // between boundaries, as in Amanatides and Woo's grid traversal
+        let axis = |origin: f64, dir: f64, index: isize, size: f64| -> (isize, f64, f64) {
+            if dir > 0.0 {
+                (1, ((index + 1) as f64 * size - origin) / dir, size / dir)
+            } else if dir < 0.0 {
+                (-1, (index as f64 * size - origin) / dir, -size / dir)
+            } else {
+                (0, f64::INFINITY, f64::INFINITY)
+            }
+        };
+        let (step_x, mut next_x, delta_x) = axis(o.x, d.x, column, self.cell.x);
+        let (step_z, mut next_z, delta_z) = axis(o.z, d.z, row, self.cell.z);
+        let mut t_cell = t_start;
+
+        loop {
+            let t_exit = next_x.min(next_z).min(t_end);
+            let (y_enter, y_exit) = (o.y + d.y * t_cell, o.y + d.y * t_exit);
+            let (cell_lo, cell_hi) = self.cell_bounds[row as usize * (self.columns - 1) + column as usize];
+
+            if y_enter.max(y_exit) >= cell_lo && y_enter.min(y_exit) <= cell_hi {
+                if let Some((t, n, face_n)) = self.intersect_cell(&local_ray, column as usize, row as usize, t_start, t_end) {
+                    let p = o + d.scale(t);
+                    let geometric_n = self.transform.normal_to_world(&face_n);
+                    // Along X and Z, climbing the slope of the triangle hit
+                    let dpdu = Vec3 { x: 1.0, y: -face_n.x / face_n.y, z: 0.0 }.scale(self.bbox.max.x);
+                    let dpdv = Vec3 { x: 0.0, y: -face_n.z / face_n.y, z: 1.0 }.scale(self.bbox.max.z);
+                    return Some(Intersection {
+                        n: self.transform.normal_to_world(&n),
+                        geometric_n: geometric_n,
+                        front_face: geometric_n.dot(&ray.direction) < 0.0,
+                        prim_id: 0,
+                        t: t,
+                        u: p.x / self.bbox.max.x,
+                        v: p.z / self.bbox.max.z,
+                        position: ray.origin + ray.direction.scale(t),
+                        dpdu: Mat4::mult_v(&self.transform.m, &dpdu),
+                        dpdv: Mat4::mult_v(&self.transform.m, &dpdv),
+                        material: &self.material
+                    });
+                }
+            }
+
+            if t_exit >= t_end {
+                return None;
+            }
+            if next_x < next_z {
+                column += step_x;
+                t_cell = next_x;
+                next_x += delta_x;
+            } else {
+                row += step_z;
+                t_cell = next_z;
+                next_z += delta_z;
+            }
+            if column < 0 || column > last_column || row < 0 || row > last_row {
+                return None;
+            }
+        }
+    }
+
+    fn mut_transform(&mut self, transform: &Transform) {
+        self.transform = Transform::new(Mat4::mult_m(&transform.m, &self.transform.m));
+    }
+    /// Made of the same triangles as `intersects` tests, so points picked
+    /// on the light lie exactly on the terrain.
+    fn area_lights(&self) -> Vec<AreaLight> {
+        self.material.emitter().into_iter().map(|emission| {
+            let corner = |column: usize, row: usize| {
+                let p = self.vertex(column, row);
+                (p, (p.x / self.bbox.max.x, p.z / self.bbox.max.z))
+            };
+            let mut triangles = Vec::with_capacity(2 * (self.columns - 1) * (self.rows - 1));
+            for row in 0..self.rows - 1 {
+                for column in 0..self.columns - 1 {
+                    let (a, b, c, d) = (corner(column, row), corner(column, row + 1), corner(column + 1, row), corner(column + 1, row + 1));
+                    for &(p, q, r) in [(a, b, c), (d, c, b)].iter() {
+                        triangles.push(EmitterTriangle { vertices: [p.0, q.0, r.0], texinfo: [p.1, q.1, r.1] });
+                    }
+                }
+            }
+            AreaLight::new(EmitterShape::Triangles(triangles).transformed(&self.transform), emission)
+        }).collect()
+    }
+}
+
+#[test]
+fn it_intersects_a_ramp() {
+    // A slope rising 1 unit along X over a 4x4 terrain
+    let mut options = HeightfieldOptions::from_fn(5, 3, |x, _| x);
+    options.size(Vec3 { x: 4.0, y: 1.0, z: 4.0 });
+    let heightfield = options.build();
+
+    let ray = Ray::new(Vec3 { x: 3.0, y: 5.0, z: 1.0 }, Vec3 { x: 0.0, y: -1.0, z: 0.0 });
+    let hit = heightfield.intersects(&ray, 0.0, 10.0).unwrap();
+    assert!((hit.t - 4.25).abs() < 1e-9);
+    assert!((hit.u - 0.75).abs() < 1e-9 && (hit.v - 0.25).abs() < 1e-9);
+    assert!((hit.n.x + 0.25 / 1.0625f64.sqrt()).abs() < 1e-9);
+
+    // Skimming along the slope, crossing several cells before meeting it
+    let ray = Ray::new(Vec3 { x: 0.0, y: 0.6, z: 2.0 }, Vec3 { x: 1.0, y: 0.0, z: 0.0 });
+    let hit = heightfield.intersects(&ray, 0.0, 10.0).unwrap();
+    assert!((hit.t - 2.4).abs() < 1e-9);
+
+    // Passing over the top, and under the terrain
+    let ray = Ray::new(Vec3 { x: -1.0, y: 1.5, z: 2.0 }, Vec3 { x: 1.0, y: 0.0, z: 0.0 });
+    assert!(heightfield.intersects(&ray, 0.0, 10.0).is_none());
+    assert!(heightfield.intersects(&Ray::new(Vec3 { x: 3.0, y: -1.0, z: 1.0 }, Vec3 { x: 0.0, y: -1.0, z: 0.0 }), 0.0, 10.0).is_none());
+}
+
+#[test]
+fn it_interpolates_normals_across_cells() {
+    // A single bump in the middle of a flat grid
+    let heights = vec![
+        0.0, 0.0, 0.0,
+        0.0, 1.0, 0.0,
+        0.0, 0.0, 0.0
+    ];
+    let mut heightfield = HeightfieldOptions::new(3, 3, heights).build();
+    heightfield.mut_transform(&Transform::new(Mat4::translate_matrix(&Vec3 { x: -0.5, y: 0.0, z: -0.5 })));
+    assert!(heightfield.partial_bounding_box().unwrap() == BBox {
+        min: Vec3 { x: -0.5, y: 0.0, z: -0.5 },
+        max: Vec3 { x: 0.5, y: 1.0, z: 0.5 }
+    });
+
+    // Straight up at the peak, tilting away from it on the way down
+    let ray = Ray::new(Vec3 { x: 0.0, y: 5.0, z: 0.0 }, Vec3 { x: 0.0, y: -1.0, z: 0.0 });
+    let hit = heightfield.intersects(&ray, 0.0, 10.0).unwrap();
+    assert!((hit.t - 4.0).abs() < 1e-9);
+    assert!((hit.n.y - 1.0).abs() < 1e-9);
+
+    let ray = Ray::new(Vec3 { x: 0.2, y: 5.0, z: 0.0 }, Vec3 { x: 0.0, y: -1.0, z: 0.0 });
+    let hit = heightfield.intersects(&ray, 0.0, 10.0).unwrap();
+    assert!(hit.n.x > 0.0 && hit.n.y > 0.0);
+}
+
+#[test]
+fn it_lights_from_the_terrain_itself() {
+    use crate::light::Light;
+    use crate::material::materials::EmissiveMaterial;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    // A slope rising 1 unit along X, which the light has to follow
+    let mut options = HeightfieldOptions::from_fn(5, 3, |x, _| x);
+    options.material(Box::new(EmissiveMaterial::default()));
+    let lights = options.build().area_lights();
+    assert_eq!(1, lights.len());
+
+    let mut rng: Box<dyn rand::RngCore> = Box::new(StdRng::seed_from_u64(38));
+    for _ in 0..100 {
+        let p = lights[0].sample(&mut rng, &Vec3 { x: 0.5, y: 5.0, z: 0.5 }).position;
+        assert!((p.y - p.x).abs() < 1e-9 && p.z >= 0.0 && p.z <= 1.0, "{:?}", p);
+    }
+}
@@ -0,0 +1,551 @@
+use crate::prelude::*;
+use crate::geometry::bbox::{union_point, union_points, BBox, PartialBoundingBox};
+use crate::geometry::meshtools::IndexedTriangles;
+use crate::geometry::prim::Prim;
+use crate::light::lights::{AreaLight, EmitterShape};
+use crate::material::Material;
+use crate::mat4::{Mat4, Transform};
+use crate::raytracer::{Octree, Ray, Intersection, TraversalStats};
+use crate::util::cache::{CacheError, CacheReader, CacheWriter};
+use crate::vec3::Vec3;
+
+use crate::material::materials::FlatMaterial;
+use super::displacement::Displacement;
+use super::triangle::{emitter_triangle, intersect_barycentric, uv_derivatives, UvValue};
+
+/// A triangle in a `Mesh`, stored as indices into the mesh's shared buffers.
+#[derive(Clone, Copy)]
+pub struct MeshFace {
+    pub vertices: [u32; 3],
+    pub normals: Option<[u32; 3]>,
+    pub texinfo: Option<[u32; 3]>,
+    pub material: u32
+}
+
+pub struct MeshOptions {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    texinfo: Vec<UvValue>,
+    faces: Vec<MeshFace>,
+    materials: Vec<Box<dyn Material+Send+Sync>>,
+}
+
+impl MeshOptions {
+    pub fn new(positions: Vec<Vec3>) -> MeshOptions {
+        MeshOptions {
+            positions: positions,
+            normals: Vec::new(),
+            texinfo: Vec::new(),
+            faces: Vec::new(),
+            materials: Vec::new(),
+        }
+    }
+
+    pub fn normals(&mut self, normals: Vec<Vec3>) -> &mut Self {
+        self.normals = normals;
+        self
+    }
+
+    pub fn texinfo(&mut self, texinfo: Vec<(f64, f64)>) -> &mut Self {
+        self.texinfo = texinfo.into_iter().map(UvValue::from_tuple).collect();
+        self
+    }
+
+    /// Starts a new face group. Faces added after this use `material`.
+    /// Call once before adding any faces for a single-material mesh.
+    pub fn material(&mut self, material: Box<dyn Material+Send+Sync>) -> &mut Self {
+        self.materials.push(material);
+        self
+    }
+
+    /// Adds a triangle to the current face group. Without normal indices the
+    /// face is flat shaded; without UV indices it uses the `Triangle` defaults.
+    pub fn face(&mut self, vertices: [u32; 3], normals: Option<[u32; 3]>, texinfo: Option<[u32; 3]>) -> &mut Self {
+        if self.materials.is_empty() {
+            self.materials.push(Box::new(FlatMaterial { color: Vec3::one() }));
+        }
+
+        self.faces.push(MeshFace {
+            vertices: vertices,
+            normals: normals,
+            texinfo: texinfo,
+            material: (self.materials.len() - 1) as u32
+        });
+        self
+    }
+
+    /// Adds `faces`, whose `material` indexes `materials`, as one face group
+    /// per material. Materials no face uses are dropped.
+    pub fn faces_with_materials(&mut self, materials: Vec<Box<dyn Material+Send+Sync>>, faces: Vec<MeshFace>) -> &mut Self {
+        let mut groups = vec![None; materials.len()];
+        for face in faces.iter() {
+            groups[face.material as usize] = Some(0);
+        }
+        for (group, material) in groups.iter_mut().zip(materials) {
+            if group.is_some() {
+                self.materials.push(material);
+                *group = Some((self.materials.len() - 1) as u32);
+            }
+        }
+
+        self.faces.extend(faces.into_iter().map(|face| MeshFace { material: groups[face.material as usize].unwrap(), ..face }));
+        self
+    }
+
+    /// Welds vertices within `weld_tolerance`, makes the winding consistent
+    /// and gives every face smooth normals, split at edges sharper than
+    /// `crease_angle` degrees. Faces that collapse when welded are dropped;
+    /// texture coordinates and materials are kept.
+    pub fn smooth(&mut self, weld_tolerance: f64, crease_angle: f64) -> &mut Self {
+        let mut indexed = IndexedTriangles::new(self.positions.clone(), self.faces.iter().map(|face| face.vertices).collect());
+        indexed.weld(weld_tolerance);
+        indexed.orient();
+        self.normals = indexed.smooth_normals(crease_angle);
+
+        // Corners are numbered 3 * face + corner of the faces as they were,
+        // so flipped faces pick up their UVs in the new order
+        let faces = indexed.faces.iter().zip(indexed.corners.iter()).enumerate().map(|(index, (&vertices, corners))| {
+            let face = self.faces[indexed.origin(index)];
+            let n = 3 * index as u32;
+            MeshFace {
+                vertices: vertices,
+                normals: Some([n, n + 1, n + 2]),
+                texinfo: face.texinfo.map(|t| [t[corners[0] % 3], t[corners[1] % 3], t[corners[2] % 3]]),
+                material: face.material
+            }
+        }).collect();
+        self.faces = faces;
+        self.positions = indexed.positions;
+        self
+    }
+
+    /// Tessellates the faces added so far and moves them along their
+    /// normals, replacing the mesh's buffers. Faces added afterwards are
+    /// left as they are.
+    #[allow(dead_code)]
+    pub fn displace(&mut self, displacement: &Displacement) -> &mut Self {
+        let tessellation = displacement.tessellate(&self.positions, &self.normals, &self.texinfo, &self.faces);
+        self.positions = tessellation.positions;
+        self.normals = tessellation.normals;
+        self.texinfo = tessellation.texinfo;
+        self.faces = tessellation.faces;
+        self
+    }
+
+    pub fn build(self) -> Mesh {
+        for face in self.faces.iter() {
+            assert!(face.vertices.iter().all(|&i| (i as usize) < self.positions.len()),
+                    "mesh face vertex index out of range");
+            assert!(face.normals.iter().flatten().all(|&i| (i as usize) < self.normals.len()),
+                    "mesh face normal index out of range");
+            assert!(face.texinfo.iter().flatten().all(|&i| (i as usize) < self.texinfo.len()),
+                    "mesh face texinfo index out of range");
+        }
+
+        Mesh::from_parts(self.positions, self.normals, self.texinfo, self.faces, self.materials)
+    }
+}
+
+fn write_vec3(w: &mut CacheWriter, v: &Vec3) {
+    w.write_f64(v.x);
+    w.write_f64(v.y);
+    w.write_f64(v.z);
+}
+
+fn read_vec3(r: &mut CacheReader) -> Result<Vec3, CacheError> {
+    Ok(Vec3 { x: r.read_f64()?, y: r.read_f64()?, z: r.read_f64()? })
+}
+
+fn write_indices(w: &mut CacheWriter, indices: &Option<[u32; 3]>) {
+    match *indices {
+        Some(i) => {
+            w.write_u8(1);
+            i.iter().for_each(|&i| w.write_u32(i));
+        },
+        None => w.write_u8(0)
+    }
+}
+
+fn read_indices(r: &mut CacheReader) -> Result<[u32; 3], CacheError> {
+    Ok([r.read_u32()?, r.read_u32()?, r.read_u32()?])
+}
+
+fn read_optional_indices(r: &mut CacheReader) -> Result<Option<[u32; 3]>, CacheError> {
+    match r.read_u8()? {
+        0 => Ok(None),
+        1 => Ok(Some(read_indices(r)?)),
+        _ => Err(CacheError::Corrupt("mesh face flag"))
+    }
+}
+
+fn write_buffers(w: &mut CacheWriter, positions: &[Vec3], normals: &[Vec3], texinfo: &[UvValue], faces: &[MeshFace]) {
+    w.write_u64(positions.len() as u64);
+    positions.iter().for_each(|p| write_vec3(w, p));
+
+    w.write_u64(normals.len() as u64);
+    normals.iter().for_each(|n| write_vec3(w, n));
+
+    w.write_u64(texinfo.len() as u64);
+    for uv in texinfo.iter() {
+        w.write_f64(uv.u);
+        w.write_f64(uv.v);
+    }
+
+    w.write_u64(faces.len() as u64);
+    for face in faces.iter() {
+        face.vertices.iter().for_each(|&i| w.write_u32(i));
+        write_indices(w, &face.normals);
+        write_indices(w, &face.texinfo);
+        w.write_u32(face.material);
+    }
+}
+
+/// An indexed triangle mesh. Positions, normals and UVs are shared between
+/// faces, and faces are accelerated by the mesh's own octree so the whole
+/// mesh is a single prim in the scene.
+pub struct Mesh {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    texinfo: Vec<UvValue>,
+    faces: Vec<MeshFace>,
+    materials: Vec<Box<dyn Material+Send+Sync>>,
+    bbox: Option<BBox>,
+    octree: Octree<usize>
+}
+
+impl Mesh {
+    fn from_parts(positions: Vec<Vec3>, normals: Vec<Vec3>, texinfo: Vec<UvValue>,
+                  faces: Vec<MeshFace>, materials: Vec<Box<dyn Material+Send+Sync>>) -> Mesh {
+        let mut mesh = Mesh {
+            positions: positions,
+            normals: normals,
+            texinfo: texinfo,
+            faces: faces,
+            materials: materials,
+            bbox: None,
+            octree: Octree::with_bounds(Vec::new(), |_| None)
+        };
+        mesh.rebuild_octree();
+        mesh
+    }
+
+    fn rebuild_octree(&mut self) {
+        self.bbox = BBox::from_union(self.positions.iter().map(|p| Some(BBox { min: *p, max: *p })));
+
+        let octree = {
+            let mesh = &*self;
+            Octree::with_bounds((0..mesh.faces.len()).collect(), |&face| mesh.triangle(face).partial_bounding_box())
+        };
+        self.octree = octree;
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.faces.len()
+    }
+
+    /// Writes the mesh data and its built octree. Materials are left out, so
+    /// `read_cache` has to be handed them again.
+    pub fn write_cache(&self, w: &mut CacheWriter) {
+        write_buffers(w, &self.positions, &self.normals, &self.texinfo, &self.faces);
+        w.write_u64(self.materials.len() as u64);
+        self.octree.write_cache(w, |w, &face| w.write_u64(face as u64));
+    }
+
+    /// Reads a mesh written by `write_cache`, giving it `materials`, which
+    /// must be as many as it was written with.
+    pub fn read_cache(r: &mut CacheReader, materials: Vec<Box<dyn Material+Send+Sync>>) -> Result<Mesh, CacheError> {
+        let positions_len = r.read_len(3 * 8)?;
+        let positions = (0..positions_len).map(|_| read_vec3(r)).collect::<Result<Vec<Vec3>, CacheError>>()?;
+
+        let normals_len = r.read_len(3 * 8)?;
+        let normals = (0..normals_len).map(|_| read_vec3(r)).collect::<Result<Vec<Vec3>, CacheError>>()?;
+
+        let texinfo_len = r.read_len(2 * 8)?;
+        let texinfo = (0..texinfo_len).map(|_| {
+            Ok(UvValue { u: r.read_f64()?, v: r.read_f64()? })
+        }).collect::<Result<Vec<UvValue>, CacheError>>()?;
+
+        let faces_len = r.read_len(3 * 4 + 2 + 4)?;
+        let faces = (0..faces_len).map(|_| {
+            Ok(MeshFace {
+                vertices: read_indices(r)?,
+                normals: read_optional_indices(r)?,
+                texinfo: read_optional_indices(r)?,
+                material: r.read_u32()?
+            })
+        }).collect::<Result<Vec<MeshFace>, CacheError>>()?;
+
+        let materials_len = r.read_u64()? as usize;
+        if materials_len != materials.len() {
+            return Err(CacheError::Stale);
+        }
+
+        let in_range = |indices: &[u32], len: usize| indices.iter().all(|&i| (i as usize) < len);
+        let faces_valid = faces.iter().all(|face| {
+            in_range(&face.vertices, positions.len()) &&
+            face.normals.iter().all(|n| in_range(n, normals.len())) &&
+            face.texinfo.iter().all(|t| in_range(t, texinfo.len())) &&
+            (face.material as usize) < materials_len
+        });
+        if !faces_valid {
+            return Err(CacheError::Corrupt("mesh face index out of range"));
+        }
+
+        let octree = Octree::read_cache(r, 8, |r| {
+            let face = r.read_u64()? as usize;
+            if face < faces.len() { Ok(face) } else { Err(CacheError::Corrupt("octree face out of range")) }
+        })?;
+
+        if !r.is_empty() {
+            return Err(CacheError::Corrupt("trailing data"));
+        }
+
+        let bbox = BBox::from_union(positions.iter().map(|p| Some(BBox { min: *p, max: *p })));
+
+        Ok(Mesh {
+            positions: positions,
+            normals: normals,
+            texinfo: texinfo,
+            faces: faces,
+            materials: materials,
+            bbox: bbox,
+            octree: octree
+        })
+    }
+
+    pub fn triangle(&self, face: usize) -> MeshTriangle<'_> {
+        MeshTriangle { mesh: self, face: face }
+    }
+
+    pub fn triangles(&self) -> impl Iterator<Item=MeshTriangle<'_>> {
+        (0..self.faces.len()).map(move |face| self.triangle(face))
+    }
+}
+
+impl PartialBoundingBox for Mesh {
+    fn partial_bounding_box(&self) -> Option<BBox> {
+        self.bbox
+    }
+}
+
+impl Prim for Mesh {
+    fn intersects<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        self.intersects_counted(ray, t_min, t_max, &mut TraversalStats::default())
+    }
+
+    fn intersects_counted<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64,
+                              stats: &mut TraversalStats) -> Option<Intersection<'a>> {
+        let mut nearest_hit = None;
+        let mut nearest_t = t_max;
+
+        let mut faces = self.octree.intersect_iter(ray);
+        for &face in &mut faces {
+            stats.prim_tests += 1;
+            if let Some(intersection) = self.triangle(face).intersects(ray, t_min, nearest_t) {
+                nearest_t = intersection.t;
+                nearest_hit = Some(intersection);
+            }
+        }
+        stats.node_visits += faces.node_visits();
+
+        nearest_hit
+    }
+
+    fn mut_transform(&mut self, transform: &Transform) {
+        for p in self.positions.iter_mut() {
+            *p = Mat4::mult_p(&transform.m, p);
+        }
+
+        for n in self.normals.iter_mut() {
+            *n = transform.normal_to_world(n);
+        }
+
+        self.rebuild_octree();
+    }
+
+    fn area_lights(&self) -> Vec<AreaLight> {
+        // One light per glowing material, over the faces made of it
+        (0..self.materials.len()).filter_map(|index| {
+            let faces: Vec<_> = self.triangles()
+                .filter(|triangle| triangle.material_index() == index)
+                .collect();
+            let emission = faces.first()?.material().emitter()?;
+            let triangles = faces.iter()
+                .map(|triangle| emitter_triangle(triangle.vertices(), triangle.texinfo()))
+                .collect();
+            Some(AreaLight::new(EmitterShape::Triangles(triangles), emission))
+        }).collect()
+    }
+}
+
+/// A lightweight reference to a single face of a `Mesh`.
+#[derive(Clone, Copy)]
+pub struct MeshTriangle<'a> {
+    mesh: &'a Mesh,
+    face: usize
+}
+
+impl<'a> MeshTriangle<'a> {
+    pub fn vertices(&self) -> [Vec3; 3] {
+        let f = &self.mesh.faces[self.face];
+        [
+            self.mesh.positions[f.vertices[0] as usize],
+            self.mesh.positions[f.vertices[1] as usize],
+            self.mesh.positions[f.vertices[2] as usize],
+        ]
+    }
+
+    pub fn normals(&self) -> [Vec3; 3] {
+        let f = &self.mesh.faces[self.face];
+        match f.normals {
+            Some(n) => [
+                self.mesh.normals[n[0] as usize],
+                self.mesh.normals[n[1] as usize],
+                self.mesh.normals[n[2] as usize],
+            ],
+            None => {
+                let v = self.vertices();
+                let n = (v[1] - v[0]).cross(&(v[2] - v[0])).unit();
+                [n, n, n]
+            }
+        }
+    }
+
+    pub fn texinfo(&self) -> [UvValue; 3] {
+        let f = &self.mesh.faces[self.face];
+        match f.texinfo {
+            Some(t) => [
+                self.mesh.texinfo[t[0] as usize],
+                self.mesh.texinfo[t[1] as usize],
+                self.mesh.texinfo[t[2] as usize],
+            ],
+            None => UvValue::default3()
+        }
+    }
+
+    fn material_index(&self) -> usize {
+        self.mesh.faces[self.face].material as usize
+    }
+
+    pub fn material(&self) -> &'a (dyn Material+Send+Sync) {
+        &*self.mesh.materials[self.material_index()]
+    }
+
+    pub fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        let (t, beta, gamma) = intersect_barycentric(&self.vertices(), ray, t_min, t_max)?;
+
+        let intersection_point = ray.origin + ray.direction.scale(t);
+
+        let alpha = 1.0 - beta - gamma;
+
+        let normals = self.normals();
+        let n = normals[0].scale(alpha) + normals[1].scale(beta) + normals[2].scale(gamma);
+
+        let v = self.vertices();
+        let face_n = (v[1] - v[0]).cross(&(v[2] - v[0])).unit();
+        let geometric_n = if face_n.dot(&n) < 0.0 { -face_n } else { face_n };
+
+        let texinfo = self.texinfo();
+        let (dpdu, dpdv) = uv_derivatives(&v, &texinfo, &geometric_n);
+        let u = texinfo[0].u * alpha + texinfo[1].u * beta + texinfo[2].u * gamma;
+        let v = texinfo[0].v * alpha + texinfo[1].v * beta + texinfo[2].v * gamma;
+
+        Some(Intersection {
+            n: n,
+            geometric_n: geometric_n,
+            front_face: geometric_n.dot(&ray.direction) < 0.0,
+            prim_id: 0,
+            t: t,
+            u: u,
+            v: v,
+            position: intersection_point,
+            dpdu: dpdu,
+            dpdv: dpdv,
+            material: &self.mesh.materials[self.material_index()]
+        })
+    }
+}
+
+impl<'a> PartialBoundingBox for MeshTriangle<'a> {
+    fn partial_bounding_box(&self) -> Option<BBox> {
+        let v = self.vertices();
+        Some(union_point(&union_points(&v[0], &v[1]), &v[2]))
+    }
+}
+
+#[cfg(test)]
+pub fn unit_quad() -> MeshOptions {
+    // Two triangles sharing the (0, 0, 0)-(1, 1, 0) diagonal
+    let mut meshopts = MeshOptions::new(vec![
+        Vec3 { x: 0.0, y: 0.0, z: 0.0 },
+        Vec3 { x: 1.0, y: 0.0, z: 0.0 },
+        Vec3 { x: 1.0, y: 1.0, z: 0.0 },
+        Vec3 { x: 0.0, y: 1.0, z: 0.0 }]);
+    meshopts.texinfo(vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]);
+    meshopts.face([0, 1, 2], None, Some([0, 1, 2]));
+    meshopts.face([0, 2, 3], None, Some([0, 2, 3]));
+    meshopts
+}
+
+#[test]
+fn it_intersects_faces_with_shared_vertices() {
+    let mesh = unit_quad().build();
+    assert_eq!(2, mesh.len());
+
+    // Lower-right triangle
+    let ray = Ray::new(Vec3 { x: 0.75, y: 0.25, z: -1.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    let intersection = mesh.intersects(&ray, 0.0, 10.0).unwrap();
+    assert_eq!(intersection.t, 1.0);
+    assert_eq!(intersection.u, 0.75);
+    assert_eq!(intersection.v, 0.25);
+    assert_eq!(intersection.n, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+
+    // Upper-left triangle
+    let ray = Ray::new(Vec3 { x: 0.25, y: 0.75, z: -1.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    let intersection = mesh.intersects(&ray, 0.0, 10.0).unwrap();
+    assert_eq!(intersection.u, 0.25);
+    assert_eq!(intersection.v, 0.75);
+
+    // Outside of the quad, and outside of tmin/tmax
+    let ray = Ray::new(Vec3 { x: 1.5, y: 0.5, z: -1.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    assert!(mesh.intersects(&ray, 0.0, 10.0).is_none());
+    let ray = Ray::new(Vec3 { x: 0.5, y: 0.25, z: -1.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    assert!(mesh.intersects(&ray, 0.0, 0.5).is_none());
+}
+
+#[test]
+fn it_transforms_shared_vertices() {
+    let mut mesh = unit_quad().build();
+    mesh.mut_transform(&Transform::new(Mat4::translate_matrix(&Vec3 { x: 0.0, y: 0.0, z: 2.0 })));
+
+    let bbox = mesh.partial_bounding_box().unwrap();
+    assert_eq!(bbox.min, Vec3 { x: 0.0, y: 0.0, z: 2.0 });
+    assert_eq!(bbox.max, Vec3 { x: 1.0, y: 1.0, z: 2.0 });
+
+    let ray = Ray::new(Vec3 { x: 0.25, y: 0.75, z: -1.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    assert_eq!(3.0, mesh.intersects(&ray, 0.0, 10.0).unwrap().t);
+}
+
+#[test]
+fn it_smooths_triangle_soup_and_fixes_the_winding() {
+    // A square folded 60 degrees along x = 0, each half with its own
+    // vertices and the second wound backwards
+    let lift = Vec3 { x: 0.5, y: 0.0, z: 0.75f64.sqrt() };
+    let (a, b, c) = (Vec3 { x: -1.0, y: 0.0, z: 0.0 }, Vec3::zero(), Vec3 { x: 0.0, y: 1.0, z: 0.0 });
+    let mut meshopts = MeshOptions::new(vec![a, b, c, b, c, lift]);
+    meshopts.texinfo(vec![(0.0, 0.0), (0.5, 0.0), (0.5, 1.0), (0.5, 0.0), (0.5, 1.0), (1.0, 0.0)]);
+    meshopts.face([0, 1, 2], None, Some([0, 1, 2]));
+    meshopts.face([3, 4, 5], None, Some([3, 4, 5]));
+    meshopts.smooth(0.0, 90.0);
+
+    let mesh = meshopts.build();
+    assert_eq!(2, mesh.len());
+    assert_eq!([b, lift, c], mesh.triangle(1).vertices());
+
+    // The fold is smoothed over, leaning the flat half's normal towards the other
+    let ray = Ray::new(Vec3 { x: -1e-6, y: 0.5, z: 5.0 }, Vec3 { x: 0.0, y: 0.0, z: -1.0 });
+    let near_fold = mesh.intersects(&ray, 0.0, 10.0).unwrap();
+    assert!(near_fold.n.x < -0.2 && near_fold.n.z > 0.5);
+    assert!((near_fold.u - 0.5).abs() < 1e-4);
+}
@@ -0,0 +1,200 @@
+use crate::prelude::*;
+use crate::geometry::bbox::{union_point, BBox, PartialBoundingBox};
+use crate::geometry::prim::Prim;
+use crate::light::lights::AreaLight;
+use crate::mat4::{Mat4, Transform};
+use crate::motion::AnimatedTransform;
+use crate::raytracer::{Ray, Intersection, TraversalStats};
+use crate::vec3::Vec3;
+
+#[cfg(test)]
+use crate::geometry::prims::Sphere;
+#[cfg(test)]
+use crate::material::materials::FlatMaterial;
+#[cfg(test)]
+use crate::motion::Keyframe;
+
+/// Steps each stretch between keyframes is sampled at when bounding the
+/// motion.
+pub static MOTION_BOUND_STEPS: usize = 16;
+
+/// Wraps a prim to move it over time, for motion blur. Each ray sees the
+/// prim where it was at `ray.time`. `mut_transform` applies on top of the
+/// animation.
+pub struct Moving {
+    pub prim: Box<dyn Prim+Send+Sync>,
+    motion: AnimatedTransform,
+    transform: Transform,
+    /// Where the prim is at the start of its motion, which is where every
+    /// ray finds it while the shutter isn't open for any time.
+    start: Transform
+}
+
+impl Moving {
+    pub fn new(prim: Box<dyn Prim+Send+Sync>, motion: AnimatedTransform) -> Moving {
+        let start = Transform::new(motion.matrix_at(motion.start_time()));
+        Moving {
+            prim: prim,
+            motion: motion,
+            transform: Transform::new(Mat4::identity()),
+            start: start
+        }
+    }
+
+    pub fn transform_at(&self, time: f64) -> Transform {
+        Transform::new(Mat4::mult_m(&self.transform.m, &self.motion.matrix_at(time)))
+    }
+}
+
+impl PartialBoundingBox for Moving {
+    /// Covers the prim's bounds all the way through its motion. Corners can
+    /// swing outwards between samples, so the box is padded by half the
+    /// furthest any corner moves in one step, which covers any arc of up
+    /// to a half turn.
+    fn partial_bounding_box(&self) -> Option<BBox> {
+        let local = self.prim.partial_bounding_box()?;
+        let corners: Vec<Vec3> = (0..8)
+            .map(|i| local.lerp((i & 1) as f64, ((i >> 1) & 1) as f64, ((i >> 2) & 1) as f64))
+            .collect();
+
+        let keyframes = &self.motion.keyframes;
+        let mut times = vec![keyframes[0].time];
+        for pair in keyframes.windows(2) {
+            times.extend((1..=MOTION_BOUND_STEPS).map(|step| {
+                pair[0].time + (pair[1].time - pair[0].time) * step as f64 / MOTION_BOUND_STEPS as f64
+            }));
+        }
+
+        let mut bbox = BBox { min: Vec3::one().scale(f64::INFINITY), max: Vec3::one().scale(f64::NEG_INFINITY) };
+        let mut previous: Option<Vec<Vec3>> = None;
+        let mut pad: f64 = 0.0;
+
+        for &time in times.iter() {
+            let m = self.transform_at(time).m;
+            let world: Vec<Vec3> = corners.iter().map(|corner| Mat4::mult_p(&m, corner)).collect();
+            for p in world.iter() {
+                bbox = union_point(&bbox, p);
+            }
+            if let Some(ref previous) = previous {
+                for (a, b) in previous.iter().zip(world.iter()) {
+                    pad = pad.max((*b - *a).len() / 2.0);
+                }
+            }
+            previous = Some(world);
+        }
+
+        Some(BBox { min: bbox.min - pad, max: bbox.max + pad })
+    }
+}
+
+impl Prim for Moving {
+    fn intersects<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        self.intersects_counted(ray, t_min, t_max, &mut TraversalStats::default())
+    }
+
+    fn intersects_counted<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64,
+                              stats: &mut TraversalStats) -> Option<Intersection<'a>> {
+        // Without an open shutter every ray comes at the start time, where
+        // the inverse is already to hand
+        let moved;
+        let transform = if ray.time == self.motion.start_time() {
+            &self.start
+        } else {
+            moved = self.transform_at(ray.time);
+            &moved
+        };
+        let local_ray = transform.ray_to_object(ray);
+        let mut hit = self.prim.intersects_counted(&local_ray, t_min, t_max, stats)?;
+
+        hit.n = transform.normal_to_world(&hit.n);
+        hit.geometric_n = transform.normal_to_world(&hit.geometric_n);
+        hit.dpdu = Mat4::mult_v(&transform.m, &hit.dpdu);
+        hit.dpdv = Mat4::mult_v(&transform.m, &hit.dpdv);
+        hit.position = ray.origin + ray.direction.scale(hit.t);
+        Some(hit)
+    }
+
+    fn mut_transform(&mut self, transform: &Transform) {
+        self.transform = Transform::new(Mat4::mult_m(&transform.m, &self.transform.m));
+        self.start = self.transform_at(self.motion.start_time());
+    }
+
+    /// Lights don't move, so they stay where the prim starts out.
+    fn area_lights(&self) -> Vec<AreaLight> {
+        self.prim.area_lights().into_iter().map(|light| light.transformed(&self.start)).collect()
+    }
+}
+
+#[cfg(test)]
+fn sliding_sphere() -> Moving {
+    let sphere = Sphere {
+        center: Vec3::zero(),
+        radius: 1.0,
+        material: Box::new(FlatMaterial { color: Vec3::one() })
+    };
+    let mut end = Keyframe::new(1.0);
+    end.translate(Vec3 { x: 10.0, y: 0.0, z: 0.0 });
+    Moving::new(Box::new(sphere), AnimatedTransform::new(vec![Keyframe::new(0.0), end]))
+}
+
+#[test]
+fn it_is_hit_where_it_was_at_the_time() {
+    let moving = sliding_sphere();
+    let ray = Ray::new(Vec3 { x: 5.0, y: 0.0, z: -5.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    assert!(moving.intersects(&ray, 0.0, 10.0).is_none());
+
+    let hit = moving.intersects(&ray.with_time(0.5), 0.0, 10.0).unwrap();
+    assert!((hit.t - 4.0).abs() < 1e-9);
+    assert_eq!(Vec3 { x: 5.0, y: 0.0, z: -1.0 }, hit.position);
+    assert_eq!(Vec3 { x: 0.0, y: 0.0, z: -1.0 }, hit.n);
+
+    let bbox = moving.partial_bounding_box().unwrap();
+    assert!(bbox.min.x <= -1.0 && bbox.max.x >= 11.0 && bbox.max.y >= 1.0);
+}
+
+#[test]
+fn it_bounds_a_spinning_prim() {
+    let sphere = Sphere {
+        center: Vec3 { x: 5.0, y: 0.0, z: 0.0 },
+        radius: 1.0,
+        material: Box::new(FlatMaterial { color: Vec3::one() })
+    };
+    let mut end = Keyframe::new(1.0);
+    end.rotate_deg(180.0, &Vec3 { x: 0.0, y: 1.0, z: 0.0 });
+    let mut moving = Moving::new(Box::new(sphere), AnimatedTransform::new(vec![Keyframe::new(0.0), end]));
+    moving.mut_transform(&Transform::new(Mat4::translate_matrix(&Vec3 { x: 0.0, y: 3.0, z: 0.0 })));
+
+    // Halfway round, the sphere sits on -Z, above where it started
+    let bbox = moving.partial_bounding_box().unwrap();
+    assert!(bbox.min.z <= -6.0 && bbox.min.x <= -6.0 && bbox.min.y <= 2.0);
+
+    let ray = Ray::new(Vec3 { x: 0.0, y: 3.0, z: -20.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 }).with_time(0.5);
+    let hit = moving.intersects(&ray, 0.0, 30.0).unwrap();
+    assert!((hit.t - 14.0).abs() < 1e-9);
+
+    // At the start it's where it began, moved up along with the rest
+    let ray = Ray::new(Vec3 { x: 5.0, y: 3.0, z: -20.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    let hit = moving.intersects(&ray, 0.0, 30.0).unwrap();
+    assert!((hit.t - 19.0).abs() < 1e-9);
+}
+
+#[test]
+fn it_lights_from_where_the_prim_starts() {
+    use crate::light::Light;
+    use crate::material::materials::EmissiveMaterial;
+
+    let sphere = Sphere {
+        center: Vec3::zero(),
+        radius: 1.0,
+        material: Box::new(EmissiveMaterial::default())
+    };
+    let mut end = Keyframe::new(1.0);
+    end.translate(Vec3 { x: 10.0, y: 0.0, z: 0.0 });
+    let mut moving = Moving::new(Box::new(sphere), AnimatedTransform::new(vec![Keyframe::new(0.0), end]));
+    moving.mut_transform(&Transform::new(Mat4::translate_matrix(&Vec3 { x: 0.0, y: 3.0, z: 0.0 })));
+
+    let lights = moving.area_lights();
+    assert_eq!(1, lights.len());
+    assert_eq!(Vec3 { x: 0.0, y: 3.0, z: 0.0 }, lights[0].center());
+    assert!(sliding_sphere().area_lights().is_empty());
+}
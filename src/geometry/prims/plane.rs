@@ -6,7 +6,7 @@ use crate::raytracer::{Ray, Intersection};
 use crate::vec3::Vec3;
 
 #[cfg(test)]
-use material::materials::FlatMaterial;
+use crate::material::materials::FlatMaterial;
 
 #[allow(dead_code)]
 pub struct Plane {
@@ -39,12 +39,26 @@ impl Prim for Plane {
             let u = intersection_point.dot(&u_axis);
             let v = intersection_point.dot(&v_axis);
 
+            // The axes needn't lie in the plane, so the derivatives are the
+            // directions in it that change one of u and v and not the other
+            let in_plane = |axis: Vec3| axis - n.scale(n.dot(&axis) / n.dot(&n));
+            let (a, b) = (in_plane(u_axis), in_plane(v_axis));
+            let (across_b, across_a) = (b.cross(&n), n.cross(&a));
+            let (det_u, det_v) = (a.dot(&across_b), b.dot(&across_a));
+            let dpdu = if det_u != 0.0 { across_b.scale(1.0 / det_u) } else { Vec3::zero() };
+            let dpdv = if det_v != 0.0 { across_a.scale(1.0 / det_v) } else { Vec3::zero() };
+
             Some(Intersection {
                 n: n,
+                geometric_n: n.unit(),
+                front_face: nrd < 0.0,
+                prim_id: 0,
                 t: t,
                 u: u,
                 v: v,
                 position: intersection_point,
+                dpdu: dpdu,
+                dpdv: dpdv,
                 material: &self.material
             })
         }
@@ -0,0 +1,94 @@
+use crate::prelude::*;
+use crate::light::lights::EmitterShape;
+use crate::material::Material;
+use crate::mat4::{Mat4, Transform};
+use crate::raytracer::{Ray, Intersection};
+use crate::vec3::Vec3;
+use core::f64::consts::PI;
+
+/// Cells around and along a glowing quadric for its area light to weigh
+/// the parts of its surface by.
+pub static EMITTER_CELLS: usize = 16;
+
+/// A hit on a cylinder, cone or disk, found in the prim's object space
+/// where its axis is +Y.
+pub struct LocalHit {
+    pub t: f64,
+    pub n: Vec3,
+    pub u: f64,
+    pub v: f64,
+    pub dpdu: Vec3,
+    pub dpdv: Vec3
+}
+
+impl LocalHit {
+    #[allow(clippy::borrowed_box)]
+    pub fn into_world<'a>(self, ray: &Ray, transform: &Transform, material: &'a Box<dyn Material+Send+Sync>) -> Intersection<'a> {
+        let n = transform.normal_to_world(&self.n);
+        Intersection {
+            n: n,
+            geometric_n: n,
+            front_face: n.dot(&ray.direction) < 0.0,
+            prim_id: 0,
+            t: self.t,
+            u: self.u,
+            v: self.v,
+            position: ray.origin + ray.direction.scale(self.t),
+            dpdu: Mat4::mult_v(&transform.m, &self.dpdu),
+            dpdv: Mat4::mult_v(&transform.m, &self.dpdv),
+            material: material
+        }
+    }
+}
+
+pub fn nearest(a: Option<LocalHit>, b: Option<LocalHit>) -> Option<LocalHit> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if a.t <= b.t { a } else { b }),
+        (a, b) => a.or(b)
+    }
+}
+
+/// Angle of `p` around the Y axis from +X towards +Z, in [0, 2π).
+pub fn phi(p: &Vec3) -> f64 {
+    let phi = p.z.atan2(p.x);
+    if phi < 0.0 { phi + 2.0 * PI } else { phi }
+}
+
+/// Flat ring in the plane at height `y`, facing up or down along Y as given
+/// by `n_y`. With `inner_radius` 0 it is a disk, as used for caps. The
+/// caller checks `t` against its range.
+pub fn intersect_ring(ray: &Ray, y: f64, n_y: f64, inner_radius: f64, radius: f64, phi_max: f64) -> Option<LocalHit> {
+    if ray.direction.y == 0.0 {
+        return None;
+    }
+
+    let t = (y - ray.origin.y) / ray.direction.y;
+    let p = ray.origin + ray.direction.scale(t);
+    let r = (p.x * p.x + p.z * p.z).sqrt();
+    let phi = phi(&p);
+    if r > radius || r < inner_radius || phi > phi_max {
+        return None;
+    }
+
+    Some(LocalHit {
+        t: t,
+        n: Vec3 { x: 0.0, y: n_y, z: 0.0 },
+        u: phi / phi_max,
+        v: (radius - r) / (radius - inner_radius),
+        dpdu: Vec3 { x: -p.z, y: 0.0, z: p.x }.scale(phi_max),
+        dpdv: Vec3 { x: phi.cos(), y: 0.0, z: phi.sin() }.scale(inner_radius - radius)
+    })
+}
+
+/// The ring `intersect_ring` hits, with the same uvs, as a glowing surface
+/// in object space.
+pub fn ring_emitter(y: f64, n_y: f64, inner_radius: f64, radius: f64, phi_max: f64) -> EmitterShape {
+    EmitterShape::Surface {
+        surface: Box::new(move |u, v| {
+            let (phi, r) = (u * phi_max, radius - v * (radius - inner_radius));
+            (Vec3 { x: r * phi.cos(), y: y, z: r * phi.sin() }, Vec3 { x: 0.0, y: n_y, z: 0.0 })
+        }),
+        columns: EMITTER_CELLS,
+        rows: EMITTER_CELLS
+    }
+}
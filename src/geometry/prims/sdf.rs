@@ -0,0 +1,380 @@
+use crate::prelude::*;
+use crate::geometry::bbox::{BBox, PartialBoundingBox};
+use crate::geometry::prim::Prim;
+use crate::material::Material;
+use crate::material::materials::FlatMaterial;
+use crate::mat4::{Mat4, Transform};
+use crate::raytracer::{Ray, Intersection};
+use crate::vec3::Vec3;
+use core::f64::consts::PI;
+
+use super::sphere::spherical_derivatives;
+
+/// A signed distance function, built up as a tree. Distances are negative
+/// inside. Sphere tracing only needs them never to overestimate, which
+/// `Twist` and the smooth blends break slightly; lower the prim's
+/// `step_scale` if those show holes.
+#[allow(dead_code)]
+pub enum SdfNode {
+    Sphere { radius: f64 },
+    /// Centred on the origin, extending `half_size` along each axis
+    Cuboid { half_size: Vec3 },
+    /// Ring around the Y axis, as `Torus`
+    Torus { major_radius: f64, minor_radius: f64 },
+    /// Capped, along the Y axis from `-half_height` to `half_height`
+    Cylinder { radius: f64, half_height: f64 },
+    Mandelbulb { power: f64, iterations: usize },
+    /// Fills the cube from -1 to 1
+    MengerSponge { iterations: usize },
+
+    Union(Box<SdfNode>, Box<SdfNode>),
+    Intersection(Box<SdfNode>, Box<SdfNode>),
+    Difference(Box<SdfNode>, Box<SdfNode>),
+    /// Union blended over roughly distance `k`
+    SmoothUnion(Box<SdfNode>, Box<SdfNode>, f64),
+
+    Translate(Vec3, Box<SdfNode>),
+    Scale(f64, Box<SdfNode>),
+    /// Grows the surface outwards by a radius, rounding off edges
+    Round(f64, Box<SdfNode>),
+    /// Tiles space with copies of the node every `period`. An axis with a
+    /// period of 0 isn't repeated.
+    Repeat(Vec3, Box<SdfNode>),
+    /// Turns by this many radians per unit of height around the Y axis
+    Twist(f64, Box<SdfNode>)
+}
+
+fn abs3(v: &Vec3) -> Vec3 {
+    Vec3 { x: v.x.abs(), y: v.y.abs(), z: v.z.abs() }
+}
+
+fn max3(v: &Vec3, min: f64) -> Vec3 {
+    Vec3 { x: v.x.max(min), y: v.y.max(min), z: v.z.max(min) }
+}
+
+fn repeat_axis(p: f64, period: f64) -> f64 {
+    if period == 0.0 { p } else { p - period * (p / period).round() }
+}
+
+#[allow(dead_code)]
+impl SdfNode {
+    pub fn union(self, other: SdfNode) -> SdfNode {
+        SdfNode::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn intersection(self, other: SdfNode) -> SdfNode {
+        SdfNode::Intersection(Box::new(self), Box::new(other))
+    }
+
+    pub fn difference(self, other: SdfNode) -> SdfNode {
+        SdfNode::Difference(Box::new(self), Box::new(other))
+    }
+
+    pub fn smooth_union(self, other: SdfNode, k: f64) -> SdfNode {
+        SdfNode::SmoothUnion(Box::new(self), Box::new(other), k)
+    }
+
+    pub fn translate(self, offset: Vec3) -> SdfNode {
+        SdfNode::Translate(offset, Box::new(self))
+    }
+
+    pub fn scale(self, factor: f64) -> SdfNode {
+        SdfNode::Scale(factor, Box::new(self))
+    }
+
+    pub fn round(self, radius: f64) -> SdfNode {
+        SdfNode::Round(radius, Box::new(self))
+    }
+
+    pub fn repeat(self, period: Vec3) -> SdfNode {
+        SdfNode::Repeat(period, Box::new(self))
+    }
+
+    pub fn twist(self, rate: f64) -> SdfNode {
+        SdfNode::Twist(rate, Box::new(self))
+    }
+
+    pub fn distance(&self, p: &Vec3) -> f64 {
+        match *self {
+            SdfNode::Sphere { radius } => p.len() - radius,
+            SdfNode::Cuboid { half_size } => {
+                let q = abs3(p) - half_size;
+                max3(&q, 0.0).len() + q.x.max(q.y).max(q.z).min(0.0)
+            },
+            SdfNode::Torus { major_radius, minor_radius } => {
+                let ring = (p.x * p.x + p.z * p.z).sqrt() - major_radius;
+                (ring * ring + p.y * p.y).sqrt() - minor_radius
+            },
+            SdfNode::Cylinder { radius, half_height } => {
+                let dx = (p.x * p.x + p.z * p.z).sqrt() - radius;
+                let dy = p.y.abs() - half_height;
+                dx.max(dy).min(0.0) + (dx.max(0.0) * dx.max(0.0) + dy.max(0.0) * dy.max(0.0)).sqrt()
+            },
+            SdfNode::Mandelbulb { power, iterations } => mandelbulb(p, power, iterations),
+            SdfNode::MengerSponge { iterations } => menger_sponge(p, iterations),
+
+            SdfNode::Union(ref a, ref b) => a.distance(p).min(b.distance(p)),
+            SdfNode::Intersection(ref a, ref b) => a.distance(p).max(b.distance(p)),
+            SdfNode::Difference(ref a, ref b) => a.distance(p).max(-b.distance(p)),
+            SdfNode::SmoothUnion(ref a, ref b, k) => {
+                let (da, db) = (a.distance(p), b.distance(p));
+                if k <= 0.0 {
+                    return da.min(db);
+                }
+                let h = (0.5 + 0.5 * (db - da) / k).clamp(0.0, 1.0);
+                db + (da - db) * h - k * h * (1.0 - h)
+            },
+
+            SdfNode::Translate(offset, ref node) => node.distance(&(*p - offset)),
+            SdfNode::Scale(factor, ref node) => node.distance(&p.scale(1.0 / factor)) * factor,
+            SdfNode::Round(radius, ref node) => node.distance(p) - radius,
+            SdfNode::Repeat(period, ref node) => node.distance(&Vec3 {
+                x: repeat_axis(p.x, period.x),
+                y: repeat_axis(p.y, period.y),
+                z: repeat_axis(p.z, period.z)
+            }),
+            SdfNode::Twist(rate, ref node) => {
+                let (sin, cos) = (rate * p.y).sin_cos();
+                node.distance(&Vec3 { x: cos * p.x - sin * p.z, y: p.y, z: sin * p.x + cos * p.z })
+            }
+        }
+    }
+}
+
+/// Distance estimate from the running derivative of z -> z^power + c
+fn mandelbulb(p: &Vec3, power: f64, iterations: usize) -> f64 {
+    let mut z = *p;
+    let mut dr = 1.0;
+    let mut r = z.len();
+
+    for _ in 0..iterations {
+        if r > 2.0 {
+            break;
+        }
+        let theta = if r > 0.0 { (z.y / r).acos() * power } else { 0.0 };
+        let phi = z.z.atan2(z.x) * power;
+        dr = r.powf(power - 1.0) * power * dr + 1.0;
+
+        let zr = r.powf(power);
+        z = Vec3 { x: theta.sin() * phi.cos(), y: theta.cos(), z: theta.sin() * phi.sin() }.scale(zr) + *p;
+        r = z.len();
+    }
+    0.5 * r.ln() * r / dr
+}
+
+/// The unit cube with crosses carved out at every scale
+fn menger_sponge(p: &Vec3, iterations: usize) -> f64 {
+    let mut d = SdfNode::Cuboid { half_size: Vec3::one() }.distance(p);
+    let mut s = 1.0;
+
+    for _ in 0..iterations {
+        let wrap = |x: f64| x * s - 2.0 * (x * s / 2.0).floor() - 1.0;
+        let a = Vec3 { x: wrap(p.x), y: wrap(p.y), z: wrap(p.z) };
+        s *= 3.0;
+        let r = abs3(&(Vec3::one() - abs3(&a).scale(3.0)));
+        let cross = r.x.max(r.y).min(r.y.max(r.z)).min(r.z.max(r.x));
+        d = d.max((cross - 1.0) / s);
+    }
+    d
+}
+
+pub struct SdfOptions {
+    node: SdfNode,
+    bbox: BBox,
+    epsilon: f64,
+    max_steps: usize,
+    step_scale: f64,
+    material: Option<Box<dyn Material+Send+Sync>>,
+}
+
+#[allow(dead_code)]
+impl SdfOptions {
+    /// `bbox` must enclose the whole surface. Only rays through it are
+    /// traced, and it is what the octree sees.
+    pub fn new(node: SdfNode, bbox: BBox) -> SdfOptions {
+        SdfOptions {
+            node: node,
+            bbox: bbox,
+            epsilon: 0.0001,
+            max_steps: 256,
+            step_scale: 1.0,
+            material: None,
+        }
+    }
+
+    /// How close to the surface counts as a hit. Also the step used for
+    /// normals, so raising it softens fine detail.
+    pub fn epsilon(&mut self, epsilon: f64) -> &mut Self {
+        self.epsilon = epsilon;
+        self
+    }
+
+    /// Rays taking more steps than this are treated as misses.
+    pub fn max_steps(&mut self, max_steps: usize) -> &mut Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// Fraction of the distance to step each time, below 1 for functions
+    /// that can overestimate.
+    pub fn step_scale(&mut self, step_scale: f64) -> &mut Self {
+        self.step_scale = step_scale;
+        self
+    }
+
+    pub fn material(&mut self, material: Box<dyn Material+Send+Sync>) -> &mut Self {
+        self.material = Some(material);
+        self
+    }
+
+    pub fn build(self) -> Sdf {
+        Sdf {
+            node: self.node,
+            bbox: self.bbox,
+            epsilon: self.epsilon,
+            max_steps: self.max_steps,
+            step_scale: self.step_scale,
+            transform: Transform::new(Mat4::identity()),
+            material: self.material.unwrap_or_else(|| Box::new(FlatMaterial { color: Vec3::one() }))
+        }
+    }
+}
+
+/// A surface traced through its distance function. Transforms should keep
+/// to a uniform scale, or distances stop meaning the same in world space.
+pub struct Sdf {
+    pub node: SdfNode,
+    pub bbox: BBox,
+    pub epsilon: f64,
+    pub max_steps: usize,
+    pub step_scale: f64,
+    pub transform: Transform,
+    pub material: Box<dyn Material+Send+Sync>
+}
+
+impl Sdf {
+    /// Central differences of the distance function
+    fn gradient(&self, p: &Vec3) -> Vec3 {
+        let h = self.epsilon;
+        let axis = |offset: Vec3| self.node.distance(&(*p + offset)) - self.node.distance(&(*p - offset));
+        Vec3 {
+            x: axis(Vec3 { x: h, y: 0.0, z: 0.0 }),
+            y: axis(Vec3 { x: 0.0, y: h, z: 0.0 }),
+            z: axis(Vec3 { x: 0.0, y: 0.0, z: h })
+        }
+    }
+}
+
+impl PartialBoundingBox for Sdf {
+    fn partial_bounding_box(&self) -> Option<BBox> {
+        Some(self.transform.bbox_to_world(&self.bbox))
+    }
+}
+
+impl Prim for Sdf {
+    fn intersects<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        let local_ray = self.transform.ray_to_object(ray);
+        let (t_near, t_far) = self.bbox.intersect_interval(&local_ray)?;
+        let (t_start, t_end) = (t_near.max(t_min), t_far.min(t_max));
+        if t_start > t_end {
+            return None;
+        }
+
+        // March in object-space distances, converting back to t at the end
+        let speed = local_ray.direction.len();
+        let dir = local_ray.direction.scale(1.0 / speed);
+        let (mut s, s_end) = (t_start * speed, t_end * speed);
+
+        // A ray starting on the surface, as reflections do, first has to get
+        // clear of it or it would hit where it started
+        let mut leaving = t_min >= t_near && self.node.distance(&(local_ray.origin + dir.scale(s))).abs() < self.epsilon;
+
+        for _ in 0..self.max_steps {
+            let p = local_ray.origin + dir.scale(s);
+            let d = self.node.distance(&p).abs();
+
+            if d < self.epsilon && !leaving {
+                let t = s / speed;
+                let center = self.bbox.lerp(0.5, 0.5, 0.5);
+                let radial = (p - center).unit();
+                let gradient = self.gradient(&p);
+                let n = self.transform.normal_to_world(&gradient);
+
+                // UVs are projected from a sphere, so their derivatives are
+                // the sphere's, laid flat onto the surface
+                let (dpdu, dpdv) = spherical_derivatives(&(p - center));
+                let onto_surface = |d: Vec3| d - gradient.scale(gradient.dot(&d) / gradient.dot(&gradient));
+                return Some(Intersection {
+                    n: n,
+                    geometric_n: n,
+                    front_face: n.dot(&ray.direction) < 0.0,
+                    prim_id: 0,
+                    t: t,
+                    u: 0.5 + radial.z.atan2(radial.x) / (2.0 * PI),
+                    v: 0.5 - radial.y.asin() / PI,
+                    position: ray.origin + ray.direction.scale(t),
+                    dpdu: Mat4::mult_v(&self.transform.m, &onto_surface(dpdu)),
+                    dpdv: Mat4::mult_v(&self.transform.m, &onto_surface(dpdv)),
+                    material: &self.material
+                });
+            }
+
+            leaving = leaving && d < self.epsilon;
+            s += d.max(self.epsilon) * self.step_scale;
+            if s > s_end {
+                break;
+            }
+        }
+        None
+    }
+
+    fn mut_transform(&mut self, transform: &Transform) {
+        self.transform = Transform::new(Mat4::mult_m(&transform.m, &self.transform.m));
+    }
+}
+
+#[test]
+fn it_measures_distances() {
+    let blob = SdfNode::Sphere { radius: 1.0 }.smooth_union(SdfNode::Sphere { radius: 1.0 }.translate(Vec3 { x: 1.5, y: 0.0, z: 0.0 }), 0.5);
+    // Between the two, the blend bulges out past either sphere alone
+    assert!(blob.distance(&Vec3 { x: 0.75, y: 0.0, z: 0.0 }) < -0.3);
+    assert!((blob.distance(&Vec3 { x: -2.0, y: 0.0, z: 0.0 }) - 1.0).abs() < 1e-9);
+
+    let rounded = SdfNode::Cuboid { half_size: Vec3::one() }.round(0.25);
+    assert!((rounded.distance(&Vec3 { x: 3.0, y: 0.0, z: 0.0 }) - 1.75).abs() < 1e-9);
+    assert!((rounded.distance(&Vec3 { x: 2.0, y: 2.0, z: 0.0 }) - (2.0f64.sqrt() - 0.25)).abs() < 1e-9);
+
+    // Every copy of a repeated sphere is the same distance away
+    let grid = SdfNode::Sphere { radius: 0.5 }.repeat(Vec3 { x: 2.0, y: 0.0, z: 2.0 });
+    assert!((grid.distance(&Vec3 { x: 4.0, y: 0.0, z: -6.0 }) + 0.5).abs() < 1e-9);
+    assert!((grid.distance(&Vec3 { x: 4.0, y: 3.0, z: -6.0 }) - 2.5).abs() < 1e-9);
+
+    // The sponge's middle is hollowed out but its corners are solid
+    let sponge = SdfNode::MengerSponge { iterations: 3 };
+    assert!(sponge.distance(&Vec3::zero()) > 0.0);
+    assert!(sponge.distance(&Vec3::one().scale(0.99)) < 0.0);
+    assert!(SdfNode::Mandelbulb { power: 8.0, iterations: 8 }.distance(&Vec3::one().scale(2.0)) > 0.0);
+}
+
+#[test]
+fn it_sphere_traces_within_the_bounds() {
+    let node = SdfNode::Sphere { radius: 1.0 }.translate(Vec3 { x: 0.0, y: 0.0, z: 5.0 });
+    let mut options = SdfOptions::new(node, BBox { min: Vec3 { x: -1.0, y: -1.0, z: 4.0 }, max: Vec3 { x: 1.0, y: 1.0, z: 6.0 } });
+    options.epsilon(1e-6);
+    let mut sdf = options.build();
+    sdf.mut_transform(&Transform::new(Mat4::scale_matrix(&Vec3::one().scale(2.0))));
+
+    // Doubled in size, the sphere is now centred at z = 10 with radius 2
+    let ray = Ray::new(Vec3::zero(), Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    let hit = sdf.intersects(&ray, 0.0, 100.0).unwrap();
+    assert!((hit.t - 8.0).abs() < 1e-4);
+    assert!((hit.n.z + 1.0).abs() < 1e-4);
+
+    // Leaving the surface, the next hit is the far side
+    let hit = sdf.intersects(&ray, hit.t, 100.0).unwrap();
+    assert!((hit.t - 12.0).abs() < 1e-4);
+
+    let ray = Ray::new(Vec3 { x: 2.5, y: 0.0, z: 0.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    assert!(sdf.intersects(&ray, 0.0, 100.0).is_none());
+    assert_eq!(Vec3 { x: -2.0, y: -2.0, z: 8.0 }, sdf.partial_bounding_box().unwrap().min);
+}
@@ -1,10 +1,12 @@
 use crate::prelude::*;
 use crate::geometry::bbox::{BBox, PartialBoundingBox};
 use crate::geometry::prim::Prim;
+use crate::light::lights::{AreaLight, EmitterShape};
 use crate::material::Material;
 use crate::mat4::{Mat4, Transform};
 use crate::raytracer::{Ray, Intersection};
 use crate::vec3::Vec3;
+use core::f64::consts::PI;
 
 #[cfg(test)]
 use crate::material::materials::FlatMaterial;
@@ -16,6 +18,17 @@ pub struct Sphere {
     pub material: Box<dyn Material+Send+Sync>
 }
 
+/// dP/du and dP/dv for the longitude/latitude UVs `Sphere` uses, at
+/// `offset` from the center. dP/du vanishes at the poles.
+pub fn spherical_derivatives(offset: &Vec3) -> (Vec3, Vec3) {
+    let ring = (offset.x * offset.x + offset.z * offset.z).sqrt();
+    let (cos_phi, sin_phi) = if ring > 0.0 { (offset.x / ring, offset.z / ring) } else { (1.0, 0.0) };
+
+    let dpdu = Vec3 { x: -offset.z, y: 0.0, z: offset.x }.scale(2.0 * PI);
+    let dpdv = Vec3 { x: offset.y * cos_phi, y: -ring, z: offset.y * sin_phi }.scale(PI);
+    (dpdu, dpdv)
+}
+
 impl PartialBoundingBox for Sphere {
     fn partial_bounding_box(&self) -> Option<BBox> {
         Some(BBox {
@@ -51,20 +64,26 @@ impl Prim for Sphere {
 
             if t1 >= t_min && t1 <= t_max ||
                t2 >= t_min && t2 <= t_max {
-                // Valid intersection(s): get nearer intersection
-                let t = if t1.abs() < t2.abs() { t1 } else { t2 };
+                // Valid intersection(s): get nearer intersection, t2 <= t1
+                let t = if t2 >= t_min && t2 <= t_max { t2 } else { t1 };
                 let intersection_point = ray.origin + ray.direction.scale(t);
                 let n = (intersection_point - self.center).unit();
 
-                let u = 0.5 + n.z.atan2(n.x) / (::core::f64::consts::PI * 2.0);
-                let v = 0.5 - n.y.asin() / ::core::f64::consts::PI;
+                let u = 0.5 + n.z.atan2(n.x) / (PI * 2.0);
+                let v = 0.5 - n.y.asin() / PI;
+                let (dpdu, dpdv) = spherical_derivatives(&(intersection_point - self.center));
 
                 Some(Intersection {
                     n: n,
+                    geometric_n: n,
+                    front_face: n.dot(&ray.direction) < 0.0,
+                    prim_id: 0,
                     t: t,
                     u: u,
                     v: v,
                     position: intersection_point,
+                    dpdu: dpdu,
+                    dpdv: dpdv,
                     material: &self.material
                 })
             } else {
@@ -85,6 +104,13 @@ impl Prim for Sphere {
         self.center = new_center;
         self.radius = new_radius;
     }
+
+    fn area_lights(&self) -> Vec<AreaLight> {
+        self.material.emitter().into_iter().map(|emission| {
+            let shape = EmitterShape::Sphere { center: self.center, radius: self.radius };
+            AreaLight::new(shape, emission)
+        }).collect()
+    }
 }
 
 #[test]
@@ -137,3 +163,27 @@ fn it_intersects_only_in_tmin_tmax() {
     non_intersection = sphere.intersects(&intersecting_ray, 0.0, 0.0001);
     assert!(non_intersection.is_none());
 }
+
+#[test]
+fn it_reports_derivatives_matching_its_uvs() {
+    let sphere = Sphere {
+        center: Vec3 { x: 1.0, y: 0.0, z: 0.0 },
+        radius: 2.0,
+        material: Box::new(FlatMaterial { color: Vec3::one() })
+    };
+    let hit_at = |p: Vec3| {
+        let outward = (p - sphere.center).unit();
+        sphere.intersects(&Ray::new(p + outward.scale(3.0), -outward), 0.0, 10.0).unwrap()
+    };
+
+    // Stepping along dP/du or dP/dv moves u or v alone by the step
+    let hit = hit_at(Vec3 { x: 2.0, y: 1.0, z: -1.0 });
+    let step = 1e-5;
+    let along_u = hit_at(hit.position + hit.dpdu.scale(step));
+    let along_v = hit_at(hit.position + hit.dpdv.scale(step));
+    assert!(((along_u.u - hit.u) / step - 1.0).abs() < 1e-3 && ((along_u.v - hit.v) / step).abs() < 1e-3);
+    assert!(((along_v.v - hit.v) / step - 1.0).abs() < 1e-3 && ((along_v.u - hit.u) / step).abs() < 1e-3);
+
+    let (tangent, bitangent) = hit.tangent_frame();
+    assert!(tangent.dot(&hit.n).abs() < 1e-9 && bitangent.dot(&hit.dpdv) > 0.0);
+}
@@ -0,0 +1,208 @@
+use crate::prelude::*;
+use crate::geometry::bbox::{BBox, PartialBoundingBox};
+use crate::geometry::prim::Prim;
+use crate::light::lights::{AreaLight, EmitterShape};
+use crate::material::Material;
+use crate::mat4::{Mat4, Transform};
+use crate::poly::Polynomial;
+use crate::raytracer::{Ray, Intersection};
+use crate::vec3::Vec3;
+use core::f64::consts::PI;
+
+use super::quadric::{phi, EMITTER_CELLS};
+
+#[cfg(test)]
+use crate::material::materials::FlatMaterial;
+
+/// A ring of tube around the Y axis, centred on the origin until moved with
+/// `mut_transform`. `u` runs around the ring and `v` around the tube.
+pub struct Torus {
+    pub major_radius: f64,
+    pub minor_radius: f64,
+    pub transform: Transform,
+    pub material: Box<dyn Material+Send+Sync>
+}
+
+impl Torus {
+    #[allow(dead_code)]
+    pub fn new(major_radius: f64, minor_radius: f64, material: Box<dyn Material+Send+Sync>) -> Torus {
+        Torus {
+            major_radius: major_radius,
+            minor_radius: minor_radius,
+            transform: Transform::new(Mat4::identity()),
+            material: material
+        }
+    }
+
+    fn local_bbox(&self) -> BBox {
+        let (r_major, r_minor) = (self.major_radius, self.minor_radius);
+        BBox {
+            min: Vec3 { x: -r_major - r_minor, y: -r_minor, z: -r_major - r_minor },
+            max: Vec3 { x: r_major + r_minor, y: r_minor, z: r_major + r_minor }
+        }
+    }
+
+    /// (|p|^2 + R^2 - r^2)^2 - 4 R^2 (x^2 + z^2) along the ray
+    fn quartic(&self, o: &Vec3, d: &Vec3) -> Polynomial {
+        let r2_major = self.major_radius * self.major_radius;
+        let r2_minor = self.minor_radius * self.minor_radius;
+        let dd = d.dot(d);
+        let od = o.dot(d);
+        let k = o.dot(o) + r2_major - r2_minor;
+        let d_xz = d.x * d.x + d.z * d.z;
+        let od_xz = o.x * d.x + o.z * d.z;
+        let o_xz = o.x * o.x + o.z * o.z;
+
+        Polynomial::new(vec![
+            k * k - 4.0 * r2_major * o_xz,
+            4.0 * od * k - 8.0 * r2_major * od_xz,
+            2.0 * dd * k + 4.0 * od * od - 4.0 * r2_major * d_xz,
+            4.0 * dd * od,
+            dd * dd
+        ])
+    }
+}
+
+impl PartialBoundingBox for Torus {
+    /// The torus is a circle swept by a ball, and both stay ellipses under
+    /// the transform, so their extents along each world axis just add.
+    /// This stays tight however the torus is rotated.
+    fn partial_bounding_box(&self) -> Option<BBox> {
+        let m = &self.transform.m.m;
+        let center = Mat4::mult_p(&self.transform.m, &Vec3::zero());
+        let extent = |row: usize| {
+            let ring = self.major_radius * (m[row][0] * m[row][0] + m[row][2] * m[row][2]).sqrt();
+            let tube = self.minor_radius * (m[row][0] * m[row][0] + m[row][1] * m[row][1] + m[row][2] * m[row][2]).sqrt();
+            ring + tube
+        };
+        let half = Vec3 { x: extent(0), y: extent(1), z: extent(2) };
+
+        Some(BBox { min: center - half, max: center + half })
+    }
+}
+
+impl Prim for Torus {
+    fn intersects<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        let local_ray = self.transform.ray_to_object(ray);
+
+        // Solving from where the ray enters the bounds keeps the quartic's
+        // coefficients small, which matters far more than for a quadratic
+        let (t_near, t_far) = self.local_bbox().intersect_interval(&local_ray)?;
+        let t_start = t_near.max(t_min);
+        let t_end = t_far.min(t_max);
+        if t_start > t_end {
+            return None;
+        }
+
+        let o = local_ray.origin + local_ray.direction.scale(t_start);
+        let roots = self.quartic(&o, &local_ray.direction).roots_in(0.0, t_end - t_start);
+        let t = t_start + *roots.first()?;
+
+        // The normal points away from the nearest point on the ring
+        let p = local_ray.origin + local_ray.direction.scale(t);
+        let ring_xz = (p.x * p.x + p.z * p.z).sqrt();
+        let ring = Vec3 { x: p.x, y: 0.0, z: p.z }.scale(self.major_radius / ring_xz);
+        let n = self.transform.normal_to_world(&(p - ring));
+
+        // Around the ring for u, around the tube for v
+        let around = Vec3 { x: p.x / ring_xz, y: 0.0, z: p.z / ring_xz };
+        let dpdu = Vec3 { x: -p.z, y: 0.0, z: p.x }.scale(2.0 * PI);
+        let dpdv = (Vec3 { x: 0.0, y: ring_xz - self.major_radius, z: 0.0 } - around.scale(p.y)).scale(2.0 * PI);
+
+        Some(Intersection {
+            n: n,
+            geometric_n: n,
+            front_face: n.dot(&ray.direction) < 0.0,
+            prim_id: 0,
+            t: t,
+            u: phi(&p) / (2.0 * PI),
+            v: 0.5 + p.y.atan2(ring_xz - self.major_radius) / (2.0 * PI),
+            position: ray.origin + ray.direction.scale(t),
+            dpdu: Mat4::mult_v(&self.transform.m, &dpdu),
+            dpdv: Mat4::mult_v(&self.transform.m, &dpdv),
+            material: &self.material
+        })
+    }
+
+    fn mut_transform(&mut self, transform: &Transform) {
+        self.transform = Transform::new(Mat4::mult_m(&transform.m, &self.transform.m));
+    }
+    fn area_lights(&self) -> Vec<AreaLight> {
+        let (r_major, r_minor) = (self.major_radius, self.minor_radius);
+        self.material.emitter().into_iter().map(|emission| {
+            let shape = EmitterShape::Surface {
+                // The same uvs as `intersects` reports
+                surface: Box::new(move |u, v| {
+                    let (phi, theta) = (u * 2.0 * PI, (v - 0.5) * 2.0 * PI);
+                    let n = Vec3 { x: theta.cos() * phi.cos(), y: theta.sin(), z: theta.cos() * phi.sin() };
+                    (Vec3 { x: r_major * phi.cos(), y: 0.0, z: r_major * phi.sin() } + n.scale(r_minor), n)
+                }),
+                columns: EMITTER_CELLS,
+                rows: EMITTER_CELLS
+            };
+            AreaLight::new(shape.transformed(&self.transform), emission)
+        }).collect()
+    }
+}
+
+#[test]
+fn it_intersects_the_outside_and_inside_of_the_ring() {
+    let torus = Torus::new(2.0, 0.5, Box::new(FlatMaterial { color: Vec3::one() }));
+
+    let ray = Ray::new(Vec3 { x: -5.0, y: 0.0, z: 0.0 }, Vec3 { x: 1.0, y: 0.0, z: 0.0 });
+    let hit = torus.intersects(&ray, 0.0, 20.0).unwrap();
+    assert!((hit.t - 2.5).abs() < 1e-9);
+    assert!((hit.n.x + 1.0).abs() < 1e-9);
+    assert!((hit.u - 0.5).abs() < 1e-9);
+
+    // Starting past the first wall, the next hit is the inner side of the tube
+    let hit = torus.intersects(&ray, 3.0, 20.0).unwrap();
+    assert!((hit.t - 3.5).abs() < 1e-9);
+    assert!((hit.n.x - 1.0).abs() < 1e-9);
+
+    // Straight down through the hole, and just over the top of the tube
+    let ray = Ray::new(Vec3 { x: 0.0, y: 5.0, z: 0.0 }, Vec3 { x: 0.0, y: -1.0, z: 0.0 });
+    assert!(torus.intersects(&ray, 0.0, 20.0).is_none());
+    let ray = Ray::new(Vec3 { x: -5.0, y: 0.51, z: 0.0 }, Vec3 { x: 1.0, y: 0.0, z: 0.0 });
+    assert!(torus.intersects(&ray, 0.0, 20.0).is_none());
+}
+
+#[test]
+fn it_bounds_a_rotated_torus_tightly() {
+    let mut torus = Torus::new(2.0, 0.5, Box::new(FlatMaterial { color: Vec3::one() }));
+    torus.mut_transform(&Transform::new(Mat4::rotate_x_deg_matrix(90.0)));
+    torus.mut_transform(&Transform::new(Mat4::translate_matrix(&Vec3 { x: 0.0, y: 0.0, z: 10.0 })));
+
+    let bbox = torus.partial_bounding_box().unwrap();
+    assert!((bbox.max.x - 2.5).abs() < 1e-9);
+    assert!((bbox.max.y - 2.5).abs() < 1e-9);
+    assert!((bbox.min.z - 9.5).abs() < 1e-9);
+
+    // Now standing up facing the ray, which goes through the hole
+    let ray = Ray::new(Vec3::zero(), Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    assert!(torus.intersects(&ray, 0.0, 20.0).is_none());
+    let ray = Ray::new(Vec3 { x: 2.0, y: 0.0, z: 0.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    let hit = torus.intersects(&ray, 0.0, 20.0).unwrap();
+    assert!((hit.t - 9.5).abs() < 1e-9);
+    assert!((hit.n.z + 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn it_lights_from_points_on_the_tube() {
+    use crate::light::Light;
+    use crate::material::materials::EmissiveMaterial;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    let torus = Torus::new(2.0, 0.5, Box::new(EmissiveMaterial::default()));
+    let lights = torus.area_lights();
+    assert_eq!(1, lights.len());
+    assert!(lights[0].center().len() < 1e-9);
+
+    let mut rng: Box<dyn rand::RngCore> = Box::new(StdRng::seed_from_u64(35));
+    for _ in 0..100 {
+        let p = lights[0].sample(&mut rng, &Vec3 { x: 0.0, y: 5.0, z: 0.0 }).position;
+        let ring_xz = (p.x * p.x + p.z * p.z).sqrt();
+        assert!((((ring_xz - 2.0).powi(2) + p.y * p.y).sqrt() - 0.5).abs() < 1e-9);
+    }
+}
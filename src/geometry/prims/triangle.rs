@@ -3,6 +3,7 @@
 use crate::prelude::*;
 use crate::geometry::bbox::{union_point, union_points, BBox, PartialBoundingBox};
 use crate::geometry::prim::Prim;
+use crate::light::lights::{AreaLight, EmitterShape, EmitterTriangle};
 use crate::material::Material;
 use crate::mat4::{Mat4, Transform};
 use crate::raytracer::{Ray, Intersection};
@@ -11,9 +12,10 @@ use crate::vec3::Vec3;
 use crate::material::materials::FlatMaterial;
 
 
-struct UvValue {
-    u: f64,
-    v: f64
+#[derive(Clone, Copy)]
+pub struct UvValue {
+    pub u: f64,
+    pub v: f64
 }
 
 impl UvValue {
@@ -21,7 +23,7 @@ impl UvValue {
         UvValue { u: uv.0, v: uv.1 }
     }
 
-    fn default3() -> [UvValue; 3] {
+    pub fn default3() -> [UvValue; 3] {
         [
             UvValue { u: 0.5, v: 1.0 },
             UvValue { u: 0.0, v: 0.0 },
@@ -30,6 +32,14 @@ impl UvValue {
     }
 }
 
+/// A triangle as an `AreaLight` picks points on it.
+pub fn emitter_triangle(vertices: [Vec3; 3], texinfo: [UvValue; 3]) -> EmitterTriangle {
+    EmitterTriangle {
+        vertices: vertices,
+        texinfo: [(texinfo[0].u, texinfo[0].v), (texinfo[1].u, texinfo[1].v), (texinfo[2].u, texinfo[2].v)]
+    }
+}
+
 pub struct TriangleOptions {
     vertices: [Vec3; 3],
     normals: Option<[Vec3; 3]>,
@@ -38,7 +48,7 @@ pub struct TriangleOptions {
 }
 
 fn get_auto_normals(v: [Vec3; 3]) -> [Vec3; 3] {
-    let n = (v[1] - v[0]).cross(&(v[2] - v[0]));
+    let n = (v[1] - v[0]).cross(&(v[2] - v[0])).unit();
     [n, n, n]
 }
 
@@ -100,60 +110,106 @@ pub struct Triangle {
     material: Box<dyn Material+Send+Sync>
 }
 
-impl PartialBoundingBox for Triangle {
-    fn partial_bounding_box(&self) -> Option<BBox> {
-        Some(union_point(&union_points(&self.vertices[0], &self.vertices[1]), &self.vertices[2]))
+impl Triangle {
+    pub fn vertices(&self) -> [Vec3; 3] {
+        self.vertices
     }
 }
 
-impl Prim for Triangle {
-    /// http://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
-    /// Barycentric coordinates.
-    fn intersects<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
-        let e1 = self.vertices[1] - self.vertices[0];
-        let e2 = self.vertices[2] - self.vertices[0];
-        let p = ray.direction.cross(&e2);
-        let det = e1.dot(&p);
-
-        // if determinant is near zero, ray lies in plane of triangle
-        if det > -::core::f64::EPSILON && det < ::core::f64::EPSILON {
-            return None
-        }
+/// http://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
+/// Returns t and the barycentric coordinates (beta, gamma) of the hit.
+/// Shared with `Mesh`, which stores its vertices elsewhere.
+pub fn intersect_barycentric(vertices: &[Vec3; 3], ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+    let e1 = vertices[1] - vertices[0];
+    let e2 = vertices[2] - vertices[0];
+    let p = ray.direction.cross(&e2);
+    let det = e1.dot(&p);
+
+    // if determinant is near zero, ray lies in plane of triangle
+    if det > -::core::f64::EPSILON && det < ::core::f64::EPSILON {
+        return None
+    }
 
-        let inv_det = 1.0 / det;
-        let s = ray.origin - self.vertices[0];
-        let beta = inv_det * s.dot(&p);
-        if beta < 0.0 || beta > 1.0 { return None }
+    let inv_det = 1.0 / det;
+    let s = ray.origin - vertices[0];
+    let beta = inv_det * s.dot(&p);
+    if beta < 0.0 || beta > 1.0 { return None }
 
-        let q = s.cross(&e1);
-        let gamma = inv_det * ray.direction.dot(&q);
-        if gamma < 0.0 || beta + gamma > 1.0 { return None }
+    let q = s.cross(&e1);
+    let gamma = inv_det * ray.direction.dot(&q);
+    if gamma < 0.0 || beta + gamma > 1.0 { return None }
 
-        let t = inv_det * e2.dot(&q);
+    let t = inv_det * e2.dot(&q);
 
-        if t < t_min || t > t_max {
-            None
-        } else {
-            let intersection_point = ray.origin + ray.direction.scale(t);
+    if t < t_min || t > t_max {
+        None
+    } else {
+        Some((t, beta, gamma))
+    }
+}
 
-            let alpha = 1.0 - beta - gamma;
+/// dP/du and dP/dv across a triangle from its UV layout. Where the UVs
+/// don't span an area, the first edge and the direction square to it in
+/// the triangle stand in for them.
+pub fn uv_derivatives(vertices: &[Vec3; 3], texinfo: &[UvValue; 3], n: &Vec3) -> (Vec3, Vec3) {
+    let (dp02, dp12) = (vertices[0] - vertices[2], vertices[1] - vertices[2]);
+    let (du02, dv02) = (texinfo[0].u - texinfo[2].u, texinfo[0].v - texinfo[2].v);
+    let (du12, dv12) = (texinfo[1].u - texinfo[2].u, texinfo[1].v - texinfo[2].v);
+    let det = du02 * dv12 - dv02 * du12;
+
+    if det.abs() > 1e-12 {
+        let dpdu = (dp02.scale(dv12) - dp12.scale(dv02)).scale(1.0 / det);
+        let dpdv = (dp12.scale(du02) - dp02.scale(du12)).scale(1.0 / det);
+        if dpdu.cross(&dpdv).len() > 0.0 {
+            return (dpdu, dpdv);
+        }
+    }
 
-            // Interpolate normals at vertices to get normal
-            let n = self.normals[0].scale(alpha) + self.normals[1].scale(beta) + self.normals[2].scale(gamma);
+    let edge = vertices[1] - vertices[0];
+    (edge, n.cross(&edge))
+}
 
-            // Interpolate UVs at vertices to get UV
-            let u = self.texinfo[0].u * alpha + self.texinfo[1].u * beta + self.texinfo[2].u * gamma;
-            let v = self.texinfo[0].v * alpha + self.texinfo[1].v * beta + self.texinfo[2].v * gamma;
+impl PartialBoundingBox for Triangle {
+    fn partial_bounding_box(&self) -> Option<BBox> {
+        Some(union_point(&union_points(&self.vertices[0], &self.vertices[1]), &self.vertices[2]))
+    }
+}
 
-            Some(Intersection {
-                n: n,
-                t: t,
-                u: u,
-                v: v,
-                position: intersection_point,
-                material: &self.material
-            })
-        }
+impl Prim for Triangle {
+    fn intersects<'a>(&'a self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection<'a>> {
+        let (t, beta, gamma) = intersect_barycentric(&self.vertices, ray, t_min, t_max)?;
+
+        let intersection_point = ray.origin + ray.direction.scale(t);
+
+        let alpha = 1.0 - beta - gamma;
+
+        // Interpolate normals at vertices to get normal
+        let n = self.normals[0].scale(alpha) + self.normals[1].scale(beta) + self.normals[2].scale(gamma);
+
+        // The winding decides which way the face points, unless the normals
+        // given with it disagree
+        let face_n = (self.vertices[1] - self.vertices[0]).cross(&(self.vertices[2] - self.vertices[0])).unit();
+        let geometric_n = if face_n.dot(&n) < 0.0 { -face_n } else { face_n };
+
+        // Interpolate UVs at vertices to get UV
+        let u = self.texinfo[0].u * alpha + self.texinfo[1].u * beta + self.texinfo[2].u * gamma;
+        let v = self.texinfo[0].v * alpha + self.texinfo[1].v * beta + self.texinfo[2].v * gamma;
+
+        let (dpdu, dpdv) = uv_derivatives(&self.vertices, &self.texinfo, &geometric_n);
+
+        Some(Intersection {
+            n: n,
+            geometric_n: geometric_n,
+            front_face: geometric_n.dot(&ray.direction) < 0.0,
+            prim_id: 0,
+            t: t,
+            u: u,
+            v: v,
+            position: intersection_point,
+            dpdu: dpdu,
+            dpdv: dpdv,
+            material: &self.material
+        })
     }
 
     fn mut_transform(&mut self, transform: &Transform) {
@@ -173,6 +229,13 @@ impl Prim for Triangle {
         self.normals[1] = n1_t;
         self.normals[2] = n2_t;
     }
+
+    fn area_lights(&self) -> Vec<AreaLight> {
+        self.material.emitter().into_iter().map(|emission| {
+            let shape = EmitterShape::Triangles(vec![emitter_triangle(self.vertices, self.texinfo)]);
+            AreaLight::new(shape, emission)
+        }).collect()
+    }
 }
 
 #[test]
@@ -236,3 +299,20 @@ fn it_intersects_only_in_tmin_tmax() {
     non_intersection = triangle.intersects(&intersecting_ray, 0.0, 0.0001);
     assert!(non_intersection.is_none());
 }
+
+#[test]
+fn it_takes_derivatives_from_the_uv_layout() {
+    let vertices = [Vec3::zero(), Vec3 { x: 2.0, y: 0.0, z: 0.0 }, Vec3 { x: 0.0, y: 4.0, z: 0.0 }];
+    let n = Vec3 { x: 0.0, y: 0.0, z: 1.0 };
+
+    // U runs down the triangle and V across it
+    let texinfo = [UvValue { u: 0.0, v: 0.0 }, UvValue { u: 0.0, v: 1.0 }, UvValue { u: 1.0, v: 0.0 }];
+    let (dpdu, dpdv) = uv_derivatives(&vertices, &texinfo, &n);
+    assert_eq!(Vec3 { x: 0.0, y: 4.0, z: 0.0 }, dpdu);
+    assert_eq!(Vec3 { x: 2.0, y: 0.0, z: 0.0 }, dpdv);
+
+    // All three UVs the same: fall back to the first edge
+    let (dpdu, dpdv) = uv_derivatives(&vertices, &[UvValue { u: 0.5, v: 0.5 }; 3], &n);
+    assert_eq!(Vec3 { x: 2.0, y: 0.0, z: 0.0 }, dpdu);
+    assert_eq!(Vec3 { x: 0.0, y: 2.0, z: 0.0 }, dpdv);
+}
@@ -0,0 +1,568 @@
+use std::collections::{HashMap, HashSet};
+use core::f64::consts::PI;
+use crate::geometry::prims::{MeshFace, MeshOptions};
+use crate::material::Material;
+use crate::vec3::Vec3;
+
+#[cfg(test)]
+use crate::material::materials::FlatMaterial;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SubdivisionScheme {
+    /// For triangle meshes. Other polygons are split into triangle fans first.
+    Loop,
+    /// For quad meshes, though any polygons work. Every face is a quad after
+    /// the first level.
+    CatmullClark
+}
+
+/// A polygon mesh to be refined at load time, before it reaches the
+/// accelerator. Edges with one face are boundaries and, like creased edges,
+/// stay sharp; edges shared by more than two faces are treated the same way.
+pub struct ControlMesh {
+    pub positions: Vec<Vec3>,
+    /// Vertex indices, counter-clockwise seen from outside
+    pub faces: Vec<Vec<u32>>,
+    /// For each face, the index of the face in the original mesh it was
+    /// split from, for looking up per-face materials
+    pub origins: Vec<usize>,
+    creases: HashSet<(u32, u32)>
+}
+
+fn edge_key(a: u32, b: u32) -> (u32, u32) {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+fn centroid(positions: &[Vec3], face: &[u32]) -> Vec3 {
+    face.iter().fold(Vec3::zero(), |sum, &v| sum + positions[v as usize]).scale(1.0 / face.len() as f64)
+}
+
+/// Area-weighted normal of a polygon, by Newell's method.
+fn face_normal(positions: &[Vec3], face: &[u32]) -> Vec3 {
+    (0..face.len()).fold(Vec3::zero(), |sum, i| {
+        let a = positions[face[i] as usize];
+        let b = positions[face[(i + 1) % face.len()] as usize];
+        sum + a.cross(&b)
+    }).scale(0.5)
+}
+
+struct Edge {
+    a: u32,
+    b: u32,
+    faces: Vec<usize>,
+    sharp: bool
+}
+
+enum VertexRule {
+    Smooth,
+    /// On a crease or boundary, between these two neighbours
+    Crease(u32, u32),
+    /// Where three or more sharp edges meet; it never moves
+    Corner
+}
+
+/// Adjacency of a `ControlMesh`, rebuilt for every level.
+struct Topology {
+    edges: Vec<Edge>,
+    edge_index: HashMap<(u32, u32), usize>,
+    /// Face to the left of each directed edge
+    half_edges: HashMap<(u32, u32), usize>,
+    vertex_edges: Vec<Vec<usize>>,
+    vertex_faces: Vec<Vec<usize>>
+}
+
+impl Topology {
+    fn new(mesh: &ControlMesh) -> Topology {
+        let mut topology = Topology {
+            edges: Vec::new(),
+            edge_index: HashMap::new(),
+            half_edges: HashMap::new(),
+            vertex_edges: vec![Vec::new(); mesh.positions.len()],
+            vertex_faces: vec![Vec::new(); mesh.positions.len()]
+        };
+
+        for (f, face) in mesh.faces.iter().enumerate() {
+            for k in 0..face.len() {
+                let (a, b) = (face[k], face[(k + 1) % face.len()]);
+                topology.half_edges.insert((a, b), f);
+                topology.vertex_faces[a as usize].push(f);
+
+                let edges = &mut topology.edges;
+                let vertex_edges = &mut topology.vertex_edges;
+                let index = *topology.edge_index.entry(edge_key(a, b)).or_insert_with(|| {
+                    edges.push(Edge { a: a, b: b, faces: Vec::new(), sharp: false });
+                    vertex_edges[a as usize].push(edges.len() - 1);
+                    vertex_edges[b as usize].push(edges.len() - 1);
+                    edges.len() - 1
+                });
+                edges[index].faces.push(f);
+            }
+        }
+
+        for edge in topology.edges.iter_mut() {
+            edge.sharp = edge.faces.len() != 2 || mesh.creases.contains(&edge_key(edge.a, edge.b));
+        }
+        topology
+    }
+
+    fn edge(&self, a: u32, b: u32) -> usize {
+        self.edge_index[&edge_key(a, b)]
+    }
+
+    fn is_sharp(&self, a: u32, b: u32) -> bool {
+        self.edge_index.get(&edge_key(a, b)).is_none_or(|&e| self.edges[e].sharp)
+    }
+
+    fn vertex_rule(&self, v: u32) -> VertexRule {
+        let sharp: Vec<&Edge> = self.vertex_edges[v as usize].iter()
+            .map(|&e| &self.edges[e])
+            .filter(|edge| edge.sharp)
+            .collect();
+
+        let other = |edge: &Edge| if edge.a == v { edge.b } else { edge.a };
+        match sharp.len() {
+            // A lone sharp edge (a dart) fades out into the smooth surface
+            0 | 1 => VertexRule::Smooth,
+            2 => VertexRule::Crease(other(sharp[0]), other(sharp[1])),
+            _ => VertexRule::Corner
+        }
+    }
+
+    /// The faces around `v` in order, each with the corner `v` is at,
+    /// starting from `start` and turning across the edge before `v` until
+    /// a sharp edge stops it or it comes back round. Walks the other way
+    /// if `backwards`.
+    fn fan(&self, faces: &[Vec<u32>], v: u32, start: usize, backwards: bool) -> (Vec<(usize, usize)>, bool) {
+        let mut fan = Vec::new();
+        let mut f = start;
+
+        loop {
+            let face = &faces[f];
+            let k = face.iter().position(|&w| w == v).unwrap();
+            fan.push((f, k));
+
+            let neighbour = if backwards { face[(k + 1) % face.len()] } else { face[(k + face.len() - 1) % face.len()] };
+            let across = if backwards { (neighbour, v) } else { (v, neighbour) };
+            if self.is_sharp(v, neighbour) {
+                return (fan, false);
+            }
+
+            f = match self.half_edges.get(&across) {
+                Some(&next) => next,
+                None => return (fan, false)
+            };
+            if f == start {
+                return (fan, true);
+            }
+            // Inconsistent winding can send the walk round forever
+            if fan.len() > self.vertex_faces[v as usize].len() {
+                return (fan, false);
+            }
+        }
+    }
+}
+
+impl ControlMesh {
+    pub fn new(positions: Vec<Vec3>, faces: Vec<Vec<u32>>) -> ControlMesh {
+        for face in faces.iter() {
+            assert!(face.len() >= 3, "a face needs at least 3 vertices, got {}", face.len());
+            assert!(face.iter().all(|&v| (v as usize) < positions.len()),
+                    "face refers to a vertex past the {} given", positions.len());
+        }
+
+        ControlMesh {
+            positions: positions,
+            origins: (0..faces.len()).collect(),
+            faces: faces,
+            creases: HashSet::new()
+        }
+    }
+
+    pub fn from_triangles(positions: Vec<Vec3>, triangles: &[[u32; 3]]) -> ControlMesh {
+        ControlMesh::new(positions, triangles.iter().map(|face| face.to_vec()).collect())
+    }
+
+    /// Keeps the edge between vertices `a` and `b` sharp.
+    #[allow(dead_code)]
+    pub fn crease(&mut self, a: u32, b: u32) -> &mut Self {
+        self.creases.insert(edge_key(a, b));
+        self
+    }
+
+    /// Creases every edge whose faces meet at more than `angle` degrees.
+    pub fn crease_sharper_than(&mut self, angle: f64) -> &mut Self {
+        let cos_crease = angle.to_radians().cos();
+        let topology = Topology::new(self);
+
+        for edge in topology.edges.iter().filter(|edge| edge.faces.len() == 2) {
+            let n0 = face_normal(&self.positions, &self.faces[edge.faces[0]]).unit();
+            let n1 = face_normal(&self.positions, &self.faces[edge.faces[1]]).unit();
+            if n0.dot(&n1) < cos_crease {
+                self.creases.insert(edge_key(edge.a, edge.b));
+            }
+        }
+        self
+    }
+
+    pub fn subdivide(&self, scheme: SubdivisionScheme, levels: u32) -> ControlMesh {
+        let mut mesh = ControlMesh {
+            positions: self.positions.clone(),
+            faces: self.faces.clone(),
+            origins: self.origins.clone(),
+            creases: self.creases.clone()
+        };
+
+        for _ in 0..levels {
+            mesh = match scheme {
+                SubdivisionScheme::Loop => mesh.triangulated().loop_step(),
+                SubdivisionScheme::CatmullClark => mesh.catmull_clark_step()
+            };
+        }
+        mesh
+    }
+
+    /// Splits every polygon into a fan of triangles around its first vertex.
+    pub fn triangulated(self) -> ControlMesh {
+        if self.faces.iter().all(|face| face.len() == 3) {
+            return self;
+        }
+
+        let mut faces = Vec::new();
+        let mut origins = Vec::new();
+        for (face, origin) in self.faces.iter().zip(self.origins.iter()) {
+            for i in 1..face.len() - 1 {
+                faces.push(vec![face[0], face[i], face[i + 1]]);
+                origins.push(*origin);
+            }
+        }
+
+        ControlMesh { positions: self.positions, faces: faces, origins: origins, creases: self.creases }
+    }
+
+    /// Both schemes use the cubic B-spline rule along creases.
+    fn vertex_point(&self, topology: &Topology, v: u32, smooth: &dyn Fn(u32) -> Vec3) -> Vec3 {
+        let p = self.positions[v as usize];
+        match topology.vertex_rule(v) {
+            VertexRule::Smooth if !topology.vertex_edges[v as usize].is_empty() => smooth(v),
+            VertexRule::Crease(a, b) => p.scale(0.75) + (self.positions[a as usize] + self.positions[b as usize]).scale(0.125),
+            _ => p
+        }
+    }
+
+    /// Creases carry over to both halves of each split edge, whose middle
+    /// vertex is `first_edge_point + edge index`.
+    fn split_creases(&self, topology: &Topology, first_edge_point: u32) -> HashSet<(u32, u32)> {
+        let mut creases = HashSet::new();
+        for &(a, b) in self.creases.iter() {
+            if let Some(&e) = topology.edge_index.get(&(a, b)) {
+                let mid = first_edge_point + e as u32;
+                creases.insert(edge_key(a, mid));
+                creases.insert(edge_key(mid, b));
+            }
+        }
+        creases
+    }
+
+    fn loop_step(&self) -> ControlMesh {
+        let topology = Topology::new(self);
+        let p = |v: u32| self.positions[v as usize];
+        let first_edge_point = self.positions.len() as u32;
+
+        let smooth = |v: u32| {
+            let neighbours = &topology.vertex_edges[v as usize];
+            let n = neighbours.len() as f64;
+            let beta = if neighbours.len() == 3 { 3.0 / 16.0 } else { 3.0 / (8.0 * n) };
+            let sum = neighbours.iter()
+                .map(|&e| { let edge = &topology.edges[e]; if edge.a == v { p(edge.b) } else { p(edge.a) } })
+                .fold(Vec3::zero(), |sum, q| sum + q);
+            p(v).scale(1.0 - n * beta) + sum.scale(beta)
+        };
+
+        let mut positions: Vec<Vec3> = (0..first_edge_point)
+            .map(|v| self.vertex_point(&topology, v, &smooth))
+            .collect();
+
+        for edge in topology.edges.iter() {
+            let mid = p(edge.a) + p(edge.b);
+            positions.push(if edge.sharp {
+                mid.scale(0.5)
+            } else {
+                let opposite = edge.faces.iter()
+                    .filter_map(|&f| self.faces[f].iter().cloned().find(|&w| w != edge.a && w != edge.b))
+                    .fold(Vec3::zero(), |sum, w| sum + p(w));
+                mid.scale(0.375) + opposite.scale(0.125)
+            });
+        }
+
+        let mut faces = Vec::with_capacity(self.faces.len() * 4);
+        let mut origins = Vec::with_capacity(self.faces.len() * 4);
+        for (face, origin) in self.faces.iter().zip(self.origins.iter()) {
+            let (a, b, c) = (face[0], face[1], face[2]);
+            let ab = first_edge_point + topology.edge(a, b) as u32;
+            let bc = first_edge_point + topology.edge(b, c) as u32;
+            let ca = first_edge_point + topology.edge(c, a) as u32;
+            faces.extend(vec![vec![a, ab, ca], vec![b, bc, ab], vec![c, ca, bc], vec![ab, bc, ca]]);
+            origins.extend(vec![*origin; 4]);
+        }
+
+        ControlMesh {
+            creases: self.split_creases(&topology, first_edge_point),
+            positions: positions,
+            faces: faces,
+            origins: origins
+        }
+    }
+
+    fn catmull_clark_step(&self) -> ControlMesh {
+        let topology = Topology::new(self);
+        let p = |v: u32| self.positions[v as usize];
+        let face_points: Vec<Vec3> = self.faces.iter().map(|face| centroid(&self.positions, face)).collect();
+        let first_edge_point = self.positions.len() as u32;
+        let first_face_point = first_edge_point + topology.edges.len() as u32;
+
+        let smooth = |v: u32| {
+            let faces = &topology.vertex_faces[v as usize];
+            let edges = &topology.vertex_edges[v as usize];
+            let n = edges.len() as f64;
+            let q = faces.iter().fold(Vec3::zero(), |sum, &f| sum + face_points[f]).scale(1.0 / faces.len() as f64);
+            let r = edges.iter()
+                .map(|&e| (p(topology.edges[e].a) + p(topology.edges[e].b)).scale(0.5))
+                .fold(Vec3::zero(), |sum, mid| sum + mid)
+                .scale(1.0 / n);
+            (q + r.scale(2.0) + p(v).scale(n - 3.0)).scale(1.0 / n)
+        };
+
+        let mut positions: Vec<Vec3> = (0..first_edge_point)
+            .map(|v| self.vertex_point(&topology, v, &smooth))
+            .collect();
+
+        for edge in topology.edges.iter() {
+            let mid = p(edge.a) + p(edge.b);
+            positions.push(if edge.sharp {
+                mid.scale(0.5)
+            } else {
+                (mid + face_points[edge.faces[0]] + face_points[edge.faces[1]]).scale(0.25)
+            });
+        }
+        positions.extend(face_points.iter().cloned());
+
+        let mut faces = Vec::new();
+        let mut origins = Vec::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            let len = face.len();
+            for k in 0..len {
+                let (prev, v, next) = (face[(k + len - 1) % len], face[k], face[(k + 1) % len]);
+                faces.push(vec![
+                    v,
+                    first_edge_point + topology.edge(v, next) as u32,
+                    first_face_point + f as u32,
+                    first_edge_point + topology.edge(prev, v) as u32
+                ]);
+                origins.push(self.origins[f]);
+            }
+        }
+
+        ControlMesh {
+            creases: self.split_creases(&topology, first_edge_point),
+            positions: positions,
+            faces: faces,
+            origins: origins
+        }
+    }
+
+    /// A unit normal for each face corner, in face order, from the tangents
+    /// of the limit surface at smooth vertices. Corners on creases and
+    /// boundaries average the faces on their side of the crease instead,
+    /// as do Catmull-Clark vertices touching anything but quads.
+    pub fn limit_normals(&self, scheme: SubdivisionScheme) -> Vec<Vec3> {
+        let topology = Topology::new(self);
+        let face_normals: Vec<Vec3> = self.faces.iter().map(|face| face_normal(&self.positions, face)).collect();
+        let mut vertex_normals: HashMap<u32, Option<Vec3>> = HashMap::new();
+        let mut normals = Vec::new();
+
+        for (f, face) in self.faces.iter().enumerate() {
+            for &v in face.iter() {
+                let limit = *vertex_normals.entry(v).or_insert_with(|| {
+                    match topology.vertex_rule(v) {
+                        VertexRule::Smooth => self.smooth_limit_normal(&topology, scheme, v, f),
+                        _ => None
+                    }
+                });
+
+                normals.push(limit.unwrap_or_else(|| {
+                    let (fan, closed) = topology.fan(&self.faces, v, f, false);
+                    let mut sector: Vec<usize> = fan.iter().map(|&(g, _)| g).collect();
+                    if !closed {
+                        sector.extend(topology.fan(&self.faces, v, f, true).0.iter().skip(1).map(|&(g, _)| g));
+                    }
+                    sector.iter().fold(Vec3::zero(), |sum, &g| sum + face_normals[g]).unit()
+                }));
+            }
+        }
+        normals
+    }
+
+    /// Uses the tangent masks of Loop (1987) and Halstead et al. (1993),
+    /// which need the whole ring of faces around `v`.
+    fn smooth_limit_normal(&self, topology: &Topology, scheme: SubdivisionScheme, v: u32, start: usize) -> Option<Vec3> {
+        let (fan, closed) = topology.fan(&self.faces, v, start, false);
+        if !closed || fan.len() != topology.vertex_faces[v as usize].len() {
+            return None;
+        }
+
+        let n = fan.len();
+        let p = |face: &Vec<u32>, k: usize| self.positions[face[k % face.len()] as usize];
+        let angle = |i: usize| 2.0 * PI * i as f64 / n as f64;
+        let (mut t1, mut t2) = (Vec3::zero(), Vec3::zero());
+
+        match scheme {
+            SubdivisionScheme::Loop => {
+                if fan.iter().any(|&(f, _)| self.faces[f].len() != 3) {
+                    return None;
+                }
+                for (i, &(f, k)) in fan.iter().enumerate() {
+                    let e = p(&self.faces[f], k + 1);
+                    t1 = t1 + e.scale(angle(i).cos());
+                    t2 = t2 + e.scale(angle(i).sin());
+                }
+            },
+            SubdivisionScheme::CatmullClark => {
+                if fan.iter().any(|&(f, _)| self.faces[f].len() != 4) {
+                    return None;
+                }
+                let a_n = 1.0 + angle(1).cos() + (PI / n as f64).cos() * (2.0 * (9.0 + angle(1).cos())).sqrt();
+                for (i, &(f, k)) in fan.iter().enumerate() {
+                    // Edge neighbour, then the diagonal between it and the next
+                    let e = p(&self.faces[f], k + 1);
+                    let d = p(&self.faces[f], k + 2);
+                    t1 = t1 + e.scale(a_n * angle(i).cos()) + d.scale(angle(i).cos() + angle(i + 1).cos());
+                    t2 = t2 + e.scale(a_n * angle(i).sin()) + d.scale(angle(i).sin() + angle(i + 1).sin());
+                }
+            }
+        }
+
+        let normal = t1.cross(&t2);
+        if normal.len() == 0.0 || !normal.len().is_finite() {
+            return None;
+        }
+
+        // The ring's direction depends on the walk, so face the same way as
+        // the faces do
+        let around = fan.iter().fold(Vec3::zero(), |sum, &(f, _)| sum + face_normal(&self.positions, &self.faces[f]));
+        Some(if normal.dot(&around) < 0.0 { -normal.unit() } else { normal.unit() })
+    }
+
+    /// Builds a mesh shaded with limit normals. Polygons are split into
+    /// triangle fans. `material` maps the original face each fan came from
+    /// to an index into `materials`.
+    pub fn into_mesh<F>(self, scheme: SubdivisionScheme, materials: Vec<Box<dyn Material+Send+Sync>>, material: F) -> MeshOptions
+            where F: Fn(usize) -> usize {
+        let normals = self.limit_normals(scheme);
+        let mut corner = 0;
+        let mut faces = Vec::new();
+        for (face, &origin) in self.faces.iter().zip(self.origins.iter()) {
+            for i in 1..face.len() - 1 {
+                let n = corner as u32;
+                faces.push(MeshFace {
+                    vertices: [face[0], face[i], face[i + 1]],
+                    normals: Some([n, n + i as u32, n + i as u32 + 1]),
+                    texinfo: None,
+                    material: material(origin) as u32
+                });
+            }
+            corner += face.len();
+        }
+
+        let mut meshopts = MeshOptions::new(self.positions);
+        meshopts.normals(normals);
+        meshopts.faces_with_materials(materials, faces);
+        meshopts
+    }
+}
+
+#[cfg(test)]
+fn cube() -> ControlMesh {
+    let positions = (0..8).map(|i| Vec3 {
+        x: if i & 1 == 0 { -1.0 } else { 1.0 },
+        y: if i & 2 == 0 { -1.0 } else { 1.0 },
+        z: if i & 4 == 0 { -1.0 } else { 1.0 }
+    }).collect();
+    ControlMesh::new(positions, vec![
+        vec![0, 2, 3, 1], vec![4, 5, 7, 6], vec![0, 1, 5, 4],
+        vec![2, 6, 7, 3], vec![0, 4, 6, 2], vec![1, 3, 7, 5]
+    ])
+}
+
+#[cfg(test)]
+fn octahedron() -> ControlMesh {
+    let axis = |x: f64, y: f64, z: f64| Vec3 { x: x, y: y, z: z };
+    let positions = vec![axis(1.0, 0.0, 0.0), axis(-1.0, 0.0, 0.0), axis(0.0, 1.0, 0.0),
+                         axis(0.0, -1.0, 0.0), axis(0.0, 0.0, 1.0), axis(0.0, 0.0, -1.0)];
+    ControlMesh::from_triangles(positions, &[
+        [0, 2, 4], [2, 1, 4], [1, 3, 4], [3, 0, 4],
+        [2, 0, 5], [1, 2, 5], [3, 1, 5], [0, 3, 5]
+    ])
+}
+
+#[test]
+fn it_rounds_a_cube_with_catmull_clark() {
+    let mesh = cube().subdivide(SubdivisionScheme::CatmullClark, 1);
+    assert_eq!(24, mesh.faces.len());
+    assert_eq!(26, mesh.positions.len());
+    assert_eq!(1, mesh.origins[4]);
+
+    // The corners are pulled in to (Q + 2R) / 3 with valence 3
+    assert!((mesh.positions[7].x - 5.0 / 9.0).abs() < 1e-12);
+
+    // Each limit normal points out of the middle of the shape
+    let mesh = mesh.subdivide(SubdivisionScheme::CatmullClark, 1);
+    let normals = mesh.limit_normals(SubdivisionScheme::CatmullClark);
+    let mut corner = 0;
+    for face in mesh.faces.iter() {
+        for &v in face.iter() {
+            assert!(normals[corner].dot(&mesh.positions[v as usize].unit()) > 0.9);
+            corner += 1;
+        }
+    }
+}
+
+#[test]
+fn it_keeps_creased_edges_sharp() {
+    let mut creased = cube();
+    creased.crease_sharper_than(45.0);
+    let mesh = creased.subdivide(SubdivisionScheme::CatmullClark, 2);
+
+    // Every vertex stays on the cube's surface
+    for p in mesh.positions.iter() {
+        let max = p.x.abs().max(p.y.abs()).max(p.z.abs());
+        assert!((max - 1.0).abs() < 1e-12);
+    }
+    // And each face keeps its own flat normal
+    let normals = mesh.limit_normals(SubdivisionScheme::CatmullClark);
+    assert_eq!(Vec3 { x: 0.0, y: 0.0, z: -1.0 }, normals[0]);
+
+    // A lone quad only has boundaries, so it stays flat
+    let quad = ControlMesh::new(cube().positions[..4].to_vec(), vec![vec![0, 2, 3, 1]]);
+    let mesh = quad.subdivide(SubdivisionScheme::CatmullClark, 2);
+    assert!(mesh.positions.iter().all(|p| p.z == -1.0));
+}
+
+#[test]
+fn it_smooths_triangles_with_loop() {
+    let mesh = octahedron().subdivide(SubdivisionScheme::Loop, 2);
+    assert_eq!(128, mesh.faces.len());
+    assert_eq!(66, mesh.positions.len());
+
+    // The original vertices shrink towards the middle but stay on their axes
+    assert!(mesh.positions[4].z < 1.0 && mesh.positions[4].z > 0.5);
+    assert!(mesh.positions[4].x.abs() < 1e-12);
+
+    // By symmetry, the normal at a tip points straight along its axis
+    let normals = mesh.limit_normals(SubdivisionScheme::Loop);
+    let corner = mesh.faces.iter().flat_map(|face| face.iter()).position(|&v| v == 4).unwrap();
+    assert!((normals[corner].z - 1.0).abs() < 1e-12);
+
+    let triangles = mesh.into_mesh(SubdivisionScheme::Loop, vec![Box::new(FlatMaterial { color: Vec3::one() })], |_| 0).build();
+    assert_eq!(128, triangles.len());
+}
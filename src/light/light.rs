@@ -1,8 +1,21 @@
 use crate::vec3::Vec3;
 
+/// A point on a light picked for one shadow sample, and the light it sends
+/// towards the point being shaded, in the same terms as `Light::color`.
+pub struct LightSample {
+    pub position: Vec3,
+    pub color: Vec3
+}
+
 pub trait Light {
     fn position(&self) -> Vec3;
     fn color(&self) -> Vec3;
     fn center(&self) -> Vec3;
     fn is_point(&self) -> bool;
+
+    /// Picks where on the light one shadow sample for a point at `from`
+    /// goes. Lights without a surface just send `color` from `position`.
+    fn sample(&self, _rng: &mut Box<dyn rand::RngCore>, _from: &Vec3) -> LightSample {
+        LightSample { position: self.position(), color: self.color() }
+    }
 }
@@ -0,0 +1,280 @@
+use crate::prelude::*;
+use crate::light::light::{Light, LightSample};
+use crate::mat4::{Mat4, Transform};
+use crate::material::materials::EmissiveMaterial;
+use crate::vec3::Vec3;
+use core::f64::consts::PI;
+use rand::Rng;
+
+/// A triangle of an emitter, with the uvs its emission is looked up at.
+#[derive(Clone, Copy)]
+pub struct EmitterTriangle {
+    pub vertices: [Vec3; 3],
+    pub texinfo: [(f64, f64); 3]
+}
+
+impl EmitterTriangle {
+    fn area(&self) -> f64 {
+        triangle_area(&self.vertices[0], &self.vertices[1], &self.vertices[2])
+    }
+}
+
+fn triangle_area(a: &Vec3, b: &Vec3, c: &Vec3) -> f64 {
+    (*b - *a).cross(&(*c - *a)).len() / 2.0
+}
+
+/// A point on a curved emitter and its normal, at uvs from 0 to 1.
+pub type EmitterSurface = dyn Fn(f64, f64) -> (Vec3, Vec3) + Send + Sync;
+
+/// The surface of a glowing prim, in a form points can be picked on.
+pub enum EmitterShape {
+    Sphere { center: Vec3, radius: f64 },
+    Triangles(Vec<EmitterTriangle>),
+    /// Points are picked on the surface itself, so they never sit inside
+    /// the prim. The grid of `columns` by `rows` cells over its uvs only
+    /// weighs how much of the surface each part covers.
+    Surface { surface: Box<EmitterSurface>, columns: usize, rows: usize }
+}
+
+impl EmitterShape {
+    /// Where the shape ends up under `transform`.
+    pub fn transformed(self, transform: &Transform) -> EmitterShape {
+        match self {
+            EmitterShape::Sphere { center, radius } => EmitterShape::Sphere {
+                center: Mat4::mult_p(&transform.m, &center),
+                radius: if transform.m.has_scale() { radius * transform.m.scale() } else { radius }
+            },
+            EmitterShape::Triangles(triangles) => EmitterShape::Triangles(triangles.into_iter().map(|triangle| {
+                let [a, b, c] = triangle.vertices;
+                EmitterTriangle {
+                    vertices: [Mat4::mult_p(&transform.m, &a), Mat4::mult_p(&transform.m, &b), Mat4::mult_p(&transform.m, &c)],
+                    texinfo: triangle.texinfo
+                }
+            }).collect()),
+            EmitterShape::Surface { surface, columns, rows } => {
+                let transform = Transform::new(transform.m);
+                EmitterShape::Surface {
+                    surface: Box::new(move |u, v| {
+                        let (position, n) = surface(u, v);
+                        (Mat4::mult_p(&transform.m, &position), transform.normal_to_world(&n))
+                    }),
+                    columns: columns,
+                    rows: rows
+                }
+            }
+        }
+    }
+}
+
+/// Area of one grid cell of a curved emitter, as two triangles across its
+/// corners.
+fn cell_area(surface: &EmitterSurface, columns: usize, rows: usize, index: usize) -> f64 {
+    let (column, row) = ((index % columns) as f64, (index / columns) as f64);
+    let corner = |du: f64, dv: f64| surface((column + du) / columns as f64, (row + dv) / rows as f64).0;
+    let (a, b, c, d) = (corner(0.0, 0.0), corner(1.0, 0.0), corner(1.0, 1.0), corner(0.0, 1.0));
+    triangle_area(&a, &b, &c) + triangle_area(&a, &c, &d)
+}
+
+/// Light given off by the surface of a prim with an `EmissiveMaterial`,
+/// lighting the scene from a different point on it for each shadow sample.
+pub struct AreaLight {
+    pub shape: EmitterShape,
+    pub emission: EmissiveMaterial,
+    // Running total of triangle areas, to pick one in proportion to its own
+    cumulative_areas: Vec<f64>
+}
+
+impl AreaLight {
+    pub fn new(shape: EmitterShape, emission: EmissiveMaterial) -> AreaLight {
+        let areas: Vec<f64> = match shape {
+            EmitterShape::Triangles(ref triangles) => triangles.iter().map(EmitterTriangle::area).collect(),
+            EmitterShape::Surface { ref surface, columns, rows } => {
+                (0..columns * rows).map(|index| cell_area(&**surface, columns, rows, index)).collect()
+            },
+            EmitterShape::Sphere { .. } => Vec::new()
+        };
+        let cumulative_areas = areas.iter().scan(0.0, |total, area| {
+            *total += area;
+            Some(*total)
+        }).collect();
+
+        AreaLight { shape: shape, emission: emission, cumulative_areas: cumulative_areas }
+    }
+
+    /// The same light, moved along with its prim by `transform`.
+    pub fn transformed(self, transform: &Transform) -> AreaLight {
+        AreaLight::new(self.shape.transformed(transform), self.emission)
+    }
+
+    /// Lights with the same key give off the same light from every point,
+    /// so `Scene::new` can join them into one. Only untextured triangle
+    /// lights have one.
+    pub fn merge_key(&self) -> Option<[u64; 4]> {
+        match (&self.shape, &self.emission.texture) {
+            (EmitterShape::Triangles(_), None) => {
+                let color = self.emission.color;
+                Some([color.x.to_bits(), color.y.to_bits(), color.z.to_bits(), self.emission.intensity.to_bits()])
+            },
+            _ => None
+        }
+    }
+
+    /// Takes on the triangles of `other`, which has the same `merge_key`.
+    pub fn merge(&mut self, other: AreaLight) {
+        if let (EmitterShape::Triangles(ref mut triangles), EmitterShape::Triangles(others)) = (&mut self.shape, other.shape) {
+            let mut total = self.cumulative_areas.last().cloned().unwrap_or(0.0);
+            for triangle in others {
+                total += triangle.area();
+                self.cumulative_areas.push(total);
+                triangles.push(triangle);
+            }
+        }
+    }
+
+    /// Picks a triangle or cell in proportion to its area, returning it and
+    /// `xi` stretched back out to cover 0 to 1 within it.
+    fn pick(&self, xi: f64) -> Option<(usize, f64)> {
+        let total = *self.cumulative_areas.last()?;
+        if total <= 0.0 { return None }
+
+        let pick = xi * total;
+        let index = self.cumulative_areas.iter().position(|&area| pick < area).unwrap_or(self.cumulative_areas.len() - 1);
+        let below = if index > 0 { self.cumulative_areas[index - 1] } else { 0.0 };
+        Some((index, ((pick - below) / (self.cumulative_areas[index] - below)).clamp(0.0, 1.0)))
+    }
+
+    /// A point on the surface that could be seen from `from`, its normal
+    /// and uvs, and the density per unit area it was picked with.
+    fn sample_surface(&self, xi: (f64, f64), from: &Vec3) -> Option<(Vec3, Vec3, (f64, f64), f64)> {
+        match self.shape {
+            EmitterShape::Sphere { center, radius } => {
+                // Uniform over the half facing `from`; the other can't be seen
+                let axis = (*from - center).unit();
+                let helper = if axis.x.abs() < 0.9 { Vec3 { x: 1.0, y: 0.0, z: 0.0 } } else { Vec3 { x: 0.0, y: 1.0, z: 0.0 } };
+                let t = axis.cross(&helper).unit();
+                let b = axis.cross(&t);
+
+                let cos_theta = xi.0;
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let phi = 2.0 * PI * xi.1;
+                let n = t.scale(sin_theta * phi.cos()) + b.scale(sin_theta * phi.sin()) + axis.scale(cos_theta);
+
+                // Matches the uvs `Sphere` reports
+                let uv = (0.5 + n.z.atan2(n.x) / (PI * 2.0), 0.5 - n.y.asin() / PI);
+                Some((center + n.scale(radius), n, uv, 1.0 / (2.0 * PI * radius * radius)))
+            },
+            EmitterShape::Triangles(ref triangles) => {
+                let (index, xi0) = self.pick(xi.0)?;
+                let total = *self.cumulative_areas.last()?;
+
+                // Uniform barycentrics
+                let s = xi0.sqrt();
+                let weights = [1.0 - s, s * (1.0 - xi.1), s * xi.1];
+                let triangle = &triangles[index];
+                let [a, b, c] = triangle.vertices;
+                let position = a.scale(weights[0]) + b.scale(weights[1]) + c.scale(weights[2]);
+                let uv = (0..3).fold((0.0, 0.0), |uv, i| {
+                    (uv.0 + triangle.texinfo[i].0 * weights[i], uv.1 + triangle.texinfo[i].1 * weights[i])
+                });
+                Some((position, (b - a).cross(&(c - a)).unit(), uv, 1.0 / total))
+            },
+            EmitterShape::Surface { ref surface, columns, rows } => {
+                // Uniform within the cell, which is close to uniform over
+                // the surface when the cells are small
+                let (index, xi0) = self.pick(xi.0)?;
+                let total = *self.cumulative_areas.last()?;
+                let u = ((index % columns) as f64 + xi0) / columns as f64;
+                let v = ((index / columns) as f64 + xi.1) / rows as f64;
+                let (position, n) = surface(u, v);
+                Some((position, n, (u, v), 1.0 / total))
+            }
+        }
+    }
+}
+
+impl Light for AreaLight {
+    fn position(&self) -> Vec3 {
+        self.center()
+    }
+
+    fn color(&self) -> Vec3 {
+        self.emission.color.scale(self.emission.intensity)
+    }
+
+    fn center(&self) -> Vec3 {
+        match self.shape {
+            EmitterShape::Sphere { center, .. } => center,
+            EmitterShape::Triangles(ref triangles) => {
+                let total = self.cumulative_areas.last().cloned().unwrap_or(0.0).max(f64::EPSILON);
+                triangles.iter().fold(Vec3::zero(), |acc, triangle| {
+                    let centroid = (triangle.vertices[0] + triangle.vertices[1] + triangle.vertices[2]).scale(1.0 / 3.0);
+                    acc + centroid.scale(triangle.area() / total)
+                })
+            },
+            EmitterShape::Surface { ref surface, columns, rows } => {
+                let total = self.cumulative_areas.last().cloned().unwrap_or(0.0).max(f64::EPSILON);
+                self.cumulative_areas.iter().enumerate().fold(Vec3::zero(), |acc, (index, &running)| {
+                    let area = running - if index > 0 { self.cumulative_areas[index - 1] } else { 0.0 };
+                    let u = ((index % columns) as f64 + 0.5) / columns as f64;
+                    let v = ((index / columns) as f64 + 0.5) / rows as f64;
+                    acc + surface(u, v).0.scale(area / total)
+                })
+            }
+        }
+    }
+
+    fn is_point(&self) -> bool {
+        false
+    }
+
+    fn sample(&self, rng: &mut Box<dyn rand::RngCore>, from: &Vec3) -> LightSample {
+        let (position, n, uv, pdf) = match self.sample_surface((rng.gen(), rng.gen()), from) {
+            Some(surface) => surface,
+            None => return LightSample { position: self.center(), color: Vec3::zero() }
+        };
+
+        // Radiance over the patch, turned into what a point light there
+        // would need to match it: a white matte surface facing a light of
+        // color c reflects c/PI, where this patch gives L·cos·dA/r² of it
+        let to_light = position - *from;
+        let distance2 = to_light.dot(&to_light);
+        if distance2 <= 0.0 {
+            return LightSample { position: position, color: Vec3::zero() };
+        }
+        let cos_light = n.dot(&to_light).abs() / distance2.sqrt();
+        let color = self.emission.radiance(uv.0, uv.1).scale(cos_light / (pdf * distance2 * PI));
+
+        LightSample { position: position, color: color }
+    }
+}
+
+#[test]
+fn it_lights_like_the_patch_it_covers() {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    // A small square of radiance 1 straight overhead at distance 10: the
+    // irradiance below is about L·A/r², so the matching color is that·PI
+    let square = |z: f64| [Vec3 { x: -0.5, y: -0.5, z: z }, Vec3 { x: 0.5, y: -0.5, z: z }, Vec3 { x: 0.5, y: 0.5, z: z }, Vec3 { x: -0.5, y: 0.5, z: z }];
+    let v = square(10.0);
+    let uvs = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)];
+    let light = AreaLight::new(EmitterShape::Triangles(vec![
+        EmitterTriangle { vertices: [v[0], v[1], v[2]], texinfo: uvs },
+        EmitterTriangle { vertices: [v[0], v[2], v[3]], texinfo: uvs }
+    ]), EmissiveMaterial::default());
+
+    let mut rng: Box<dyn rand::RngCore> = Box::new(StdRng::seed_from_u64(50));
+    let count = 2000;
+    let color = (0..count).fold(Vec3::zero(), |acc, _| {
+        acc + light.sample(&mut rng, &Vec3::zero()).color.scale(1.0 / count as f64)
+    });
+    assert!((color.x * PI - 0.01).abs() < 1e-4, "{:?}", color);
+    assert_eq!(Vec3 { x: 0.0, y: 0.0, z: 10.0 }, light.center());
+
+    // A sphere seen from afar is a disk of the same radius
+    let ball = AreaLight::new(EmitterShape::Sphere { center: Vec3 { x: 0.0, y: 0.0, z: 10.0 }, radius: 0.5 }, EmissiveMaterial::default());
+    let color = (0..count).fold(Vec3::zero(), |acc, _| {
+        acc + ball.sample(&mut rng, &Vec3::zero()).color.scale(1.0 / count as f64)
+    });
+    assert!((color.x * PI - PI * 0.25 / 100.0).abs() < 2e-3, "{:?}", color);
+}
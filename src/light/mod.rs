@@ -2,9 +2,11 @@ pub use self::light::Light;
 pub mod light;
 
 pub mod lights {
+    pub use self::arealight::{AreaLight, EmitterShape, EmitterTriangle};
     pub use self::pointlight::PointLight;
     pub use self::spherelight::SphereLight;
 
+    mod arealight;
     mod pointlight;
     mod spherelight;
 }
@@ -27,6 +27,8 @@ mod raytracer;
 mod scene;
 mod vec3;
 mod mat4;
+mod motion;
+mod poly;
 
 pub use raytracer::compositor::Surface;
 
@@ -40,6 +42,11 @@ struct SceneConfig {
     shadow_samples: u32,
     gloss_samples: u32,
     pixel_samples: u32,
+    shutter: (f64, f64), // Open and close times, spread over for motion blur
+    spectral: bool, // Sample wavelengths instead of RGB, for dispersion
+    heatmap: Option<raytracer::HeatmapMetric>, // Debug render of traversal cost instead of the scene
+    scene_file: Option<&'static str>, // .gltf/.glb to render instead of my_scene
+    cache_dir: Option<&'static str>, // Where built scene_file meshes are kept between runs
 }
 
 pub fn run(mut rng: Box<dyn rand::RngCore>) -> Surface {
@@ -51,18 +58,27 @@ pub fn run(mut rng: Box<dyn rand::RngCore>) -> Surface {
         shadow_samples: 16,
         gloss_samples: 8,
         pixel_samples: 2,
+        shutter: (0.0, 0.0),
+        spectral: false,
+        heatmap: None,
+        scene_file: None,
+        cache_dir: None,
     };
 
-    let scene_config = my_scene::get_scene();
+    let scene_config: Box<dyn my_scene::SceneConfig> = match config.scene_file {
+        Some(path) => Box::new(my_scene::gltf::GltfConfig::load(std::path::Path::new(path), config.cache_dir.map(std::path::Path::new)).expect("glTF load failure")),
+        None => my_scene::get_scene()
+    };
 
     let (image_width, image_height) = config.size;
     let fov = config.fov;
 
     let shared_scene = scene_config.get_scene();
 
-    let camera = 
+    let mut camera = 
         scene_config.get_camera(image_width, image_height, fov)
         ;
+    camera.set_shutter(config.shutter.0, config.shutter.1);
 
     let render_options = raytracer::RenderOptions {
         reflect_depth: config.reflect_depth,
@@ -70,12 +86,21 @@ pub fn run(mut rng: Box<dyn rand::RngCore>) -> Surface {
         shadow_samples: config.shadow_samples,
         gloss_samples: config.gloss_samples,
         pixel_samples: config.pixel_samples,
+        spectral: config.spectral,
     };
 
     let renderer = raytracer::Renderer {
         options: render_options,
     };
 
+    if let Some(metric) = config.heatmap {
+        eprintln!("octree stats:\n{}", shared_scene.octree.stats());
+
+        let (image_data, max_cost) = renderer.render_heatmap(camera, &shared_scene, metric);
+        eprintln!("heatmap legend: 0 (blue) to {} (red) per primary ray", max_cost);
+        return image_data;
+    }
+
     let image_data = renderer.render(camera, &mut rng, &shared_scene);
 
     image_data
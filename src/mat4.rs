@@ -1,6 +1,6 @@
 #![allow(dead_code)]
 
-use crate::geometry::bbox::BBox;
+use crate::geometry::bbox::{union_point, BBox};
 use crate::raytracer::Ray;
 use core::cmp;
 use core::f64;
@@ -29,6 +29,33 @@ impl Transform {
             inv: mat.inverse()
         }
     }
+
+    /// Takes a world-space ray into the space the transform was applied to.
+    /// The direction is left unnormalised so `t` means the same in both.
+    pub fn ray_to_object(&self, ray: &Ray) -> Ray {
+        Ray::new(Mat4::mult_p(&self.inv, &ray.origin), Mat4::mult_v(&self.inv, &ray.direction)).with_time(ray.time)
+    }
+
+    /// Object-space normal to a unit world-space normal, using the cached
+    /// inverse rather than inverting again like `Mat4::transform_normal`.
+    pub fn normal_to_world(&self, n: &Vec3) -> Vec3 {
+        let inv = &self.inv.m;
+        Vec3 {
+            x: inv[0][0] * n.x + inv[1][0] * n.y + inv[2][0] * n.z,
+            y: inv[0][1] * n.x + inv[1][1] * n.y + inv[2][1] * n.z,
+            z: inv[0][2] * n.x + inv[1][2] * n.y + inv[2][2] * n.z
+        }.unit()
+    }
+
+    /// World-space box enclosing all eight transformed corners of `bbox`.
+    pub fn bbox_to_world(&self, bbox: &BBox) -> BBox {
+        let mut world = BBox { min: Vec3::one().scale(f64::INFINITY), max: Vec3::one().scale(f64::NEG_INFINITY) };
+        for i in 0..8 {
+            let corner = bbox.lerp((i & 1) as f64, ((i >> 1) & 1) as f64, ((i >> 2) & 1) as f64);
+            world = union_point(&world, &Mat4::mult_p(&self.m, &corner));
+        }
+        world
+    }
 }
 
 fn are_equal_rel(a: f64, b: f64) -> bool {
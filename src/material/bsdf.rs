@@ -0,0 +1,636 @@
+use crate::prelude::*;
+use core::f64::consts::PI;
+use core::ops::BitOr;
+use crate::vec3::Vec3;
+
+/// Kinds of scattering, both to describe a lobe and as a mask of which lobes
+/// to sample. A lobe scatters either in reflection or transmission, and is
+/// one of diffuse, glossy or specular.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Lobe(u8);
+
+impl Lobe {
+    pub const REFLECTION: Lobe = Lobe(1);
+    pub const TRANSMISSION: Lobe = Lobe(2);
+    pub const DIFFUSE: Lobe = Lobe(4);
+    /// Highlights: scattering spread about the mirror direction that only
+    /// light sources are expected to show up in
+    pub const GLOSSY: Lobe = Lobe(8);
+    /// Mirror reflection or refraction of the scene, sharp or blurred
+    pub const SPECULAR: Lobe = Lobe(16);
+    /// Scatters into a single direction, so `eval` and `pdf` never see it
+    /// and only `sample` can find it
+    pub const DELTA: Lobe = Lobe(32);
+    #[allow(dead_code)]
+    pub const ALL: Lobe = Lobe(63);
+
+    pub fn contains(self, other: Lobe) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Whether a lobe of this kind is let through by `mask`. Masks needn't
+    /// mention `DELTA`.
+    fn matches(self, mask: Lobe) -> bool {
+        self.0 & !Lobe::DELTA.0 & !mask.0 == 0
+    }
+}
+
+impl BitOr for Lobe {
+    type Output = Lobe;
+
+    fn bitor(self, other: Lobe) -> Lobe {
+        Lobe(self.0 | other.0)
+    }
+}
+
+/// An orthonormal shading frame. BSDFs work in its local coordinates, where
+/// z is the outward shading normal and x the surface tangent, so a direction
+/// with negative z is inside the surface.
+#[derive(Clone, Copy)]
+pub struct Frame {
+    pub t: Vec3,
+    pub b: Vec3,
+    pub n: Vec3
+}
+
+impl Frame {
+    /// Frame about unit normal `n`, with its tangent as near to `tangent`
+    /// as is square to `n`.
+    pub fn new(n: Vec3, tangent: Vec3) -> Frame {
+        let in_plane = tangent - n.scale(n.dot(&tangent));
+        let t = if in_plane.len() > 1e-12 {
+            in_plane.unit()
+        } else {
+            let other = if n.x.abs() < 0.9 { Vec3 { x: 1.0, y: 0.0, z: 0.0 } } else { Vec3 { x: 0.0, y: 1.0, z: 0.0 } };
+            n.cross(&other).unit()
+        };
+
+        Frame { t: t, b: n.cross(&t), n: n }
+    }
+
+    pub fn to_local(self, w: Vec3) -> Vec3 {
+        Vec3 { x: w.dot(&self.t), y: w.dot(&self.b), z: w.dot(&self.n) }
+    }
+
+    pub fn to_world(self, w: Vec3) -> Vec3 {
+        self.t.scale(w.x) + self.b.scale(w.y) + self.n.scale(w.z)
+    }
+}
+
+/// A direction drawn from a BSDF.
+pub struct BsdfSample {
+    pub wi: Vec3,
+    /// BSDF × |cos θi| / pdf: what light arriving along `wi` is scaled by
+    pub weight: Vec3,
+    /// Solid angle density of `wi`, or for a `DELTA` lobe the probability
+    /// of having picked it
+    #[allow(dead_code)]
+    pub pdf: f64,
+    /// The lobe `wi` was drawn from
+    pub lobe: Lobe
+}
+
+/// One lobe of a BSDF, in local shading coordinates. Opaque lobes are two
+/// sided, reflecting on whichever side `wo` is.
+#[derive(Clone)]
+pub enum Bxdf {
+    /// Lambertian reflection of `albedo`
+    Lambert { albedo: Vec3 },
+    /// Energy normalized Blinn-Phong highlight
+    BlinnPhong { color: Vec3, exponent: f64 },
+    /// Cook-Torrance microfacet highlight, with a Beckmann distribution of
+    /// RMS slope `roughness` and Schlick's Fresnel for `ior`
+    Beckmann { color: Vec3, roughness: f64, ior: f64 },
+    /// Kajiya-Kay strand shading about the tangent
+    KajiyaKay { diffuse: Vec3, specular: Vec3, exponent: f64 },
+    /// Mirror reflection scaled by Schlick's Fresnel for `ior`. Glossiness
+    /// blurs it into a Phong lobe that falls to 1/e about that many
+    /// radians off the mirror direction.
+    Mirror { scale: f64, ior: f64, glossiness: f64 },
+    /// Refraction into or out of a medium of `ior`, tinted by `color` and
+    /// scaled by what Fresnel doesn't reflect. Under total internal
+    /// reflection it reflects it all.
+    Refraction { color: Vec3, ior: f64 },
+    /// GGX microfacet reflection of width `alpha`, with height-correlated
+    /// Smith shadowing and Schlick's Fresnel from reflectance `f0`. Energy
+    /// lost to single scattering off rough microfacets is put back.
+    Ggx { f0: Vec3, scale: f64, alpha: f64 },
+    /// Burley's sheen: a soft rim of `color` at grazing angles, for cloth
+    Sheen { color: Vec3 }
+}
+
+/// Schlick's approximation to how much is reflected leaving `wo` at cosine
+/// `cos_o` to a surface of `ior`, from inside it if `cos_o` is negative.
+/// http://graphics.stanford.edu/courses/cs148-10-summer/docs/2006--degreve--reflection_refraction.pdf
+pub fn fresnel(ior: f64, cos_o: f64) -> f64 {
+    let (n1, n2) = if cos_o < 0.0 { (ior, 1.0) } else { (1.0, ior) };
+    let cos_i = cos_o.abs();
+
+    let r0_sqrt = (n1 - n2) / (n1 + n2);
+    let r0 = r0_sqrt * r0_sqrt;
+
+    let cos_angle = if n1 <= n2 {
+        cos_i
+    } else {
+        let ratio = n1 / n2;
+        let sin2_t = ratio * ratio * (1.0 - cos_i * cos_i);
+        if sin2_t > 1.0 { return 1.0 } // n1 > n2 && TIR
+        (1.0 - sin2_t).sqrt()
+    };
+
+    (r0 + (1.0 - r0) * (1.0 - cos_angle).powi(5)).clamp(0.0, 1.0)
+}
+
+/// `wo` refracted through a surface of `ior` in local coordinates, or None
+/// under total internal reflection.
+fn refract(wo: &Vec3, ior: f64) -> Option<Vec3> {
+    Vec3::refract(wo, &Vec3 { x: 0.0, y: 0.0, z: 1.0 }, ior, wo.z < 0.0)
+}
+
+fn reflect(wo: &Vec3) -> Vec3 {
+    Vec3 { x: -wo.x, y: -wo.y, z: wo.z }
+}
+
+fn average(color: &Vec3) -> f64 {
+    (color.x + color.y + color.z) / 3.0
+}
+
+fn cosine_hemisphere(xi: (f64, f64)) -> Vec3 {
+    let r = xi.0.sqrt();
+    let phi = 2.0 * PI * xi.1;
+    Vec3 { x: r * phi.cos(), y: r * phi.sin(), z: (1.0 - xi.0).max(0.0).sqrt() }
+}
+
+/// Direction `cos_theta` off the z axis, `xi` turned about it.
+fn spherical(cos_theta: f64, xi: f64) -> Vec3 {
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * xi;
+    Vec3 { x: sin_theta * phi.cos(), y: sin_theta * phi.sin(), z: cos_theta }
+}
+
+fn glossy_exponent(glossiness: f64) -> f64 {
+    2.0 / (glossiness * glossiness)
+}
+
+/// Beckmann distribution of microfacet normals at cosine `cos_h`.
+fn beckmann(cos_h: f64, roughness: f64) -> f64 {
+    if cos_h <= 0.0 { return 0.0 }
+    let cos2 = cos_h * cos_h;
+    let tan2 = (1.0 - cos2) / cos2;
+    let m2 = roughness * roughness;
+    (-tan2 / m2).exp() / (PI * m2 * cos2 * cos2)
+}
+
+fn schlick(f0: &Vec3, cos: f64) -> Vec3 {
+    let c = (1.0 - cos.clamp(0.0, 1.0)).powi(5);
+    *f0 + (Vec3::one() - *f0).scale(c)
+}
+
+/// GGX distribution of microfacet normals at cosine `cos_h`.
+fn ggx(cos_h: f64, alpha: f64) -> f64 {
+    if cos_h <= 0.0 { return 0.0 }
+    let a2 = alpha * alpha;
+    let d = cos_h * cos_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d)
+}
+
+/// Smith's Λ for GGX: how much of the microsurface faces away from `w`.
+fn ggx_lambda(w: &Vec3, alpha: f64) -> f64 {
+    let cos2 = w.z * w.z;
+    if cos2 <= 0.0 { return f64::INFINITY }
+    let tan2 = (1.0 - cos2).max(0.0) / cos2;
+    ((1.0 + alpha * alpha * tan2).sqrt() - 1.0) / 2.0
+}
+
+/// Directional albedo of single-scattering GGX reflection with white
+/// Fresnel and height-correlated Smith shadowing, tabulated by numerical
+/// integration. Rows run over cos_o from 1/16 to 1, columns over
+/// perceptual roughness `sqrt(alpha)` from 0 to 1. What it falls short of
+/// one is energy that really scatters more than once between microfacets.
+static GGX_ALBEDO: [[f64; 16]; 16] = [
+    [1.0000, 0.9973, 0.9562, 0.8981, 0.8904, 0.9037, 0.9154, 0.9208, 0.9201, 0.9145, 0.9050, 0.8925, 0.8775, 0.8607, 0.8423, 0.8229],
+    [1.0000, 0.9997, 0.9879, 0.9469, 0.9009, 0.8806, 0.8776, 0.8777, 0.8740, 0.8649, 0.8503, 0.8312, 0.8083, 0.7825, 0.7546, 0.7254],
+    [1.0000, 0.9999, 0.9949, 0.9727, 0.9305, 0.8919, 0.8692, 0.8567, 0.8456, 0.8311, 0.8115, 0.7867, 0.7576, 0.7250, 0.6902, 0.6539],
+    [1.0000, 0.9999, 0.9973, 0.9842, 0.9525, 0.9113, 0.8761, 0.8507, 0.8299, 0.8084, 0.7830, 0.7527, 0.7181, 0.6799, 0.6394, 0.5976],
+    [1.0000, 1.0000, 0.9982, 0.9899, 0.9666, 0.9293, 0.8889, 0.8536, 0.8233, 0.7939, 0.7622, 0.7263, 0.6863, 0.6430, 0.5977, 0.5515],
+    [1.0000, 1.0000, 0.9989, 0.9931, 0.9755, 0.9435, 0.9027, 0.8613, 0.8229, 0.7858, 0.7473, 0.7056, 0.6604, 0.6123, 0.5627, 0.5128],
+    [1.0000, 1.0000, 0.9996, 0.9950, 0.9813, 0.9543, 0.9154, 0.8712, 0.8265, 0.7822, 0.7370, 0.6894, 0.6390, 0.5863, 0.5327, 0.4796],
+    [1.0000, 1.0000, 0.9997, 0.9961, 0.9851, 0.9623, 0.9264, 0.8815, 0.8325, 0.7821, 0.7305, 0.6769, 0.6212, 0.5641, 0.5067, 0.4507],
+    [1.0000, 1.0000, 0.9998, 0.9971, 0.9879, 0.9683, 0.9356, 0.8915, 0.8399, 0.7845, 0.7268, 0.6674, 0.6064, 0.5448, 0.4840, 0.4253],
+    [1.0000, 1.0000, 0.9999, 0.9976, 0.9899, 0.9729, 0.9432, 0.9007, 0.8478, 0.7885, 0.7255, 0.6603, 0.5941, 0.5281, 0.4638, 0.4028],
+    [1.0000, 1.0000, 0.9999, 0.9979, 0.9912, 0.9764, 0.9494, 0.9089, 0.8558, 0.7936, 0.7259, 0.6553, 0.5839, 0.5135, 0.4459, 0.3827],
+    [1.0000, 1.0000, 0.9999, 0.9982, 0.9924, 0.9792, 0.9546, 0.9162, 0.8636, 0.7995, 0.7277, 0.6520, 0.5755, 0.5007, 0.4298, 0.3645],
+    [1.0000, 1.0000, 1.0000, 0.9984, 0.9932, 0.9813, 0.9588, 0.9226, 0.8709, 0.8057, 0.7306, 0.6502, 0.5686, 0.4894, 0.4153, 0.3481],
+    [1.0000, 1.0000, 1.0000, 0.9988, 0.9938, 0.9831, 0.9622, 0.9281, 0.8778, 0.8120, 0.7342, 0.6494, 0.5630, 0.4795, 0.4022, 0.3331],
+    [1.0000, 1.0000, 1.0000, 0.9996, 0.9946, 0.9844, 0.9651, 0.9329, 0.8842, 0.8183, 0.7384, 0.6497, 0.5586, 0.4708, 0.3903, 0.3194],
+    [1.0000, 1.0000, 1.0000, 0.9998, 0.9953, 0.9858, 0.9676, 0.9371, 0.8900, 0.8245, 0.7430, 0.6507, 0.5552, 0.4631, 0.3794, 0.3069]
+];
+
+fn ggx_albedo(cos_o: f64, alpha: f64) -> f64 {
+    let x = (alpha.sqrt() * 15.0).clamp(0.0, 15.0);
+    let y = (cos_o * 16.0 - 1.0).clamp(0.0, 15.0);
+    let (i, j) = ((y as usize).min(14), (x as usize).min(14));
+    let (fy, fx) = (y - i as f64, x - j as f64);
+
+    let row = |i: usize| GGX_ALBEDO[i][j] * (1.0 - fx) + GGX_ALBEDO[i][j + 1] * fx;
+    (row(i) * (1.0 - fy) + row(i + 1) * fy).clamp(0.01, 1.0)
+}
+
+/// A microfacet normal drawn from those visible from `wo` (Heitz 2018).
+fn sample_ggx_visible(wo: &Vec3, alpha: f64, xi: (f64, f64)) -> Vec3 {
+    // Stretch to a hemisphere of unit roughness
+    let v = Vec3 { x: alpha * wo.x, y: alpha * wo.y, z: wo.z }.unit();
+    let len2 = v.x * v.x + v.y * v.y;
+    let t1 = if len2 > 0.0 { Vec3 { x: -v.y, y: v.x, z: 0.0 }.scale(1.0 / len2.sqrt()) } else { Vec3 { x: 1.0, y: 0.0, z: 0.0 } };
+    let t2 = v.cross(&t1);
+
+    // Uniform on a disk, squashed to the part of it v can see
+    let r = xi.0.sqrt();
+    let phi = 2.0 * PI * xi.1;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + v.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+    let n = t1.scale(p1) + t2.scale(p2) + v.scale((1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt());
+
+    Vec3 { x: alpha * n.x, y: alpha * n.y, z: n.z.max(0.0) }.unit()
+}
+
+/// `wo` and `wi` turned over together so `wo` is above the surface.
+fn upper(wo: &Vec3, wi: &Vec3) -> (Vec3, Vec3) {
+    if wo.z < 0.0 {
+        (Vec3 { z: -wo.z, ..*wo }, Vec3 { z: -wi.z, ..*wi })
+    } else {
+        (*wo, *wi)
+    }
+}
+
+impl Bxdf {
+    fn flags(&self) -> Lobe {
+        match *self {
+            Bxdf::Lambert { .. } | Bxdf::KajiyaKay { .. } | Bxdf::Sheen { .. } => Lobe::REFLECTION | Lobe::DIFFUSE,
+            Bxdf::BlinnPhong { .. } | Bxdf::Beckmann { .. } => Lobe::REFLECTION | Lobe::GLOSSY,
+            Bxdf::Mirror { glossiness, .. } if glossiness > 0.0 => Lobe::REFLECTION | Lobe::SPECULAR,
+            Bxdf::Ggx { .. } => Lobe::REFLECTION | Lobe::SPECULAR,
+            Bxdf::Mirror { .. } => Lobe::REFLECTION | Lobe::SPECULAR | Lobe::DELTA,
+            Bxdf::Refraction { .. } => Lobe::TRANSMISSION | Lobe::SPECULAR | Lobe::DELTA
+        }
+    }
+
+    fn is_delta(&self) -> bool {
+        self.flags().contains(Lobe::DELTA)
+    }
+
+    /// Roughly how much this lobe scatters from `wo`, to pick lobes by.
+    fn weight(&self, wo: &Vec3) -> f64 {
+        match *self {
+            Bxdf::Lambert { albedo } => average(&albedo),
+            Bxdf::BlinnPhong { color, .. } | Bxdf::Beckmann { color, .. } => average(&color),
+            Bxdf::KajiyaKay { diffuse, specular, .. } => average(&diffuse) + average(&specular),
+            Bxdf::Mirror { scale, ior, .. } => scale * fresnel(ior, wo.z),
+            Bxdf::Refraction { color, ior } => match refract(wo, ior) {
+                Some(_) => average(&color) * (1.0 - fresnel(ior, wo.z)),
+                None => average(&color)
+            },
+            Bxdf::Ggx { f0, scale, .. } => scale * average(&schlick(&f0, wo.z.abs())),
+            // Sheen only shows at grazing angles, so it's seldom worth a ray
+            Bxdf::Sheen { color } => 0.1 * average(&color)
+        }.max(0.0)
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        if wo.z * wi.z <= 0.0 || self.is_delta() {
+            return Vec3::zero();
+        }
+        let (wo, wi) = upper(wo, wi);
+
+        match *self {
+            Bxdf::Lambert { albedo } => albedo.scale(1.0 / PI),
+            Bxdf::BlinnPhong { color, exponent } => {
+                let h = (wo + wi).unit();
+                color.scale((exponent + 8.0) / (8.0 * PI) * h.z.powf(exponent))
+            },
+            Bxdf::Beckmann { color, roughness, ior } => {
+                let h = (wo + wi).unit();
+                let o_dot_h = wo.dot(&h);
+                let f = fresnel(ior, o_dot_h);
+                let d = beckmann(h.z, roughness);
+                let g = (2.0 * h.z * wo.z / o_dot_h).min(2.0 * h.z * wi.z / o_dot_h).min(1.0);
+                color.scale(f * d * g / (4.0 * wo.z * wi.z))
+            },
+            Bxdf::KajiyaKay { diffuse, specular, exponent } => {
+                let t_l = wi.x;
+                let t_i = wo.x;
+                let sin_l = (1.0 - t_l * t_l).max(0.0).sqrt();
+                let sin_i = (1.0 - t_i * t_i).max(0.0).sqrt();
+
+                // Brightest when the eye is on the cone of directions light
+                // glints off the strand into
+                let glint = (sin_l * sin_i - t_l * t_i).max(0.0).powf(exponent);
+                (diffuse.scale(sin_l) + specular.scale(glint)).scale(1.0 / PI)
+            },
+            Bxdf::Mirror { scale, ior, glossiness } => {
+                let e = glossy_exponent(glossiness);
+                let cos_a = reflect(&wo).dot(&wi).max(0.0);
+                Vec3::one().scale(scale * fresnel(ior, wo.z) * (e + 2.0) / (2.0 * PI) * cos_a.powf(e))
+            },
+            Bxdf::Refraction { .. } => Vec3::zero(),
+            Bxdf::Ggx { f0, scale, alpha } => {
+                let h = (wo + wi).unit();
+                let f = schlick(&f0, wo.dot(&h));
+                let g = 1.0 / (1.0 + ggx_lambda(&wo, alpha) + ggx_lambda(&wi, alpha));
+                let single = f.scale(scale * ggx(h.z, alpha) * g / (4.0 * wo.z * wi.z));
+
+                // Scale up by what multiple scattering adds back, more for
+                // brighter f0 (Fdez-Agüera 2019)
+                let albedo = ggx_albedo(wo.z, alpha);
+                single * (Vec3::one() + f0.scale(1.0 / albedo - 1.0))
+            },
+            Bxdf::Sheen { color } => {
+                let h = (wo + wi).unit();
+                color.scale((1.0 - wi.dot(&h)).max(0.0).powi(5))
+            }
+        }
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        if wo.z * wi.z <= 0.0 || self.is_delta() {
+            return 0.0;
+        }
+        let (wo, wi) = upper(wo, wi);
+
+        match *self {
+            Bxdf::Lambert { .. } | Bxdf::KajiyaKay { .. } | Bxdf::Sheen { .. } => wi.z / PI,
+            Bxdf::BlinnPhong { exponent, .. } => {
+                let h = (wo + wi).unit();
+                (exponent + 1.0) / (2.0 * PI) * h.z.powf(exponent) / (4.0 * wo.dot(&h))
+            },
+            Bxdf::Beckmann { roughness, .. } => {
+                let h = (wo + wi).unit();
+                beckmann(h.z, roughness) * h.z / (4.0 * wo.dot(&h))
+            },
+            Bxdf::Mirror { glossiness, .. } => {
+                let e = glossy_exponent(glossiness);
+                let cos_a = reflect(&wo).dot(&wi).max(0.0);
+                (e + 1.0) / (2.0 * PI) * cos_a.powf(e)
+            },
+            Bxdf::Refraction { .. } => 0.0,
+            Bxdf::Ggx { alpha, .. } => {
+                // Visible normals, reflected about
+                let h = (wo + wi).unit();
+                let g1 = 1.0 / (1.0 + ggx_lambda(&wo, alpha));
+                g1 * ggx(h.z, alpha) / (4.0 * wo.z)
+            }
+        }
+    }
+
+    /// A direction drawn from a non-delta lobe, roughly in proportion to it.
+    fn sample_direction(&self, wo: &Vec3, xi: (f64, f64)) -> Option<Vec3> {
+        let side = if wo.z < 0.0 { -1.0 } else { 1.0 };
+        let up = Vec3 { z: wo.z.abs(), ..*wo };
+
+        let wi = match *self {
+            Bxdf::Lambert { .. } | Bxdf::KajiyaKay { .. } | Bxdf::Sheen { .. } => cosine_hemisphere(xi),
+            Bxdf::BlinnPhong { exponent, .. } => {
+                let h = spherical(xi.0.powf(1.0 / (exponent + 1.0)), xi.1);
+                h.scale(2.0 * up.dot(&h)) - up
+            },
+            Bxdf::Beckmann { roughness, .. } => {
+                let tan2 = -roughness * roughness * (1.0 - xi.0).ln();
+                let h = spherical(1.0 / (1.0 + tan2).sqrt(), xi.1);
+                h.scale(2.0 * up.dot(&h)) - up
+            },
+            Bxdf::Mirror { glossiness, .. } => {
+                let e = glossy_exponent(glossiness);
+                let around = Frame::new(reflect(&up), Vec3 { x: 1.0, y: 0.0, z: 0.0 });
+                around.to_world(spherical(xi.0.powf(1.0 / (e + 1.0)), xi.1))
+            },
+            Bxdf::Ggx { alpha, .. } => {
+                let h = sample_ggx_visible(&up, alpha, xi);
+                h.scale(2.0 * up.dot(&h)) - up
+            },
+            Bxdf::Refraction { .. } => return None
+        };
+
+        if wi.z <= 0.0 {
+            None
+        } else {
+            Some(Vec3 { z: wi.z * side, ..wi })
+        }
+    }
+
+    /// The single direction a delta lobe scatters `wo` into, its weight and
+    /// which way it went.
+    fn sample_delta(&self, wo: &Vec3) -> Option<(Vec3, Vec3, Lobe)> {
+        match *self {
+            Bxdf::Mirror { scale, ior, .. } => {
+                Some((reflect(wo), Vec3::one().scale(scale * fresnel(ior, wo.z)), self.flags()))
+            },
+            Bxdf::Refraction { color, ior } => match refract(wo, ior) {
+                Some(wi) => Some((wi, color.scale(1.0 - fresnel(ior, wo.z)), self.flags())),
+                None => Some((reflect(wo), color, Lobe::REFLECTION | Lobe::SPECULAR | Lobe::DELTA))
+            },
+            _ => None
+        }
+    }
+}
+
+/// A material's BSDF at one point on a surface: a mix of lobes, evaluated
+/// and sampled in local shading coordinates (see `Frame`). Directions all
+/// point away from the surface.
+pub struct Bsdf {
+    lobes: Vec<Bxdf>,
+    coat: Option<Bxdf>
+}
+
+impl Bsdf {
+    pub fn new(lobes: Vec<Bxdf>) -> Bsdf {
+        Bsdf { lobes: lobes, coat: None }
+    }
+
+    /// `lobes` under a clear coat, which reflects off the top and dims
+    /// everything beneath by what it reflects.
+    pub fn coated(coat: Bxdf, lobes: Vec<Bxdf>) -> Bsdf {
+        Bsdf { lobes: lobes, coat: Some(coat) }
+    }
+
+    /// Whether any lobe is let through by `mask`.
+    pub fn has(&self, mask: Lobe) -> bool {
+        self.lobes.iter().chain(self.coat.iter()).any(|lobe| lobe.flags().matches(mask))
+    }
+
+    /// How much light arriving along `wi` scatters out along `wo`, leaving
+    /// out the cosine at `wi`. Delta lobes don't show up here.
+    pub fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        self.parts(&wo).iter().fold(Vec3::zero(), |acc, &(lobe, scale)| acc + lobe.eval(&wo, &wi).scale(scale))
+    }
+
+    /// Density with which `sample` draws `wi` for `wo` from the lobes
+    /// `mask` lets through.
+    pub fn pdf(&self, wo: Vec3, wi: Vec3, mask: Lobe) -> f64 {
+        let parts = self.parts(&wo);
+        let weights = Bsdf::weights(&parts, &wo, mask);
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 { return 0.0 }
+
+        parts.iter().zip(weights.iter())
+            .map(|(&(lobe, _), weight)| weight / total * lobe.pdf(&wo, &wi))
+            .sum()
+    }
+
+    /// Draws an incoming direction for `wo` from the lobes `mask` lets
+    /// through, choosing between lobes in proportion to how much they
+    /// scatter. `xi` is a pair of uniform numbers in [0, 1).
+    pub fn sample(&self, wo: Vec3, xi: (f64, f64), mask: Lobe) -> Option<BsdfSample> {
+        let parts = self.parts(&wo);
+        let weights = Bsdf::weights(&parts, &wo, mask);
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 { return None }
+
+        // Pick a lobe with xi.0, then stretch what's left of it back to [0, 1)
+        let mut pick = xi.0 * total;
+        let mut chosen = weights.len() - 1;
+        for (index, weight) in weights.iter().enumerate() {
+            if pick < *weight || index == weights.len() - 1 {
+                chosen = index;
+                break;
+            }
+            pick -= weight;
+        }
+        let (lobe, scale) = parts[chosen];
+        let chosen_weight = weights[chosen];
+        if chosen_weight <= 0.0 { return None }
+        let probability = chosen_weight / total;
+        let xi = ((pick / chosen_weight).min(1.0 - f64::EPSILON), xi.1);
+
+        if lobe.is_delta() {
+            let (wi, weight, flags) = lobe.sample_delta(&wo)?;
+            return Some(BsdfSample { wi: wi, weight: weight.scale(scale / probability), pdf: probability, lobe: flags });
+        }
+
+        // Every non-delta lobe in the mask could have drawn wi
+        let wi = lobe.sample_direction(&wo, xi)?;
+        let pdf = self.pdf(wo, wi, mask);
+        if pdf <= 0.0 { return None }
+        let f = parts.iter().zip(weights.iter())
+            .filter(|&(_, weight)| *weight > 0.0)
+            .fold(Vec3::zero(), |f, (&(other, scale), _)| f + other.eval(&wo, &wi).scale(scale));
+
+        Some(BsdfSample { wi: wi, weight: f.scale(wi.z.abs() / pdf), pdf: pdf, lobe: lobe.flags() })
+    }
+
+    /// Each lobe with what's left of it seen from `wo`, the coat last.
+    fn parts(&self, wo: &Vec3) -> Vec<(&Bxdf, f64)> {
+        let under_coat = match self.coat {
+            Some(ref coat) => 1.0 - coat.weight(wo).min(1.0),
+            None => 1.0
+        };
+
+        self.lobes.iter().map(|lobe| (lobe, under_coat))
+            .chain(self.coat.iter().map(|coat| (coat, 1.0)))
+            .collect()
+    }
+
+    fn weights(parts: &[(&Bxdf, f64)], wo: &Vec3, mask: Lobe) -> Vec<f64> {
+        parts.iter()
+            .map(|&(lobe, scale)| if lobe.flags().matches(mask) { lobe.weight(wo) * scale } else { 0.0 })
+            .collect()
+    }
+}
+
+#[test]
+fn it_refracts_by_snells_law() {
+    let wo = Vec3 { x: 0.6, y: 0.0, z: 0.8 };
+
+    // Bent towards the normal going in, sines in the ratio of the indices
+    let entering = refract(&wo, 1.5).unwrap();
+    assert!(entering.z < 0.0 && (entering.len() - 1.0).abs() < 1e-12);
+    assert!((entering.x + wo.x / 1.5).abs() < 1e-12);
+
+    // Seen from inside along the refracted ray, the way back out is wo
+    let out = refract(&entering, 1.5).unwrap();
+    assert!((out - wo).len() < 1e-12);
+
+    // Glancing from inside glass: totally reflected
+    let glancing = Vec3 { x: 0.9, y: 0.0, z: -(0.19f64).sqrt() };
+    assert!(refract(&glancing, 1.5).is_none());
+    assert_eq!(1.0, fresnel(1.5, glancing.z));
+    assert!((fresnel(1.5, 1.0) - 0.04).abs() < 1e-12);
+}
+
+#[test]
+fn it_samples_in_proportion_to_eval() {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    let bsdf = Bsdf::new(vec![
+        Bxdf::Lambert { albedo: Vec3 { x: 0.3, y: 0.2, z: 0.1 } },
+        Bxdf::BlinnPhong { color: Vec3::one().scale(0.2), exponent: 20.0 },
+        Bxdf::Beckmann { color: Vec3::one().scale(0.2), roughness: 0.3, ior: 1.5 },
+        Bxdf::Mirror { scale: 0.5, ior: 1.5, glossiness: 0.2 },
+        Bxdf::Ggx { f0: Vec3 { x: 0.2, y: 0.1, z: 0.05 }, scale: 1.0, alpha: 0.4 },
+        Bxdf::Sheen { color: Vec3::one().scale(0.2) }
+    ]);
+    let wo = Vec3 { x: 0.3, y: -0.2, z: 0.9 }.unit();
+    let mut rng = StdRng::seed_from_u64(46);
+
+    // Each sample's weight is what eval and pdf say it should be, and the
+    // average weight estimates the albedo, which can't exceed one
+    let count = 20000;
+    let mut albedo = Vec3::zero();
+    for _ in 0..count {
+        if let Some(sample) = bsdf.sample(wo, (rng.gen(), rng.gen()), Lobe::ALL) {
+            assert_eq!(bsdf.pdf(wo, sample.wi, Lobe::ALL), sample.pdf);
+            let expected = bsdf.eval(wo, sample.wi).scale(sample.wi.z / sample.pdf);
+            assert!((sample.weight - expected).len() < 1e-9 * expected.len().max(1.0));
+            albedo = albedo + sample.weight.scale(1.0 / count as f64);
+        }
+    }
+    assert!(albedo.x > 0.3 && albedo.x < 1.0 && albedo.z < albedo.x);
+
+    // Seen from inside, an opaque surface reflects the same way
+    let below = Vec3 { z: -wo.z, ..wo };
+    let wi = Vec3 { x: -0.1, y: 0.4, z: 0.8 }.unit();
+    assert_eq!(bsdf.eval(wo, wi), bsdf.eval(below, Vec3 { z: -wi.z, ..wi }));
+    assert_eq!(Vec3::zero(), bsdf.eval(wo, Vec3 { z: -wi.z, ..wi }));
+}
+
+#[test]
+fn it_splits_glass_between_reflection_and_refraction() {
+    let bsdf = Bsdf::new(vec![
+        Bxdf::Mirror { scale: 1.0, ior: 1.5, glossiness: 0.0 },
+        Bxdf::Refraction { color: Vec3::one(), ior: 1.5 }
+    ]);
+    let wo = Vec3 { x: 0.0, y: 0.6, z: 0.8 };
+    let reflected = fresnel(1.5, wo.z);
+
+    // Masks pick out each delta lobe whole
+    let mirror = bsdf.sample(wo, (0.5, 0.5), Lobe::REFLECTION | Lobe::SPECULAR).unwrap();
+    assert_eq!(Vec3 { x: 0.0, y: -0.6, z: 0.8 }, mirror.wi);
+    assert!((mirror.weight.x - reflected).abs() < 1e-12 && mirror.pdf == 1.0);
+
+    let refracted = bsdf.sample(wo, (0.5, 0.5), Lobe::TRANSMISSION | Lobe::SPECULAR).unwrap();
+    assert!(refracted.lobe.contains(Lobe::TRANSMISSION | Lobe::DELTA) && refracted.wi.z < 0.0);
+    assert!((refracted.weight.x - (1.0 - reflected)).abs() < 1e-12);
+
+    // Together they're picked in proportion, each weighted back up by its odds
+    let either = bsdf.sample(wo, (0.01, 0.5), Lobe::ALL).unwrap();
+    assert!((either.pdf - reflected).abs() < 1e-12 && (either.weight.x - 1.0).abs() < 1e-12);
+    assert_eq!(Vec3::zero(), bsdf.eval(wo, mirror.wi));
+    assert!(!bsdf.has(Lobe::DIFFUSE | Lobe::REFLECTION));
+}
+
+#[test]
+fn it_dims_what_lies_under_a_coat() {
+    let coat = Bxdf::Ggx { f0: Vec3::one().scale(0.04), scale: 1.0, alpha: 0.05 };
+    let bsdf = Bsdf::coated(coat, vec![Bxdf::Lambert { albedo: Vec3::one() }]);
+    let wo = Vec3 { x: 0.0, y: 0.0, z: 1.0 };
+
+    // Well off the mirror direction only the base shows, less what the
+    // coat reflects head on
+    let wi = Vec3 { x: 0.8, y: 0.0, z: 0.6 };
+    assert!((bsdf.eval(wo, wi).x * PI - 0.96).abs() < 0.01);
+
+    // Towards the mirror direction the coat shines through
+    assert!(bsdf.eval(wo, wo).x > 1.0);
+    assert!(bsdf.has(Lobe::REFLECTION | Lobe::SPECULAR));
+}
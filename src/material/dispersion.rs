@@ -0,0 +1,64 @@
+/// How a dielectric's index of refraction varies with wavelength, which
+/// spreads white light into a rainbow as it refracts. Coefficients are
+/// for wavelengths in micrometres, as they're usually quoted.
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
+pub enum Dispersion {
+    /// n = a + b / λ²
+    Cauchy { a: f64, b: f64 },
+    /// n² = 1 + Σ bᵢ λ² / (λ² - cᵢ)
+    Sellmeier { b: [f64; 3], c: [f64; 3] }
+}
+
+/// Fraunhofer lines the Abbe number is defined over, in nm.
+static D_LINE: f64 = 587.6;
+static F_LINE: f64 = 486.1;
+static C_LINE: f64 = 656.3;
+
+#[allow(dead_code)]
+impl Dispersion {
+    /// Schott N-BK7, a common crown glass.
+    pub fn crown_glass() -> Dispersion {
+        Dispersion::Sellmeier {
+            b: [1.03961212, 0.231792344, 1.01046945],
+            c: [0.00600069867, 0.0200179144, 103.560653]
+        }
+    }
+
+    pub fn diamond() -> Dispersion {
+        Dispersion::Sellmeier { b: [4.3356, 0.3306, 0.0], c: [0.011236, 0.030625, 0.0] }
+    }
+
+    /// A Cauchy fit through index `ior` at the yellow d line, spreading
+    /// as much as an Abbe number of `abbe` says: the smaller, the more.
+    pub fn from_abbe(ior: f64, abbe: f64) -> Dispersion {
+        let inverse_square = |nm: f64| 1e6 / (nm * nm);
+        let b = (ior - 1.0) / (abbe * (inverse_square(F_LINE) - inverse_square(C_LINE)));
+        Dispersion::Cauchy { a: ior - b * inverse_square(D_LINE), b: b }
+    }
+
+    /// Index of refraction at `wavelength` nm.
+    pub fn ior(&self, wavelength: f64) -> f64 {
+        let um2 = (wavelength / 1000.0).powi(2);
+        match *self {
+            Dispersion::Cauchy { a, b } => a + b / um2,
+            Dispersion::Sellmeier { b, c } => {
+                (1.0 + (0..3).fold(0.0, |acc, i| acc + b[i] * um2 / (um2 - c[i]))).sqrt()
+            }
+        }
+    }
+}
+
+#[test]
+fn it_bends_blue_more_than_red() {
+    // N-BK7 is 1.5168 at the d line and has an Abbe number of 64.17
+    let glass = Dispersion::crown_glass();
+    assert!((glass.ior(D_LINE) - 1.5168).abs() < 1e-4);
+    let abbe = (glass.ior(D_LINE) - 1.0) / (glass.ior(F_LINE) - glass.ior(C_LINE));
+    assert!((abbe - 64.17).abs() < 0.1);
+
+    let fit = Dispersion::from_abbe(1.5168, 64.17);
+    assert!((fit.ior(D_LINE) - 1.5168).abs() < 1e-9);
+    assert!((fit.ior(F_LINE) - glass.ior(F_LINE)).abs() < 1e-3);
+    assert!(Dispersion::diamond().ior(450.0) > Dispersion::diamond().ior(650.0));
+}
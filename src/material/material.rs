@@ -1,14 +1,65 @@
+use crate::material::Bsdf;
+use crate::material::materials::EmissiveMaterial;
 use crate::vec3::Vec3;
 
-/// TODO: Move specular/transmissive properties into traits
+/// How a surface scatters, lets through and gives off light. Scattering
+/// is described by a `Bsdf`, in the local shading frame of `Frame`.
 pub trait Material {
-    fn sample(&self, n: Vec3, i: Vec3, l: Vec3, u: f64, v: f64) -> Vec3;
-    fn is_reflective(&self) -> bool;
-    fn is_refractive(&self) -> bool;
-    fn global_specular(&self, color: &Vec3) -> Vec3;
-    fn global_transmissive(&self, color: &Vec3) -> Vec3;
+    /// The BSDF at surface coordinates `u`, `v`.
+    fn bsdf(&self, u: f64, v: f64) -> Bsdf;
+
+    /// The BSDF for light of `wavelength` nm, in spectral renders. Only
+    /// materials whose index of refraction varies with it need this.
+    fn spectral_bsdf(&self, u: f64, v: f64, _wavelength: f64) -> Bsdf {
+        self.bsdf(u, v)
+    }
+
+    /// Color shadow rays passing through the surface are filtered by.
     fn transmission(&self) -> Vec3;
-    fn ior(&self) -> f64;
-    fn is_glossy(&self) -> bool;
-    fn glossiness(&self) -> f64;
+
+    /// Absorption coefficient per unit length of the medium behind the
+    /// surface, for light refracted into it. Clear by default.
+    fn absorption(&self) -> Vec3 {
+        Vec3::zero()
+    }
+
+    /// Radiance the surface gives off at `u`, `v`. Most don't glow.
+    fn emission(&self, _u: f64, _v: f64) -> Vec3 {
+        Vec3::zero()
+    }
+
+    /// What an area light made of this material gives off, if it glows.
+    fn emitter(&self) -> Option<EmissiveMaterial> {
+        None
+    }
+}
+
+/// How much light is left after travelling `distance` through a medium
+/// with absorption coefficient `absorption` (Beer–Lambert).
+pub fn attenuation(absorption: Vec3, distance: f64) -> Vec3 {
+    Vec3 {
+        x: (-absorption.x * distance).exp(),
+        y: (-absorption.y * distance).exp(),
+        z: (-absorption.z * distance).exp()
+    }
+}
+
+/// The absorption coefficient that tints white light to `color` after
+/// `distance`, which is an easier way to pick how colored glass looks.
+pub fn absorption_at_distance(color: Vec3, distance: f64) -> Vec3 {
+    let absorb = |c: f64| -c.clamp(1e-6, 1.0).ln() / distance;
+    Vec3 { x: absorb(color.x), y: absorb(color.y), z: absorb(color.z) }
+}
+
+#[test]
+fn it_attenuates_exponentially_with_distance() {
+    let color = Vec3 { x: 0.8, y: 0.5, z: 1.0 };
+    let absorption = absorption_at_distance(color, 2.0);
+
+    let at_distance = attenuation(absorption, 2.0);
+    assert!((at_distance - color).len() < 1e-9);
+    // Twice as thick, twice the tint
+    let twice = attenuation(absorption, 4.0);
+    assert!((twice - color * color).len() < 1e-9);
+    assert_eq!(Vec3::one(), attenuation(absorption, 0.0));
 }
@@ -0,0 +1,52 @@
+use crate::prelude::*;
+use crate::material::{Bsdf, Material, Texture};
+use crate::raytracer::compositor::ColorRGBA;
+use crate::vec3::Vec3;
+
+/// A surface that glows with `color` times `intensity`, tinted by its
+/// texture if it has one, and reflects nothing. `Scene::new` turns the
+/// prims made of it that can be sampled into area lights; the rest still
+/// glow where rays see them, but light nothing.
+#[allow(dead_code)]
+#[derive(Clone)]
+pub struct EmissiveMaterial {
+    pub color: Vec3,
+    pub intensity: f64,
+    pub texture: Option<Box<dyn Texture+Send+Sync>>
+}
+
+impl EmissiveMaterial {
+    /// Radiance given off at surface coordinates `u`, `v`.
+    pub fn radiance(&self, u: f64, v: f64) -> Vec3 {
+        let texture = match self.texture {
+            Some(ref x) => x.color(u, v),
+            None => ColorRGBA::white()
+        }.to_vec3();
+
+        self.color.scale(self.intensity) * texture
+    }
+}
+
+impl Material for EmissiveMaterial {
+    fn bsdf(&self, _u: f64, _v: f64) -> Bsdf {
+        Bsdf::new(vec![])
+    }
+
+    fn transmission(&self) -> Vec3 {
+        Vec3::zero()
+    }
+
+    fn emission(&self, u: f64, v: f64) -> Vec3 {
+        self.radiance(u, v)
+    }
+
+    fn emitter(&self) -> Option<EmissiveMaterial> {
+        Some(self.clone())
+    }
+}
+
+impl Default for EmissiveMaterial {
+    fn default() -> EmissiveMaterial {
+        EmissiveMaterial { color: Vec3::one(), intensity: 1.0, texture: None }
+    }
+}
@@ -1,6 +1,8 @@
-use crate::material::Material;
+use crate::prelude::*;
+use crate::material::{Bsdf, Bxdf, Material};
 use crate::vec3::Vec3;
 
+/// A plain matte surface of one color.
 #[allow(dead_code)]
 #[derive(Clone)]
 pub struct FlatMaterial {
@@ -8,41 +10,13 @@ pub struct FlatMaterial {
 }
 
 impl Material for FlatMaterial {
-    fn sample(&self, _n: Vec3, _i: Vec3, _l: Vec3, _u: f64, _v: f64) -> Vec3 {
-        self.color
-    }
-
-    fn is_reflective(&self) -> bool {
-        false
-    }
-
-    fn is_refractive(&self) -> bool {
-        false
-    }
-
-    fn global_specular(&self, _color: &Vec3) -> Vec3 {
-        Vec3::zero()
-    }
-
-    fn global_transmissive(&self, _color: &Vec3) -> Vec3 {
-        Vec3::zero()
+    fn bsdf(&self, _u: f64, _v: f64) -> Bsdf {
+        Bsdf::new(vec![Bxdf::Lambert { albedo: self.color }])
     }
 
     fn transmission(&self) -> Vec3 {
         Vec3::zero()
     }
-
-    fn ior(&self) -> f64 {
-        1.0
-    }
-
-    fn is_glossy(&self) -> bool {
-        false
-    }
-
-    fn glossiness(&self) -> f64 {
-        0.0
-    }
 }
 
 impl Default for FlatMaterial {
@@ -0,0 +1,59 @@
+use crate::prelude::*;
+use crate::material::{Bsdf, Bxdf, Material};
+use crate::vec3::Vec3;
+
+/// Kajiya-Kay hair shading. A strand is lit like a thin cylinder, so light
+/// depends on the angle to the strand's tangent rather than to its normal,
+/// which `Curves` run along their `u` and the shading frame takes as its x
+/// axis.
+#[allow(dead_code)]
+#[derive(Clone)]
+pub struct HairMaterial {
+    pub k_d: f64,           // Diffuse coefficient
+    pub k_s: f64,           // Specular coefficient
+    pub diffuse: Vec3,      // Diffuse color
+    pub specular: Vec3,     // Specular color
+    pub shininess: f64      // Tightness of the highlight along the strand
+}
+
+impl Material for HairMaterial {
+    fn bsdf(&self, _u: f64, _v: f64) -> Bsdf {
+        Bsdf::new(vec![Bxdf::KajiyaKay {
+            diffuse: self.diffuse.scale(self.k_d),
+            specular: self.specular.scale(self.k_s),
+            exponent: self.shininess
+        }])
+    }
+
+    fn transmission(&self) -> Vec3 {
+        Vec3::zero()
+    }
+}
+
+impl Default for HairMaterial {
+    fn default() -> HairMaterial {
+        HairMaterial {
+            k_d: 0.6,
+            k_s: 0.4,
+            diffuse: Vec3 { x: 0.35, y: 0.22, z: 0.12 },
+            specular: Vec3 { x: 0.8, y: 0.75, z: 0.7 },
+            shininess: 60.0
+        }
+    }
+}
+
+#[test]
+fn it_peaks_where_light_mirrors_about_the_strand() {
+    use core::f64::consts::PI;
+
+    let hair = HairMaterial { k_d: 0.0, k_s: 1.0, specular: Vec3::one(), shininess: 50.0, ..Default::default() };
+    let half = (0.5f64).sqrt();
+
+    // Strand along x. Light coming down the strand at 45 degrees, eye up it
+    // at 45 degrees
+    let l = Vec3 { x: half, y: 0.0, z: half };
+    let mirrored = hair.bsdf(0.0, 0.0).eval(Vec3 { x: -half, y: 0.0, z: half }, l);
+    let same_side = hair.bsdf(0.0, 0.0).eval(l, l);
+    assert!((mirrored.x * PI - 1.0).abs() < 1e-9);
+    assert!(same_side.x < 1e-6);
+}
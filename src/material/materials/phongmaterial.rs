@@ -1,78 +1,64 @@
 use crate::prelude::*;
-use crate::material::{Material, Texture};
+use crate::material::{Bsdf, Bxdf, Dispersion, Material, Texture};
 use crate::raytracer::compositor::ColorRGBA;
 use crate::vec3::Vec3;
 
 #[allow(dead_code)]
 #[derive(Clone)]
 pub struct PhongMaterial {
-    pub k_a: f64,           // Ambient coefficient
     pub k_d: f64,           // Diffuse coefficient
     pub k_s: f64,           // Local specular coefficient
     pub k_sg: f64,          // Global specular coefficient (mirror reflection)
     pub k_tg: f64,          // Global transmissive coefficient (refraction)
-    pub ambient: Vec3,      // Ambient color
     pub diffuse: Vec3,      // Diffuse color
     pub transmission: Vec3, // Transmissive color
+    pub absorption: Vec3,   // Absorbed per unit length travelled inside, for colored glass
     pub specular: Vec3,     // Specular color
-    pub shininess: f64,     // Size of Phong specular highlight
-    pub glossiness: f64,    // How glossy reflections are. 0 for non-glossy surfaces.
+    pub shininess: f64,     // Blinn-Phong exponent: bigger = smaller highlight
+    pub glossiness: f64,    // How blurred mirror reflections are, in radians. 0 for sharp ones.
     pub ior: f64,           // Index of refraction
+    pub dispersion: Option<Dispersion>, // Replaces ior in spectral renders, to split colors
     pub diffuse_texture: Option<Box<dyn Texture+Send+Sync>>
 }
 
-impl Material for PhongMaterial {
-    fn sample(&self, n: Vec3, i: Vec3, l: Vec3, u: f64, v: f64) -> Vec3 {
-        let h = (l + i).unit();
-
-        // Blinn-Phong approximation
-        let ambient  = self.ambient.scale(self.k_a);
-        let diffuse  = self.diffuse.scale(self.k_d).scale(n.dot(&l)) * match self.diffuse_texture {
+impl PhongMaterial {
+    fn bsdf_with_ior(&self, u: f64, v: f64, ior: f64) -> Bsdf {
+        let texture = match self.diffuse_texture {
             Some(ref x) => x.color(u, v),
             None => ColorRGBA::white()
         }.to_vec3();
-        let specular = self.specular.scale(self.k_s).scale(n.dot(&h).powf(self.shininess));
-
-        ambient + diffuse + specular
-    }
-
-    fn is_reflective(&self) -> bool {
-        self.k_sg > 0.0
-    }
 
-    fn is_refractive(&self) -> bool {
-        self.k_tg > 0.0
+        Bsdf::new(vec![
+            Bxdf::Lambert { albedo: self.diffuse.scale(self.k_d) * texture },
+            Bxdf::BlinnPhong { color: self.specular.scale(self.k_s), exponent: self.shininess },
+            Bxdf::Mirror { scale: self.k_sg, ior: ior, glossiness: self.glossiness },
+            Bxdf::Refraction { color: Vec3::one().scale(self.k_tg), ior: ior }
+        ])
     }
+}
 
-    fn global_specular(&self, color: &Vec3) -> Vec3 {
-        color.scale(self.k_sg)
+impl Material for PhongMaterial {
+    fn bsdf(&self, u: f64, v: f64) -> Bsdf {
+        self.bsdf_with_ior(u, v, self.ior)
     }
 
-    fn global_transmissive(&self, color: &Vec3) -> Vec3 {
-        color.scale(self.k_tg)
+    fn spectral_bsdf(&self, u: f64, v: f64, wavelength: f64) -> Bsdf {
+        let ior = self.dispersion.map_or(self.ior, |dispersion| dispersion.ior(wavelength));
+        self.bsdf_with_ior(u, v, ior)
     }
 
     fn transmission(&self) -> Vec3 {
         self.transmission
     }
 
-    fn ior(&self) -> f64 {
-        self.ior
-    }
-
-    fn is_glossy(&self) -> bool {
-        self.glossiness > ::core::f64::EPSILON
-    }
-
-    fn glossiness(&self) -> f64 {
-        self.glossiness
+    fn absorption(&self) -> Vec3 {
+        self.absorption
     }
 }
 
 impl Default for PhongMaterial {
     fn default() -> PhongMaterial {
         PhongMaterial {
-            k_a: 0.0,
             k_d: 1.0,
             k_s: 1.0,
             k_sg: 0.0,
@@ -80,10 +66,11 @@ impl Default for PhongMaterial {
             shininess: 10.0,
             glossiness: 0.0,
             ior: 1.0,
-            ambient: Vec3::one(),
             diffuse: Vec3 { x: 0.5, y: 0.5, z: 0.5 },
             specular: Vec3::one(),
             transmission: Vec3::zero(),
+            absorption: Vec3::zero(),
+            dispersion: None,
             diffuse_texture: None
         }
     }
@@ -0,0 +1,167 @@
+use crate::prelude::*;
+use crate::material::{Bsdf, Bxdf, Dispersion, Material, ScalarTexture, Texture};
+use crate::raytracer::compositor::ColorRGBA;
+use crate::vec3::Vec3;
+
+/// A metallic-roughness material in the style of Disney's principled BSDF
+/// and glTF: a GGX specular layer over a diffuse base, blended towards
+/// metal, with optional clear coat, sheen and transmission. Each parameter
+/// is scaled by its texture, if it has one.
+#[allow(dead_code)]
+#[derive(Clone)]
+pub struct PrincipledMaterial {
+    pub base_color: Vec3,          // Diffuse color, or reflectance for metals
+    pub metallic: f64,             // 0 for dielectrics, 1 for metals
+    pub roughness: f64,            // Perceptual roughness, 0 (mirror) to 1
+    pub specular: f64,             // Dielectric reflectance head on, 0.5 = 4%
+    pub clearcoat: f64,            // Strength of a clear varnish layer
+    pub clearcoat_roughness: f64,  // Perceptual roughness of the varnish
+    pub sheen: f64,                // Soft grazing rim, for cloth
+    pub transmission: f64,         // How much of a dielectric lets light through
+    pub ior: f64,                  // Index of refraction for transmission
+    pub absorption: Vec3,          // Absorbed per unit length travelled inside
+    pub dispersion: Option<Dispersion>, // Replaces ior in spectral renders
+    pub base_color_texture: Option<Box<dyn Texture+Send+Sync>>,
+    pub metallic_texture: Option<ScalarTexture>,
+    pub roughness_texture: Option<ScalarTexture>,
+    pub specular_texture: Option<ScalarTexture>,
+    pub clearcoat_texture: Option<ScalarTexture>,
+    pub clearcoat_roughness_texture: Option<ScalarTexture>,
+    pub sheen_texture: Option<ScalarTexture>,
+    pub transmission_texture: Option<ScalarTexture>
+}
+
+fn textured(factor: f64, texture: &Option<ScalarTexture>, u: f64, v: f64) -> f64 {
+    match *texture {
+        Some(ref x) => factor * x.value(u, v),
+        None => factor
+    }.clamp(0.0, 1.0)
+}
+
+/// GGX width from perceptual roughness, kept off zero where the
+/// distribution would become a spike.
+fn alpha(roughness: f64) -> f64 {
+    (roughness * roughness).max(0.001)
+}
+
+impl PrincipledMaterial {
+    fn bsdf_with_ior(&self, u: f64, v: f64, ior: f64) -> Bsdf {
+        let base_color = self.base_color * match self.base_color_texture {
+            Some(ref x) => x.color(u, v),
+            None => ColorRGBA::white()
+        }.to_vec3();
+        let metallic = textured(self.metallic, &self.metallic_texture, u, v);
+        let roughness = textured(self.roughness, &self.roughness_texture, u, v);
+        let specular = textured(self.specular, &self.specular_texture, u, v);
+        let clearcoat = textured(self.clearcoat, &self.clearcoat_texture, u, v);
+        let clearcoat_roughness = textured(self.clearcoat_roughness, &self.clearcoat_roughness_texture, u, v);
+        let sheen = textured(self.sheen, &self.sheen_texture, u, v);
+        let transmission = textured(self.transmission, &self.transmission_texture, u, v);
+
+        // Dielectrics reflect a little, white; metals reflect their color
+        let dielectric = 1.0 - metallic;
+        let dielectric_f0 = 0.08 * specular;
+        let f0 = Vec3::lerp(&Vec3::one().scale(dielectric_f0), &base_color, metallic);
+
+        let lobes = vec![
+            Bxdf::Lambert { albedo: base_color.scale(dielectric * (1.0 - transmission) * (1.0 - dielectric_f0)) },
+            Bxdf::Sheen { color: Vec3::one().scale(sheen * dielectric) },
+            Bxdf::Ggx { f0: f0, scale: 1.0, alpha: alpha(roughness) },
+            Bxdf::Refraction { color: base_color.scale(dielectric * transmission), ior: ior }
+        ];
+
+        if clearcoat > 0.0 {
+            let coat = Bxdf::Ggx { f0: Vec3::one().scale(0.04), scale: clearcoat, alpha: alpha(clearcoat_roughness) };
+            Bsdf::coated(coat, lobes)
+        } else {
+            Bsdf::new(lobes)
+        }
+    }
+}
+
+impl Material for PrincipledMaterial {
+    fn bsdf(&self, u: f64, v: f64) -> Bsdf {
+        self.bsdf_with_ior(u, v, self.ior)
+    }
+
+    fn spectral_bsdf(&self, u: f64, v: f64, wavelength: f64) -> Bsdf {
+        let ior = self.dispersion.map_or(self.ior, |dispersion| dispersion.ior(wavelength));
+        self.bsdf_with_ior(u, v, ior)
+    }
+
+    fn transmission(&self) -> Vec3 {
+        self.base_color.scale((1.0 - self.metallic) * self.transmission)
+    }
+
+    fn absorption(&self) -> Vec3 {
+        self.absorption
+    }
+}
+
+impl Default for PrincipledMaterial {
+    fn default() -> PrincipledMaterial {
+        PrincipledMaterial {
+            base_color: Vec3 { x: 0.8, y: 0.8, z: 0.8 },
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.03,
+            sheen: 0.0,
+            transmission: 0.0,
+            ior: 1.5,
+            absorption: Vec3::zero(),
+            dispersion: None,
+            base_color_texture: None,
+            metallic_texture: None,
+            roughness_texture: None,
+            specular_texture: None,
+            clearcoat_texture: None,
+            clearcoat_roughness_texture: None,
+            sheen_texture: None,
+            transmission_texture: None
+        }
+    }
+}
+
+#[test]
+fn it_keeps_rough_metal_close_to_white_in_a_furnace() {
+    use crate::material::Lobe;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    // A white metal reflects everything, however rough; without putting
+    // back multiple scattering a rough one would lose a good part of it
+    let metal = PrincipledMaterial { base_color: Vec3::one(), metallic: 1.0, roughness: 1.0, ..Default::default() };
+    let bsdf = metal.bsdf(0.0, 0.0);
+    let wo = Vec3 { x: 0.6, y: 0.0, z: 0.8 };
+    let mut rng = StdRng::seed_from_u64(47);
+
+    let count = 20000;
+    let albedo = (0..count).fold(Vec3::zero(), |acc, _| {
+        match bsdf.sample(wo, (rng.gen(), rng.gen()), Lobe::ALL) {
+            Some(sample) => acc + sample.weight.scale(1.0 / count as f64),
+            None => acc
+        }
+    });
+    assert!(albedo.x > 0.9 && albedo.x < 1.1, "{:?}", albedo);
+}
+
+#[test]
+fn it_reads_parameters_from_textures() {
+    use crate::material::TextureChannel;
+    use crate::material::textures::UVTexture;
+
+    // Metallic follows u: plastic at the left edge, metal at the right
+    let material = PrincipledMaterial {
+        metallic: 1.0,
+        metallic_texture: Some(ScalarTexture::new(Box::new(UVTexture), TextureChannel::Red)),
+        ..Default::default()
+    };
+    let wo = Vec3 { x: 0.0, y: 0.0, z: 1.0 };
+    let wi = Vec3 { x: 0.8, y: 0.0, z: 0.6 };
+
+    let plastic = material.bsdf(0.0, 0.5).eval(wo, wi);
+    let metal = material.bsdf(0.99, 0.5).eval(wo, wi);
+    assert!(plastic.x > 0.2 && plastic.x > 2.0 * metal.x);
+}
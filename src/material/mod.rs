@@ -1,22 +1,35 @@
-pub use self::material::Material;
-pub use self::texture::Texture;
+pub use self::bsdf::{Bsdf, Bxdf, Frame, Lobe};
+pub use self::dispersion::Dispersion;
+pub use self::material::{absorption_at_distance, attenuation, Material};
+pub use self::texture::{ScalarTexture, Texture, TextureChannel};
+pub mod bsdf;
+pub mod dispersion;
 pub mod material;
 pub mod texture;
 
 pub mod materials {
     pub use self::cooktorrancematerial::CookTorranceMaterial;
+    pub use self::emissivematerial::EmissiveMaterial;
     pub use self::flatmaterial::FlatMaterial;
+    #[allow(unused_imports)]
+    pub use self::hairmaterial::HairMaterial;
     pub use self::phongmaterial::PhongMaterial;
+    pub use self::principledmaterial::PrincipledMaterial;
 
     mod cooktorrancematerial;
+    mod emissivematerial;
     mod flatmaterial;
+    mod hairmaterial;
     mod phongmaterial;
+    mod principledmaterial;
 }
 
 pub mod textures {
     pub use self::checkertexture::CheckerTexture;
+    pub use self::imagetexture::ImageTexture;
     pub use self::uvtexture::UVTexture;
 
     mod checkertexture;
+    mod imagetexture;
     mod uvtexture;
 }
@@ -11,3 +11,36 @@ impl Clone for Box<dyn Texture+Send+Sync> {
         self.clone_self()
     }
 }
+
+/// Which channel of a texture a `ScalarTexture` reads.
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+pub enum TextureChannel {
+    Red,
+    Green,
+    Blue,
+    Alpha
+}
+
+/// One channel of a texture, to vary a scalar material parameter.
+#[derive(Clone)]
+pub struct ScalarTexture {
+    pub texture: Box<dyn Texture+Send+Sync>,
+    pub channel: TextureChannel
+}
+
+impl ScalarTexture {
+    pub fn new(texture: Box<dyn Texture+Send+Sync>, channel: TextureChannel) -> ScalarTexture {
+        ScalarTexture { texture: texture, channel: channel }
+    }
+
+    pub fn value(&self, u: f64, v: f64) -> f64 {
+        let color = self.texture.color(u, v);
+        match self.channel {
+            TextureChannel::Red => color.r,
+            TextureChannel::Green => color.g,
+            TextureChannel::Blue => color.b,
+            TextureChannel::Alpha => color.a
+        }
+    }
+}
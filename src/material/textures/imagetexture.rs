@@ -0,0 +1,48 @@
+use crate::prelude::*;
+use alloc::sync::Arc;
+use crate::material::Texture;
+use crate::raytracer::compositor::{ColorRGBA, Surface};
+
+
+/// Samples an image with (0, 0) at the bottom left, wrapping outside [0, 1].
+/// The image is shared between clones, so texturing every triangle of a mesh
+/// doesn't copy it per triangle.
+#[derive(Clone)]
+pub struct ImageTexture {
+    pub image: Arc<Surface>
+}
+
+impl ImageTexture {
+    pub fn new(image: Surface) -> ImageTexture {
+        ImageTexture { image: Arc::new(image) }
+    }
+}
+
+impl Texture for ImageTexture {
+    fn color(&self, u: f64, v: f64) -> ColorRGBA<f64> {
+        let wrapped_u = u - u.floor();
+        let wrapped_v = v - v.floor();
+
+        let x = ((wrapped_u * self.image.width as f64) as usize).min(self.image.width - 1);
+        let y = (((1.0 - wrapped_v) * self.image.height as f64) as usize).min(self.image.height - 1);
+
+        self.image[(x, y)].channel_f64()
+    }
+
+    fn clone_self(&self) -> Box<dyn Texture+Send+Sync> {
+        Box::new(self.clone()) as Box<dyn Texture+Send+Sync>
+    }
+}
+
+#[test]
+fn it_wraps_and_flips_v() {
+    let mut image = Surface::new(2, 2, ColorRGBA::new_rgb(0, 0, 0));
+    image[(0, 0)] = ColorRGBA::new_rgb(255, 0, 0); // top left
+    image[(1, 1)] = ColorRGBA::new_rgb(0, 0, 255); // bottom right
+    let texture = ImageTexture::new(image);
+
+    assert_eq!(1.0, texture.color(0.25, 0.75).r);
+    assert_eq!(1.0, texture.color(0.75, 0.25).b);
+    assert_eq!(1.0, texture.color(-0.75, 1.75).r);
+    assert_eq!(1.0, texture.color(1.75, 0.25).b);
+}
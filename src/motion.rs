@@ -0,0 +1,189 @@
+use crate::mat4::Mat4;
+use crate::vec3::Vec3;
+
+/// A rotation as a unit quaternion, which unlike a matrix can be
+/// interpolated without shearing.
+#[derive(Clone, Copy, Debug)]
+pub struct Quaternion {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64
+}
+
+impl Quaternion {
+    pub fn identity() -> Quaternion {
+        Quaternion { w: 1.0, x: 0.0, y: 0.0, z: 0.0 }
+    }
+
+    pub fn from_axis_angle_deg(angle: f64, axis: &Vec3) -> Quaternion {
+        let a = axis.unit();
+        let half = angle.to_radians() / 2.0;
+        let s = half.sin();
+        Quaternion { w: half.cos(), x: a.x * s, y: a.y * s, z: a.z * s }
+    }
+
+    pub fn dot(&self, other: &Quaternion) -> f64 {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn mult(a: &Quaternion, b: &Quaternion) -> Quaternion {
+        Quaternion {
+            w: a.w * b.w - a.x * b.x - a.y * b.y - a.z * b.z,
+            x: a.w * b.x + a.x * b.w + a.y * b.z - a.z * b.y,
+            y: a.w * b.y - a.x * b.z + a.y * b.w + a.z * b.x,
+            z: a.w * b.z + a.x * b.y - a.y * b.x + a.z * b.w
+        }
+    }
+
+    fn scale(&self, s: f64) -> Quaternion {
+        Quaternion { w: self.w * s, x: self.x * s, y: self.y * s, z: self.z * s }
+    }
+
+    fn add(&self, other: &Quaternion) -> Quaternion {
+        Quaternion { w: self.w + other.w, x: self.x + other.x, y: self.y + other.y, z: self.z + other.z }
+    }
+
+    fn unit(&self) -> Quaternion {
+        self.scale(1.0 / self.dot(self).sqrt())
+    }
+
+    /// Turns at a constant rate from `a` to `b` the short way round.
+    pub fn slerp(a: &Quaternion, b: &Quaternion, alpha: f64) -> Quaternion {
+        let mut cos_theta = a.dot(b);
+        let b = if cos_theta < 0.0 { cos_theta = -cos_theta; b.scale(-1.0) } else { *b };
+
+        // Nearly the same rotation: lerping is as good and avoids dividing by ~0
+        if cos_theta > 0.9995 {
+            return a.scale(1.0 - alpha).add(&b.scale(alpha)).unit();
+        }
+
+        let theta = cos_theta.acos();
+        let sin_theta = theta.sin();
+        a.scale(((1.0 - alpha) * theta).sin() / sin_theta).add(&b.scale((alpha * theta).sin() / sin_theta))
+    }
+
+    pub fn to_matrix(self) -> Mat4 {
+        let Quaternion { w, x, y, z } = self.unit();
+        Mat4::new(
+            1.0 - 2.0 * (y * y + z * z),       2.0 * (x * y - w * z),       2.0 * (x * z + w * y), 0.0,
+                  2.0 * (x * y + w * z), 1.0 - 2.0 * (x * x + z * z),       2.0 * (y * z - w * x), 0.0,
+                  2.0 * (x * z - w * y),       2.0 * (y * z + w * x), 1.0 - 2.0 * (x * x + y * y), 0.0,
+                                    0.0,                         0.0,                         0.0, 1.0
+        )
+    }
+}
+
+/// Where an object is at one moment: scaled, then rotated, then moved.
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe {
+    pub time: f64,
+    pub translation: Vec3,
+    pub rotation: Quaternion,
+    pub scale: Vec3
+}
+
+impl Keyframe {
+    /// Leaves the object as it is, at `time`.
+    pub fn new(time: f64) -> Keyframe {
+        Keyframe {
+            time: time,
+            translation: Vec3::zero(),
+            rotation: Quaternion::identity(),
+            scale: Vec3::one()
+        }
+    }
+
+    pub fn translate(&mut self, translation: Vec3) -> &mut Self {
+        self.translation = translation;
+        self
+    }
+
+    /// Rotations given one after another add up.
+    #[allow(dead_code)]
+    pub fn rotate_deg(&mut self, angle: f64, axis: &Vec3) -> &mut Self {
+        self.rotation = Quaternion::mult(&Quaternion::from_axis_angle_deg(angle, axis), &self.rotation);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn scale(&mut self, scale: Vec3) -> &mut Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn to_matrix(self) -> Mat4 {
+        let rotate_scale = Mat4::mult_m(&self.rotation.to_matrix(), &Mat4::scale_matrix(&self.scale));
+        Mat4::mult_m(&Mat4::translate_matrix(&self.translation), &rotate_scale)
+    }
+}
+
+/// A transform that changes over time. Translation and scale are
+/// interpolated linearly between keyframes and rotation by slerp; before
+/// the first keyframe and after the last the object stays put.
+#[derive(Clone, Debug)]
+pub struct AnimatedTransform {
+    pub keyframes: Vec<Keyframe>
+}
+
+impl AnimatedTransform {
+    pub fn new(mut keyframes: Vec<Keyframe>) -> AnimatedTransform {
+        assert!(!keyframes.is_empty(), "an animated transform needs at least one keyframe");
+        keyframes.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(::core::cmp::Ordering::Equal));
+        AnimatedTransform { keyframes: keyframes }
+    }
+
+    pub fn start_time(&self) -> f64 {
+        self.keyframes[0].time
+    }
+
+    pub fn keyframe_at(&self, time: f64) -> Keyframe {
+        let next = match self.keyframes.iter().position(|key| key.time > time) {
+            Some(0) => return self.keyframes[0],
+            Some(next) => next,
+            None => return self.keyframes[self.keyframes.len() - 1]
+        };
+
+        let (a, b) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        let alpha = (time - a.time) / (b.time - a.time);
+        Keyframe {
+            time: time,
+            translation: Vec3::lerp(&a.translation, &b.translation, alpha),
+            rotation: Quaternion::slerp(&a.rotation, &b.rotation, alpha),
+            scale: Vec3::lerp(&a.scale, &b.scale, alpha)
+        }
+    }
+
+    pub fn matrix_at(&self, time: f64) -> Mat4 {
+        self.keyframe_at(time).to_matrix()
+    }
+}
+
+#[test]
+fn it_interpolates_between_keyframes() {
+    let mut start = Keyframe::new(0.0);
+    start.translate(Vec3 { x: 0.0, y: 0.0, z: 0.0 });
+    let mut end = Keyframe::new(2.0);
+    end.translate(Vec3 { x: 4.0, y: 0.0, z: 0.0 }).rotate_deg(90.0, &Vec3 { x: 0.0, y: 1.0, z: 0.0 });
+    let motion = AnimatedTransform::new(vec![end, start]);
+
+    // Halfway there, turned 45 degrees about Y
+    let m = motion.matrix_at(1.0);
+    let p = Mat4::mult_p(&m, &Vec3 { x: 1.0, y: 0.0, z: 0.0 });
+    let half = (0.5f64).sqrt();
+    assert!((p - Vec3 { x: 2.0 + half, y: 0.0, z: -half }).len() < 1e-9);
+
+    // Held at the ends
+    assert!((Mat4::mult_p(&motion.matrix_at(-1.0), &Vec3::one()) - Vec3::one()).len() < 1e-9);
+    let p = Mat4::mult_p(&motion.matrix_at(5.0), &Vec3 { x: 1.0, y: 0.0, z: 0.0 });
+    assert!((p - Vec3 { x: 4.0, y: 0.0, z: -1.0 }).len() < 1e-9);
+}
+
+#[test]
+fn it_matches_the_rotation_matrix() {
+    let axis = Vec3 { x: 1.0, y: 2.0, z: 3.0 };
+    let q = Quaternion::from_axis_angle_deg(70.0, &axis).to_matrix();
+    let m = Mat4::rotate_axis_deg_matrix(70.0, &axis);
+    let v = Vec3 { x: -0.5, y: 2.0, z: 1.0 };
+    assert!((Mat4::mult_p(&q, &v) - Mat4::mult_p(&m, &v)).len() < 1e-9);
+}
@@ -0,0 +1,51 @@
+use core::cell::RefCell;
+use std::path::{Path, PathBuf};
+use crate::scene::{Camera, Scene};
+use crate::util::import::{from_gltf, GltfCamera, GltfScene, ImportError};
+use crate::vec3::Vec3;
+
+/// A scene loaded from a .gltf or .glb file, viewed through the file's first
+/// camera if it has one.
+pub struct GltfConfig {
+    path: PathBuf,
+    cache_dir: Option<PathBuf>,
+    camera: Option<GltfCamera>,
+    loaded: RefCell<Option<GltfScene>>,
+}
+
+impl GltfConfig {
+    pub fn load(path: &Path, cache_dir: Option<&Path>) -> Result<GltfConfig, ImportError> {
+        let scene = from_gltf(path, cache_dir)?;
+        Ok(GltfConfig {
+            path: path.to_path_buf(),
+            cache_dir: cache_dir.map(Path::to_path_buf),
+            camera: scene.cameras.first().cloned(),
+            loaded: RefCell::new(Some(scene)),
+        })
+    }
+}
+
+impl super::SceneConfig for GltfConfig {
+    fn get_camera(&self, image_width: u32, image_height: u32, fov: f64) -> Camera {
+        match self.camera {
+            Some(ref camera) => camera.to_camera(image_width, image_height),
+            None => Camera::new(
+                Vec3 { x: 0.0, y: 0.0, z: 5.0 },
+                Vec3::zero(),
+                Vec3 { x: 0.0, y: 1.0, z: 0.0 },
+                fov,
+                image_width,
+                image_height
+            )
+        }
+    }
+
+    fn get_scene(&self) -> Scene {
+        // The scene loaded up front is handed out once, then reloaded
+        let scene = match self.loaded.borrow_mut().take() {
+            Some(scene) => scene,
+            None => from_gltf(&self.path, self.cache_dir.as_deref()).expect("glTF scene failed to reload")
+        };
+        scene.into_scene(Vec3::zero())
+    }
+}
@@ -3,6 +3,7 @@ use crate::prelude::*;
 use crate::scene::{Camera, Scene};
 
 pub mod cornell;
+pub mod gltf;
 
 pub trait SceneConfig {
     fn get_camera(&self, image_width: u32, image_height: u32, fov: f64) -> Camera;
@@ -0,0 +1,150 @@
+use crate::prelude::*;
+
+/// Real roots of `a*t^2 + b*t + c = 0`, smallest first. Uses the form that
+/// avoids cancellation when `b*b` dwarfs `4*a*c`.
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Option<(f64, f64)> {
+    if a == 0.0 {
+        return if b == 0.0 { None } else { Some((-c / b, -c / b)) };
+    }
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    let t0 = q / a;
+    let t1 = if q == 0.0 { t0 } else { c / q };
+    Some(if t0 <= t1 { (t0, t1) } else { (t1, t0) })
+}
+
+/// A polynomial in one variable, with coefficients stored lowest power
+/// first, so `coeffs[i]` multiplies `t^i`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Polynomial {
+    pub coeffs: Vec<f64>
+}
+
+impl Polynomial {
+    /// Leading zero coefficients are dropped so `degree` is meaningful.
+    pub fn new(mut coeffs: Vec<f64>) -> Polynomial {
+        while coeffs.len() > 1 && coeffs[coeffs.len() - 1] == 0.0 {
+            coeffs.pop();
+        }
+        Polynomial { coeffs: coeffs }
+    }
+
+    pub fn degree(&self) -> usize {
+        self.coeffs.len().saturating_sub(1)
+    }
+
+    pub fn eval(&self, t: f64) -> f64 {
+        self.coeffs.iter().rev().fold(0.0, |acc, &c| acc * t + c)
+    }
+
+    pub fn derivative(&self) -> Polynomial {
+        Polynomial::new(self.coeffs.iter().enumerate().skip(1).map(|(i, &c)| c * i as f64).collect())
+    }
+
+    /// Real roots within `[lo, hi]`, in ascending order.
+    ///
+    /// The roots of the derivative split the range into pieces where the
+    /// polynomial is monotonic, so each piece holds at most one root, which
+    /// is then found by Newton's method kept inside a shrinking bracket.
+    /// This is slower than the closed forms for cubics and quartics, but
+    /// doesn't fall apart when their intermediate terms nearly cancel.
+    pub fn roots_in(&self, lo: f64, hi: f64) -> Vec<f64> {
+        match self.degree() {
+            0 => Vec::new(),
+            1 => {
+                let t = -self.coeffs[0] / self.coeffs[1];
+                if t >= lo && t <= hi { vec![t] } else { Vec::new() }
+            },
+            2 => match solve_quadratic(self.coeffs[2], self.coeffs[1], self.coeffs[0]) {
+                Some((t0, t1)) => {
+                    let mut roots: Vec<f64> = vec![t0, t1].into_iter().filter(|&t| t >= lo && t <= hi).collect();
+                    roots.dedup();
+                    roots
+                },
+                None => Vec::new()
+            },
+            _ => {
+                let derivative = self.derivative();
+                let mut bounds = vec![lo];
+                bounds.extend(derivative.roots_in(lo, hi));
+                bounds.push(hi);
+
+                let mut roots = Vec::new();
+                for pair in bounds.windows(2) {
+                    let (a, b) = (pair[0], pair[1]);
+                    let (fa, fb) = (self.eval(a), self.eval(b));
+                    if fa == 0.0 {
+                        // Touching zero at a turning point counts once
+                        if roots.last() != Some(&a) {
+                            roots.push(a);
+                        }
+                    } else if fa.signum() != fb.signum() && fb != 0.0 {
+                        roots.push(self.refine(&derivative, a, b, fa));
+                    }
+                }
+                if self.eval(hi) == 0.0 && roots.last() != Some(&hi) {
+                    roots.push(hi);
+                }
+                roots
+            }
+        }
+    }
+
+    /// Newton's method, falling back to bisection whenever a step would
+    /// leave the bracket `[a, b]` known to hold a sign change.
+    fn refine(&self, derivative: &Polynomial, mut a: f64, mut b: f64, fa: f64) -> f64 {
+        let mut t = 0.5 * (a + b);
+        for _ in 0..100 {
+            let ft = self.eval(t);
+            if ft == 0.0 {
+                return t;
+            }
+            if ft.signum() == fa.signum() { a = t; } else { b = t; }
+            if b - a <= 1e-12 * t.abs().max(1.0) {
+                break;
+            }
+
+            let newton = t - ft / derivative.eval(t);
+            t = if newton > a && newton < b { newton } else { 0.5 * (a + b) };
+        }
+        t
+    }
+}
+
+#[test]
+fn it_solves_quadratics() {
+    assert_eq!(Some((-3.0, 2.0)), solve_quadratic(1.0, 1.0, -6.0));
+    assert_eq!(Some((2.0, 2.0)), solve_quadratic(0.0, 2.0, -4.0));
+    assert_eq!(None, solve_quadratic(1.0, 0.0, 1.0));
+
+    // Catastrophic cancellation would lose the small root entirely
+    let (small, large) = solve_quadratic(1.0, -1e9, 1.0).unwrap();
+    assert!((small - 1e-9).abs() < 1e-20);
+    assert!((large - 1e9).abs() < 1.0);
+}
+
+#[test]
+fn it_finds_roots_in_a_range() {
+    // (t - 1)(t - 2)(t - 3)(t - 4)
+    let quartic = Polynomial::new(vec![24.0, -50.0, 35.0, -10.0, 1.0, 0.0]);
+    assert_eq!(4, quartic.degree());
+
+    let roots = quartic.roots_in(-10.0, 10.0);
+    assert_eq!(4, roots.len());
+    for (root, expected) in roots.iter().zip(&[1.0, 2.0, 3.0, 4.0]) {
+        assert!((root - expected).abs() < 1e-9);
+    }
+
+    let roots = quartic.roots_in(1.5, 3.5);
+    assert_eq!(2, roots.len());
+    assert!((roots[0] - 2.0).abs() < 1e-9);
+
+    // t^4 + 1 never crosses zero; t^3 has a triple root at 0
+    assert!(Polynomial::new(vec![1.0, 0.0, 0.0, 0.0, 1.0]).roots_in(-10.0, 10.0).is_empty());
+    assert_eq!(vec![0.0], Polynomial::new(vec![0.0, 0.0, 0.0, 1.0]).roots_in(-1.0, 1.0));
+}
@@ -1,6 +1,7 @@
 pub use self::colorrgba::{Channel, ColorRGBA};
 pub use self::surface::Surface;
 pub use self::surfacefactory::SurfaceFactory;
+#[allow(unused_imports)]
 pub use self::surfaceiterator::SurfaceIterator;
 
 pub mod colorrgba;
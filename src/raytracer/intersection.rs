@@ -3,10 +3,141 @@ use crate::material::Material;
 use crate::vec3::Vec3;
 
 pub struct Intersection<'a> {
+    /// The shading normal, which may be interpolated and so lean away from
+    /// the true surface. Not necessarily unit length.
     pub n: Vec3,
+    /// Unit normal of the surface itself. Points out of solids, and to the
+    /// same side of the surface as `n`.
+    pub geometric_n: Vec3,
+    /// Whether the ray came from the side `geometric_n` points to, so it's
+    /// entering rather than leaving a solid.
+    pub front_face: bool,
+    /// Which of the scene's prims was hit, as numbered by its octree. Prims
+    /// leave this 0 and `Ray::get_nearest_hit` fills it in, so a mesh face or
+    /// CSG child reports the id of the mesh or CSG it belongs to.
+    pub prim_id: usize,
     pub t: f64,
     pub u: f64,
     pub v: f64,
     pub position: Vec3,
+    /// How the position changes with `u` and `v`, for normal mapping,
+    /// anisotropic shading and texture filtering. Either can be zero where
+    /// the parameterization pinches, as at a sphere's poles.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub material: &'a Box<dyn Material + Send + Sync + 'a>
 }
+
+/// Some unit vector square to unit vector `n`.
+fn perpendicular(n: &Vec3) -> Vec3 {
+    let other = if n.x.abs() < 0.9 { Vec3 { x: 1.0, y: 0.0, z: 0.0 } } else { Vec3 { x: 0.0, y: 1.0, z: 0.0 } };
+    n.cross(&other).unit()
+}
+
+impl<'a> Intersection<'a> {
+    /// Unit tangent and bitangent, square to each other and to the shading
+    /// normal. The tangent follows `dpdu` and the bitangent `n × tangent`,
+    /// which is along `dpdv` for the usual right-handed layout. Where `dpdu`
+    /// is no use, `dpdv` decides the frame, and failing that any direction.
+    pub fn tangent_frame(&self) -> (Vec3, Vec3) {
+        let n = self.n.unit();
+        let in_plane = |d: &Vec3| *d - n.scale(n.dot(d));
+
+        let along_u = in_plane(&self.dpdu);
+        let along_v = in_plane(&self.dpdv);
+        let tangent = if along_u.len() > 1e-12 {
+            along_u.unit()
+        } else if along_v.len() > 1e-12 {
+            along_v.unit().cross(&n)
+        } else {
+            perpendicular(&n)
+        };
+
+        (tangent, n.cross(&tangent))
+    }
+
+    /// The geometric and shading normals as unit vectors, turned to the
+    /// side of the surface that `i`, pointing back along the ray, is on.
+    /// Where interpolation leaves the shading normal facing away from `i`,
+    /// it's bent just far enough back to face it, so the surface doesn't
+    /// shade as if seen from behind.
+    pub fn facing_normals(&self, i: &Vec3) -> (Vec3, Vec3) {
+        let (ng, ns) = if self.front_face {
+            (self.geometric_n, self.n.unit())
+        } else {
+            (-self.geometric_n, -self.n.unit())
+        };
+
+        let cos_i = ns.dot(i);
+        if cos_i < 0.001 {
+            (ng, (ns + i.scale(0.001 - cos_i)).unit())
+        } else {
+            (ng, ns)
+        }
+    }
+}
+
+#[test]
+fn it_turns_normals_to_face_the_ray() {
+    use crate::material::materials::FlatMaterial;
+
+    let material: Box<dyn Material+Send+Sync> = Box::new(FlatMaterial { color: Vec3::one() });
+    let up = Vec3 { x: 0.0, y: 1.0, z: 0.0 };
+    let mut hit = Intersection {
+        // Interpolated to lean well away from the geometric normal
+        n: Vec3 { x: 1.0, y: 0.2, z: 0.0 },
+        geometric_n: up,
+        front_face: false,
+        prim_id: 0,
+        t: 1.0,
+        u: 0.0,
+        v: 0.0,
+        position: Vec3::zero(),
+        dpdu: Vec3 { x: 1.0, y: 0.0, z: 0.0 },
+        dpdv: Vec3 { x: 0.0, y: 0.0, z: 1.0 },
+        material: &material
+    };
+
+    // Seen from below and to the left, everything turns over
+    let i = Vec3 { x: -1.0, y: -1.0, z: 0.0 }.unit();
+    let (ng, ns) = hit.facing_normals(&i);
+    assert_eq!(-up, ng);
+    assert!(ns.x < -0.9);
+
+    // Seen from above, glancing from the right: the shading normal faces
+    // away, so it's bent back to just face the eye
+    hit.front_face = true;
+    let i = Vec3 { x: -1.0, y: 0.1, z: 0.0 }.unit();
+    let (ng, ns) = hit.facing_normals(&i);
+    assert_eq!(up, ng);
+    assert!(ns.dot(&i) > 0.0 && ns.dot(&i) < 0.01);
+}
+
+#[test]
+fn it_builds_a_tangent_frame_square_to_the_normal() {
+    use crate::material::materials::FlatMaterial;
+
+    let material: Box<dyn Material+Send+Sync> = Box::new(FlatMaterial { color: Vec3::one() });
+    let mut hit = Intersection {
+        n: Vec3 { x: 0.0, y: 2.0, z: 0.0 },
+        geometric_n: Vec3 { x: 0.0, y: 1.0, z: 0.0 },
+        front_face: true,
+        prim_id: 0,
+        t: 1.0,
+        u: 0.0,
+        v: 0.0,
+        position: Vec3::zero(),
+        // Leaning out of the surface, and stretched
+        dpdu: Vec3 { x: 3.0, y: 1.0, z: 0.0 },
+        dpdv: Vec3 { x: 0.0, y: 0.0, z: -2.0 },
+        material: &material
+    };
+
+    let (t, b) = hit.tangent_frame();
+    assert_eq!(Vec3 { x: 1.0, y: 0.0, z: 0.0 }, t);
+    assert_eq!(Vec3 { x: 0.0, y: 0.0, z: -1.0 }, b);
+
+    // Pinched in u, as at a pole: dpdv still gives the same frame
+    hit.dpdu = Vec3::zero();
+    assert_eq!((t, b), hit.tangent_frame());
+}
@@ -1,7 +1,7 @@
 pub use self::intersection::Intersection;
 pub use self::ray::Ray;
-pub use self::octree::Octree;
-pub use self::renderer::{Renderer, RenderOptions};
+pub use self::octree::{Octree, TraversalStats};
+pub use self::renderer::{HeatmapMetric, Renderer, RenderOptions};
 
 //pub mod animator;
 pub mod compositor;
@@ -9,3 +9,4 @@ pub mod intersection;
 pub mod octree;
 pub mod ray;
 pub mod renderer;
+pub mod spectrum;
@@ -1,13 +1,16 @@
 use crate::prelude::*;
+use core::fmt;
+use core::iter::Enumerate;
 use core::slice::Iter;
 use core::iter::FromIterator;
 use crate::geometry::{BBox, PartialBoundingBox};
 use crate::raytracer::Ray;
+use crate::util::cache::{CacheError, CacheReader, CacheWriter};
 use crate::vec3::Vec3;
 
 //
 
-pub struct Octree<T> where T: PartialBoundingBox {
+pub struct Octree<T> {
     prims: Vec<T>,
     infinites: Vec<T>, // for infinite prims (planes)
     root: OctreeNode,
@@ -15,23 +18,24 @@ pub struct Octree<T> where T: PartialBoundingBox {
 
 impl<T> FromIterator<T> for Octree<T> where T: PartialBoundingBox {
     fn from_iter<I>(iterator: I) -> Self where I: IntoIterator<Item=T> {
-        let iterator = iterator.into_iter();
+        Octree::with_bounds(iterator.into_iter().collect(), |item| item.partial_bounding_box())
+    }
+}
 
+impl<T> Octree<T> {
+    /// Builds an octree using `bound` to find the bounding box of each item.
+    /// This lets us partition items which can't compute their own bounds,
+    /// such as face indices into a shared mesh.
+    pub fn with_bounds<F>(items: Vec<T>, bound: F) -> Octree<T> where F: Fn(&T) -> Option<BBox> {
         let (finites, infinites): (Vec<T>, Vec<T>) =
-            iterator.partition(|item| item.partial_bounding_box().is_some());
+            items.into_iter().partition(|item| bound(item).is_some());
 
-        // TODO(sell): why do we need to map here? &T isn't PartialBoundingBox,
-        //             but we need to find out how to make it so.
-        let bounds = BBox::from_union(finites.iter().map(|i| i.partial_bounding_box()))
+        let bounds = BBox::from_union(finites.iter().map(&bound))
             .unwrap_or(BBox::zero());
 
-        // pbrt recommended max depth for a k-d tree (though, we're using an octree)
-        // For a k-d tree: 8 + 1.3 * log2(N)
-        let depth = (1.2 * (finites.len() as f64).log(8.0)).round() as i32;
-
-        let mut root_node = OctreeNode::new(bounds, depth);
+        let mut root_node = OctreeNode::new(bounds, Self::max_depth(finites.len()));
         for (i, prim) in finites.iter().enumerate() {
-            root_node.insert(i, prim.partial_bounding_box().unwrap());
+            root_node.insert(i, bound(prim).unwrap());
         }
 
         Octree {
@@ -40,12 +44,79 @@ impl<T> FromIterator<T> for Octree<T> where T: PartialBoundingBox {
             root: root_node,
         }
     }
-}
 
-impl<T> Octree<T> where T: PartialBoundingBox {
+    /// The depth an octree over `count` finite items is built to.
+    pub fn max_depth(count: usize) -> i32 {
+        // pbrt recommended max depth for a k-d tree (though, we're using an octree)
+        // For a k-d tree: 8 + 1.3 * log2(N)
+        (1.2 * (count as f64).log(8.0)).round() as i32
+    }
+
     pub fn intersect_iter<'a>(&'a self, ray: &'a Ray) -> OctreeIterator<'a, T> {
         OctreeIterator::new(self, ray)
     }
+
+    pub fn stats(&self) -> OctreeStats {
+        let mut stats = OctreeStats {
+            prims: self.prims.len(),
+            infinites: self.infinites.len(),
+            nodes: 0,
+            max_depth_reached: 0,
+            leaf_occupancy: Vec::new(),
+            references: 0
+        };
+        self.root.collect_stats(&mut stats, 0);
+        stats
+    }
+
+    pub fn write_cache<F>(&self, w: &mut CacheWriter, write_item: F) where F: Fn(&mut CacheWriter, &T) {
+        w.write_u64(self.prims.len() as u64);
+        for prim in self.prims.iter() {
+            write_item(w, prim);
+        }
+
+        w.write_u64(self.infinites.len() as u64);
+        for prim in self.infinites.iter() {
+            write_item(w, prim);
+        }
+
+        self.root.write_cache(w);
+    }
+
+    /// `item_size` is the smallest number of bytes `read_item` consumes,
+    /// used to sanity check item counts before allocating.
+    pub fn read_cache<F>(r: &mut CacheReader, item_size: usize, read_item: F) -> Result<Octree<T>, CacheError>
+            where F: Fn(&mut CacheReader) -> Result<T, CacheError> {
+        let prims_len = r.read_len(item_size)?;
+        let prims = (0..prims_len).map(|_| read_item(r)).collect::<Result<Vec<T>, CacheError>>()?;
+
+        let infinites_len = r.read_len(item_size)?;
+        let infinites = (0..infinites_len).map(|_| read_item(r)).collect::<Result<Vec<T>, CacheError>>()?;
+
+        let root = OctreeNode::read_cache(r, prims.len(), Self::max_depth(prims.len()))?;
+
+        Ok(Octree {
+            prims: prims,
+            infinites: infinites,
+            root: root,
+        })
+    }
+}
+
+fn write_bbox(w: &mut CacheWriter, bbox: &BBox) {
+    for v in [bbox.min, bbox.max].iter() {
+        w.write_f64(v.x);
+        w.write_f64(v.y);
+        w.write_f64(v.z);
+    }
+}
+
+fn read_bbox(r: &mut CacheReader) -> Result<BBox, CacheError> {
+    let mut v = [Vec3::zero(); 2];
+    for p in v.iter_mut() {
+        *p = Vec3 { x: r.read_f64()?, y: r.read_f64()?, z: r.read_f64()? };
+    }
+    Ok(BBox { min: v[0], max: v[1] })
 }
 
 pub struct OctreeNode {
@@ -130,6 +201,109 @@ impl OctreeNode {
     fn is_leaf(&self) -> bool {
         self.children.len() == 0
     }
+
+    fn collect_stats(&self, stats: &mut OctreeStats, level: usize) {
+        stats.nodes += 1;
+        stats.max_depth_reached = stats.max_depth_reached.max(level);
+
+        if self.is_leaf() {
+            let occupancy = self.leaf_data.len();
+            if stats.leaf_occupancy.len() <= occupancy {
+                stats.leaf_occupancy.resize(occupancy + 1, 0);
+            }
+            stats.leaf_occupancy[occupancy] += 1;
+        }
+        stats.references += self.leaf_data.len();
+
+        for child in self.children.iter() {
+            child.collect_stats(stats, level + 1);
+        }
+    }
+
+    fn write_cache(&self, w: &mut CacheWriter) {
+        write_bbox(w, &self.bbox);
+        w.write_i32(self.depth);
+
+        w.write_u64(self.leaf_data.len() as u64);
+        for data in self.leaf_data.iter() {
+            w.write_u64(data.index as u64);
+            write_bbox(w, &data.bbox);
+        }
+
+        w.write_u8(self.children.len() as u8);
+        for child in self.children.iter() {
+            child.write_cache(w);
+        }
+    }
+
+    /// The node must be at `depth`: the root at the depth the octree is
+    /// built to, and children one level below their parent. That also
+    /// bounds the recursion when reading a corrupt cache.
+    fn read_cache(r: &mut CacheReader, prims_len: usize, depth: i32) -> Result<OctreeNode, CacheError> {
+        let mut node = OctreeNode::new(read_bbox(r)?, r.read_i32()?);
+        if node.depth != depth {
+            return Err(CacheError::Corrupt("octree node depth"));
+        }
+
+        let leaf_len = r.read_len(7 * 8)?;
+        for _ in 0..leaf_len {
+            let index = r.read_u64()? as usize;
+            if index >= prims_len {
+                return Err(CacheError::Corrupt("octree leaf index out of range"));
+            }
+            node.leaf_data.push(OctreeData { index: index, bbox: read_bbox(r)? });
+        }
+
+        let children_len = r.read_u8()?;
+        if children_len != 0 && (children_len != 8 || node.depth <= 0) {
+            return Err(CacheError::Corrupt("octree node child count"));
+        }
+        for _ in 0..children_len {
+            node.children.push(OctreeNode::read_cache(r, prims_len, depth - 1)?);
+        }
+
+        Ok(node)
+    }
+}
+
+/// Summary of how an octree was built, for spotting badly partitioned scenes.
+pub struct OctreeStats {
+    pub prims: usize,
+    pub infinites: usize,
+    pub nodes: usize,
+    pub max_depth_reached: usize,
+    /// leaf_occupancy[n] is the number of leaves holding n prims
+    pub leaf_occupancy: Vec<usize>,
+    /// Prims overlapping several leaves are referenced once per leaf
+    pub references: usize,
+}
+
+impl OctreeStats {
+    pub fn duplicated_references(&self) -> usize {
+        self.references.saturating_sub(self.prims)
+    }
+}
+
+impl fmt::Display for OctreeStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "prims: {} (+{} infinite)", self.prims, self.infinites)?;
+        writeln!(f, "nodes: {}, max depth reached: {}", self.nodes, self.max_depth_reached)?;
+        writeln!(f, "references: {} ({} duplicated)", self.references, self.duplicated_references())?;
+        writeln!(f, "leaf occupancy:")?;
+        for (occupancy, &leaves) in self.leaf_occupancy.iter().enumerate() {
+            if leaves > 0 {
+                writeln!(f, "  {:>4} prims: {} leaves", occupancy, leaves)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Cost of tracing a single ray, for the traversal heatmap.
+#[derive(Clone, Copy, Default)]
+pub struct TraversalStats {
+    pub node_visits: u32,
+    pub prim_tests: u32,
 }
 
 pub struct OctreeIterator<'a, T:'a> {
@@ -137,44 +311,51 @@ pub struct OctreeIterator<'a, T:'a> {
     stack: Vec<&'a OctreeNode>,
     leaf_iter: Option<Iter<'a, OctreeData>>,
     ray: &'a Ray,
-    infinites: Iter<'a, T>,
-    just_infinites: bool
+    infinites: Enumerate<Iter<'a, T>>,
+    just_infinites: bool,
+    node_visits: u32
 }
 
 
-impl<'a, T> OctreeIterator<'a, T> where T: PartialBoundingBox {
+impl<'a, T> OctreeIterator<'a, T> {
     fn new<'b>(octree: &'b Octree<T>, ray: &'b Ray) -> OctreeIterator<'b, T> {
         OctreeIterator {
             prims: &octree.prims[..],
             stack: vec![&octree.root],
             leaf_iter: None,
             ray: ray,
-            infinites: octree.infinites.iter(),
-            just_infinites: false
+            infinites: octree.infinites.iter().enumerate(),
+            just_infinites: false,
+            node_visits: 0
         }
     }
-}
 
+    /// Number of octree nodes popped so far, for traversal cost debugging.
+    pub fn node_visits(&self) -> u32 {
+        self.node_visits
+    }
 
-impl<'a, T> Iterator for OctreeIterator<'a, T> where T: PartialBoundingBox {
-    type Item = &'a T;
+    /// As `next`, along with the item's index: finite items are numbered
+    /// first, then infinite ones.
+    pub fn next_indexed(&mut self) -> Option<(usize, &'a T)> {
+        let infinite = |prims: &[T], (index, item): (usize, &'a T)| (prims.len() + index, item);
 
-    fn next(&mut self) -> Option<&'a T> {
         if self.just_infinites {
-            return self.infinites.next();
+            return self.infinites.next().map(|item| infinite(self.prims, item));
         }
 
         loop {
             let ray = self.ray;
             if let Some(leaf_iter) = self.leaf_iter.as_mut() {
                 if let Some(val) = leaf_iter.filter(|x| x.bbox.intersects(ray)).next() {
-                    return Some(&self.prims[val.index]);
+                    return Some((val.index, &self.prims[val.index]));
                 }
                 // iterator went empty, so we'll pop from the stack and
                 // iterate on the next node's children now,
             }
 
             if let Some(node) = self.stack.pop() {
+                self.node_visits += 1;
                 for child in node.children.iter() {
                     if child.bbox.intersects(self.ray) {
                         self.stack.push(child);
@@ -183,8 +364,91 @@ impl<'a, T> Iterator for OctreeIterator<'a, T> where T: PartialBoundingBox {
                 self.leaf_iter = Some(node.leaf_data.iter());
             } else {
                 self.just_infinites = true;
-                return self.infinites.next()
+                return self.infinites.next().map(|item| infinite(self.prims, item));
             }
         }
     }
 }
+
+
+impl<'a, T> Iterator for OctreeIterator<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        self.next_indexed().map(|(_, item)| item)
+    }
+}
+
+#[test]
+fn it_reports_build_stats() {
+    let boxes: Vec<BBox> = (0..3).map(|i| {
+        let offset = Vec3 { x: i as f64 * 2.0, y: 0.0, z: 0.0 };
+        BBox { min: offset, max: offset + 1.0 }
+    }).collect();
+    let mut spanning = boxes.clone();
+    spanning.push(BBox { min: Vec3::zero(), max: Vec3 { x: 5.0, y: 1.0, z: 1.0 } });
+
+    let stats = spanning.into_iter().collect::<Octree<BBox>>().stats();
+    assert_eq!(4, stats.prims);
+    assert_eq!(0, stats.infinites);
+    assert!(stats.max_depth_reached >= 1);
+    assert!(stats.duplicated_references() > 0);
+    assert_eq!(stats.references, stats.leaf_occupancy.iter().enumerate().map(|(n, leaves)| n * leaves).sum::<usize>());
+}
+
+#[test]
+fn it_leaves_unbounded_prims_out_of_the_references() {
+    let items = vec![Some(BBox { min: Vec3::zero(), max: Vec3::one() }), None];
+    let stats = Octree::with_bounds(items, |item| *item).stats();
+    assert_eq!(1, stats.prims);
+    assert_eq!(1, stats.infinites);
+    assert_eq!(1, stats.references);
+    assert_eq!(0, stats.duplicated_references());
+    assert!(format!("{}", stats).contains("references: 1 (0 duplicated)"));
+}
+
+#[test]
+fn it_rejects_cached_nodes_at_the_wrong_depth() {
+    let boxes: Vec<BBox> = (0..8).map(|i| {
+        let offset = Vec3 { x: i as f64 * 2.0, y: 0.0, z: 0.0 };
+        BBox { min: offset, max: offset + 1.0 }
+    }).collect();
+    let octree: Octree<BBox> = boxes.into_iter().collect();
+    let mut w = CacheWriter::new();
+    octree.write_cache(&mut w, write_bbox);
+    let read = |bytes: &[u8]| Octree::read_cache(&mut CacheReader::new(bytes), 6 * 8, read_bbox);
+    assert_eq!(8, read(&w.bytes).unwrap().stats().prims);
+
+    // The root's depth follows its bounding box, after the items
+    let root_depth = 8 + 8 * 6 * 8 + 8 + 6 * 8;
+    for depth in [Octree::<BBox>::max_depth(8) + 1, i32::MAX].iter() {
+        let mut bytes = w.bytes.clone();
+        bytes[root_depth..root_depth + 4].copy_from_slice(&depth.to_le_bytes());
+        assert!(read(&bytes).is_err());
+    }
+
+    // A child count other than none or eight
+    let root_children = root_depth + 4 + 8 + octree.root.leaf_data.len() * 7 * 8;
+    let mut bytes = w.bytes.clone();
+    bytes[root_children] = 3;
+    assert!(read(&bytes).is_err());
+}
+
+#[test]
+fn it_counts_node_visits() {
+    let boxes: Vec<BBox> = (0..8).map(|i| {
+        let offset = Vec3 { x: i as f64 * 2.0, y: 0.0, z: 0.0 };
+        BBox { min: offset, max: offset + 1.0 }
+    }).collect();
+    let octree: Octree<BBox> = boxes.into_iter().collect();
+
+    let ray = Ray::new(Vec3 { x: 0.5, y: 0.5, z: -1.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    let mut iter = octree.intersect_iter(&ray);
+    assert!(iter.by_ref().count() >= 1);
+    assert!(iter.node_visits() >= 2);
+
+    let missing_ray = Ray::new(Vec3 { x: 0.5, y: 5.0, z: -1.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    let mut iter = octree.intersect_iter(&missing_ray);
+    assert_eq!(0, iter.by_ref().count());
+    assert_eq!(1, iter.node_visits());
+}
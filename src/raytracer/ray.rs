@@ -1,23 +1,24 @@
-use crate::prelude::*;
 use core::f64::INFINITY;
-use crate::raytracer::Intersection;
+use crate::raytracer::{Intersection, TraversalStats};
 use crate::scene::Scene;
 use crate::vec3::Vec3;
 
 #[cfg(test)]
-use geometry::prim::Prim;
+use crate::geometry::prim::Prim;
 #[cfg(test)]
-use geometry::prims::Sphere;
+use crate::geometry::prims::Sphere;
 #[cfg(test)]
-use light::light::Light;
+use crate::light::light::Light;
 #[cfg(test)]
-use material::materials::FlatMaterial;
+use crate::material::materials::FlatMaterial;
 
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
     pub inverse_dir: Vec3, // This is used to optimise ray-bbox intersection checks
     pub signs: [bool; 3], // Handle degenerate case in bbox intersection
+    pub time: f64, // When the ray was cast, for motion blur
+    pub wavelength: Option<f64>, // In nm, for the path a spectral render follows
 }
 
 impl Ray {
@@ -38,22 +39,42 @@ impl Ray {
                 inv_x > 0.0,
                 inv_y > 0.0,
                 inv_z > 0.0
-            ]
+            ],
+            time: 0.0,
+            wavelength: None
         }
     }
 
+    pub fn with_time(mut self, time: f64) -> Ray {
+        self.time = time;
+        self
+    }
+
+    pub fn with_wavelength(mut self, wavelength: Option<f64>) -> Ray {
+        self.wavelength = wavelength;
+        self
+    }
+
     pub fn get_nearest_hit<'a>(&'a self, scene: &'a Scene) -> Option<Intersection<'a>> {
+        self.get_nearest_hit_counted(scene, &mut TraversalStats::default())
+    }
+
+    /// As `get_nearest_hit`, also counting octree node visits and prim
+    /// intersection tests into `stats`.
+    pub fn get_nearest_hit_counted<'a>(&'a self, scene: &'a Scene, stats: &mut TraversalStats) -> Option<Intersection<'a>> {
         let t_min = 0.000001;
         let mut nearest_hit = None;
         let mut nearest_t = INFINITY;
 
-        for prim in scene.octree.intersect_iter(self) {
-            let intersection = prim.intersects(self, t_min, nearest_t);
+        let mut candidates = scene.octree.intersect_iter(self);
+        while let Some((prim_id, prim)) = candidates.next_indexed() {
+            let intersection = prim.intersects_counted(self, t_min, nearest_t, stats);
 
             nearest_hit = match intersection {
-                Some(intersection) => {
+                Some(mut intersection) => {
                     if intersection.t > t_min && intersection.t < nearest_t {
                         nearest_t = intersection.t;
+                        intersection.prim_id = prim_id;
                         Some(intersection)
                     } else {
                         nearest_hit
@@ -62,31 +83,17 @@ impl Ray {
                 None => nearest_hit
             };
         }
+        stats.node_visits += candidates.node_visits();
 
         nearest_hit
     }
-
-    pub fn perturb(&self, rng : &mut Box<dyn rand::RngCore>, magnitude: f64) -> Ray {
-        let rand_vec = Vec3::random(rng) * magnitude;
-
-        // Force random vectors to be in same direction as original vector
-        let corrected_rand_vec = if rand_vec.dot(&self.direction) < 0.0 {
-            rand_vec * -1.0
-        } else {
-            rand_vec
-        };
-
-        let direction = (corrected_rand_vec + self.direction).unit();
-
-        Ray::new(self.origin, direction)
-    }
 }
 
 #[test]
 fn it_gets_the_nearest_hit() {
-    let lights: Vec<Box<Light+Send+Sync>> = Vec::new();
+    let lights: Vec<Box<dyn Light+Send+Sync>> = Vec::new();
 
-    let mut prims: Vec<Box<Prim+Send+Sync>> = Vec::new();
+    let mut prims: Vec<Box<dyn Prim+Send+Sync>> = Vec::new();
     let mat = FlatMaterial { color: Vec3::one() };
     let sphere_top = Sphere {
         center: Vec3::zero(),
@@ -132,3 +139,59 @@ fn it_gets_the_nearest_hit() {
     let non_intersection = non_intersecting_ray.get_nearest_hit(&scene);
     assert!(non_intersection.is_none());
 }
+
+#[test]
+fn it_reports_which_prim_and_side_was_hit() {
+    let mut prims: Vec<Box<dyn Prim+Send+Sync>> = Vec::new();
+    for x in [0.0, 5.0].iter() {
+        prims.push(Box::new(Sphere {
+            center: Vec3 { x: *x, y: 0.0, z: 0.0 },
+            radius: 1.0,
+            material: Box::new(FlatMaterial { color: Vec3::one() }),
+        }));
+    }
+    let scene = Scene {
+        lights: Vec::new(),
+        background: Vec3::one(),
+        octree: prims.into_iter().collect(),
+    };
+
+    let entering = Ray::new(Vec3 { x: 5.0, y: 0.0, z: -5.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    let hit = entering.get_nearest_hit(&scene).unwrap();
+    assert_eq!(1, hit.prim_id);
+    assert!(hit.front_face);
+
+    // From the middle of the other sphere, the ray is on its way out
+    let leaving = Ray::new(Vec3::zero(), Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    let hit = leaving.get_nearest_hit(&scene).unwrap();
+    assert_eq!(0, hit.prim_id);
+    assert!(!hit.front_face);
+    assert_eq!(Vec3 { x: 0.0, y: 0.0, z: 1.0 }, hit.geometric_n);
+}
+
+#[test]
+fn it_reports_the_container_for_faces_and_children() {
+    use crate::geometry::prims::{unit_quad, Csg, CsgOp};
+
+    let sphere = |x: f64| Box::new(Sphere {
+        center: Vec3 { x: x, y: 0.0, z: 5.0 },
+        radius: 1.0,
+        material: Box::new(FlatMaterial { color: Vec3::one() }),
+    });
+    let prims: Vec<Box<dyn Prim+Send+Sync>> = vec![
+        sphere(10.0),
+        Box::new(unit_quad().build()),
+        Box::new(Csg::new(CsgOp::Union, sphere(-3.0), sphere(-4.0)))
+    ];
+    let scene = Scene {
+        lights: Vec::new(),
+        background: Vec3::one(),
+        octree: prims.into_iter().collect(),
+    };
+
+    // Either face of the quad, and either sphere of the union
+    for &(x, y, id) in [(0.75, 0.25, 1), (0.25, 0.75, 1), (-2.5, 0.0, 2), (-4.5, 0.0, 2)].iter() {
+        let ray = Ray::new(Vec3 { x: x, y: y, z: -1.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+        assert_eq!(id, ray.get_nearest_hit(&scene).unwrap().prim_id);
+    }
+}
@@ -1,14 +1,19 @@
 use crate::prelude::*;
-use crate::light::Light;
+use crate::material::{attenuation, Bsdf, Frame, Lobe};
 use crate::raytracer::compositor::{ColorRGBA, Surface, SurfaceFactory};
-use crate::raytracer::{Intersection, Ray};
+use crate::raytracer::{Intersection, Ray, TraversalStats};
+use crate::raytracer::spectrum::{at_wavelength, cie_xyz, sample_wavelength, xyz_to_rgb};
 use crate::scene::{Camera, Scene};
+use core::f64::consts::PI;
 use core::ops::Deref;
 use crate::vec3::Vec3;
 use rand::{Rng};
 
 pub static EPSILON: f64 = ::core::f64::EPSILON * 10000.0;
 
+/// Fraction of the way to a light that shadow feelers stop short by.
+static SHADOW_BIAS: f64 = 1e-6;
+
 #[derive(Clone, Copy)]
 pub struct RenderOptions {
     pub reflect_depth: u32,  // Maximum reflection recursions.
@@ -16,6 +21,48 @@ pub struct RenderOptions {
     pub shadow_samples: u32, // Number of samples for soft shadows and area lights.
     pub gloss_samples: u32,  // Number of samples for glossy reflections.
     pub pixel_samples: u32,  // The square of this is the number of samples per pixel.
+    pub spectral: bool,      // Trace a wavelength per sample, for dispersion. Needs more pixel samples.
+}
+
+/// A hit being shaded, as `Renderer::global_scatter` scatters off it.
+#[derive(Clone, Copy)]
+struct ScatterContext<'a> {
+    hit: &'a Intersection<'a>,
+    ray: &'a Ray,
+    options: RenderOptions,
+    bsdf: &'a Bsdf,
+    frame: Frame,
+    wo: Vec3,
+}
+
+/// What the traversal heatmap debug render counts per primary ray.
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+pub enum HeatmapMetric {
+    NodeVisits,
+    PrimTests,
+    Total,
+}
+
+impl HeatmapMetric {
+    fn cost(&self, stats: &TraversalStats) -> u32 {
+        match *self {
+            HeatmapMetric::NodeVisits => stats.node_visits,
+            HeatmapMetric::PrimTests => stats.prim_tests,
+            HeatmapMetric::Total => stats.node_visits + stats.prim_tests,
+        }
+    }
+}
+
+/// Maps t in [0, 1] to blue -> cyan -> green -> yellow -> red.
+fn heatmap_color(t: f64) -> Vec3 {
+    let t = t.clamp(0.0, 1.0) * 4.0;
+    match t as u32 {
+        0 => Vec3 { x: 0.0, y: t, z: 1.0 },
+        1 => Vec3 { x: 0.0, y: 1.0, z: 2.0 - t },
+        2 => Vec3 { x: t - 2.0, y: 1.0, z: 0.0 },
+        _ => Vec3 { x: 1.0, y: (4.0 - t).max(0.0), z: 0.0 },
+    }
 }
 
 #[derive(Clone)]
@@ -43,6 +90,43 @@ impl Renderer {
         surface
     }
 
+    /// Debug render: colors each pixel by how much octree traversal and prim
+    /// intersection work its primary ray took. A legend strip along the
+    /// bottom runs from 0 (blue) to the returned maximum cost (red).
+    pub fn render_heatmap(&self, camera: Camera, scene: &Scene, metric: HeatmapMetric) -> (Surface, u32) {
+        let width = camera.image_width as usize;
+        let height = camera.image_height as usize;
+
+        let mut costs = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let abs_y = height - y - 1;
+            for x in 0..width {
+                let mut stats = TraversalStats::default();
+                let ray = camera.get_ray(x as f64, abs_y as f64);
+                ray.get_nearest_hit_counted(scene, &mut stats);
+                costs.push(metric.cost(&stats));
+            }
+        }
+
+        let max_cost = costs.iter().cloned().max().unwrap_or(0).max(1);
+        let legend_height = (height / 32).max(4).min(height);
+
+        let mut surface = Surface::new(width, height, ColorRGBA::new_rgb(0, 0, 0));
+        for y in 0..height {
+            for x in 0..width {
+                let t = if y >= height - legend_height {
+                    x as f64 / (width - 1).max(1) as f64
+                } else {
+                    costs[y * width + x] as f64 / max_cost as f64
+                };
+                let color = heatmap_color(t);
+                surface[(x, y)] = ColorRGBA::new_rgb_clamped(color.x, color.y, color.z);
+            }
+        }
+
+        (surface, max_cost)
+    }
+
     fn render_tile(camera: Camera, rng: &mut Box<dyn rand::RngCore>, scene: &Scene, options: RenderOptions, tile_factory: SurfaceFactory) -> Surface {
         let mut tile = tile_factory.create();
         let pixel_samples = options.pixel_samples;
@@ -56,6 +140,7 @@ impl Renderer {
                 // Supersampling, jitter algorithm
                 let pixel_width = 1.0 / pixel_samples as f64;
                 let mut color = Vec3::zero();
+                let mut xyz = Vec3::zero();
 
                 for y_subpixel in 0u32..pixel_samples {
                     for x_subpixel in 0u32..pixel_samples {
@@ -67,13 +152,28 @@ impl Renderer {
                             (0.0, 0.0)
                         };
 
-                        let ray = camera.get_ray(abs_x as f64 + j_x, abs_y as f64 + j_y);
-                        let result = Renderer::trace(rng, scene, &ray, options, false);
-                        // Clamp subpixels for now to avoid intense aliasing when combined value is clamped later
-                        // Should think of a better way to handle this
-                        color = color + result.clamp(0.0, 1.0).scale(1.0 / (pixel_samples * pixel_samples) as f64);
+                        let time = camera.sample_time(rng);
+                        let ray = camera.get_ray_at(abs_x as f64 + j_x, abs_y as f64 + j_y, time);
+
+                        if options.spectral {
+                            // Spread the pixel's wavelengths evenly over the spectrum
+                            let stratum = y_subpixel * pixel_samples + x_subpixel;
+                            let u = (stratum as f64 + rng.gen::<f64>()) / (pixel_samples * pixel_samples) as f64;
+                            let wavelength = sample_wavelength(u);
+                            let result = Renderer::trace(rng, scene, &ray.with_wavelength(Some(wavelength)), options, true);
+                            // Spectral paths come back gray: any channel is the radiance
+                            xyz = xyz + cie_xyz(wavelength).scale(result.x.clamp(0.0, 1.0) / (pixel_samples * pixel_samples) as f64);
+                        } else {
+                            let result = Renderer::trace(rng, scene, &ray, options, true);
+                            // Clamp subpixels for now to avoid intense aliasing when combined value is clamped later
+                            // Should think of a better way to handle this
+                            color = color + result.clamp(0.0, 1.0).scale(1.0 / (pixel_samples * pixel_samples) as f64);
+                        }
                     }
                 }
+                if options.spectral {
+                    color = xyz_to_rgb(&xyz);
+                }
                 tile[(rel_x, rel_y)] = ColorRGBA::new_rgb_clamped(color.x, color.y, color.z);
             }
         }
@@ -81,143 +181,162 @@ impl Renderer {
         tile
     }
 
-    fn trace(rng : &mut Box<dyn rand::RngCore>, scene: &Scene, ray: &Ray, options: RenderOptions, inside: bool) -> Vec3 {
+    /// Light arriving back along `ray`. `emission` says whether to count
+    /// light given off by what it hits, which direct lighting has already
+    /// covered for rays scattered off anything but a perfect mirror or glass.
+    fn trace(rng : &mut Box<dyn rand::RngCore>, scene: &Scene, ray: &Ray, options: RenderOptions, emission: bool) -> Vec3 {
         if options.reflect_depth <= 0 || options.refract_depth <= 0 { return Vec3::zero() }
 
         match ray.get_nearest_hit(scene) {
             Some(hit) => {
-                let n = hit.n.unit();
                 let i = (-ray.direction).unit();
+                let (ng, n) = hit.facing_normals(&i);
+
+                // Shade in a frame about the outward normal, so hitting the
+                // back of a surface (leaving whatever it bounds) puts the eye
+                // below it
+                let outward_n = if hit.front_face { n } else { -n };
+                let (tangent, _) = hit.tangent_frame();
+                let frame = Frame::new(outward_n, tangent);
+                let bsdf = match ray.wavelength {
+                    Some(wavelength) => hit.material.spectral_bsdf(hit.u, hit.v, wavelength),
+                    None => hit.material.bsdf(hit.u, hit.v)
+                };
+                let wo = frame.to_local(i);
+
+                // Light given off by the surface itself, unless the path
+                // already counted it by sampling the surface as a light
+                let mut result = if emission {
+                    at_wavelength(hit.material.emission(hit.u, hit.v), ray.wavelength)
+                } else {
+                    Vec3::zero()
+                };
 
                 // Local lighting computation: surface shading, shadows
-                let mut result = scene.lights.iter().fold(Vec3::zero(), |color_acc, light| {
-                    let shadow = Renderer::shadow_intensity(scene, &hit, light, options.shadow_samples);
-                    let l = (light.center() - hit.position).unit();
-
-                    color_acc + light.color() * hit.material.sample(n, i, l, hit.u, hit.v) * shadow
-                });
-
-                // Global lighting computation: reflections, refractions
-                if hit.material.is_reflective() || hit.material.is_refractive() {
-                    let reflect_fresnel = Renderer::fresnel_reflect(hit.material.ior(), &i, &n, inside);
-                    let refract_fresnel = 1.0 - reflect_fresnel;
-
-                    if hit.material.is_reflective() {
-                        result = result + Renderer::global_reflection(rng, scene, &hit, options, inside,
-                                                                      &i, &n, reflect_fresnel);
+                for light in scene.lights.iter() {
+                    // Point light speedup (no point in sampling a point light multiple times)
+                    let light_samples = if light.is_point() { 1 } else { options.shadow_samples.max(1) };
+                    let mut light_acc = Vec3::zero();
+
+                    for _ in 0..light_samples {
+                        let sample = light.sample(rng, &hit.position);
+                        let l = (sample.position - hit.position).unit();
+
+                        // Lights behind the surface itself can't reach this side,
+                        // whatever the shading normal says
+                        if l.dot(&ng) <= 0.0 {
+                            continue;
+                        }
+                        let shadow = Renderer::shadow_intensity(scene, &hit, ray, &sample.position, options.shadow_samples);
+
+                        // A light's color is how bright it makes a white matte
+                        // surface facing it, hence the PI
+                        let wi = frame.to_local(l);
+                        let color = at_wavelength(bsdf.eval(wo, wi), ray.wavelength).scale(wi.z.abs() * PI);
+
+                        light_acc = light_acc + at_wavelength(sample.color, ray.wavelength) * color * shadow;
                     }
+                    result = result + light_acc.scale(1.0 / light_samples as f64);
+                }
 
-                    if hit.material.is_refractive() {
-                        result = result + Renderer::global_transmission(rng, scene, &hit, options, inside,
-                                                                        &i, &n, refract_fresnel);
-                    }
+                // Global lighting computation: reflections, refractions
+                let context = ScatterContext { hit: &hit, ray: ray, options: options, bsdf: &bsdf, frame: frame, wo: wo };
+                result = result + Renderer::global_scatter(rng, scene, &context, Lobe::REFLECTION);
+                result = result + Renderer::global_scatter(rng, scene, &context, Lobe::TRANSMISSION);
+
+                // Leaving a surface from behind, the ray crossed whatever it
+                // bounds and lost some of its light on the way
+                if !hit.front_face {
+                    result = result * attenuation(at_wavelength(hit.material.absorption(), ray.wavelength), hit.t);
                 }
 
                 result
             },
-            None => scene.background
+            None => at_wavelength(scene.background, ray.wavelength)
         }
     }
 
-    fn global_reflection(rng : &mut Box<dyn rand::RngCore>, scene: &Scene, hit: &Intersection, options: RenderOptions, inside: bool,
-                         i: &Vec3, n: &Vec3, reflect_fresnel: f64) -> Vec3 {
+    /// Light the specular lobes of `bsdf` pick up from the scene, in
+    /// reflection or transmission as `direction` says. Sharp lobes have one
+    /// direction to trace; blurred ones average `gloss_samples` of them.
+    fn global_scatter(rng : &mut Box<dyn rand::RngCore>, scene: &Scene, context: &ScatterContext, direction: Lobe) -> Vec3 {
+        let ScatterContext { hit, ray, options, bsdf, frame, wo } = *context;
 
-        let r = Vec3::reflect(&i, &n);
-        let reflect_ray = Ray::new(hit.position, r);
-        let next_reflect_options = RenderOptions { reflect_depth: options.reflect_depth - 1, ..options };
+        let mask = direction | Lobe::SPECULAR;
+        if !bsdf.has(mask) { return Vec3::zero() }
 
-        let reflection = if hit.material.is_glossy() {
-            // For glossy materials, average multiple perturbed reflection rays
-            // Potential overflow by scaling after everything is done instead of scaling every iteration?
-            (0..options.gloss_samples).fold(Vec3::zero(), |acc, _| {
-                let gloss_reflect_ray = reflect_ray.perturb(rng, hit.material.glossiness());
-                acc + Renderer::trace(rng, scene, &gloss_reflect_ray, next_reflect_options, inside)
-            }).scale(1.0 / options.gloss_samples as f64)
+        let transmits = direction == Lobe::TRANSMISSION;
+        let next_options = if transmits {
+            RenderOptions { refract_depth: options.refract_depth - 1, ..options }
         } else {
-            // For mirror-like materials just shoot a perfectly reflected ray instead
-            Renderer::trace(rng, scene, &reflect_ray, next_reflect_options, inside)
-        };
-
-        hit.material.global_specular(&reflection).scale(reflect_fresnel)
-    }
-
-    fn global_transmission(rng : &mut Box<dyn rand::RngCore>, scene: &Scene, hit: &Intersection, options: RenderOptions, inside: bool,
-                           i: &Vec3, n: &Vec3, refract_fresnel: f64) -> Vec3 {
-
-        let (t, actual_refract_fresnel) = match Vec3::refract(&i, &n, hit.material.ior(), inside) {
-            Some(ref t) => (*t, refract_fresnel),
-            None => {
-                (Vec3::reflect(&i, &n), 1.0) // Fresnel of 1.0 = total internal reflection (TODO: verify)
-            }
+            RenderOptions { reflect_depth: options.reflect_depth - 1, ..options }
         };
 
-        // Offset ray origin by EPSILON * direction to avoid hitting self when refracting
-        let refract_ray = Ray::new(hit.position + t.scale(EPSILON), t);
-        let next_refract_options = RenderOptions { refract_depth: options.refract_depth - 1, ..options };
-        let refraction = Renderer::trace(rng, scene, &refract_ray, next_refract_options, !inside);
-
-        hit.material.global_transmissive(&refraction).scale(actual_refract_fresnel)
-    }
-
-    fn shadow_intensity(scene: &Scene, hit: &Intersection,
-                        light: &Box<dyn Light+Send+Sync>, shadow_samples: u32) -> Vec3 {
+        let mut color = Vec3::zero();
+        let mut samples = 0;
+        while samples < options.gloss_samples.max(1) {
+            samples += 1;
+            let sample = match bsdf.sample(wo, (rng.gen(), rng.gen()), mask) {
+                Some(sample) => sample,
+                None => continue
+            };
 
-        if shadow_samples <= 0 { return Vec3::one() }
+            let d = frame.to_world(sample.wi);
+            // Offset ray origin by EPSILON * direction to avoid hitting self when refracting
+            let origin = if transmits { hit.position + d.scale(EPSILON) } else { hit.position };
+            let scattered_ray = Ray::new(origin, d).with_time(ray.time).with_wavelength(ray.wavelength);
+            let delta = sample.lobe.contains(Lobe::DELTA);
+            color = color + Renderer::trace(rng, scene, &scattered_ray, next_options, delta) * at_wavelength(sample.weight, ray.wavelength);
 
-        // Point light speedup (no point in sampling a point light multiple times)
-        let shadow_sample_tries = if light.is_point() { 1 } else { shadow_samples };
-        let mut shadow = Vec3::zero();
-
-        // Take average shadow color after jittering/sampling light position
-        for _ in 0..shadow_sample_tries {
-            // L has to be a unit vector for t_max 1:1 correspondence to
-            // distance to light to work. Shadow feelers only search up
-            // until light source.
-            let sampled_light_position = light.position();
-            let shadow_l = (sampled_light_position - hit.position).unit();
-            let shadow_ray = Ray::new(hit.position, shadow_l);
-            let distance_to_light = (sampled_light_position - hit.position).len();
-
-            // Check against candidate primitives in scene for occlusion
-            // and multiply shadow color by occluders' shadow colors
-            let candidate_nodes = scene.octree.intersect_iter(&shadow_ray);
-
-            shadow = shadow + candidate_nodes.fold(Vec3::one(), |shadow_acc, prim| {
-                let occlusion = prim.intersects(&shadow_ray, EPSILON, distance_to_light);
-                match occlusion {
-                    Some(occlusion) => shadow_acc * occlusion.material.transmission(),
-                    None => shadow_acc
-                }
-            });
+            if delta { break }
         }
 
-        shadow.scale(1.0 / shadow_sample_tries as f64)
+        color.scale(1.0 / samples as f64)
     }
 
-    /// Calculates the fresnel (reflectivity) given the index of refraction and the cos_angle
-    /// This uses Schlick's approximation. cos_angle is normal_dot_incoming
-    /// http://graphics.stanford.edu/courses/cs148-10-summer/docs/2006--degreve--reflection_refraction.pdf
-    fn fresnel_reflect(ior: f64, i: &Vec3, n: &Vec3, inside: bool) -> f64 {
-        let (n1, n2) = if inside { (ior, 1.0) } else { (1.0, ior) };
-        let actual_n = if inside { -*n } else { *n };
-
-        let r0_sqrt = (n1 - n2) / (n1 + n2);
-        let r0 = r0_sqrt * r0_sqrt;
-
-        let cos_angle = if n1 <= n2 {
-            i.dot(&actual_n)
-        } else {
-            let t = match Vec3::refract(i, &-actual_n, ior, inside) {
-                Some(x) => x,
-                None => return 1.0 // n1 > n2 && TIR
-            };
+    /// How much of the light from `light_position` gets through to the hit
+    /// point, through whatever lies in between.
+    fn shadow_intensity(scene: &Scene, hit: &Intersection, ray: &Ray,
+                        light_position: &Vec3, shadow_samples: u32) -> Vec3 {
 
-            -actual_n.dot(&t) // n1 > n2 && !TIR
-        };
+        if shadow_samples <= 0 { return Vec3::one() }
 
-        let cos_term = 1.0 - cos_angle;
+        // L has to be a unit vector for t_max 1:1 correspondence to
+        // distance to light to work. Shadow feelers only search up
+        // until light source, stopping just short in case the light is
+        // a glowing surface that would otherwise shadow itself.
+        let shadow_l = (*light_position - hit.position).unit();
+        let shadow_ray = Ray::new(hit.position, shadow_l).with_time(ray.time);
+        let distance_to_light = (*light_position - hit.position).len() * (1.0 - SHADOW_BIAS);
+
+        // Check against candidate primitives in scene for occlusion
+        // and multiply shadow color by occluders' shadow colors
+        let candidate_nodes = scene.octree.intersect_iter(&shadow_ray);
+
+        candidate_nodes.fold(Vec3::one(), |shadow_acc, prim| {
+            let occlusion = prim.intersects(&shadow_ray, EPSILON, distance_to_light);
+            match occlusion {
+                Some(occlusion) => {
+                    let transmission = at_wavelength(occlusion.material.transmission(), ray.wavelength);
+                    let absorption = at_wavelength(occlusion.material.absorption(), ray.wavelength);
+                    if absorption == Vec3::zero() {
+                        return shadow_acc * transmission;
+                    }
 
-        (r0 + ((1.0 - r0) * cos_term * cos_term * cos_term * cos_term * cos_term)).max(0.0).min(1.0)
+                    // Absorb along the stretch of the feeler inside the
+                    // occluder: to where it leaves, or all the way if it
+                    // started inside
+                    let inside = if occlusion.front_face {
+                        let exit = prim.intersects(&shadow_ray, occlusion.t + EPSILON, distance_to_light);
+                        exit.map_or(distance_to_light, |exit| exit.t) - occlusion.t
+                    } else {
+                        occlusion.t
+                    };
+                    shadow_acc * transmission * attenuation(absorption, inside)
+                },
+                None => shadow_acc
+            }
+        })
     }
 }
 
@@ -246,15 +365,15 @@ fn it_renders_the_background_of_an_empty_scene() {
         shadow_samples: 1,
         gloss_samples: 1,
         pixel_samples: 1,
+        spectral: false,
     };
 
 
     let renderer = Renderer {
         options: render_options,
-        tasks: 2,
     };
 
-    let image_data = renderer.render(camera, shared_scene);
+    let image_data = renderer.render(camera, &mut crate::util::get_rng(), shared_scene);
 
     for color in image_data.buffer.iter() {
         assert_eq!(color.r, 255);
@@ -262,3 +381,64 @@ fn it_renders_the_background_of_an_empty_scene() {
         assert_eq!(color.b, 0);
     }
 }
+
+#[test]
+fn it_darkens_shadows_through_thicker_glass() {
+    use crate::geometry::Prim;
+    use crate::geometry::prims::Sphere;
+    use crate::material::absorption_at_distance;
+    use crate::material::materials::{CookTorranceMaterial, FlatMaterial};
+
+    // A light straight above a floor point, with a tinted glass ball in between
+    let shadow_through = |radius: f64| {
+        let glass = CookTorranceMaterial {
+            transmission: Vec3::one(),
+            absorption: absorption_at_distance(Vec3 { x: 0.5, y: 1.0, z: 1.0 }, 1.0),
+            ..Default::default()
+        };
+        let prims: Vec<Box<dyn Prim+Send+Sync>> = vec![
+            Box::new(Sphere { center: Vec3::zero(), radius: radius, material: Box::new(glass) })
+        ];
+        let scene = Scene { lights: vec![], octree: prims.into_iter().collect(), background: Vec3::zero() };
+
+        let floor = Sphere { center: Vec3 { x: 0.0, y: -10.0, z: 0.0 }, radius: 5.0, material: Box::new(FlatMaterial { color: Vec3::one() }) };
+        let hit = floor.intersects(&Ray::new(Vec3::zero(), Vec3 { x: 0.0, y: -1.0, z: 0.0 }), 0.0, 100.0).unwrap();
+        Renderer::shadow_intensity(&scene, &hit, &Ray::new(Vec3::zero(), Vec3::one()), &Vec3 { x: 0.0, y: 5.0, z: 0.0 }, 1)
+    };
+
+    // Half the red is left per unit crossed, so a diameter of 1 then of 2
+    let thin = shadow_through(0.5);
+    let thick = shadow_through(1.0);
+    assert!((thin.x - 0.5).abs() < 1e-6 && (thick.x - 0.25).abs() < 1e-6, "{:?} {:?}", thin, thick);
+    assert!((thick.y - 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn it_lights_the_scene_from_glowing_prims() {
+    use crate::geometry::Prim;
+    use crate::geometry::prims::Sphere;
+    use crate::material::materials::{EmissiveMaterial, FlatMaterial};
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    // A glowing ball over a big white one, and no lights of its own
+    let glow = EmissiveMaterial { color: Vec3 { x: 1.0, y: 0.5, z: 0.25 }, intensity: 2.0, texture: None };
+    let prims: Vec<Box<dyn Prim+Send+Sync>> = vec![
+        Box::new(Sphere { center: Vec3 { x: 0.0, y: 3.0, z: 0.0 }, radius: 1.0, material: Box::new(glow) }),
+        Box::new(Sphere { center: Vec3 { x: 0.0, y: -100.0, z: 0.0 }, radius: 100.0, material: Box::new(FlatMaterial { color: Vec3::one() }) })
+    ];
+    let scene = Scene::new(vec![], prims, Vec3::zero());
+    assert_eq!(1, scene.lights.len());
+
+    let options = RenderOptions { reflect_depth: 2, refract_depth: 2, shadow_samples: 1024, gloss_samples: 1, pixel_samples: 1, spectral: false };
+    let mut rng: Box<dyn rand::RngCore> = Box::new(StdRng::seed_from_u64(50));
+
+    // Seen directly, it shows its own radiance
+    let at_ball = Ray::new(Vec3 { x: 0.0, y: 3.0, z: 5.0 }, Vec3 { x: 0.0, y: 0.0, z: -1.0 });
+    assert_eq!(Vec3 { x: 2.0, y: 1.0, z: 0.5 }, Renderer::trace(&mut rng, &scene, &at_ball, options, true));
+
+    // Below it, the floor gets about L·πr²/d² ≈ 2π/9 of red, over π
+    let at_floor = Ray::new(Vec3 { x: 0.0, y: 1.0, z: 0.0 }, Vec3 { x: 0.0, y: -1.0, z: 0.0 });
+    let floor = Renderer::trace(&mut rng, &scene, &at_floor, options, true);
+    assert!((floor.x - 2.0 / 9.0).abs() < 0.03 && (floor.y - floor.x / 2.0).abs() < 0.02, "{:?}", floor);
+}
@@ -0,0 +1,91 @@
+use crate::vec3::Vec3;
+
+/// The visible range spectral renders sample wavelengths from, in nm.
+pub static WAVELENGTH_MIN: f64 = 380.0;
+pub static WAVELENGTH_MAX: f64 = 730.0;
+
+/// What a flat spectrum averages to per channel once through the CIE
+/// matching functions and into linear sRGB; dividing by it keeps white
+/// white.
+static WHITE: Vec3 = Vec3 { x: 0.366753, y: 0.290127, z: 0.277286 };
+
+/// Maps `u` in [0, 1) uniformly onto the visible range.
+pub fn sample_wavelength(u: f64) -> f64 {
+    WAVELENGTH_MIN + (WAVELENGTH_MAX - WAVELENGTH_MIN) * u
+}
+
+/// A Gaussian with a different width either side of its mean.
+fn lobe(wavelength: f64, mean: f64, below: f64, above: f64) -> f64 {
+    let t = (wavelength - mean) / if wavelength < mean { below } else { above };
+    (-0.5 * t * t).exp()
+}
+
+/// The CIE 1931 2° color matching functions, from the multi-lobe fit of
+/// Wyman, Sloan and Shirley (2013).
+pub fn cie_xyz(wavelength: f64) -> Vec3 {
+    Vec3 {
+        x: 1.056 * lobe(wavelength, 599.8, 37.9, 31.0) + 0.362 * lobe(wavelength, 442.0, 16.0, 26.7)
+            - 0.065 * lobe(wavelength, 501.1, 20.4, 26.2),
+        y: 0.821 * lobe(wavelength, 568.8, 46.9, 40.5) + 0.286 * lobe(wavelength, 530.9, 16.3, 31.1),
+        z: 1.217 * lobe(wavelength, 437.0, 11.8, 36.0) + 0.681 * lobe(wavelength, 459.0, 26.0, 13.8)
+    }
+}
+
+/// Linear sRGB for CIE XYZ averaged over uniformly sampled wavelengths,
+/// balanced so a flat spectrum comes out as (1, 1, 1).
+pub fn xyz_to_rgb(xyz: &Vec3) -> Vec3 {
+    Vec3 {
+        x: (3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z) / WHITE.x,
+        y: (-0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z) / WHITE.y,
+        z: (0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z) / WHITE.z
+    }
+}
+
+fn smoothstep(from: f64, to: f64, x: f64) -> f64 {
+    let t = ((x - from) / (to - from)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// A smooth spectrum for an RGB color, at `wavelength`: blue below about
+/// 488nm, red above 588nm and green in between. Grays come out flat and
+/// colors within [0, 1] stay there, so albedos keep conserving energy.
+pub fn rgb_to_spectrum(rgb: &Vec3, wavelength: f64) -> f64 {
+    let red = smoothstep(558.0, 618.0, wavelength);
+    let blue = 1.0 - smoothstep(458.0, 518.0, wavelength);
+    rgb.x * red + rgb.y * (1.0 - red - blue) + rgb.z * blue
+}
+
+/// `color` as the renderer carries it along a path of `wavelength`: a gray
+/// of its spectral value, so multiplying colors multiplies spectra. RGB
+/// paths, with no wavelength, keep it as it is.
+pub fn at_wavelength(color: Vec3, wavelength: Option<f64>) -> Vec3 {
+    match wavelength {
+        Some(wavelength) => Vec3::one().scale(rgb_to_spectrum(&color, wavelength)),
+        None => color
+    }
+}
+
+#[test]
+fn it_round_trips_rgb_through_spectra() {
+    let count = 3500;
+    let round_trip = |rgb: Vec3| {
+        let xyz = (0..count).fold(Vec3::zero(), |acc, i| {
+            let wavelength = sample_wavelength((i as f64 + 0.5) / count as f64);
+            acc + cie_xyz(wavelength).scale(rgb_to_spectrum(&rgb, wavelength) / count as f64)
+        });
+        xyz_to_rgb(&xyz)
+    };
+
+    let white = round_trip(Vec3::one());
+    assert!((white - Vec3::one()).len() < 1e-3, "{:?}", white);
+    for &rgb in [Vec3 { x: 1.0, y: 0.0, z: 0.0 }, Vec3 { x: 0.0, y: 1.0, z: 0.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 }].iter() {
+        assert!((round_trip(rgb) - rgb).len() < 0.06, "{:?} {:?}", rgb, round_trip(rgb));
+    }
+}
+
+#[test]
+fn it_peaks_luminance_in_the_green() {
+    let y = |wavelength| cie_xyz(wavelength).y;
+    assert!(y(555.0) > 0.95 && y(555.0) < 1.05);
+    assert!(y(450.0) < 0.1 && y(650.0) < 0.15);
+}
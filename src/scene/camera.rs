@@ -1,5 +1,6 @@
 use crate::raytracer::Ray;
 use crate::vec3::Vec3;
+use rand::Rng;
 
 #[derive(Clone)]
 pub struct Camera {
@@ -17,6 +18,13 @@ pub struct Camera {
     pub pixel_width: f64,
     pub pixel_height: f64,
 
+    /// Rays are cast at times spread over this interval, for motion blur
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+    /// Where the camera is at `shutter_close`, if it moves while open. It
+    /// moves in a straight line from `position` and `look_at`.
+    pub end_position: Option<Vec3>,
+    pub end_look_at: Option<Vec3>,
 }
 
 impl Camera {
@@ -37,6 +45,10 @@ impl Camera {
             half_height: 0.0,
             pixel_width: 0.0,
             pixel_height: 0.0,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            end_position: None,
+            end_look_at: None,
         };
 
         camera.update_eye_vector();
@@ -45,12 +57,47 @@ impl Camera {
         camera
     }
 
+    pub fn set_shutter(&mut self, open: f64, close: f64) {
+        self.shutter_open = open;
+        self.shutter_close = close.max(open);
+    }
+
+    /// A random time while the shutter is open. Only draws from `rng` if
+    /// the shutter is open for any time at all.
+    pub fn sample_time(&self, rng: &mut Box<dyn rand::RngCore>) -> f64 {
+        if self.shutter_close > self.shutter_open {
+            self.shutter_open + rng.gen::<f64>() * (self.shutter_close - self.shutter_open)
+        } else {
+            self.shutter_open
+        }
+    }
+
     pub fn get_ray(&self, x: f64, y: f64) -> Ray {
+        self.get_ray_at(x, y, self.shutter_open)
+    }
+
+    pub fn get_ray_at(&self, x: f64, y: f64, time: f64) -> Ray {
+        let (position, eye, right) = self.pose_at(time);
         Ray::new(
-            self.position,
-            (self.eye + self.right.scale(x * self.pixel_width - self.half_width) +
+            position,
+            (eye + right.scale(x * self.pixel_width - self.half_width) +
             self.up.scale(y * self.pixel_height - self.half_height)).unit()
-        )
+        ).with_time(time)
+    }
+
+    /// Position, eye and right vectors at `time`.
+    fn pose_at(&self, time: f64) -> (Vec3, Vec3, Vec3) {
+        if self.end_position.is_none() && self.end_look_at.is_none() {
+            return (self.position, self.eye, self.right);
+        }
+
+        let duration = self.shutter_close - self.shutter_open;
+        let alpha = if duration > 0.0 { ((time - self.shutter_open) / duration).clamp(0.0, 1.0) } else { 0.0 };
+        let position = Vec3::lerp(&self.position, &self.end_position.unwrap_or(self.position), alpha);
+        let look_at = Vec3::lerp(&self.look_at, &self.end_look_at.unwrap_or(self.look_at), alpha);
+
+        let eye = (look_at - position).unit();
+        (position, eye, eye.cross(&self.up))
     }
 
     fn update_eye_vector(&mut self) {
@@ -72,3 +119,22 @@ impl Camera {
         self.pixel_height = camera_height / (self.image_height - 1) as f64;
     }
 }
+
+#[test]
+fn it_moves_over_the_shutter_interval() {
+    let mut camera = Camera::new(Vec3::zero(), Vec3 { x: 0.0, y: 0.0, z: 1.0 }, Vec3 { x: 0.0, y: 1.0, z: 0.0 }, 45.0, 33, 33);
+    camera.set_shutter(1.0, 2.0);
+    camera.end_position = Some(Vec3 { x: 10.0, y: 0.0, z: 0.0 });
+    camera.end_look_at = Some(Vec3 { x: 10.0, y: 0.0, z: 1.0 });
+
+    let ray = camera.get_ray_at(16.0, 16.0, 1.5);
+    assert_eq!(Vec3 { x: 5.0, y: 0.0, z: 0.0 }, ray.origin);
+    assert_eq!(Vec3 { x: 0.0, y: 0.0, z: 1.0 }, ray.direction);
+    assert_eq!(1.5, ray.time);
+
+    // A still camera's rays only differ in time
+    assert_eq!(Vec3::zero(), camera.get_ray(16.0, 16.0).origin);
+    camera.end_position = None;
+    camera.end_look_at = None;
+    assert_eq!(camera.get_ray(3.0, 7.0).direction, camera.get_ray_at(3.0, 7.0, 2.0).direction);
+}
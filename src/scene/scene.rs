@@ -1,5 +1,7 @@
 use crate::prelude::*;
+use std::collections::HashMap;
 use crate::light::Light;
+use crate::light::lights::AreaLight;
 use crate::geometry::Prim;
 use crate::raytracer::Octree;
 use crate::vec3::Vec3;
@@ -9,3 +11,55 @@ pub struct Scene {
     pub octree: Octree<Box<dyn Prim+Send+Sync>>,
     pub background: Vec3,
 }
+
+impl Scene {
+    /// Builds the octree over `prims`, adding an area light to `lights` for
+    /// each glowing prim that can be sampled. Loose triangles that glow
+    /// alike share one light, as the faces of a mesh would.
+    pub fn new(mut lights: Vec<Box<dyn Light+Send+Sync>>, prims: Vec<Box<dyn Prim+Send+Sync>>, background: Vec3) -> Scene {
+        let mut area_lights: Vec<AreaLight> = Vec::new();
+        let mut merged: HashMap<[u64; 4], usize> = HashMap::new();
+        for light in prims.iter().flat_map(|prim| prim.area_lights()) {
+            match light.merge_key() {
+                Some(key) => match merged.get(&key) {
+                    Some(&index) => area_lights[index].merge(light),
+                    None => {
+                        merged.insert(key, area_lights.len());
+                        area_lights.push(light);
+                    }
+                },
+                None => area_lights.push(light)
+            }
+        }
+        lights.extend(area_lights.into_iter().map(|light| Box::new(light) as Box<dyn Light+Send+Sync>));
+
+        Scene {
+            lights: lights,
+            octree: prims.into_iter().collect(),
+            background: background,
+        }
+    }
+}
+
+#[test]
+fn it_joins_triangles_that_glow_alike_into_one_light() {
+    use crate::geometry::prims::TriangleOptions;
+    use crate::material::materials::EmissiveMaterial;
+
+    let glowing = |x: f64, color: Vec3| -> Box<dyn Prim+Send+Sync> {
+        let mut triopts = TriangleOptions::new(
+            Vec3 { x: x, y: 0.0, z: 0.0 },
+            Vec3 { x: x + 1.0, y: 0.0, z: 0.0 },
+            Vec3 { x: x, y: 1.0, z: 0.0 });
+        triopts.material(Box::new(EmissiveMaterial { color: color, ..EmissiveMaterial::default() }));
+        Box::new(triopts.build())
+    };
+    let red = Vec3 { x: 1.0, y: 0.0, z: 0.0 };
+    let prims = vec![glowing(0.0, Vec3::one()), glowing(2.0, red), glowing(4.0, Vec3::one())];
+
+    let scene = Scene::new(Vec::new(), prims, Vec3::zero());
+    assert_eq!(2, scene.lights.len());
+    // The white light sits between its two triangles
+    assert!((scene.lights[0].center() - Vec3 { x: 2.0 + 1.0 / 3.0, y: 1.0 / 3.0, z: 0.0 }).len() < 1e-9);
+    assert_eq!(red, scene.lights[1].color());
+}
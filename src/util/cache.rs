@@ -0,0 +1,311 @@
+use std::fmt;
+use std::fs;
+use std::hash::Hasher;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::geometry::prims::Mesh;
+use crate::material::Material;
+
+/// Bump this whenever the layout written by `write_cache` changes anywhere.
+pub static CACHE_VERSION: u32 = 2;
+
+static MAGIC: &[u8; 4] = b"RTAC";
+static HEADER_LEN: usize = 4 + 4 + 8 + 8;
+
+#[derive(Debug)]
+pub enum CacheError {
+    Io(io::Error),
+    Stale,
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CacheError::Io(ref err) => write!(f, "{}", err),
+            CacheError::Stale => write!(f, "built from other geometry or by another version"),
+            CacheError::Corrupt(message) => write!(f, "corrupt: {}", message),
+        }
+    }
+}
+
+impl From<io::Error> for CacheError {
+    fn from(err: io::Error) -> CacheError {
+        CacheError::Io(err)
+    }
+}
+
+/// 64-bit FNV-1a. Unlike `DefaultHasher` this is stable across Rust
+/// releases, so keys written by one build can be checked by another.
+pub struct Fnv1a(u64);
+
+impl Fnv1a {
+    pub fn new() -> Fnv1a {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Little-endian binary writer for cache payloads.
+pub struct CacheWriter {
+    pub bytes: Vec<u8>
+}
+
+impl CacheWriter {
+    pub fn new() -> CacheWriter {
+        CacheWriter { bytes: Vec::new() }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_i32(&mut self, value: i32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_f64(&mut self, value: f64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_str(&mut self, value: &str) {
+        self.write_u64(value.len() as u64);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+}
+
+/// Reads back what `CacheWriter` wrote. Every read is bounds-checked so a
+/// truncated or garbled file turns into `CacheError::Corrupt`, never a panic.
+pub struct CacheReader<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+
+impl<'a> CacheReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> CacheReader<'a> {
+        CacheReader { bytes: bytes, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], CacheError> {
+        if self.bytes.len() - self.pos < len {
+            return Err(CacheError::Corrupt("unexpected end of cache"));
+        }
+        let slice = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, CacheError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, CacheError> {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32, CacheError> {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(i32::from_le_bytes(buf))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, CacheError> {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    pub fn read_f64(&mut self) -> Result<f64, CacheError> {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(f64::from_le_bytes(buf))
+    }
+
+    pub fn read_str(&mut self) -> Result<String, CacheError> {
+        let len = self.read_len(1)?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| CacheError::Corrupt("string is not UTF-8"))
+    }
+
+    /// Reads an element count, rejecting counts that could not possibly fit
+    /// in the rest of the cache so we never allocate on a corrupt length.
+    pub fn read_len(&mut self, min_elem_size: usize) -> Result<usize, CacheError> {
+        let len = self.read_u64()? as usize;
+        if len.saturating_mul(min_elem_size) > self.bytes.len() - self.pos {
+            return Err(CacheError::Corrupt("length exceeds cache size"));
+        }
+        Ok(len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+}
+
+pub fn checksum(bytes: &[u8]) -> u64 {
+    let mut hasher = Fnv1a::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// Where the cache for one mesh built from a source file lives. `source` is
+/// the `checksum` of the file, so an edited or renamed-over file never
+/// reuses another's cache; `part` tells apart meshes built from one file.
+pub fn mesh_path(dir: &Path, source: u64, part: usize) -> PathBuf {
+    dir.join(format!("{:016x}-{}.mesh", source, part))
+}
+
+/// Reads the payload of a cache file, checking that it was written by this
+/// cache version for the same `key` and that it hasn't been truncated or
+/// otherwise damaged since.
+pub fn read(path: &Path, key: u64) -> Result<Vec<u8>, CacheError> {
+    let mut bytes = fs::read(path)?;
+    if bytes.len() < HEADER_LEN || &bytes[0..4] != MAGIC {
+        return Err(CacheError::Corrupt("bad header"));
+    }
+
+    let (version, file_key, sum) = {
+        let mut header = CacheReader::new(&bytes[4..HEADER_LEN]);
+        (header.read_u32()?, header.read_u64()?, header.read_u64()?)
+    };
+
+    if version != CACHE_VERSION || file_key != key {
+        return Err(CacheError::Stale);
+    }
+
+    let payload = bytes.split_off(HEADER_LEN);
+    if checksum(&payload) != sum {
+        return Err(CacheError::Corrupt("checksum mismatch"));
+    }
+
+    Ok(payload)
+}
+
+/// Writes to a temporary file first so an interrupted write can't leave a
+/// half-written cache behind.
+pub fn write(path: &Path, key: u64, payload: &[u8]) -> io::Result<()> {
+    let mut header = CacheWriter::new();
+    header.bytes.extend_from_slice(MAGIC);
+    header.write_u32(CACHE_VERSION);
+    header.write_u64(key);
+    header.write_u64(checksum(payload));
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp_path = path.with_extension("tmp");
+    let mut bytes = header.bytes;
+    bytes.extend_from_slice(payload);
+    fs::write(&tmp_path, &bytes)?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Reads back a mesh `write_mesh` cached at `path` under `key`. The
+/// materials aren't cached: `materials` makes them again from whatever
+/// `write_mesh` was given to write ahead of the mesh.
+pub fn read_mesh<F>(path: &Path, key: u64, materials: F) -> Result<Mesh, CacheError>
+        where F: FnOnce(&mut CacheReader) -> Result<Vec<Box<dyn Material+Send+Sync>>, CacheError> {
+    let payload = read(path, key)?;
+    let mut reader = CacheReader::new(&payload);
+    let materials = materials(&mut reader)?;
+    Mesh::read_cache(&mut reader, materials)
+}
+
+/// Caches `mesh` at `path` under `key`, which should hash everything the
+/// mesh was built from. `write_materials` writes what its materials can be
+/// made again from.
+pub fn write_mesh<F>(path: &Path, key: u64, mesh: &Mesh, write_materials: F) -> io::Result<()>
+        where F: FnOnce(&mut CacheWriter) {
+    let mut writer = CacheWriter::new();
+    write_materials(&mut writer);
+    mesh.write_cache(&mut writer);
+    write(path, key, &writer.bytes)
+}
+
+#[cfg(test)]
+fn test_materials(r: &mut CacheReader) -> Result<Vec<Box<dyn Material+Send+Sync>>, CacheError> {
+    use crate::material::materials::FlatMaterial;
+    use crate::vec3::Vec3;
+
+    let count = r.read_len(0)?;
+    Ok((0..count).map(|_| Box::new(FlatMaterial { color: Vec3::one() }) as Box<dyn Material+Send+Sync>).collect())
+}
+
+#[test]
+fn it_round_trips_a_mesh_through_the_cache() {
+    use crate::geometry::Prim;
+    use crate::raytracer::Ray;
+    use crate::vec3::Vec3;
+
+    let path = std::env::temp_dir().join(format!("raytracer-cache-test-{}.bin", std::process::id()));
+    let built = crate::geometry::prims::unit_quad().build();
+    write_mesh(&path, 7, &built, |w| w.write_u64(1)).unwrap();
+    let cached = read_mesh(&path, 7, test_materials).unwrap();
+
+    let ray = Ray::new(Vec3 { x: 0.25, y: 0.75, z: -1.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    assert_eq!(built.intersects(&ray, 0.0, 10.0).unwrap().t, cached.intersects(&ray, 0.0, 10.0).unwrap().t);
+    assert_eq!(built.len(), cached.len());
+
+    let mut name = CacheWriter::new();
+    name.write_str("glass");
+    assert_eq!("glass", CacheReader::new(&name.bytes).read_str().unwrap());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_rejects_stale_and_corrupt_caches() {
+    let path = std::env::temp_dir().join(format!("raytracer-cache-test-stale-{}.bin", std::process::id()));
+    write_mesh(&path, 7, &crate::geometry::prims::unit_quad().build(), |w| w.write_u64(1)).unwrap();
+
+    // Caches built from other sources have other keys
+    match read(&path, 8) {
+        Err(CacheError::Stale) => {},
+        other => panic!("expected stale cache, got {:?}", other.map(|_| ()))
+    }
+
+    // Materials that can't be made as they were cached
+    match read_mesh(&path, 7, |r| test_materials(r).map(|_| Vec::new())) {
+        Err(CacheError::Stale) => {},
+        other => panic!("expected stale cache, got {:?}", other.map(|_| ()))
+    }
+
+    // Truncated payloads are caught by the reader even if the checksum matched
+    let payload = read(&path, 7).unwrap();
+    let mut reader = CacheReader::new(&payload[..payload.len() / 2]);
+    let materials = test_materials(&mut reader).unwrap();
+    assert!(Mesh::read_cache(&mut reader, materials).is_err());
+
+    // Flip a byte in the payload
+    let mut bytes = fs::read(&path).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 0xff;
+    fs::write(&path, &bytes).unwrap();
+    match read(&path, 7) {
+        Err(CacheError::Corrupt(_)) => {},
+        other => panic!("expected corrupt cache, got {:?}", other.map(|_| ()))
+    }
+
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,775 @@
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+use crate::geometry::prims::{Mesh, MeshOptions};
+use crate::geometry::Prim;
+use crate::light::Light;
+use crate::light::lights::PointLight;
+use crate::mat4::Mat4;
+use crate::material::{absorption_at_distance, Dispersion, ScalarTexture, TextureChannel};
+use crate::material::materials::PrincipledMaterial;
+use crate::material::textures::ImageTexture;
+use crate::scene::{Camera, Scene};
+use crate::util::cache::{self, Fnv1a};
+use crate::vec3::Vec3;
+use super::json::{self, Json};
+use super::{parse_image, read_file, ImportError};
+
+/// A perspective camera placed by the node hierarchy. The image size comes
+/// from the render settings, so this only becomes a `Camera` once it's known.
+#[derive(Clone)]
+pub struct GltfCamera {
+    pub position: Vec3,
+    pub look_at: Vec3,
+    pub up: Vec3,
+    /// Full vertical field of view in radians
+    pub yfov: f64,
+}
+
+impl GltfCamera {
+    pub fn to_camera(&self, image_width: u32, image_height: u32) -> Camera {
+        // `Camera` takes the half-angle of the horizontal field of view
+        let aspect = image_width as f64 / image_height as f64;
+        let fov_deg = ((self.yfov / 2.0).tan() * aspect).atan().to_degrees();
+        Camera::new(self.position, self.look_at, self.up, fov_deg, image_width, image_height)
+    }
+}
+
+pub struct GltfScene {
+    /// One mesh per primitive per node, in world space
+    pub meshes: Vec<Mesh>,
+    pub cameras: Vec<GltfCamera>,
+    pub lights: Vec<PointLight>,
+}
+
+impl GltfScene {
+    pub fn into_scene(self, background: Vec3) -> Scene {
+        let prims: Vec<Box<dyn Prim+Send+Sync>> = self.meshes.into_iter()
+            .map(|mesh| Box::new(mesh) as Box<dyn Prim+Send+Sync>)
+            .collect();
+        let lights: Vec<Box<dyn Light+Send+Sync>> = self.lights.into_iter()
+            .map(|light| Box::new(light) as Box<dyn Light+Send+Sync>)
+            .collect();
+
+        Scene::new(lights, prims, background)
+    }
+}
+
+static GLB_MAGIC: &[u8; 4] = b"glTF";
+static GLB_JSON_CHUNK: u32 = 0x4e4f_534a;
+static GLB_BIN_CHUNK: u32 = 0x004e_4942;
+
+fn read_u32_le(bytes: &[u8], pos: usize) -> Option<u32> {
+    bytes.get(pos..pos + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Splits a .glb container into its JSON and binary chunks.
+fn parse_glb(bytes: &[u8]) -> Result<(&[u8], Option<&[u8]>), String> {
+    let version = read_u32_le(bytes, 4).ok_or_else(|| "truncated GLB header".to_string())?;
+    if version != 2 {
+        return Err(format!("unsupported glTF version {}", version));
+    }
+
+    let mut chunks = Vec::new();
+    let mut pos = 12;
+    while let (Some(len), Some(kind)) = (read_u32_le(bytes, pos), read_u32_le(bytes, pos + 4)) {
+        let data = bytes.get(pos + 8..pos + 8 + len as usize).ok_or_else(|| "truncated GLB chunk".to_string())?;
+        chunks.push((kind, data));
+        pos += 8 + len as usize;
+    }
+
+    match chunks.first() {
+        Some(&(kind, json)) if kind == GLB_JSON_CHUNK => {
+            let bin = chunks.get(1).filter(|&&(kind, _)| kind == GLB_BIN_CHUNK).map(|&(_, data)| data);
+            Ok((json, bin))
+        },
+        _ => Err("GLB file doesn't start with a JSON chunk".to_string())
+    }
+}
+
+fn decode_base64(text: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for c in text.bytes().filter(|c| !c.is_ascii_whitespace() && *c != b'=') {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return Err(format!("invalid base64 character '{}'", c as char))
+        };
+        buffer = buffer << 6 | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+
+    Ok(out)
+}
+
+/// Undoes the percent-encoding glTF URIs use for spaces and the like.
+fn decode_uri_path(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' {
+            bytes.get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        match escaped {
+            Some(byte) => { out.push(byte); i += 3; },
+            None => { out.push(bytes[i]); i += 1; }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+struct Loader<'a> {
+    json: &'a Json,
+    dir: PathBuf,
+    buffers: Vec<Vec<u8>>,
+    textures: HashMap<usize, Option<ImageTexture>>,
+    materials: HashMap<usize, PrincipledMaterial>,
+    scene: GltfScene,
+    /// Cache directory and the checksum of the file and its buffers, to
+    /// build meshes through
+    cache: Option<(PathBuf, u64)>,
+}
+
+impl<'a> Loader<'a> {
+    fn load_uri(&self, uri: &str) -> Result<Vec<u8>, String> {
+        if uri.starts_with("data:") {
+            match uri.find(";base64,") {
+                Some(start) => decode_base64(&uri[start + 8..]),
+                None => Err("only base64 data URIs are supported".to_string())
+            }
+        } else {
+            let path = self.dir.join(decode_uri_path(uri));
+            read_file(&path).map_err(|err| err.to_string())
+        }
+    }
+
+    fn buffer_view(&self, index: usize) -> Result<&[u8], String> {
+        let view = self.json.get("bufferViews").index(index);
+        let buffer = view.get("buffer").as_usize()
+            .and_then(|b| self.buffers.get(b))
+            .ok_or_else(|| format!("bufferView {} has no valid buffer", index))?;
+        let offset = view.get("byteOffset").as_usize().unwrap_or(0);
+        let len = view.get("byteLength").as_usize().unwrap_or(0);
+
+        buffer.get(offset..offset.saturating_add(len))
+            .ok_or_else(|| format!("bufferView {} is out of bounds", index))
+    }
+
+    /// Reads an accessor as a flat list of values, `components` per element.
+    fn accessor(&self, index: usize) -> Result<(Vec<f64>, usize), String> {
+        let accessor = self.json.get("accessors").index(index);
+        if !accessor.get("sparse").is_null() {
+            return Err(format!("accessor {}: sparse accessors are not supported", index));
+        }
+
+        let components = match accessor.get("type").as_str() {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") | Some("MAT2") => 4,
+            Some("MAT3") => 9,
+            Some("MAT4") => 16,
+            _ => return Err(format!("accessor {} has an invalid type", index))
+        };
+        let component_type = accessor.get("componentType").as_usize().unwrap_or(0);
+        let size = match component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            _ => return Err(format!("accessor {} has an invalid componentType", index))
+        };
+        let count = accessor.get("count").as_usize().unwrap_or(0);
+        let normalized = accessor.get("normalized") == &Json::Bool(true);
+
+        let view_index = match accessor.get("bufferView").as_usize() {
+            Some(view_index) => view_index,
+            None => return Ok((vec![0.0; count * components], components))
+        };
+        let view = self.buffer_view(view_index)?;
+        let offset = accessor.get("byteOffset").as_usize().unwrap_or(0);
+        let stride = self.json.get("bufferViews").index(view_index).get("byteStride").as_usize()
+            .unwrap_or(size * components);
+
+        let end = count.checked_sub(1)
+            .and_then(|last| last.checked_mul(stride))
+            .and_then(|last| last.checked_add(offset + size * components));
+        if count > 0 && end.is_none_or(|end| end > view.len()) {
+            return Err(format!("accessor {} is out of bounds", index));
+        }
+
+        let mut values = Vec::with_capacity(count * components);
+        for element in 0..count {
+            for component in 0..components {
+                let pos = offset + element * stride + component * size;
+                let b = &view[pos..pos + size];
+                let value = match component_type {
+                    5120 => if normalized { (b[0] as i8 as f64 / 127.0).max(-1.0) } else { b[0] as i8 as f64 },
+                    5121 => if normalized { b[0] as f64 / 255.0 } else { b[0] as f64 },
+                    5122 => {
+                        let v = i16::from_le_bytes([b[0], b[1]]) as f64;
+                        if normalized { (v / 32767.0).max(-1.0) } else { v }
+                    },
+                    5123 => {
+                        let v = u16::from_le_bytes([b[0], b[1]]) as f64;
+                        if normalized { v / 65535.0 } else { v }
+                    },
+                    5125 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    _ => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64
+                };
+                values.push(value);
+            }
+        }
+
+        Ok((values, components))
+    }
+
+    fn vec3_accessor(&self, index: usize) -> Result<Vec<Vec3>, String> {
+        match self.accessor(index)? {
+            (values, 3) => Ok(values.chunks(3).map(|v| Vec3 { x: v[0], y: v[1], z: v[2] }).collect()),
+            _ => Err(format!("accessor {} is not a VEC3", index))
+        }
+    }
+
+    /// Textures that fail to load are skipped with a warning, like in OBJ
+    /// materials.
+    fn texture(&mut self, index: usize) -> Option<ImageTexture> {
+        if let Some(texture) = self.textures.get(&index) {
+            return texture.clone();
+        }
+
+        let image = self.json.get("textures").index(index).get("source").as_usize()
+            .map(|source| self.json.get("images").index(source));
+        let bytes = match image {
+            Some(image) => match (image.get("uri").as_str(), image.get("bufferView").as_usize()) {
+                (Some(uri), _) => self.load_uri(uri),
+                (None, Some(view)) => self.buffer_view(view).map(|bytes| bytes.to_vec()),
+                _ => Err("image has no data".to_string())
+            },
+            None => Err("texture has no source image".to_string())
+        };
+
+        let texture = match bytes.and_then(|bytes| parse_image(&bytes)) {
+            Ok(surface) => Some(ImageTexture::new(surface)),
+            Err(err) => {
+                eprintln!("Skipping glTF texture {}: {}", index, err);
+                None
+            }
+        };
+        self.textures.insert(index, texture.clone());
+        texture
+    }
+
+    /// Reads a metallic-roughness material, along with the clearcoat,
+    /// transmission, volume, ior, specular and dispersion extensions that map
+    /// onto `PrincipledMaterial`.
+    fn material(&mut self, index: Option<usize>) -> PrincipledMaterial {
+        let index = match index {
+            Some(index) => index,
+            None => return PrincipledMaterial::default()
+        };
+        if let Some(material) = self.materials.get(&index) {
+            return material.clone();
+        }
+
+        let json = self.json;
+        let json = json.get("materials").index(index);
+        let pbr = json.get("pbrMetallicRoughness");
+        let extensions = json.get("extensions");
+        let factor = |value: &Json, default: f64| value.as_f64().unwrap_or(default).clamp(0.0, 1.0);
+
+        let clearcoat = extensions.get("KHR_materials_clearcoat");
+        let transmission = extensions.get("KHR_materials_transmission");
+        let volume = extensions.get("KHR_materials_volume");
+        let ior = extensions.get("KHR_materials_ior").get("ior").as_f64().unwrap_or(1.5).max(1.0);
+        // Our specular of 0.5 is glTF's 4% at an ior of 1.5
+        let f0 = ((ior - 1.0) / (ior + 1.0)).powi(2);
+        let specular = factor(extensions.get("KHR_materials_specular").get("specularFactor"), 1.0);
+
+        let default = PrincipledMaterial::default();
+        let mut material = PrincipledMaterial {
+            base_color: color(pbr.get("baseColorFactor")),
+            metallic: factor(pbr.get("metallicFactor"), 1.0),
+            roughness: factor(pbr.get("roughnessFactor"), 1.0),
+            specular: (f0 * specular / 0.08).min(1.0),
+            clearcoat: factor(clearcoat.get("clearcoatFactor"), 0.0),
+            clearcoat_roughness: factor(clearcoat.get("clearcoatRoughnessFactor"), 0.0),
+            transmission: factor(transmission.get("transmissionFactor"), 0.0),
+            ior: ior,
+            absorption: match volume.get("attenuationDistance").as_f64() {
+                Some(distance) if distance > 0.0 => absorption_at_distance(color(volume.get("attenuationColor")), distance),
+                _ => Vec3::zero()
+            },
+            // The extension's dispersion is 20 over the Abbe number
+            dispersion: match extensions.get("KHR_materials_dispersion").get("dispersion").as_f64() {
+                Some(dispersion) if dispersion > 0.0 => Some(Dispersion::from_abbe(ior, 20.0 / dispersion)),
+                _ => None
+            },
+            ..default
+        };
+
+        material.base_color_texture = self.texture_of(pbr.get("baseColorTexture"))
+            .map(|texture| Box::new(texture) as Box<_>);
+        // Roughness is in green and metalness in blue of the same image
+        let metallic_roughness = self.texture_of(pbr.get("metallicRoughnessTexture"));
+        material.roughness_texture = metallic_roughness.clone()
+            .map(|texture| ScalarTexture::new(Box::new(texture), TextureChannel::Green));
+        material.metallic_texture = metallic_roughness
+            .map(|texture| ScalarTexture::new(Box::new(texture), TextureChannel::Blue));
+        material.clearcoat_texture = self.texture_of(clearcoat.get("clearcoatTexture"))
+            .map(|texture| ScalarTexture::new(Box::new(texture), TextureChannel::Red));
+        material.clearcoat_roughness_texture = self.texture_of(clearcoat.get("clearcoatRoughnessTexture"))
+            .map(|texture| ScalarTexture::new(Box::new(texture), TextureChannel::Green));
+        material.transmission_texture = self.texture_of(transmission.get("transmissionTexture"))
+            .map(|texture| ScalarTexture::new(Box::new(texture), TextureChannel::Red));
+
+        self.materials.insert(index, material.clone());
+        material
+    }
+
+    /// The texture a textureInfo object points at, if any.
+    fn texture_of(&mut self, info: &Json) -> Option<ImageTexture> {
+        info.get("index").as_usize().and_then(|texture| self.texture(texture))
+    }
+
+    fn mesh(&mut self, index: usize, transform: &Mat4) -> Result<(), String> {
+        let primitives = self.json.get("meshes").index(index).get("primitives").members();
+
+        for (primitive_index, primitive) in primitives.iter().enumerate() {
+            let mode = primitive.get("mode").as_usize().unwrap_or(4);
+            if mode != 4 {
+                eprintln!("Skipping glTF mesh {} primitive {}: mode {} is not triangles", index, primitive_index, mode);
+                continue;
+            }
+
+            // A primitive cached from the same file and placement skips
+            // decoding its accessors
+            let material = self.material(primitive.get("material").as_usize());
+            let cache = self.cache.as_ref().map(|&(ref dir, source)| {
+                (cache::mesh_path(dir, source, self.scene.meshes.len()), primitive_key(source, index, primitive_index, transform))
+            });
+            if let Some((ref path, key)) = cache {
+                if let Ok(mesh) = cache::read_mesh(path, key, |_| Ok(vec![Box::new(material.clone())])) {
+                    self.scene.meshes.push(mesh);
+                    continue;
+                }
+            }
+
+            let attributes = primitive.get("attributes");
+            let positions = attributes.get("POSITION").as_usize()
+                .ok_or_else(|| format!("mesh {} primitive {} has no POSITION", index, primitive_index))?;
+            let positions: Vec<Vec3> = self.vec3_accessor(positions)?.iter()
+                .map(|p| Mat4::mult_p(transform, p))
+                .collect();
+
+            let normals = match attributes.get("NORMAL").as_usize() {
+                Some(normals) => Some(self.vec3_accessor(normals)?.iter()
+                    .map(|n| Mat4::transform_normal(n, transform).unit())
+                    .collect::<Vec<Vec3>>()),
+                None => None
+            };
+
+            // glTF puts the UV origin at the top left of the image
+            let texinfo = match attributes.get("TEXCOORD_0").as_usize() {
+                Some(texcoords) => match self.accessor(texcoords)? {
+                    (values, 2) => Some(values.chunks(2).map(|uv| (uv[0], 1.0 - uv[1])).collect::<Vec<(f64, f64)>>()),
+                    _ => return Err(format!("accessor {} is not a VEC2", texcoords))
+                },
+                None => None
+            };
+
+            let indices: Vec<u32> = match primitive.get("indices").as_usize() {
+                Some(indices) => self.accessor(indices)?.0.iter().map(|&i| i as u32).collect(),
+                None => (0..positions.len() as u32).collect()
+            };
+            if indices.iter().any(|&i| i as usize >= positions.len()) {
+                return Err(format!("mesh {} primitive {} has an index out of range", index, primitive_index));
+            }
+            if normals.as_ref().is_some_and(|n| n.len() != positions.len()) ||
+               texinfo.as_ref().is_some_and(|t| t.len() != positions.len()) {
+                return Err(format!("mesh {} primitive {} has mismatched attribute counts", index, primitive_index));
+            }
+
+            let has_normals = normals.is_some();
+            let has_texinfo = texinfo.is_some();
+            let mut meshopts = MeshOptions::new(positions);
+            if let Some(normals) = normals {
+                meshopts.normals(normals);
+            }
+            if let Some(texinfo) = texinfo {
+                meshopts.texinfo(texinfo);
+            }
+            meshopts.material(Box::new(material));
+            for face in indices.chunks(3).filter(|face| face.len() == 3) {
+                let face = [face[0], face[1], face[2]];
+                meshopts.face(face,
+                              if has_normals { Some(face) } else { None },
+                              if has_texinfo { Some(face) } else { None });
+            }
+
+            let mesh = meshopts.build();
+            if let Some((ref path, key)) = cache {
+                cache::write_mesh(path, key, &mesh, |_| {})
+                    .map_err(|err| format!("mesh cache {}: {}", path.display(), err))?;
+            }
+            self.scene.meshes.push(mesh);
+        }
+
+        Ok(())
+    }
+
+    fn camera(&mut self, index: usize, transform: &Mat4) {
+        let camera = self.json.get("cameras").index(index);
+        if camera.get("type").as_str() != Some("perspective") {
+            eprintln!("Skipping glTF camera {}: only perspective cameras are supported", index);
+            return;
+        }
+
+        // Cameras look down -Z with +Y up in their own space
+        let position = Mat4::mult_p(transform, &Vec3::zero());
+        let forward = Mat4::mult_v(transform, &Vec3 { x: 0.0, y: 0.0, z: -1.0 }).unit();
+        self.scene.cameras.push(GltfCamera {
+            position: position,
+            look_at: position + forward,
+            up: Mat4::mult_v(transform, &Vec3 { x: 0.0, y: 1.0, z: 0.0 }).unit(),
+            yfov: camera.get("perspective").get("yfov").as_f64().unwrap_or(0.8)
+        });
+    }
+
+    /// KHR_lights_punctual lights become point lights of color times
+    /// intensity. Spot cones are ignored; directional lights are skipped.
+    fn light(&mut self, index: usize, transform: &Mat4) {
+        let light = self.json.get("extensions").get("KHR_lights_punctual").get("lights").index(index);
+        match light.get("type").as_str() {
+            Some("point") | Some("spot") => {
+                let intensity = light.get("intensity").as_f64().unwrap_or(1.0);
+                self.scene.lights.push(PointLight {
+                    position: Mat4::mult_p(transform, &Vec3::zero()),
+                    color: color(light.get("color")).scale(intensity)
+                });
+            },
+            other => eprintln!("Skipping glTF light {}: unsupported type {:?}", index, other)
+        }
+    }
+
+    fn node(&mut self, index: usize, parent: &Mat4, depth: usize) -> Result<(), String> {
+        // Node graphs must be trees; this also stops cycles in broken files
+        if depth > 64 {
+            return Err(format!("node {} is nested too deeply", index));
+        }
+
+        let node = self.json.get("nodes").index(index);
+        let transform = Mat4::mult_m(parent, &node_matrix(node));
+
+        if let Some(mesh) = node.get("mesh").as_usize() {
+            self.mesh(mesh, &transform)?;
+        }
+        if let Some(camera) = node.get("camera").as_usize() {
+            self.camera(camera, &transform);
+        }
+        if let Some(light) = node.get("extensions").get("KHR_lights_punctual").get("light").as_usize() {
+            self.light(light, &transform);
+        }
+        for child in node.get("children").members() {
+            let child = child.as_usize().ok_or_else(|| format!("node {} has an invalid child", index))?;
+            self.node(child, &transform, depth + 1)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// An RGB or RGBA color factor, white if missing.
+fn color(factor: &Json) -> Vec3 {
+    match factor.as_f64_array() {
+        Some(ref rgb) if rgb.len() >= 3 => Vec3 { x: rgb[0], y: rgb[1], z: rgb[2] },
+        _ => Vec3::one()
+    }
+}
+
+/// A node's local transform, from its column-major `matrix` or from its
+/// translation, rotation (a quaternion) and scale.
+fn node_matrix(node: &Json) -> Mat4 {
+    if let Some(m) = node.get("matrix").as_f64_array().filter(|m| m.len() == 16) {
+        return Mat4::new(
+            m[0], m[4], m[8], m[12],
+            m[1], m[5], m[9], m[13],
+            m[2], m[6], m[10], m[14],
+            m[3], m[7], m[11], m[15]
+        );
+    }
+
+    let t = node.get("translation").as_f64_array().filter(|t| t.len() == 3).unwrap_or_else(|| vec![0.0; 3]);
+    let q = node.get("rotation").as_f64_array().filter(|q| q.len() == 4).unwrap_or_else(|| vec![0.0, 0.0, 0.0, 1.0]);
+    let s = node.get("scale").as_f64_array().filter(|s| s.len() == 3).unwrap_or_else(|| vec![1.0; 3]);
+    let (x, y, z, w) = (q[0], q[1], q[2], q[3]);
+
+    let rotation = Mat4::new(
+        1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - z * w),       2.0 * (x * z + y * w),       0.0,
+        2.0 * (x * y + z * w),       1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - x * w),       0.0,
+        2.0 * (x * z - y * w),       2.0 * (y * z + x * w),       1.0 - 2.0 * (x * x + y * y), 0.0,
+        0.0,                         0.0,                         0.0,                         1.0
+    );
+
+    let translation = Mat4::translate_matrix(&Vec3 { x: t[0], y: t[1], z: t[2] });
+    let scale = Mat4::scale_matrix(&Vec3 { x: s[0], y: s[1], z: s[2] });
+    Mat4::mult_m(&translation, &Mat4::mult_m(&rotation, &scale))
+}
+
+/// Cache key of primitive `primitive_index` of mesh `index`, placed by
+/// `transform`, in a file and buffers of checksum `source`.
+fn primitive_key(source: u64, index: usize, primitive_index: usize, transform: &Mat4) -> u64 {
+    let mut hasher = Fnv1a::new();
+    hasher.write_u64(source);
+    hasher.write_u64(index as u64);
+    hasher.write_u64(primitive_index as u64);
+    for value in transform.m.iter().flat_map(|row| row.iter()) {
+        hasher.write_u64(value.to_bits());
+    }
+    hasher.finish()
+}
+
+fn parse_gltf(bytes: &[u8], dir: &Path, cache_dir: Option<&Path>) -> Result<GltfScene, String> {
+    let (json_bytes, bin) = if bytes.starts_with(GLB_MAGIC) {
+        parse_glb(bytes)?
+    } else {
+        (bytes, None)
+    };
+    let json = json::parse(json_bytes)?;
+
+    let version = json.get("asset").get("version").as_str().unwrap_or("");
+    if !version.starts_with("2.") {
+        return Err(format!("unsupported glTF version '{}'", version));
+    }
+
+    let mut loader = Loader {
+        json: &json,
+        dir: dir.to_path_buf(),
+        buffers: Vec::new(),
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        scene: GltfScene { meshes: Vec::new(), cameras: Vec::new(), lights: Vec::new() },
+        cache: None,
+    };
+
+    for (index, buffer) in json.get("buffers").members().iter().enumerate() {
+        let data = match (buffer.get("uri").as_str(), bin) {
+            (Some(uri), _) => loader.load_uri(uri).map_err(|err| format!("buffer {}: {}", index, err))?,
+            (None, Some(bin)) if index == 0 => bin.to_vec(),
+            _ => return Err(format!("buffer {} has no data", index))
+        };
+        loader.buffers.push(data);
+    }
+
+    // Meshes come from the buffers as much as from the file itself
+    if let Some(cache_dir) = cache_dir {
+        let mut hasher = Fnv1a::new();
+        hasher.write(bytes);
+        for buffer in loader.buffers.iter() {
+            hasher.write(buffer);
+        }
+        loader.cache = Some((cache_dir.to_path_buf(), hasher.finish()));
+    }
+
+    // Without a default scene, render every node that isn't a child
+    let roots: Vec<usize> = match json.get("scene").as_usize().or(Some(0)).map(|s| json.get("scenes").index(s)) {
+        Some(scene) if !scene.is_null() => scene.get("nodes").members().iter().filter_map(Json::as_usize).collect(),
+        _ => {
+            let nodes = json.get("nodes").members();
+            let children: Vec<usize> = nodes.iter()
+                .flat_map(|node| node.get("children").members().iter().filter_map(Json::as_usize))
+                .collect();
+            (0..nodes.len()).filter(|node| !children.contains(node)).collect()
+        }
+    };
+
+    for root in roots {
+        loader.node(root, &Mat4::identity(), 0)?;
+    }
+
+    Ok(loader.scene)
+}
+
+/// Loads the default scene of a .gltf or .glb file: triangle meshes with
+/// their metallic-roughness materials, perspective cameras and
+/// KHR_lights_punctual lights, all placed by the node hierarchy. With a
+/// `cache_dir`, built meshes and their octrees are kept there between runs.
+pub fn from_gltf(path: &Path, cache_dir: Option<&Path>) -> Result<GltfScene, ImportError> {
+    let bytes = read_file(path)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    parse_gltf(&bytes, dir, cache_dir).map_err(|message| ImportError::parse(path, 0, message))
+}
+
+#[cfg(test)]
+fn triangle_gltf() -> String {
+    // A unit right triangle in the XY plane, moved 2 along -Z by its node,
+    // viewed by a camera at the origin and lit by a point light
+    let mut buffer = Vec::new();
+    for v in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0].iter() {
+        buffer.extend_from_slice(&v.to_le_bytes());
+    }
+    for uv in [0.0f32, 1.0, 1.0, 1.0, 0.0, 0.0].iter() {
+        buffer.extend_from_slice(&uv.to_le_bytes());
+    }
+    for i in [0u16, 1, 2].iter() {
+        buffer.extend_from_slice(&i.to_le_bytes());
+    }
+
+    const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in buffer.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            encoded.push(if i <= chunk.len() { BASE64[(n >> (18 - 6 * i) & 63) as usize] as char } else { '=' });
+        }
+    }
+
+    format!(r#"{{
+        "asset": {{ "version": "2.0" }},
+        "scene": 0,
+        "scenes": [{{ "nodes": [0, 2, 3] }}],
+        "nodes": [
+            {{ "translation": [0, 0, -2], "children": [1] }},
+            {{ "mesh": 0, "scale": [2, 2, 2] }},
+            {{ "camera": 0, "rotation": [0, 0, 0, 1] }},
+            {{ "translation": [0, 5, 0], "extensions": {{ "KHR_lights_punctual": {{ "light": 0 }} }} }}
+        ],
+        "meshes": [{{ "primitives": [{{
+            "attributes": {{ "POSITION": 0, "TEXCOORD_0": 1 }},
+            "indices": 2,
+            "material": 0
+        }}] }}],
+        "materials": [{{ "pbrMetallicRoughness": {{ "baseColorFactor": [1, 0, 0, 1], "metallicFactor": 0 }} }}],
+        "cameras": [{{ "type": "perspective", "perspective": {{ "yfov": 0.5, "znear": 0.1 }} }}],
+        "extensions": {{ "KHR_lights_punctual": {{ "lights": [{{ "type": "point", "intensity": 2 }}] }} }},
+        "buffers": [{{ "byteLength": {}, "uri": "data:application/octet-stream;base64,{}" }}],
+        "bufferViews": [{{ "buffer": 0, "byteLength": {} }}],
+        "accessors": [
+            {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" }},
+            {{ "bufferView": 0, "byteOffset": 36, "componentType": 5126, "count": 3, "type": "VEC2" }},
+            {{ "bufferView": 0, "byteOffset": 60, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+        ]
+    }}"#, buffer.len(), encoded, buffer.len())
+}
+
+#[test]
+fn it_loads_meshes_cameras_and_lights() {
+    use crate::raytracer::Ray;
+
+    let scene = parse_gltf(triangle_gltf().as_bytes(), Path::new(""), None).unwrap();
+    assert_eq!(1, scene.meshes.len());
+    assert_eq!(1, scene.cameras.len());
+    assert_eq!(1, scene.lights.len());
+
+    // Scaled by the child node, then translated by its parent
+    let ray = Ray::new(Vec3 { x: 0.5, y: 0.5, z: 0.0 }, Vec3 { x: 0.0, y: 0.0, z: -1.0 });
+    let hit = scene.meshes[0].intersects(&ray, 0.0, 10.0).unwrap();
+    assert_eq!(2.0, hit.t);
+    // UVs are flipped into our bottom-left origin
+    assert!((hit.u - 0.25).abs() < 1e-9 && (hit.v - 0.25).abs() < 1e-9);
+
+    let camera = &scene.cameras[0];
+    assert_eq!(Vec3 { x: 0.0, y: 0.0, z: -1.0 }, camera.look_at);
+    assert_eq!(Vec3 { x: 0.0, y: 5.0, z: 0.0 }, scene.lights[0].position);
+    assert_eq!(Vec3 { x: 2.0, y: 2.0, z: 2.0 }, scene.lights[0].color);
+
+    // A square image keeps the vertical field of view
+    let camera = camera.to_camera(64, 64);
+    assert!((camera.fov_deg - 0.25f64.to_degrees()).abs() < 1e-9);
+}
+
+#[test]
+fn it_reuses_cached_meshes() {
+    use crate::raytracer::Ray;
+
+    let dir = std::env::temp_dir().join(format!("raytracer-gltf-cache-test-{}", std::process::id()));
+    let gltf = triangle_gltf();
+    let built = parse_gltf(gltf.as_bytes(), Path::new(""), Some(&dir)).unwrap();
+    assert_eq!(1, std::fs::read_dir(&dir).unwrap().count());
+    let cached = parse_gltf(gltf.as_bytes(), Path::new(""), Some(&dir)).unwrap();
+
+    let ray = Ray::new(Vec3 { x: 0.5, y: 0.5, z: 0.0 }, Vec3 { x: 0.0, y: 0.0, z: -1.0 });
+    let t = |scene: &GltfScene| scene.meshes[0].intersects(&ray, 0.0, 10.0).unwrap().t;
+    assert_eq!(t(&built), t(&cached));
+
+    // Nor is a mesh placed elsewhere taken from the cache
+    let moved = parse_gltf(gltf.replace("[0, 0, -2]", "[0, 0, -3]").as_bytes(), Path::new(""), Some(&dir)).unwrap();
+    assert_eq!(3.0, t(&moved));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn it_reads_glb_containers() {
+    let json = triangle_gltf().into_bytes();
+    let mut glb = GLB_MAGIC.to_vec();
+    glb.extend_from_slice(&2u32.to_le_bytes());
+    glb.extend_from_slice(&((12 + 8 + json.len()) as u32).to_le_bytes());
+    glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    glb.extend_from_slice(&GLB_JSON_CHUNK.to_le_bytes());
+    glb.extend_from_slice(&json);
+
+    assert_eq!(1, parse_gltf(&glb, Path::new(""), None).unwrap().meshes.len());
+
+    glb[4] = 1;
+    assert_eq!(Some("unsupported glTF version 1".to_string()), parse_gltf(&glb, Path::new(""), None).err());
+}
+
+#[test]
+fn it_composes_trs_transforms() {
+    // 90 degrees around Z, then scale, then translate
+    let node = json::parse(br#"{ "translation": [1, 2, 3], "rotation": [0, 0, 0.7071067811865476, 0.7071067811865476], "scale": [2, 2, 2] }"#).unwrap();
+    let p = Mat4::mult_p(&node_matrix(&node), &Vec3 { x: 1.0, y: 0.0, z: 0.0 });
+    assert!((p - Vec3 { x: 1.0, y: 4.0, z: 3.0 }).len() < 1e-9);
+
+    let node = json::parse(b"{ \"matrix\": [1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1, 0, 5, 6, 7, 1] }").unwrap();
+    assert_eq!(Vec3 { x: 5.0, y: 6.0, z: 7.0 }, Mat4::mult_p(&node_matrix(&node), &Vec3::zero()));
+
+    assert_eq!(b"hello".to_vec(), decode_base64("aGVsbG8=").unwrap());
+}
+
+#[test]
+fn it_reads_material_extensions() {
+    let json = json::parse(br#"{ "materials": [{
+        "pbrMetallicRoughness": { "baseColorFactor": [0.5, 0.5, 0.5, 1], "metallicFactor": 0, "roughnessFactor": 0.25 },
+        "extensions": {
+            "KHR_materials_clearcoat": { "clearcoatFactor": 1, "clearcoatRoughnessFactor": 0.1 },
+            "KHR_materials_transmission": { "transmissionFactor": 0.9 },
+            "KHR_materials_ior": { "ior": 1.33 },
+            "KHR_materials_volume": { "attenuationColor": [0.5, 1, 1], "attenuationDistance": 2 },
+            "KHR_materials_dispersion": { "dispersion": 1 }
+        }
+    }] }"#).unwrap();
+    let mut loader = Loader {
+        json: &json,
+        dir: PathBuf::new(),
+        buffers: Vec::new(),
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        scene: GltfScene { meshes: Vec::new(), cameras: Vec::new(), lights: Vec::new() },
+        cache: None,
+    };
+
+    let material = loader.material(Some(0));
+    assert_eq!((0.0, 0.25, 1.0, 0.1, 0.9, 1.33), (material.metallic, material.roughness, material.clearcoat,
+                                                  material.clearcoat_roughness, material.transmission, material.ior));
+    // Water reflects 2% head on, a quarter of our default
+    assert!((material.specular - 0.25).abs() < 0.01);
+    assert!((material.absorption.x - 0.5f64.ln() / -2.0).abs() < 1e-9 && material.absorption.y == 0.0);
+    let dispersion = material.dispersion.unwrap();
+    assert!((dispersion.ior(587.6) - 1.33).abs() < 1e-9 && dispersion.ior(450.0) > dispersion.ior(650.0));
+}
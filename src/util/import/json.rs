@@ -0,0 +1,254 @@
+/// A parsed JSON value. Objects keep their keys in file order.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+static NULL: Json = Json::Null;
+
+impl Json {
+    /// Looks up `key` in an object. Missing keys and non-objects give `Null`,
+    /// so lookups can be chained.
+    pub fn get(&self, key: &str) -> &Json {
+        match *self {
+            Json::Object(ref members) => members.iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v)
+                .unwrap_or(&NULL),
+            _ => &NULL
+        }
+    }
+
+    pub fn index(&self, index: usize) -> &Json {
+        match *self {
+            Json::Array(ref items) => items.get(index).unwrap_or(&NULL),
+            _ => &NULL
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Json::Null
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Json::Number(n) => Some(n),
+            _ => None
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        match *self {
+            Json::Number(n) if n >= 0.0 && n.fract() == 0.0 => Some(n as usize),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Json::String(ref s) => Some(s),
+            _ => None
+        }
+    }
+
+    /// Arrays as a slice; anything else as an empty slice.
+    pub fn members(&self) -> &[Json] {
+        match *self {
+            Json::Array(ref items) => items,
+            _ => &[]
+        }
+    }
+
+    /// Reads an array of numbers, as used for vectors and matrices.
+    pub fn as_f64_array(&self) -> Option<Vec<f64>> {
+        match *self {
+            Json::Array(ref items) => items.iter().map(Json::as_f64).collect(),
+            _ => None
+        }
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, message: &str) -> Result<T, String> {
+        Err(format!("{} at byte {}", message, self.pos))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.bytes.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, literal: &str) -> Result<(), String> {
+        if self.bytes[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            Ok(())
+        } else {
+            self.error(&format!("expected '{}'", literal))
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json, String> {
+        if depth > 128 {
+            return self.error("nesting too deep");
+        }
+
+        match self.peek() {
+            Some(b'n') => self.expect("null").map(|_| Json::Null),
+            Some(b't') => self.expect("true").map(|_| Json::Bool(true)),
+            Some(b'f') => self.expect("false").map(|_| Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => { self.pos += 1; return Ok(Json::Array(items)); },
+                        _ => return self.error("expected ',' or ']'")
+                    }
+                }
+            },
+            Some(b'{') => {
+                self.pos += 1;
+                let mut members = Vec::new();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    if self.peek() != Some(b'"') {
+                        return self.error("expected a key");
+                    }
+                    let key = self.string()?;
+                    if self.peek() != Some(b':') {
+                        return self.error("expected ':'");
+                    }
+                    self.pos += 1;
+                    members.push((key, self.value(depth + 1)?));
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => { self.pos += 1; return Ok(Json::Object(members)); },
+                        _ => return self.error("expected ',' or '}'")
+                    }
+                }
+            },
+            Some(b'-') | Some(b'0'..=b'9') => self.number(),
+            Some(_) => self.error("unexpected character"),
+            None => self.error("unexpected end of input")
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        while self.pos < self.bytes.len() && b"+-.eE0123456789".contains(&self.bytes[self.pos]) {
+            self.pos += 1;
+        }
+        match std::str::from_utf8(&self.bytes[start..self.pos]).ok().and_then(|s| s.parse().ok()) {
+            Some(n) => Ok(Json::Number(n)),
+            None => { self.pos = start; self.error("invalid number") }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self.bytes.get(self.pos..self.pos + 4)
+            .and_then(|d| std::str::from_utf8(d).ok())
+            .and_then(|d| u32::from_str_radix(d, 16).ok());
+        match digits {
+            Some(code) => { self.pos += 4; Ok(code) },
+            None => self.error("invalid unicode escape")
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.pos += 1; // Opening quote
+        let mut bytes = Vec::new();
+
+        loop {
+            let byte = match self.bytes.get(self.pos) {
+                Some(&byte) => byte,
+                None => return self.error("unterminated string")
+            };
+            self.pos += 1;
+
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escape = self.bytes.get(self.pos).cloned();
+                    self.pos += 1;
+                    let c = match escape {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            let mut code = self.hex4()?;
+                            // Surrogate pair
+                            if (0xd800..0xdc00).contains(&code) && self.bytes[self.pos..].starts_with(b"\\u") {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            std::char::from_u32(code).unwrap_or('\u{fffd}')
+                        },
+                        _ => return self.error("invalid escape")
+                    };
+                    let mut buf = [0u8; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                },
+                _ => bytes.push(byte)
+            }
+        }
+
+        String::from_utf8(bytes).or_else(|_| self.error("invalid UTF-8 in string"))
+    }
+}
+
+pub fn parse(bytes: &[u8]) -> Result<Json, String> {
+    let mut parser = Parser { bytes: bytes, pos: 0 };
+    let value = parser.value(0)?;
+    match parser.peek() {
+        None => Ok(value),
+        Some(_) => parser.error("trailing characters")
+    }
+}
+
+#[test]
+fn it_parses_json() {
+    let json = parse(br#" { "a": [1, -2.5e1, true, null], "b": {"c": "x\"\u00e9\ud83d\ude00"}, "d": {} } "#).unwrap();
+    assert_eq!(Some(-25.0), json.get("a").index(1).as_f64());
+    assert_eq!(4, json.get("a").members().len());
+    assert_eq!(None, json.get("a").as_f64_array());
+    assert_eq!(Json::Bool(true), *json.get("a").index(2));
+    assert!(json.get("a").index(3).is_null());
+    assert_eq!(Some("x\"\u{e9}\u{1f600}"), json.get("b").get("c").as_str());
+    assert!(json.get("missing").get("deeper").is_null());
+
+    assert_eq!(Err("expected ',' or ']' at byte 3".to_string()), parse(b"[1 2]"));
+    assert!(parse(b"{\"a\": 1} x").is_err());
+    assert!(parse(b"\"unterminated").is_err());
+}
@@ -0,0 +1,616 @@
+use std::fmt;
+use std::fs;
+use std::hash::Hasher;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use crate::geometry::prims::{HeightfieldOptions, Mesh, MeshOptions};
+use crate::geometry::subdivision::{ControlMesh, SubdivisionScheme};
+use crate::material::Material;
+use crate::material::materials::{CookTorranceMaterial, PhongMaterial};
+use crate::raytracer::compositor::{ColorRGBA, Surface};
+use crate::util::cache::{self, CacheError, CacheReader, CacheWriter, Fnv1a};
+use crate::vec3::Vec3;
+
+pub use self::gltf::{from_gltf, GltfCamera, GltfScene};
+#[allow(unused_imports)]
+pub use self::obj::{from_obj, mtl_materials, MaterialModel, ObjGroup, ObjModel};
+#[allow(unused_imports)]
+pub use self::ply::{from_ply, PlyModel};
+#[allow(unused_imports)]
+pub use self::stl::{from_stl, StlModel};
+#[allow(unused_imports)]
+pub use self::strand::{from_strands, Strand};
+
+pub mod gltf;
+pub mod json;
+pub mod obj;
+pub mod ply;
+pub mod png;
+pub mod stl;
+pub mod strand;
+
+pub enum ImportError {
+    Io(PathBuf, io::Error),
+    /// `line` is 1-based; 0 means the error isn't tied to a single line
+    Parse { path: PathBuf, line: usize, message: String },
+}
+
+impl ImportError {
+    pub fn parse(path: &Path, line: usize, message: String) -> ImportError {
+        ImportError::Parse { path: path.to_path_buf(), line: line, message: message }
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ImportError::Io(ref path, ref err) => write!(f, "{}: {}", path.display(), err),
+            ImportError::Parse { ref path, line: 0, ref message } => write!(f, "{}: {}", path.display(), message),
+            ImportError::Parse { ref path, line, ref message } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl fmt::Debug for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+pub fn read_file(path: &Path) -> Result<Vec<u8>, ImportError> {
+    fs::read(path).map_err(|err| ImportError::Io(path.to_path_buf(), err))
+}
+
+/// Parses a whitespace-separated token, naming what was expected on failure.
+pub fn parse_token<T: FromStr>(token: Option<&str>, what: &str) -> Result<T, String> {
+    match token {
+        Some(token) => token.parse().map_err(|_| format!("invalid {} '{}'", what, token)),
+        None => Err(format!("missing {}", what))
+    }
+}
+
+pub fn parse_vec3<'a, I>(tokens: &mut I, what: &str) -> Result<Vec3, String> where I: Iterator<Item=&'a str> {
+    Ok(Vec3 {
+        x: parse_token(tokens.next(), what)?,
+        y: parse_token(tokens.next(), what)?,
+        z: parse_token(tokens.next(), what)?
+    })
+}
+
+/// Makes a material for a face, given its vertex color if the file has any.
+pub type MaterialFn = dyn Fn(Option<Vec3>) -> Box<dyn Material+Send+Sync>;
+
+/// Settings shared by every mesh format `from_file` understands.
+pub struct ImportOptions {
+    material_model: MaterialModel,
+    crease_angle: f64,
+    weld_tolerance: f64,
+    material: Option<Box<MaterialFn>>,
+    subdivision: Option<(SubdivisionScheme, u32)>,
+    subdivision_crease_angle: f64,
+    cache_dir: Option<PathBuf>,
+}
+
+#[allow(dead_code)]
+impl ImportOptions {
+    pub fn new() -> ImportOptions {
+        ImportOptions {
+            material_model: MaterialModel::CookTorrance,
+            crease_angle: 30.0,
+            weld_tolerance: 0.0,
+            material: None,
+            subdivision: None,
+            subdivision_crease_angle: 180.0,
+            cache_dir: None,
+        }
+    }
+
+    /// What MTL materials are mapped onto, and the default material for
+    /// formats without materials of their own.
+    pub fn material_model(&mut self, material_model: MaterialModel) -> &mut Self {
+        self.material_model = material_model;
+        self
+    }
+
+    /// In degrees. Edges sharper than this stay sharp when smoothing STL
+    /// files, and OBJ and PLY files that come without normals.
+    pub fn crease_angle(&mut self, crease_angle: f64) -> &mut Self {
+        self.crease_angle = crease_angle;
+        self
+    }
+
+    /// Vertices closer than this are joined when smoothing OBJ and PLY
+    /// files that come without normals. By default only identical ones are.
+    pub fn weld_tolerance(&mut self, weld_tolerance: f64) -> &mut Self {
+        self.weld_tolerance = weld_tolerance;
+        self
+    }
+
+    /// Material for formats without materials of their own.
+    pub fn material<F>(&mut self, material: F) -> &mut Self
+            where F: Fn(Option<Vec3>) -> Box<dyn Material+Send+Sync> + 'static {
+        self.material = Some(Box::new(material));
+        self
+    }
+
+    /// Refines every mesh `levels` times as it's loaded, shading it with
+    /// limit normals. OBJ polygons are subdivided as written; normals and
+    /// texture coordinates from the file are dropped.
+    pub fn subdivide(&mut self, scheme: SubdivisionScheme, levels: u32) -> &mut Self {
+        self.subdivision = Some((scheme, levels));
+        self
+    }
+
+    /// In degrees. Edges of the control mesh sharper than this stay sharp
+    /// when subdividing. By default only open boundaries do.
+    pub fn subdivision_crease_angle(&mut self, angle: f64) -> &mut Self {
+        self.subdivision_crease_angle = angle;
+        self
+    }
+
+    /// Keeps built meshes and their octrees in `dir`, so loading the same
+    /// file with the same settings again skips building the octree.
+    pub fn cache_dir(&mut self, dir: &Path) -> &mut Self {
+        self.cache_dir = Some(dir.to_path_buf());
+        self
+    }
+
+    fn subdivided<F>(&self, mut mesh: ControlMesh, materials: Vec<Box<dyn Material+Send+Sync>>, material: F) -> MeshOptions
+            where F: Fn(usize) -> usize {
+        let (scheme, levels) = self.subdivision.unwrap();
+        if self.subdivision_crease_angle < 180.0 {
+            mesh.crease_sharper_than(self.subdivision_crease_angle);
+        }
+        mesh.subdivide(scheme, levels).into_mesh(scheme, materials, material)
+    }
+
+    /// Hash of a file of checksum `source` and the settings that shape the
+    /// mesh built from it. Materials are made afresh on every load, so what
+    /// they're made with is left out.
+    fn cache_key(&self, extension: &str, source: u64) -> u64 {
+        let mut hasher = Fnv1a::new();
+        hasher.write_u64(source);
+        hasher.write(extension.as_bytes());
+        hasher.write_u64(self.crease_angle.to_bits());
+        hasher.write_u64(self.weld_tolerance.to_bits());
+        if let Some((scheme, levels)) = self.subdivision {
+            hasher.write_u8(scheme as u8 + 1);
+            hasher.write_u32(levels);
+            hasher.write_u64(self.subdivision_crease_angle.to_bits());
+        }
+        hasher.finish()
+    }
+
+    fn smoothed(&self, mut meshopts: MeshOptions) -> MeshOptions {
+        meshopts.smooth(self.weld_tolerance, self.crease_angle);
+        meshopts
+    }
+
+    fn make_material(&self, color: Option<Vec3>) -> Box<dyn Material+Send+Sync> {
+        if let Some(ref material) = self.material {
+            return material(color);
+        }
+
+        match self.material_model {
+            MaterialModel::Phong => {
+                let default = PhongMaterial::default();
+                Box::new(PhongMaterial { diffuse: color.unwrap_or(default.diffuse), ..default })
+            },
+            MaterialModel::CookTorrance => {
+                let default = CookTorranceMaterial::default();
+                Box::new(CookTorranceMaterial { diffuse: color.unwrap_or(default.diffuse), ..default })
+            }
+        }
+    }
+}
+
+/// What the materials of a mesh loaded by `from_file` are made from. The
+/// cache keeps these in place of the materials, which can't be written out.
+enum MaterialSources {
+    /// `ImportOptions::make_material` for each face color, if the file has any
+    Colors(Vec<Option<Vec3>>),
+    /// OBJ materials by name from MTL files, None for the default one
+    Mtl { libraries: Vec<PathBuf>, names: Vec<Option<String>> },
+}
+
+impl MaterialSources {
+    fn materials(&self, path: &Path, options: &ImportOptions) -> Result<Vec<Box<dyn Material+Send+Sync>>, ImportError> {
+        match *self {
+            MaterialSources::Colors(ref colors) => Ok(colors.iter().map(|&color| options.make_material(color)).collect()),
+            MaterialSources::Mtl { ref libraries, ref names } => mtl_materials(path, libraries, names, options.material_model)
+        }
+    }
+
+    fn write_cache(&self, w: &mut CacheWriter) {
+        match *self {
+            MaterialSources::Colors(ref colors) => {
+                w.write_u8(0);
+                w.write_u64(colors.len() as u64);
+                for color in colors.iter() {
+                    match *color {
+                        Some(color) => {
+                            w.write_u8(1);
+                            w.write_f64(color.x);
+                            w.write_f64(color.y);
+                            w.write_f64(color.z);
+                        },
+                        None => w.write_u8(0)
+                    }
+                }
+            },
+            MaterialSources::Mtl { ref libraries, ref names } => {
+                w.write_u8(1);
+                w.write_u64(libraries.len() as u64);
+                for library in libraries.iter() {
+                    w.write_str(&library.to_string_lossy());
+                }
+                w.write_u64(names.len() as u64);
+                for name in names.iter() {
+                    match *name {
+                        Some(ref name) => {
+                            w.write_u8(1);
+                            w.write_str(name);
+                        },
+                        None => w.write_u8(0)
+                    }
+                }
+            }
+        }
+    }
+
+    fn read_cache(r: &mut CacheReader) -> Result<MaterialSources, CacheError> {
+        match r.read_u8()? {
+            0 => {
+                let len = r.read_len(1)?;
+                let colors = (0..len).map(|_| match r.read_u8()? {
+                    0 => Ok(None),
+                    1 => Ok(Some(Vec3 { x: r.read_f64()?, y: r.read_f64()?, z: r.read_f64()? })),
+                    _ => Err(CacheError::Corrupt("material color"))
+                }).collect::<Result<Vec<Option<Vec3>>, CacheError>>()?;
+                Ok(MaterialSources::Colors(colors))
+            },
+            1 => {
+                let len = r.read_len(8)?;
+                let libraries = (0..len).map(|_| r.read_str().map(PathBuf::from))
+                    .collect::<Result<Vec<PathBuf>, CacheError>>()?;
+                let len = r.read_len(1)?;
+                let names = (0..len).map(|_| match r.read_u8()? {
+                    0 => Ok(None),
+                    1 => r.read_str().map(Some),
+                    _ => Err(CacheError::Corrupt("material name"))
+                }).collect::<Result<Vec<Option<String>>, CacheError>>()?;
+                Ok(MaterialSources::Mtl { libraries: libraries, names: names })
+            },
+            _ => Err(CacheError::Corrupt("material sources"))
+        }
+    }
+}
+
+/// Loads an OBJ, PLY or STL file as one mesh, picking the format from the
+/// file extension. Faces share the file's vertex buffers and are grouped
+/// by material. Any subdivision happens here, so the accelerator only ever
+/// sees the refined triangles. Meshes without normals of their own are
+/// welded, consistently wound and smoothed.
+#[allow(dead_code)]
+pub fn from_file(path: &Path, options: &ImportOptions) -> Result<Mesh, ImportError> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    if !["obj", "ply", "stl"].contains(&extension.as_str()) {
+        return Err(ImportError::parse(path, 0, format!("unsupported mesh format '{}'", extension)));
+    }
+
+    // A mesh cached from the same file and settings skips parsing entirely;
+    // one that can't be read back for any reason is simply built again
+    let cache = match options.cache_dir {
+        Some(ref dir) => {
+            let source = cache::checksum(&read_file(path)?);
+            Some((cache::mesh_path(dir, source, 0), options.cache_key(&extension, source)))
+        },
+        None => None
+    };
+    if let Some((ref cache_path, key)) = cache {
+        let cached = cache::read_mesh(cache_path, key, |r| {
+            MaterialSources::read_cache(r)?.materials(path, options).map_err(|_| CacheError::Stale)
+        });
+        if let Ok(mesh) = cached {
+            return Ok(mesh);
+        }
+    }
+
+    let (meshopts, sources) = match extension.as_str() {
+        "obj" => {
+            let model = from_obj(path, options.material_model)?;
+            let sources = MaterialSources::Mtl { libraries: model.libraries.clone(), names: model.material_names() };
+            let has_normals = !model.normals.is_empty();
+            let meshopts = match options.subdivision {
+                Some(_) => options.subdivided(model.control_mesh(), model.materials(), |face| model.face_material(face).unwrap_or(0)),
+                None if has_normals => model.into_mesh(),
+                None => options.smoothed(model.into_mesh())
+            };
+            (meshopts, sources)
+        },
+        "ply" => {
+            let model = from_ply(path)?;
+            let sources = MaterialSources::Colors(model.face_materials(|color| color).0);
+            let has_normals = model.normals.is_some();
+            let meshopts = match options.subdivision {
+                Some(_) => {
+                    let (materials, indices) = model.face_materials(|color| options.make_material(color));
+                    options.subdivided(model.control_mesh(), materials, |face| indices[face] as usize)
+                },
+                None if has_normals => model.into_mesh(|color| options.make_material(color)),
+                None => options.smoothed(model.into_mesh(|color| options.make_material(color)))
+            };
+            (meshopts, sources)
+        },
+        _ => {
+            let model = from_stl(path, options.crease_angle)?;
+            let meshopts = match options.subdivision {
+                Some(_) => options.subdivided(model.control_mesh(), vec![options.make_material(None)], |_| 0),
+                None => model.into_mesh(options.make_material(None))
+            };
+            (meshopts, MaterialSources::Colors(vec![None]))
+        }
+    };
+
+    let mesh = meshopts.build();
+    if let Some((ref cache_path, key)) = cache {
+        cache::write_mesh(cache_path, key, &mesh, |w| sources.write_cache(w))
+            .map_err(|err| ImportError::Io(cache_path.clone(), err))?;
+    }
+    Ok(mesh)
+}
+
+/// Loads a PNG, PGM or PPM image, telling them apart by their contents.
+#[allow(dead_code)]
+pub fn from_image(path: &Path) -> Result<Surface, ImportError> {
+    let bytes = read_file(path)?;
+    parse_image(&bytes).map_err(|message| ImportError::parse(path, 0, message))
+}
+
+fn is_netpbm(bytes: &[u8]) -> bool {
+    [b"P2", b"P3", b"P5", b"P6"].iter().any(|magic| bytes.starts_with(*magic))
+}
+
+pub fn parse_image(bytes: &[u8]) -> Result<Surface, String> {
+    if bytes.starts_with(b"\x89PNG") {
+        png::parse_png(bytes)
+    } else if is_netpbm(bytes) {
+        parse_ppm(bytes)
+    } else {
+        Err("unsupported image format, expected PNG, PGM or PPM".to_string())
+    }
+}
+
+/// Loads a grayscale image as terrain heights from 0 to 1, with image rows
+/// running along +Z. PGMs keep their full 16-bit precision; colour images
+/// use the average of their channels.
+#[allow(dead_code)]
+pub fn from_heightmap(path: &Path) -> Result<HeightfieldOptions, ImportError> {
+    let bytes = read_file(path)?;
+    parse_heightmap(&bytes).map_err(|message| ImportError::parse(path, 0, message))
+}
+
+fn parse_heightmap(bytes: &[u8]) -> Result<HeightfieldOptions, String> {
+    let (width, height, heights) = if is_netpbm(bytes) {
+        let image = parse_netpbm(bytes)?;
+        let scale = 1.0 / (image.max_value as f64 * image.channels as f64);
+        let heights = image.samples.chunks(image.channels)
+            .map(|pixel| pixel.iter().map(|&sample| sample.min(image.max_value) as f64).sum::<f64>() * scale)
+            .collect();
+        (image.width, image.height, heights)
+    } else {
+        let image = parse_image(bytes)?;
+        let heights = image.buffer.iter()
+            .map(|pixel| (pixel.r as f64 + pixel.g as f64 + pixel.b as f64) / (3.0 * 255.0))
+            .collect();
+        (image.width, image.height, heights)
+    };
+
+    if width < 2 || height < 2 {
+        return Err(format!("heightmap is {}x{}, needs at least 2x2", width, height));
+    }
+    Ok(HeightfieldOptions::new(width, height, heights))
+}
+
+/// Header and raw samples of a PGM or PPM, before any scaling.
+struct Netpbm {
+    width: usize,
+    height: usize,
+    channels: usize,
+    max_value: u32,
+    samples: Vec<u32>,
+}
+
+fn parse_netpbm(bytes: &[u8]) -> Result<Netpbm, String> {
+    // The header is four whitespace-separated tokens, with #-comments allowed
+    let mut pos = 0;
+    let mut header = Vec::new();
+    while header.len() < 4 {
+        while pos < bytes.len() && (bytes[pos].is_ascii_whitespace() || bytes[pos] == b'#') {
+            if bytes[pos] == b'#' {
+                while pos < bytes.len() && bytes[pos] != b'\n' { pos += 1; }
+            } else {
+                pos += 1;
+            }
+        }
+        let start = pos;
+        while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() { pos += 1; }
+        if start == pos {
+            return Err("truncated PPM header".to_string());
+        }
+        header.push(String::from_utf8_lossy(&bytes[start..pos]).into_owned());
+    }
+
+    let (binary, channels) = match header[0].as_str() {
+        "P6" => (true, 3),
+        "P5" => (true, 1),
+        "P3" => (false, 3),
+        "P2" => (false, 1),
+        magic => return Err(format!("unsupported image format '{}', expected PGM or PPM", magic))
+    };
+    let width: usize = parse_token(Some(&header[1]), "width")?;
+    let height: usize = parse_token(Some(&header[2]), "height")?;
+    let max_value: u32 = parse_token(Some(&header[3]), "max value")?;
+    if max_value == 0 || max_value > 65535 {
+        return Err(format!("invalid max value {}", max_value));
+    }
+
+    let expected = width.checked_mul(height).and_then(|pixels| pixels.checked_mul(channels))
+        .ok_or_else(|| format!("image size {}x{} is too large", width, height))?;
+
+    let samples: Vec<u32> = if binary {
+        // Exactly one whitespace byte separates the header from the raster
+        let raster = &bytes[(pos + 1).min(bytes.len())..];
+        let sample_size = if max_value < 256 { 1 } else { 2 };
+        if raster.len() / sample_size < expected {
+            return Err(format!("expected {} samples, found {}", expected, raster.len() / sample_size));
+        }
+        if sample_size == 1 {
+            raster[..expected].iter().map(|&b| b as u32).collect()
+        } else {
+            raster[..expected * 2].chunks(2).map(|c| (c[0] as u32) << 8 | c[1] as u32).collect()
+        }
+    } else {
+        // Every sample takes at least a digit, which bounds how many there
+        // can be before reading any
+        let raster = &bytes[pos..];
+        if raster.len() < expected {
+            return Err(format!("expected {} samples, found at most {}", expected, raster.len()));
+        }
+        let samples = String::from_utf8_lossy(raster).split_whitespace().take(expected)
+            .map(|token| parse_token(Some(token), "sample"))
+            .collect::<Result<Vec<u32>, String>>()?;
+        if samples.len() < expected {
+            return Err(format!("expected {} samples, found {}", expected, samples.len()));
+        }
+        samples
+    };
+
+    Ok(Netpbm {
+        width: width,
+        height: height,
+        channels: channels,
+        max_value: max_value,
+        samples: samples,
+    })
+}
+
+fn parse_ppm(bytes: &[u8]) -> Result<Surface, String> {
+    let image = parse_netpbm(bytes)?;
+    let mut surface = Surface::new(image.width, image.height, ColorRGBA::black());
+    for (pixel, samples) in surface.buffer.iter_mut().zip(image.samples.chunks(image.channels)) {
+        let scale = |sample: u32| (sample.min(image.max_value) * 255 / image.max_value) as u8;
+        // Gray images have one sample, repeated across all three channels
+        let rgb = |channel: usize| scale(samples[channel.min(image.channels - 1)]);
+        *pixel = ColorRGBA::new_rgb(rgb(0), rgb(1), rgb(2));
+    }
+
+    Ok(surface)
+}
+
+#[test]
+fn it_picks_the_format_from_the_extension() {
+    let dir = std::env::temp_dir();
+    let stl = dir.join(format!("raytracer-import-test-{}.STL", std::process::id()));
+    fs::write(&stl, "solid t\nfacet normal 0 0 1\nvertex 0 0 0\nvertex 1 0 0\nvertex 0 1 0\nendfacet\nendsolid t\n").unwrap();
+    assert_eq!(1, from_file(&stl, &ImportOptions::new()).unwrap().len());
+    let mut options = ImportOptions::new();
+    options.subdivide(SubdivisionScheme::Loop, 2);
+    assert_eq!(16, from_file(&stl, &options).unwrap().len());
+    fs::remove_file(&stl).unwrap();
+
+    let err = from_file(Path::new("model.3ds"), &ImportOptions::new()).err().unwrap();
+    assert_eq!("model.3ds: unsupported mesh format '3ds'", err.to_string());
+}
+
+#[test]
+fn it_reuses_cached_meshes() {
+    let dir = std::env::temp_dir().join(format!("raytracer-import-cache-test-{}", std::process::id()));
+    let stl = dir.with_extension("stl");
+    fs::write(&stl, "solid t\nfacet normal 0 0 1\nvertex 0 0 0\nvertex 1 0 0\nvertex 0 1 0\nendfacet\nendsolid t\n").unwrap();
+    let mut options = ImportOptions::new();
+    options.cache_dir(&dir);
+
+    assert_eq!(1, from_file(&stl, &options).unwrap().len());
+    assert_eq!(1, fs::read_dir(&dir).unwrap().count());
+    assert_eq!(1, from_file(&stl, &options).unwrap().len());
+
+    // Settings that change the geometry rewrite the same file's cache
+    options.subdivide(SubdivisionScheme::Loop, 1);
+    assert_eq!(4, from_file(&stl, &options).unwrap().len());
+    assert_eq!(1, fs::read_dir(&dir).unwrap().count());
+
+    // A cached mesh is taken as it is, without parsing the file again
+    let source = cache::checksum(&fs::read(&stl).unwrap());
+    let sources = MaterialSources::Colors(vec![None]);
+    cache::write_mesh(&cache::mesh_path(&dir, source, 0), options.cache_key("stl", source), &crate::geometry::prims::unit_quad().build(), |w| sources.write_cache(w)).unwrap();
+    assert_eq!(2, from_file(&stl, &options).unwrap().len());
+
+    fs::remove_dir_all(&dir).unwrap();
+    fs::remove_file(&stl).unwrap();
+}
+
+#[test]
+fn it_makes_cached_obj_materials_again_from_their_mtl() {
+    use core::f64::consts::PI;
+    use crate::geometry::Prim;
+    use crate::raytracer::Ray;
+
+    let dir = std::env::temp_dir().join(format!("raytracer-import-mtl-cache-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let obj = dir.join("test.obj");
+    fs::write(&obj, "mtllib test.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\n").unwrap();
+    fs::write(dir.join("test.mtl"), "newmtl red\nKd 1 0 0\n").unwrap();
+    let mut options = ImportOptions::new();
+    options.cache_dir(&dir.join("cache"));
+
+    let red = |mesh: &Mesh| {
+        let ray = Ray::new(Vec3 { x: 0.25, y: 0.25, z: 1.0 }, Vec3 { x: 0.0, y: 0.0, z: -1.0 });
+        let hit = mesh.intersects(&ray, 0.0, 10.0).unwrap();
+        let up = Vec3 { x: 0.0, y: 0.0, z: 1.0 };
+        hit.material.bsdf(hit.u, hit.v).eval(up, Vec3 { x: 0.8, y: 0.0, z: 0.6 }).scale(PI)
+    };
+    let built = red(&from_file(&obj, &options).unwrap());
+    assert!(built.x > 0.5 && built.y < 0.1);
+
+    // The MTL file is read again, so edits to it show up in cached meshes
+    assert_eq!(built, red(&from_file(&obj, &options).unwrap()));
+    fs::write(dir.join("test.mtl"), "newmtl red\nKd 0 1 0\n").unwrap();
+    assert!(red(&from_file(&obj, &options).unwrap()).y > 0.5);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn it_parses_ascii_and_binary_ppms() {
+    let ascii = parse_ppm(b"P3\n# comment\n2 1\n255\n255 0 0  0 0 255\n").unwrap();
+    assert_eq!((2, 1), (ascii.width, ascii.height));
+    assert_eq!(255, ascii[(0, 0)].r);
+    assert_eq!(255, ascii[(1, 0)].b);
+
+    let binary = parse_ppm(b"P6 1 2 15\n\x0f\x00\x00\x00\x0f\x00").unwrap();
+    assert_eq!(255, binary[(0, 0)].r);
+    assert_eq!(255, binary[(0, 1)].g);
+
+    assert!(parse_ppm(b"P6 2 2 255\n\x00").is_err());
+
+    // Sizes the data can't back fail before anything is allocated for them
+    let huge = parse_ppm(b"P5 18446744073709551615 2 255\n\x00").err().unwrap();
+    assert!(huge.contains("too large"), "{}", huge);
+    assert!(parse_ppm(b"P5 100000 100000 255\n\x00").is_err());
+    assert!(parse_ppm(b"P2 100000 100000 255\n1 2 3\n").is_err());
+    assert!(parse_ppm(b"\x89PNG").is_err());
+}
+
+#[test]
+fn it_reads_heightmaps_at_full_precision() {
+    let heightmap = parse_heightmap(b"P5 2 2 1000\n\x00\x00\x01\xf4\x03\xe8\x00\x01").unwrap().build();
+    assert_eq!((2, 2), (heightmap.columns, heightmap.rows));
+    assert_eq!(vec![0.0, 0.5, 1.0, 0.001], heightmap.heights);
+
+    let gray = parse_ppm(b"P2 1 1 4 2").unwrap();
+    assert_eq!((127, 127, 127), (gray[(0, 0)].r, gray[(0, 0)].g, gray[(0, 0)].b));
+
+    assert!(parse_heightmap(b"P2 1 1 4 2").is_err());
+}
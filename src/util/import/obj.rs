@@ -0,0 +1,531 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use crate::geometry::prims::{MeshFace, MeshOptions};
+use crate::geometry::subdivision::ControlMesh;
+use crate::material::Material;
+use crate::material::materials::{CookTorranceMaterial, PhongMaterial};
+use crate::material::textures::ImageTexture;
+use crate::vec3::Vec3;
+use super::{from_image, parse_token, parse_vec3, read_file, ImportError};
+
+/// Which of our materials MTL definitions are mapped onto.
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+pub enum MaterialModel {
+    Phong,
+    CookTorrance,
+}
+
+#[derive(Clone)]
+enum ObjMaterial {
+    Phong(PhongMaterial),
+    CookTorrance(CookTorranceMaterial),
+}
+
+impl ObjMaterial {
+    fn default(model: MaterialModel) -> ObjMaterial {
+        match model {
+            MaterialModel::Phong => ObjMaterial::Phong(PhongMaterial::default()),
+            MaterialModel::CookTorrance => ObjMaterial::CookTorrance(CookTorranceMaterial::default()),
+        }
+    }
+
+    fn boxed(&self) -> Box<dyn Material+Send+Sync> {
+        match *self {
+            ObjMaterial::Phong(ref m) => Box::new(m.clone()),
+            ObjMaterial::CookTorrance(ref m) => Box::new(m.clone()),
+        }
+    }
+}
+
+/// The subset of an MTL `newmtl` block we understand. Unset values keep the
+/// defaults of the material model.
+#[derive(Default)]
+struct MtlDefinition {
+    diffuse: Option<Vec3>,
+    specular: Option<Vec3>,
+    shininess: Option<f64>,
+    ior: Option<f64>,
+    dissolve: Option<f64>,
+    diffuse_map: Option<ImageTexture>,
+}
+
+impl MtlDefinition {
+    fn to_material(&self, model: MaterialModel) -> ObjMaterial {
+        let dissolve = self.dissolve.unwrap_or(1.0).clamp(0.0, 1.0);
+        let transmissive = 1.0 - dissolve;
+        let specular = self.specular.unwrap_or(Vec3::zero());
+        let diffuse_texture = self.diffuse_map.clone()
+            .map(|texture| Box::new(texture) as Box<_>);
+
+        match model {
+            MaterialModel::Phong => {
+                let default = PhongMaterial::default();
+                ObjMaterial::Phong(PhongMaterial {
+                    k_d: dissolve,
+                    k_tg: transmissive,
+                    diffuse: self.diffuse.unwrap_or(default.diffuse),
+                    specular: specular,
+                    transmission: Vec3::one().scale(transmissive),
+                    shininess: self.shininess.unwrap_or(default.shininess),
+                    ior: self.ior.unwrap_or(default.ior),
+                    diffuse_texture: diffuse_texture,
+                    ..default
+                })
+            },
+            MaterialModel::CookTorrance => {
+                let default = CookTorranceMaterial::default();
+                // Beckmann slope from a Phong exponent (Walter et al. 2007)
+                let roughness = self.shininess
+                    .map(|ns| (2.0 / (ns.max(0.0) + 2.0)).sqrt())
+                    .unwrap_or(default.roughness);
+
+                ObjMaterial::CookTorrance(CookTorranceMaterial {
+                    k_d: dissolve,
+                    k_tg: transmissive,
+                    diffuse: self.diffuse.unwrap_or(default.diffuse),
+                    specular: specular,
+                    transmission: Vec3::one().scale(transmissive),
+                    roughness: roughness,
+                    ior: self.ior.unwrap_or(default.ior),
+                    diffuse_texture: diffuse_texture,
+                    ..default
+                })
+            }
+        }
+    }
+}
+
+pub struct ObjGroup {
+    pub name: String,
+    /// Triangulated faces, indexing the model's buffers and materials
+    pub faces: Vec<MeshFace>,
+    /// The faces as written, before triangulation, as indices into
+    /// `ObjModel::positions`
+    pub polygons: Vec<Vec<u32>>,
+    polygon_materials: Vec<u32>,
+}
+
+impl ObjGroup {
+    fn new(name: String) -> ObjGroup {
+        ObjGroup { name: name, faces: Vec::new(), polygons: Vec::new(), polygon_materials: Vec::new() }
+    }
+}
+
+pub struct ObjModel {
+    pub groups: Vec<ObjGroup>,
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub texinfo: Vec<(f64, f64)>,
+    /// MTL files named by `mtllib`, in order
+    pub libraries: Vec<PathBuf>,
+    materials: Vec<ObjMaterial>,
+    /// Name of each of `materials`, None for the default one
+    names: Vec<Option<String>>,
+}
+
+impl ObjModel {
+    /// Every group's faces in one mesh, sharing the model's buffers, with a
+    /// face group for each material.
+    pub fn into_mesh(self) -> MeshOptions {
+        let materials = self.materials();
+        let faces = self.groups.into_iter().flat_map(|group| group.faces).collect();
+
+        let mut meshopts = MeshOptions::new(self.positions);
+        meshopts.normals(self.normals);
+        meshopts.texinfo(self.texinfo);
+        meshopts.faces_with_materials(materials, faces);
+        meshopts
+    }
+
+    /// The materials faces index, in order.
+    pub fn materials(&self) -> Vec<Box<dyn Material+Send+Sync>> {
+        self.materials.iter().map(|material| material.boxed()).collect()
+    }
+
+    /// Names of the materials some face uses, which are the ones `into_mesh`
+    /// and subdividing keep, in order. None stands for the default material.
+    pub fn material_names(&self) -> Vec<Option<String>> {
+        let mut used = vec![false; self.names.len()];
+        for &material in self.groups.iter().flat_map(|group| group.polygon_materials.iter()) {
+            used[material as usize] = true;
+        }
+        self.names.iter().zip(used).filter(|&(_, used)| used).map(|(name, _)| name.clone()).collect()
+    }
+
+    /// Every group's polygons in one mesh, so subdividing doesn't open
+    /// cracks where groups meet. Faces are numbered across groups in order.
+    pub fn control_mesh(&self) -> ControlMesh {
+        let polygons = self.groups.iter().flat_map(|group| group.polygons.iter().cloned()).collect();
+        ControlMesh::new(self.positions.clone(), polygons)
+    }
+
+    /// The index into `materials` of a face of `control_mesh`, or None past
+    /// its last face.
+    pub fn face_material(&self, mut face: usize) -> Option<usize> {
+        for group in self.groups.iter() {
+            if face < group.polygon_materials.len() {
+                return Some(group.polygon_materials[face] as usize);
+            }
+            face -= group.polygon_materials.len();
+        }
+        None
+    }
+}
+
+/// OBJ indices are 1-based, or negative to count back from the most recently
+/// defined element.
+fn resolve_index(token: &str, len: usize, what: &str) -> Result<usize, String> {
+    let index: i64 = parse_token(Some(token), what)?;
+    let resolved = if index > 0 { index - 1 } else { len as i64 + index };
+
+    if index == 0 || resolved < 0 || resolved >= len as i64 {
+        Err(format!("{} index {} out of range (have {})", what, index, len))
+    } else {
+        Ok(resolved as usize)
+    }
+}
+
+fn parse_mtl(path: &Path, materials: &mut HashMap<String, MtlDefinition>) -> Result<(), ImportError> {
+    let source = read_file(path)?;
+    let source = String::from_utf8_lossy(&source);
+    let mut current: Option<String> = None;
+
+    for (line_index, line) in source.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) if !keyword.starts_with('#') => keyword,
+            _ => continue
+        };
+
+        let result = if keyword == "newmtl" {
+            let name = tokens.collect::<Vec<&str>>().join(" ");
+            if name.is_empty() {
+                Err("newmtl without a name".to_string())
+            } else {
+                materials.insert(name.clone(), MtlDefinition::default());
+                current = Some(name);
+                Ok(())
+            }
+        } else {
+            match current.as_ref().and_then(|name| materials.get_mut(name)) {
+                Some(material) => parse_mtl_statement(path, keyword, &mut tokens, material),
+                None => Err(format!("'{}' before newmtl", keyword))
+            }
+        };
+
+        result.map_err(|message| ImportError::parse(path, line_index + 1, message))?;
+    }
+
+    Ok(())
+}
+
+fn parse_mtl_statement<'a, I>(path: &Path, keyword: &str, tokens: &mut I,
+                              material: &mut MtlDefinition) -> Result<(), String>
+        where I: Iterator<Item=&'a str> {
+    match keyword {
+        "Kd" => material.diffuse = Some(parse_vec3(tokens, "color")?),
+        "Ks" => material.specular = Some(parse_vec3(tokens, "color")?),
+        "Ns" => material.shininess = Some(parse_token(tokens.next(), "exponent")?),
+        "Ni" => material.ior = Some(parse_token(tokens.next(), "index of refraction")?),
+        "d" => material.dissolve = Some(parse_token(tokens.next(), "dissolve")?),
+        "Tr" => material.dissolve = Some(1.0 - parse_token::<f64>(tokens.next(), "transparency")?),
+        "map_Kd" => {
+            // Texture options (-s, -o, ...) come before the file name
+            let file = tokens.last().ok_or_else(|| "map_Kd without a file".to_string())?;
+            let texture_path = path.parent().unwrap_or_else(|| Path::new("")).join(file);
+            let image = from_image(&texture_path).map_err(|err| format!("map_Kd texture {}", err))?;
+            material.diffuse_map = Some(ImageTexture::new(image));
+        },
+        _ => {} // Ka, Ke, illum, ... have no equivalent in our materials
+    }
+
+    Ok(())
+}
+
+struct ObjParser<'a> {
+    path: &'a Path,
+    model: MaterialModel,
+    positions: Vec<Vec3>,
+    texinfo: Vec<(f64, f64)>,
+    normals: Vec<Vec3>,
+    definitions: HashMap<String, MtlDefinition>,
+    libraries: Vec<PathBuf>,
+    materials: Vec<ObjMaterial>,
+    material_names: HashMap<String, u32>,
+    /// Index into `materials` of the material faces get
+    material: u32,
+    groups: Vec<ObjGroup>,
+}
+
+impl<'a> ObjParser<'a> {
+    fn statement<'b, I>(&mut self, keyword: &str, tokens: &mut I) -> Result<(), String>
+            where I: Iterator<Item=&'b str> {
+        match keyword {
+            "v" => {
+                let v = parse_vec3(tokens, "vertex coordinate")?;
+                self.positions.push(v);
+            },
+            "vt" => {
+                let u = parse_token(tokens.next(), "texture coordinate")?;
+                let v = match tokens.next() {
+                    Some(v) => parse_token(Some(v), "texture coordinate")?,
+                    None => 0.0
+                };
+                self.texinfo.push((u, v));
+            },
+            "vn" => {
+                let n = parse_vec3(tokens, "normal")?;
+                self.normals.push(n);
+            },
+            "f" => self.face(tokens)?,
+            "g" | "o" => {
+                let name = tokens.collect::<Vec<&str>>().join(" ");
+                self.start_group(name);
+            },
+            "usemtl" => {
+                let name = tokens.collect::<Vec<&str>>().join(" ");
+                self.material = self.lookup_material(&name)?;
+            },
+            "mtllib" => {
+                for file in tokens {
+                    let mtl_path = self.path.parent().unwrap_or_else(|| Path::new("")).join(file);
+                    parse_mtl(&mtl_path, &mut self.definitions).map_err(|err| err.to_string())?;
+                    self.libraries.push(mtl_path);
+                }
+            },
+            _ => {} // s, l, p, curves and other statements are ignored
+        }
+
+        Ok(())
+    }
+
+    fn lookup_material(&mut self, name: &str) -> Result<u32, String> {
+        if let Some(&index) = self.material_names.get(name) {
+            return Ok(index);
+        }
+
+        let material = self.definitions.get(name)
+            .ok_or_else(|| format!("unknown material '{}'", name))?
+            .to_material(self.model);
+        self.materials.push(material);
+        let index = (self.materials.len() - 1) as u32;
+        self.material_names.insert(name.to_string(), index);
+        Ok(index)
+    }
+
+    fn start_group(&mut self, name: String) {
+        match self.groups.last_mut() {
+            Some(ref mut group) if group.faces.is_empty() => group.name = name,
+            _ => self.groups.push(ObjGroup::new(name))
+        }
+    }
+
+    /// Polygons are triangulated as a fan around their first vertex.
+    fn face<'b, I>(&mut self, tokens: &mut I) -> Result<(), String> where I: Iterator<Item=&'b str> {
+        let mut vertices = Vec::new();
+        let mut texinfo = Vec::new();
+        let mut normals = Vec::new();
+
+        for token in tokens {
+            let mut parts = token.split('/');
+            let v = parts.next().unwrap_or("");
+            vertices.push(resolve_index(v, self.positions.len(), "vertex")? as u32);
+
+            match parts.next() {
+                Some(vt) if !vt.is_empty() => texinfo.push(resolve_index(vt, self.texinfo.len(), "texture")? as u32),
+                _ => {}
+            }
+            match parts.next() {
+                Some(vn) if !vn.is_empty() => normals.push(resolve_index(vn, self.normals.len(), "normal")? as u32),
+                _ => {}
+            }
+        }
+
+        if vertices.len() < 3 {
+            return Err(format!("face needs at least 3 vertices, got {}", vertices.len()));
+        }
+
+        if self.groups.is_empty() {
+            self.start_group("default".to_string());
+        }
+
+        // Partially specified attributes can't be interpolated, so drop them
+        let fan = |indices: &[u32], i: usize| if indices.len() == vertices.len() {
+            Some([indices[0], indices[i], indices[i + 1]])
+        } else {
+            None
+        };
+        let group = self.groups.last_mut().unwrap();
+        for i in 1..vertices.len() - 1 {
+            group.faces.push(MeshFace {
+                vertices: [vertices[0], vertices[i], vertices[i + 1]],
+                normals: fan(&normals, i),
+                texinfo: fan(&texinfo, i),
+                material: self.material
+            });
+        }
+
+        group.polygons.push(vertices);
+        group.polygon_materials.push(self.material);
+
+        Ok(())
+    }
+}
+
+/// Loads a Wavefront OBJ model along with any MTL files it references.
+/// Faces before the first `g`/`o` end up in a group named "default", and
+/// faces before the first `usemtl` get the default material of `model`.
+pub fn from_obj(path: &Path, model: MaterialModel) -> Result<ObjModel, ImportError> {
+    let source = read_file(path)?;
+    let source = String::from_utf8_lossy(&source);
+
+    let mut parser = ObjParser {
+        path: path,
+        model: model,
+        positions: Vec::new(),
+        texinfo: Vec::new(),
+        normals: Vec::new(),
+        definitions: HashMap::new(),
+        libraries: Vec::new(),
+        materials: vec![ObjMaterial::default(model)],
+        material_names: HashMap::new(),
+        material: 0,
+        groups: Vec::new(),
+    };
+
+    for (line_index, line) in source.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some(keyword) if !keyword.starts_with('#') => {
+                parser.statement(keyword, &mut tokens)
+                    .map_err(|message| ImportError::parse(path, line_index + 1, message))?;
+            },
+            _ => {}
+        }
+    }
+
+    let mut groups = parser.groups;
+    groups.retain(|group| !group.faces.is_empty());
+
+    let mut names = vec![None; parser.materials.len()];
+    for (name, &index) in parser.material_names.iter() {
+        names[index as usize] = Some(name.clone());
+    }
+
+    Ok(ObjModel {
+        groups: groups,
+        positions: parser.positions,
+        normals: parser.normals,
+        texinfo: parser.texinfo,
+        libraries: parser.libraries,
+        materials: parser.materials,
+        names: names
+    })
+}
+
+/// Makes the materials of a model `from_obj` loaded from `path` again,
+/// without reading the OBJ itself: each named one from the MTL `libraries`,
+/// and the default material of `model` for None.
+pub fn mtl_materials(path: &Path, libraries: &[PathBuf], names: &[Option<String>],
+                     model: MaterialModel) -> Result<Vec<Box<dyn Material+Send+Sync>>, ImportError> {
+    let mut definitions = HashMap::new();
+    for library in libraries.iter() {
+        parse_mtl(library, &mut definitions)?;
+    }
+
+    names.iter().map(|name| match *name {
+        Some(ref name) => definitions.get(name)
+            .map(|definition| definition.to_material(model).boxed())
+            .ok_or_else(|| ImportError::parse(path, 0, format!("unknown material '{}'", name))),
+        None => Ok(ObjMaterial::default(model).boxed())
+    }).collect()
+}
+
+#[cfg(test)]
+fn write_test_files(name: &str, obj: &str, mtl: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("raytracer-obj-test-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("test.mtl"), mtl).unwrap();
+    std::fs::write(dir.join("test.obj"), obj).unwrap();
+    dir.join("test.obj")
+}
+
+#[test]
+fn it_loads_polygons_groups_and_materials() {
+    use core::f64::consts::PI;
+    use crate::geometry::Prim;
+    use crate::raytracer::Ray;
+
+    let path = write_test_files("quad", "
+        mtllib test.mtl
+        v 0 0 0
+        v 1 0 0
+        v 1 1 0
+        v 0 1 0
+        vt 0 0
+        vt 1 0
+        vt 1 1
+        vt 0 1
+        vn 0 0 -1
+        g front
+        usemtl red
+        f 1/1/1 2/2/1 3/3/1 4/4/1
+        g back
+        f -4//-1 -2//-1 -3//-1
+    ", "
+        # A comment
+        newmtl red
+        Kd 1 0 0
+        Ks 0.5 0.5 0.5
+        Ns 20
+        d 1
+    ");
+
+    let model = from_obj(&path, MaterialModel::Phong).unwrap();
+    assert_eq!(2, model.groups.len());
+    assert_eq!("front", model.groups[0].name);
+    assert_eq!(2, model.groups[0].faces.len());
+    assert_eq!("back", model.groups[1].name);
+    assert_eq!(1, model.groups[1].faces.len());
+
+    // Subdividing starts from the quad as written
+    assert_eq!(vec![0, 1, 2, 3], model.groups[0].polygons[0]);
+    assert_eq!(2, model.control_mesh().faces.len());
+    assert_eq!(Some(1), model.face_material(1));
+    assert_eq!(None, model.face_material(2));
+
+    // Both groups end up in one mesh, the back one with the default material
+    let mesh = model.into_mesh().build();
+    assert_eq!(3, mesh.len());
+    let ray = Ray::new(Vec3 { x: 0.25, y: 0.75, z: -1.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+    let hit = mesh.intersects(&ray, 0.0, 10.0).unwrap();
+    assert_eq!(0.25, hit.u);
+    assert_eq!(0.75, hit.v);
+    assert_eq!(Vec3 { x: 0.0, y: 0.0, z: -1.0 }, hit.n);
+
+    // Lit from off to the side, clear of the highlight
+    let flat = Vec3 { x: 1.0, y: 0.0, z: 0.0 };
+    let up = Vec3 { x: 0.0, y: 0.0, z: 1.0 };
+    let color = hit.material.bsdf(hit.u, hit.v).eval(up, Vec3 { x: 0.8, y: 0.0, z: 0.6 }).scale(PI);
+    assert!(color.x > 0.9 && color.y < 0.6 && color.dot(&flat) > 0.0);
+}
+
+#[test]
+fn it_reports_line_numbers_for_malformed_lines() {
+    let path = write_test_files("malformed", "v 0 0 0\nv 1 0 0\nv 1 1 oops\n", "");
+    let err = from_obj(&path, MaterialModel::Phong).err().unwrap().to_string();
+    assert!(err.ends_with(":3: invalid vertex coordinate 'oops'"), "{}", err);
+
+    let path = write_test_files("range", "v 0 0 0\nv 1 0 0\nv 1 1 0\n\nf 1 2 4\n", "");
+    let err = from_obj(&path, MaterialModel::Phong).err().unwrap().to_string();
+    assert!(err.ends_with(":5: vertex index 4 out of range (have 3)"), "{}", err);
+
+    let path = write_test_files("material", "mtllib test.mtl\nusemtl missing\n", "Kd 1 1 1\n");
+    let err = from_obj(&path, MaterialModel::CookTorrance).err().unwrap().to_string();
+    assert!(err.contains("test.mtl:1: 'Kd' before newmtl"), "{}", err);
+
+    let path = write_test_files("texture", "mtllib test.mtl\n", "newmtl a\nmap_Kd -s 2 2 missing.ppm\n");
+    let err = from_obj(&path, MaterialModel::Phong).err().unwrap().to_string();
+    assert!(err.contains("test.mtl:2: map_Kd texture ") && err.contains("missing.ppm"), "{}", err);
+}
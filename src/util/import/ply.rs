@@ -0,0 +1,517 @@
+use std::collections::HashMap;
+use std::path::Path;
+use crate::geometry::prims::{MeshFace, MeshOptions};
+use crate::geometry::subdivision::ControlMesh;
+use crate::material::Material;
+use crate::vec3::Vec3;
+use super::{parse_token, read_file, ImportError};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Encoding {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ScalarType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Result<ScalarType, String> {
+        Ok(match name {
+            "char" | "int8" => ScalarType::Int8,
+            "uchar" | "uint8" => ScalarType::UInt8,
+            "short" | "int16" => ScalarType::Int16,
+            "ushort" | "uint16" => ScalarType::UInt16,
+            "int" | "int32" => ScalarType::Int32,
+            "uint" | "uint32" => ScalarType::UInt32,
+            "float" | "float32" => ScalarType::Float32,
+            "double" | "float64" => ScalarType::Float64,
+            _ => return Err(format!("unknown property type '{}'", name))
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::Int8 | ScalarType::UInt8 => 1,
+            ScalarType::Int16 | ScalarType::UInt16 => 2,
+            ScalarType::Int32 | ScalarType::UInt32 | ScalarType::Float32 => 4,
+            ScalarType::Float64 => 8,
+        }
+    }
+
+    /// Integer colors are stored in the full range of their type, float
+    /// colors in [0, 1].
+    fn color_scale(self) -> f64 {
+        match self {
+            ScalarType::Int8 => 127.0,
+            ScalarType::UInt8 => 255.0,
+            ScalarType::Int16 => 32767.0,
+            ScalarType::UInt16 => 65535.0,
+            ScalarType::Int32 => 2147483647.0,
+            ScalarType::UInt32 => 4294967295.0,
+            ScalarType::Float32 | ScalarType::Float64 => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum PropertyKind {
+    Scalar(ScalarType),
+    List { count: ScalarType, item: ScalarType },
+}
+
+#[derive(Clone, Debug)]
+struct Property {
+    name: String,
+    kind: PropertyKind,
+}
+
+#[derive(Clone, Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn find(&self, names: &[&str]) -> Option<usize> {
+        self.properties.iter().position(|p| names.contains(&p.name.as_str()))
+    }
+}
+
+struct Header {
+    encoding: Encoding,
+    elements: Vec<Element>,
+    /// Number of lines in the header, for line numbers in ASCII bodies
+    lines: usize,
+    /// Byte offset of the body
+    len: usize,
+}
+
+fn parse_header(bytes: &[u8]) -> Result<Header, (usize, String)> {
+    let mut encoding = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut pos = 0;
+    let mut line_number = 0;
+
+    loop {
+        let end = match bytes[pos..].iter().position(|&b| b == b'\n') {
+            Some(end) => pos + end,
+            None => return Err((line_number, "missing end_header".to_string()))
+        };
+        let line = String::from_utf8_lossy(&bytes[pos..end]);
+        pos = end + 1;
+        line_number += 1;
+
+        let mut tokens = line.split_whitespace();
+        let keyword = tokens.next().unwrap_or("");
+        let error = |message: String| (line_number, message);
+
+        if line_number == 1 {
+            if keyword != "ply" {
+                return Err(error("not a PLY file".to_string()));
+            }
+            continue;
+        }
+
+        match keyword {
+            "format" => {
+                encoding = Some(match tokens.next() {
+                    Some("ascii") => Encoding::Ascii,
+                    Some("binary_little_endian") => Encoding::BinaryLittleEndian,
+                    Some("binary_big_endian") => Encoding::BinaryBigEndian,
+                    other => return Err(error(format!("unknown format '{}'", other.unwrap_or(""))))
+                });
+            },
+            "element" => {
+                let name = tokens.next().ok_or_else(|| error("element without a name".to_string()))?;
+                let count = parse_token(tokens.next(), "element count").map_err(error)?;
+                elements.push(Element { name: name.to_string(), count: count, properties: Vec::new() });
+            },
+            "property" => {
+                let element = elements.last_mut().ok_or_else(|| error("property before element".to_string()))?;
+                let type_name = tokens.next().unwrap_or("");
+                let kind = if type_name == "list" {
+                    PropertyKind::List {
+                        count: ScalarType::parse(tokens.next().unwrap_or("")).map_err(error)?,
+                        item: ScalarType::parse(tokens.next().unwrap_or("")).map_err(error)?
+                    }
+                } else {
+                    PropertyKind::Scalar(ScalarType::parse(type_name).map_err(error)?)
+                };
+                let name = tokens.next().ok_or_else(|| error("property without a name".to_string()))?;
+                element.properties.push(Property { name: name.to_string(), kind: kind });
+            },
+            "end_header" => break,
+            "comment" | "obj_info" | "" => {},
+            _ => return Err(error(format!("unknown header keyword '{}'", keyword)))
+        }
+    }
+
+    match encoding {
+        Some(encoding) => Ok(Header { encoding: encoding, elements: elements, lines: line_number, len: pos }),
+        None => Err((line_number, "missing format".to_string()))
+    }
+}
+
+/// Reads property values from the body of a PLY file. ASCII bodies have one
+/// element per line; binary bodies are packed without any padding.
+enum BodyReader<'a> {
+    Ascii { lines: std::iter::Enumerate<std::str::Lines<'a>>, first_line: usize, line: usize, tokens: Vec<&'a str>, next: usize },
+    Binary { bytes: &'a [u8], pos: usize, big_endian: bool },
+}
+
+impl<'a> BodyReader<'a> {
+    /// Moves to the next element instance.
+    fn begin_element(&mut self) -> Result<(), String> {
+        if let BodyReader::Ascii { ref mut lines, first_line, ref mut line, ref mut tokens, ref mut next } = *self {
+            loop {
+                let (index, text) = lines.next().ok_or_else(|| "unexpected end of file".to_string())?;
+                *line = first_line + index;
+                tokens.clear();
+                tokens.extend(text.split_whitespace());
+                *next = 0;
+                if !tokens.is_empty() {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn line(&self) -> usize {
+        match *self {
+            BodyReader::Ascii { line, .. } => line,
+            BodyReader::Binary { .. } => 0
+        }
+    }
+
+    fn read(&mut self, ty: ScalarType) -> Result<f64, String> {
+        match *self {
+            BodyReader::Ascii { ref tokens, ref mut next, .. } => {
+                let value: f64 = parse_token(tokens.get(*next).cloned(), "property value")?;
+                *next += 1;
+                Ok(value)
+            },
+            BodyReader::Binary { bytes, ref mut pos, big_endian } => {
+                let size = ty.size();
+                if bytes.len() - *pos < size {
+                    return Err(format!("unexpected end of file at byte {}", *pos));
+                }
+                let mut buf = [0u8; 8];
+                buf[..size].copy_from_slice(&bytes[*pos..*pos + size]);
+                if big_endian {
+                    buf[..size].reverse();
+                }
+                *pos += size;
+
+                Ok(match ty {
+                    ScalarType::Int8 => buf[0] as i8 as f64,
+                    ScalarType::UInt8 => buf[0] as f64,
+                    ScalarType::Int16 => i16::from_le_bytes([buf[0], buf[1]]) as f64,
+                    ScalarType::UInt16 => u16::from_le_bytes([buf[0], buf[1]]) as f64,
+                    ScalarType::Int32 => i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+                    ScalarType::UInt32 => u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+                    ScalarType::Float32 => f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+                    ScalarType::Float64 => f64::from_le_bytes(buf),
+                })
+            }
+        }
+    }
+
+    /// Reads every property of one element instance into `values`, one
+    /// entry per property. List properties are read in full; scalars as
+    /// one-element lists. The buffers are reused from one instance to the
+    /// next, so reading a large body doesn't allocate per element.
+    fn read_element(&mut self, element: &Element, values: &mut Vec<Vec<f64>>) -> Result<(), String> {
+        self.begin_element()?;
+        values.resize_with(element.properties.len(), Vec::new);
+        for (property, value) in element.properties.iter().zip(values.iter_mut()) {
+            value.clear();
+            match property.kind {
+                PropertyKind::Scalar(ty) => value.push(self.read(ty)?),
+                PropertyKind::List { count, item } => {
+                    let len = self.read(count)?;
+                    if len < 0.0 || len.fract() != 0.0 {
+                        return Err(format!("invalid list length {}", len));
+                    }
+                    for _ in 0..len as usize {
+                        value.push(self.read(item)?);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Geometry read from a PLY file. Attributes are only present if every
+/// vertex has them; colors are in [0, 1].
+pub struct PlyModel {
+    pub positions: Vec<Vec3>,
+    pub normals: Option<Vec<Vec3>>,
+    pub texinfo: Option<Vec<(f64, f64)>>,
+    pub colors: Option<Vec<Vec3>>,
+    pub faces: Vec<[u32; 3]>,
+}
+
+impl PlyModel {
+    /// A material for each distinct face color, and the index of each
+    /// face's. `material` is called with the average of a face's vertex
+    /// colors, if the file has any.
+    pub fn face_materials<T, F>(&self, material: F) -> (Vec<T>, Vec<u32>) where F: Fn(Option<Vec3>) -> T {
+        let mut materials = Vec::new();
+        let mut by_color: HashMap<Option<[u64; 3]>, u32> = HashMap::new();
+        let indices = (0..self.faces.len()).map(|face| {
+            let color = self.face_color(face);
+            let key = color.map(|c| [c.x.to_bits(), c.y.to_bits(), c.z.to_bits()]);
+            *by_color.entry(key).or_insert_with(|| {
+                materials.push(material(color));
+                (materials.len() - 1) as u32
+            })
+        }).collect();
+        (materials, indices)
+    }
+
+    /// Builds the model into a mesh, with faces of the same color sharing
+    /// a material as in `face_materials`.
+    pub fn into_mesh<F>(self, material: F) -> MeshOptions
+            where F: Fn(Option<Vec3>) -> Box<dyn Material+Send+Sync> {
+        let (materials, indices) = self.face_materials(material);
+        let faces = self.faces.iter().zip(indices).map(|(&face, index)| MeshFace {
+            vertices: face,
+            normals: self.normals.as_ref().map(|_| face),
+            texinfo: self.texinfo.as_ref().map(|_| face),
+            material: index
+        }).collect();
+
+        let mut meshopts = MeshOptions::new(self.positions);
+        if let Some(normals) = self.normals {
+            meshopts.normals(normals);
+        }
+        if let Some(texinfo) = self.texinfo {
+            meshopts.texinfo(texinfo);
+        }
+        meshopts.faces_with_materials(materials, faces);
+        meshopts
+    }
+
+    /// The average of a face's vertex colors, if the file has any.
+    fn face_color(&self, face: usize) -> Option<Vec3> {
+        let face = self.faces[face];
+        self.colors.as_ref().map(|colors| {
+            face.iter().fold(Vec3::zero(), |sum, &v| sum + colors[v as usize]).scale(1.0 / 3.0)
+        })
+    }
+
+    pub fn control_mesh(&self) -> ControlMesh {
+        ControlMesh::from_triangles(self.positions.clone(), &self.faces)
+    }
+}
+
+fn vertex_layout(element: &Element, names: &[&[&str]]) -> Option<Vec<usize>> {
+    names.iter().map(|alternatives| element.find(alternatives)).collect()
+}
+
+fn parse_ply(bytes: &[u8]) -> Result<PlyModel, (usize, String)> {
+    let header = parse_header(bytes)?;
+    let body = &bytes[header.len..];
+    let mut reader = match header.encoding {
+        Encoding::Ascii => {
+            let text = std::str::from_utf8(body).map_err(|_| (header.lines + 1, "ASCII body is not valid UTF-8".to_string()))?;
+            BodyReader::Ascii { lines: text.lines().enumerate(), first_line: header.lines + 1, line: header.lines, tokens: Vec::new(), next: 0 }
+        },
+        Encoding::BinaryLittleEndian => BodyReader::Binary { bytes: body, pos: 0, big_endian: false },
+        Encoding::BinaryBigEndian => BodyReader::Binary { bytes: body, pos: 0, big_endian: true },
+    };
+
+    let mut model = PlyModel { positions: Vec::new(), normals: None, texinfo: None, colors: None, faces: Vec::new() };
+    let mut values = Vec::new();
+
+    for element in header.elements.iter() {
+        match element.name.as_str() {
+            "vertex" => {
+                let position = vertex_layout(element, &[&["x"], &["y"], &["z"]])
+                    .ok_or_else(|| (header.lines, "vertex element without x, y and z".to_string()))?;
+                let normal = vertex_layout(element, &[&["nx"], &["ny"], &["nz"]]);
+                let texinfo = vertex_layout(element, &[&["u", "s", "texture_u", "texture_s"], &["v", "t", "texture_v", "texture_t"]]);
+                let color = vertex_layout(element, &[&["red", "r", "diffuse_red"], &["green", "g", "diffuse_green"], &["blue", "b", "diffuse_blue"]]);
+                let color_scale = color.as_ref().map(|c| match element.properties[c[0]].kind {
+                    PropertyKind::Scalar(ty) => ty.color_scale(),
+                    PropertyKind::List { .. } => 1.0
+                });
+
+                let mut normals = Vec::new();
+                let mut texcoords = Vec::new();
+                let mut colors = Vec::new();
+
+                for _ in 0..element.count {
+                    reader.read_element(element, &mut values).map_err(|message| (reader.line(), message))?;
+                    let scalar = |index: usize| values[index].first().cloned().unwrap_or(0.0);
+                    let vec3 = |indices: &[usize]| Vec3 { x: scalar(indices[0]), y: scalar(indices[1]), z: scalar(indices[2]) };
+
+                    model.positions.push(vec3(&position));
+                    if let Some(ref n) = normal { normals.push(vec3(n)); }
+                    if let Some(ref t) = texinfo { texcoords.push((scalar(t[0]), scalar(t[1]))); }
+                    if let Some(ref c) = color { colors.push(vec3(c).scale(1.0 / color_scale.unwrap_or(1.0))); }
+                }
+
+                if normal.is_some() { model.normals = Some(normals); }
+                if texinfo.is_some() { model.texinfo = Some(texcoords); }
+                if color.is_some() { model.colors = Some(colors); }
+            },
+            "face" => {
+                let indices = element.find(&["vertex_indices", "vertex_index"])
+                    .ok_or_else(|| (header.lines, "face element without vertex_indices".to_string()))?;
+
+                let mut face = Vec::new();
+                for _ in 0..element.count {
+                    reader.read_element(element, &mut values).map_err(|message| (reader.line(), message))?;
+                    let polygon = &values[indices];
+                    if polygon.len() < 3 {
+                        return Err((reader.line(), format!("face needs at least 3 vertices, got {}", polygon.len())));
+                    }
+
+                    face.clear();
+                    for &index in polygon.iter() {
+                        if index < 0.0 || index >= model.positions.len() as f64 {
+                            return Err((reader.line(), format!("vertex index {} out of range (have {})", index, model.positions.len())));
+                        }
+                        face.push(index as u32);
+                    }
+
+                    // Fan triangulation around the first vertex
+                    for i in 1..face.len() - 1 {
+                        model.faces.push([face[0], face[i], face[i + 1]]);
+                    }
+                }
+            },
+            _ => {
+                // Edges, materials, range grids, ... are skipped
+                for _ in 0..element.count {
+                    reader.read_element(element, &mut values).map_err(|message| (reader.line(), message))?;
+                }
+            }
+        }
+    }
+
+    Ok(model)
+}
+
+/// Loads the vertex and face elements of an ASCII or binary PLY file, with
+/// optional normals, texture coordinates and vertex colors. Other elements
+/// are skipped, and polygons are triangulated as fans.
+pub fn from_ply(path: &Path) -> Result<PlyModel, ImportError> {
+    let bytes = read_file(path)?;
+    parse_ply(&bytes).map_err(|(line, message)| ImportError::parse(path, line, message))
+}
+
+#[test]
+fn it_parses_ascii_plys() {
+    let model = parse_ply(b"ply
+format ascii 1.0
+comment a quad with an edge
+element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property uchar red
+property uchar green
+property uchar blue
+element edge 1
+property int vertex1
+property int vertex2
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 0 0 1 255 0 0
+1 0 0 0 0 1 255 0 0
+
+1 1 0 0 0 1 0 0 255
+0 1 0 0 0 1 0 0 255
+0 1
+4 0 1 2 3
+").unwrap();
+
+    assert_eq!(4, model.positions.len());
+    assert_eq!(vec![[0, 1, 2], [0, 2, 3]], model.faces);
+    assert_eq!(Vec3 { x: 0.0, y: 0.0, z: 1.0 }, model.normals.as_ref().unwrap()[2]);
+    assert_eq!(Vec3 { x: 0.0, y: 0.0, z: 1.0 }, model.colors.as_ref().unwrap()[3]);
+    assert!(model.texinfo.is_none());
+
+    // A material for each face color; the corner colors average differently
+    let materials = std::cell::Cell::new(0);
+    let mesh = model.into_mesh(|color| {
+        materials.set(materials.get() + 1);
+        Box::new(crate::material::materials::FlatMaterial { color: color.unwrap() })
+    }).build();
+    assert_eq!((2, 2), (mesh.len(), materials.get()));
+
+    let err = parse_ply(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nproperty float y\nproperty float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n0 0 0\n3 0 1 2\n");
+    assert_eq!(Some((11, "vertex index 1 out of range (have 1)".to_string())), err.err());
+}
+
+#[test]
+fn it_parses_binary_plys_in_both_byte_orders() {
+    use crate::geometry::Prim;
+
+    let header = |format: &str| format!("ply
+format {} 1.0
+element vertex 3
+property double x
+property float y
+property float z
+property float u
+property float v
+element face 1
+property list uchar uint vertex_indices
+end_header
+", format);
+
+    let vertices = [(0.0f64, 0.0f32, 0.0f32, 0.0f32, 0.0f32), (1.0, 0.0, 0.0, 1.0, 0.0), (0.0, 1.0, 0.0, 0.0, 1.0)];
+
+    let mut little = header("binary_little_endian").into_bytes();
+    let mut big = header("binary_big_endian").into_bytes();
+    for &(x, y, z, u, v) in vertices.iter() {
+        little.extend_from_slice(&x.to_le_bytes());
+        big.extend_from_slice(&x.to_be_bytes());
+        for value in [y, z, u, v].iter() {
+            little.extend_from_slice(&value.to_le_bytes());
+            big.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+    little.push(3);
+    big.push(3);
+    for index in [0u32, 1, 2].iter() {
+        little.extend_from_slice(&index.to_le_bytes());
+        big.extend_from_slice(&index.to_be_bytes());
+    }
+
+    for bytes in [little, big].iter() {
+        let model = parse_ply(bytes).unwrap();
+        assert_eq!(Vec3 { x: 1.0, y: 0.0, z: 0.0 }, model.positions[1]);
+        assert_eq!((0.0, 1.0), model.texinfo.as_ref().unwrap()[2]);
+        assert_eq!(vec![[0, 1, 2]], model.faces);
+
+        let mesh = model.into_mesh(|_| Box::new(crate::material::materials::FlatMaterial { color: Vec3::one() })).build();
+        let ray = crate::raytracer::Ray::new(Vec3 { x: 0.25, y: 0.5, z: -1.0 }, Vec3 { x: 0.0, y: 0.0, z: 1.0 });
+        let hit = mesh.intersects(&ray, 0.0, 10.0).unwrap();
+        assert_eq!((0.25, 0.5), (hit.u, hit.v));
+    }
+
+    let truncated = parse_ply(&header("binary_little_endian").into_bytes()[..]);
+    assert_eq!(Some((0, "unexpected end of file at byte 0".to_string())), truncated.err());
+}
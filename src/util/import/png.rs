@@ -0,0 +1,359 @@
+use crate::raytracer::compositor::{ColorRGBA, Surface};
+
+static LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+                                 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+static LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+                                 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+static DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+                                   257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+                                   8193, 12289, 16385, 24577];
+static DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+                                   7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+static CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// A canonical Huffman code, stored as the number of codes of each length
+/// and the symbols ordered by code.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &len in lengths.iter() {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        Huffman { counts: counts, symbols: symbols }
+    }
+}
+
+/// Reads a DEFLATE stream least significant bit first.
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, count: u32) -> Result<u32, String> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = *self.bytes.get(self.pos).ok_or_else(|| "truncated compressed data".to_string())?;
+            value |= ((byte >> self.bit) as u32 & 1) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+
+    fn decode(&mut self, huffman: &Huffman) -> Result<u16, String> {
+        // Canonical codes of each length are consecutive, so a code is found
+        // by comparing against the first code of its length
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for len in 1..16 {
+            code |= self.bits(1)? as i32;
+            let count = huffman.counts[len] as i32;
+            if code - first < count {
+                return Ok(huffman.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".to_string())
+    }
+}
+
+fn inflate_block(reader: &mut BitReader, out: &mut Vec<u8>, lengths: &Huffman, distances: &Huffman) -> Result<(), String> {
+    loop {
+        let symbol = reader.decode(lengths)? as usize;
+        if symbol < 256 {
+            out.push(symbol as u8);
+        } else if symbol == 256 {
+            return Ok(());
+        } else {
+            let symbol = symbol - 257;
+            if symbol >= LENGTH_BASE.len() {
+                return Err("invalid length symbol".to_string());
+            }
+            let len = LENGTH_BASE[symbol] as usize + reader.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+            let symbol = reader.decode(distances)? as usize;
+            if symbol >= DISTANCE_BASE.len() {
+                return Err("invalid distance symbol".to_string());
+            }
+            let distance = DISTANCE_BASE[symbol] as usize + reader.bits(DISTANCE_EXTRA[symbol] as u32)? as usize;
+            if distance > out.len() {
+                return Err("distance too far back".to_string());
+            }
+
+            // Copies may overlap their own output, so go byte by byte
+            let start = out.len() - distance;
+            for i in 0..len {
+                let byte = out[start + i];
+                out.push(byte);
+            }
+        }
+    }
+}
+
+fn dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &index in CODE_LENGTH_ORDER[..code_length_count].iter() {
+        code_lengths[index] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let symbol = reader.decode(&code_length_code)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => match lengths.last() {
+                Some(&previous) => (previous, 3 + reader.bits(2)?),
+                None => return Err("repeated code length with no previous length".to_string())
+            },
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?)
+        };
+        for _ in 0..repeat {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err("code lengths overflow".to_string());
+    }
+
+    Ok((Huffman::new(&lengths[..literal_count]), Huffman::new(&lengths[literal_count..])))
+}
+
+/// Decompresses a zlib stream (RFC 1950/1951).
+pub fn inflate_zlib(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    if bytes.len() < 2 || bytes[0] & 0x0f != 8 || !(bytes[0] as u16 * 256 + bytes[1] as u16).is_multiple_of(31) {
+        return Err("invalid zlib header".to_string());
+    }
+    if bytes[1] & 0x20 != 0 {
+        return Err("zlib preset dictionaries are not supported".to_string());
+    }
+
+    let mut reader = BitReader { bytes: &bytes[2..], pos: 0, bit: 0 };
+    let mut out = Vec::new();
+
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let header = reader.bytes.get(reader.pos..reader.pos + 4).ok_or_else(|| "truncated stored block".to_string())?;
+                let len = header[0] as usize | (header[1] as usize) << 8;
+                reader.pos += 4;
+                let data = reader.bytes.get(reader.pos..reader.pos + len).ok_or_else(|| "truncated stored block".to_string())?;
+                out.extend_from_slice(data);
+                reader.pos += len;
+            },
+            1 => {
+                let mut lengths = [0u8; 288];
+                for (symbol, len) in lengths.iter_mut().enumerate() {
+                    *len = match symbol {
+                        0..=143 => 8,
+                        144..=255 => 9,
+                        256..=279 => 7,
+                        _ => 8
+                    };
+                }
+                inflate_block(&mut reader, &mut out, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+            },
+            2 => {
+                let (lengths, distances) = dynamic_tables(&mut reader)?;
+                inflate_block(&mut reader, &mut out, &lengths, &distances)?;
+            },
+            _ => return Err("invalid block type".to_string())
+        }
+
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+/// Decodes a non-interlaced PNG of any bit depth and color type. Alpha is
+/// kept, but the renderer's textures ignore it.
+pub fn parse_png(bytes: &[u8]) -> Result<Surface, String> {
+    if !bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Err("not a PNG file".to_string());
+    }
+
+    let mut pos = 8;
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut data = Vec::new();
+
+    while pos + 8 <= bytes.len() {
+        let len = u32::from_be_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]]) as usize;
+        let kind = &bytes[pos + 4..pos + 8];
+        let chunk = bytes.get(pos + 8..(pos + 8).saturating_add(len)).ok_or_else(|| "truncated PNG chunk".to_string())?;
+        pos += 12 + len; // Length, type, data and CRC
+
+        match kind {
+            b"IHDR" if chunk.len() >= 13 => header = Some((
+                u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as usize,
+                u32::from_be_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as usize,
+                chunk[8], chunk[9], chunk[12])),
+            b"PLTE" => palette = chunk,
+            b"IDAT" => data.extend_from_slice(chunk),
+            b"IEND" => break,
+            _ => {}
+        }
+    }
+
+    let (width, height, depth, color_type, interlace) = header.ok_or_else(|| "missing PNG header".to_string())?;
+    if interlace != 0 {
+        return Err("interlaced PNGs are not supported".to_string());
+    }
+    let channels = match (color_type, depth) {
+        (0, 1) | (0, 2) | (0, 4) | (0, 8) | (0, 16) => 1,
+        (3, 1) | (3, 2) | (3, 4) | (3, 8) => 1,
+        (4, 8) | (4, 16) => 2,
+        (2, 8) | (2, 16) => 3,
+        (6, 8) | (6, 16) => 4,
+        _ => return Err(format!("unsupported PNG color type {} at bit depth {}", color_type, depth))
+    };
+
+    let bits_per_pixel = channels * depth as usize;
+    let stride = (width * bits_per_pixel).div_ceil(8);
+    let filter_offset = (bits_per_pixel / 8).max(1);
+
+    let mut raw = inflate_zlib(&data)?;
+    if raw.len() < (stride + 1) * height {
+        return Err("truncated PNG image data".to_string());
+    }
+
+    // Undo the per-row filters in place. Each row starts with its filter type.
+    for y in 0..height {
+        let row_start = y * (stride + 1) + 1;
+        let filter = raw[row_start - 1];
+        for x in 0..stride {
+            let i = row_start + x;
+            let left = if x >= filter_offset { raw[i - filter_offset] } else { 0 };
+            let up = if y > 0 { raw[i - stride - 1] } else { 0 };
+            let up_left = if y > 0 && x >= filter_offset { raw[i - stride - 1 - filter_offset] } else { 0 };
+            raw[i] = raw[i].wrapping_add(match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return Err(format!("invalid PNG filter type {}", filter))
+            });
+        }
+    }
+
+    let max_value = (1u32 << depth.min(8)) - 1;
+    let mut surface = Surface::new(width, height, ColorRGBA::black());
+    for y in 0..height {
+        let row = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        // 16-bit samples are big endian, so their high byte comes first
+        let sample = |x: usize, channel: usize| -> u8 {
+            let bit = (x * channels + channel) * depth as usize;
+            let value = if depth >= 8 {
+                row[bit / 8] as u32
+            } else {
+                (row[bit / 8] as u32 >> (8 - depth as usize - bit % 8)) & max_value
+            };
+            if color_type == 3 { value as u8 } else { (value * 255 / max_value) as u8 }
+        };
+
+        for x in 0..width {
+            surface[(x, y)] = match color_type {
+                0 => { let v = sample(x, 0); ColorRGBA::new_rgb(v, v, v) },
+                4 => { let v = sample(x, 0); ColorRGBA::new_rgba(v, v, v, sample(x, 1)) },
+                2 => ColorRGBA::new_rgb(sample(x, 0), sample(x, 1), sample(x, 2)),
+                6 => ColorRGBA::new_rgba(sample(x, 0), sample(x, 1), sample(x, 2), sample(x, 3)),
+                _ => {
+                    let index = sample(x, 0) as usize * 3;
+                    let rgb = palette.get(index..index + 3).ok_or_else(|| "PNG palette index out of range".to_string())?;
+                    ColorRGBA::new_rgb(rgb[0], rgb[1], rgb[2])
+                }
+            };
+        }
+    }
+
+    Ok(surface)
+}
+
+#[test]
+fn it_inflates_stored_fixed_and_dynamic_blocks() {
+    // Python's zlib.compress with stored, fixed and dynamic Huffman blocks
+    assert_eq!(b"abc".to_vec(), inflate_zlib(b"\x78\x01\x01\x03\x00\xfc\xff\x61\x62\x63\x02\x4d\x01\x27").unwrap());
+    assert_eq!(b"hello hello hello".to_vec(),
+               inflate_zlib(b"\x78\x01\xcb\x48\xcd\xc9\xc9\x57\xc8\x40\x90\x00\x3a\x2e\x06\x7d").unwrap());
+
+    let text: Vec<u8> = (0..60).map(|i| b'a' + ((i * i * 7 + i / 3) % 13) as u8).collect();
+    let dynamic = b"\x78\xda\x6d\xca\xb1\x01\x00\x20\x08\x03\xb0\x5b\x81\x82\x45\xca\xff\xab\x0f\x98\x39\xc6\xd8\x4b\xb2\xe5\xa5\x14\x61\x2b\x59\xd4\x44\x43\x2c\x04\xf2\x8c\x1f\xfb\xb5\x07\xe2\x06\x18\x39";
+    assert_eq!(text, inflate_zlib(dynamic).unwrap());
+
+    assert!(inflate_zlib(b"\x78\x9c\xcb\x48").is_err());
+}
+
+#[test]
+fn it_decodes_filtered_and_paletted_pngs() {
+    // 2x2 RGB, rows filtered with Sub and Up
+    let rgb = b"\x89\x50\x4e\x47\x0d\x0a\x1a\x0a\x00\x00\x00\x0d\x49\x48\x44\x52\x00\x00\x00\x02\x00\x00\x00\x02\x08\x02\x00\x00\x00\xfd\xd4\x9a\x73\x00\x00\x00\x12\x49\x44\x41\x54\x78\xda\x63\xfc\xcf\x00\x02\x4c\x0c\x0c\xff\x81\x10\x00\x13\x17\x03\x01\x98\xac\xd1\xe5\x00\x00\x00\x00\x49\x45\x4e\x44\xae\x42\x60\x82";
+    let image = parse_png(rgb).unwrap();
+    assert_eq!((2, 2), (image.width, image.height));
+    assert_eq!((255, 0, 0), (image[(0, 0)].r, image[(0, 0)].g, image[(0, 0)].b));
+    assert_eq!((255, 0, 0), (image[(1, 0)].r, image[(1, 0)].g, image[(1, 0)].b));
+    assert_eq!((255, 0, 255), (image[(0, 1)].r, image[(0, 1)].g, image[(0, 1)].b));
+    assert_eq!((255, 255, 0), (image[(1, 1)].r, image[(1, 1)].g, image[(1, 1)].b));
+
+    // 2x2 grayscale, rows filtered with Average and Paeth
+    let gray = b"\x89\x50\x4e\x47\x0d\x0a\x1a\x0a\x00\x00\x00\x0d\x49\x48\x44\x52\x00\x00\x00\x02\x00\x00\x00\x02\x08\x00\x00\x00\x00\x57\xdd\x52\xf8\x00\x00\x00\x0e\x49\x44\x41\x54\x78\xda\x63\x16\xe0\x60\x61\x65\x05\x00\x00\xa3\x00\x2a\x22\x28\x9e\x6c\x00\x00\x00\x00\x49\x45\x4e\x44\xae\x42\x60\x82";
+    let image = parse_png(gray).unwrap();
+    assert_eq!([0x10, 0x10, 0x15, 0x1a], [image[(0, 0)].r, image[(1, 0)].g, image[(0, 1)].b, image[(1, 1)].r]);
+
+    // 2x1 with a 1-bit palette
+    let paletted = b"\x89\x50\x4e\x47\x0d\x0a\x1a\x0a\x00\x00\x00\x0d\x49\x48\x44\x52\x00\x00\x00\x02\x00\x00\x00\x01\x01\x03\x00\x00\x00\xce\xec\xed\xc9\x00\x00\x00\x06\x50\x4c\x54\x45\x00\x00\xff\x00\xff\x00\xe8\x46\x42\x4c\x00\x00\x00\x0a\x49\x44\x41\x54\x78\xda\x63\x70\x00\x00\x00\x42\x00\x41\x84\xbf\x8e\x62\x00\x00\x00\x00\x49\x45\x4e\x44\xae\x42\x60\x82";
+    let image = parse_png(paletted).unwrap();
+    assert_eq!(255, image[(0, 0)].b);
+    assert_eq!(255, image[(1, 0)].g);
+
+    assert!(parse_png(b"\x89PNG\r\n\x1a\n").is_err());
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::path::Path;
+use crate::geometry::meshtools::smooth_normals;
+use crate::geometry::prims::MeshOptions;
+use crate::geometry::subdivision::ControlMesh;
+use crate::material::Material;
+use crate::vec3::Vec3;
+use super::{parse_vec3, read_file, ImportError};
+
+/// Triangles as they appear in the file: stored normal, then vertices.
+type Facet = [Vec3; 4];
+
+fn parse_binary_stl(bytes: &[u8]) -> Result<Vec<Facet>, (usize, String)> {
+    if bytes.len() < 84 {
+        return Err((0, "truncated binary STL header".to_string()));
+    }
+
+    let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+    if bytes.len() - 84 < count.saturating_mul(50) {
+        return Err((0, format!("expected {} facets, file holds {}", count, (bytes.len() - 84) / 50)));
+    }
+
+    let read_f32 = |pos: usize| f32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]]) as f64;
+    let read_vec3 = |pos: usize| Vec3 { x: read_f32(pos), y: read_f32(pos + 4), z: read_f32(pos + 8) };
+
+    // Each record is a normal and three vertices, then a 2-byte attribute
+    Ok((0..count).map(|i| {
+        let pos = 84 + i * 50;
+        [read_vec3(pos), read_vec3(pos + 12), read_vec3(pos + 24), read_vec3(pos + 36)]
+    }).collect())
+}
+
+fn parse_ascii_stl(text: &str) -> Result<Vec<Facet>, (usize, String)> {
+    let mut facets = Vec::new();
+    let mut facet = [Vec3::zero(); 4];
+    let mut vertices = 0;
+
+    for (line_index, line) in text.lines().enumerate() {
+        let error = |message: String| (line_index + 1, message);
+        let mut tokens = line.split_whitespace();
+
+        match tokens.next() {
+            Some("facet") => {
+                if tokens.next() != Some("normal") {
+                    return Err(error("expected 'facet normal'".to_string()));
+                }
+                facet[0] = parse_vec3(&mut tokens, "normal").map_err(error)?;
+                vertices = 0;
+            },
+            Some("vertex") => {
+                if vertices == 3 {
+                    return Err(error("more than 3 vertices in facet".to_string()));
+                }
+                facet[vertices + 1] = parse_vec3(&mut tokens, "vertex").map_err(error)?;
+                vertices += 1;
+            },
+            Some("endfacet") => {
+                if vertices != 3 {
+                    return Err(error(format!("facet has {} vertices, expected 3", vertices)));
+                }
+                facets.push(facet);
+            },
+            _ => {} // solid, outer loop, endloop, endsolid
+        }
+    }
+
+    Ok(facets)
+}
+
+/// ASCII files start with "solid", but so do the 80-byte headers written by
+/// some binary exporters, so a binary file is recognised by its exact size.
+fn parse_stl(bytes: &[u8]) -> Result<Vec<Facet>, (usize, String)> {
+    let binary_size = if bytes.len() >= 84 {
+        Some(84 + 50 * u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize)
+    } else {
+        None
+    };
+
+    if bytes.starts_with(b"solid") && binary_size != Some(bytes.len()) {
+        parse_ascii_stl(&String::from_utf8_lossy(bytes))
+    } else {
+        parse_binary_stl(bytes)
+    }
+}
+
+/// An STL model with coincident vertices welded together. Each face has its
+/// own three corner normals, smoothed across edges within the crease angle.
+pub struct StlModel {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub faces: Vec<[u32; 3]>,
+    /// Indices (in file order) of facets dropped for having no area
+    pub degenerate: Vec<usize>,
+}
+
+impl StlModel {
+    fn from_facets(facets: &[Facet], crease_angle: f64) -> StlModel {
+        let mut positions = Vec::new();
+        let mut welded: HashMap<[u64; 3], u32> = HashMap::new();
+        let mut faces = Vec::new();
+        let mut degenerate = Vec::new();
+
+        for (index, facet) in facets.iter().enumerate() {
+            let (a, b, c) = (facet[1], facet[2], facet[3]);
+            let n = (b - a).cross(&(c - a));
+            if n.len() == 0.0 || !n.len().is_finite() {
+                degenerate.push(index);
+                continue;
+            }
+
+            let mut face = [0u32; 3];
+            for (corner, v) in facet[1..].iter().enumerate() {
+                // -0.0 and 0.0 should weld together
+                let key = [(v.x + 0.0).to_bits(), (v.y + 0.0).to_bits(), (v.z + 0.0).to_bits()];
+                face[corner] = *welded.entry(key).or_insert_with(|| {
+                    positions.push(*v);
+                    (positions.len() - 1) as u32
+                });
+            }
+
+            // Trust the winding unless the stored normal clearly disagrees
+            if n.dot(&facet[0]) < 0.0 {
+                face.swap(1, 2);
+            }
+
+            faces.push(face);
+        }
+
+        let normals = smooth_normals(&positions, &faces, crease_angle);
+
+        StlModel { positions: positions, normals: normals, faces: faces, degenerate: degenerate }
+    }
+
+    pub fn into_mesh(self, material: Box<dyn Material+Send+Sync>) -> MeshOptions {
+        let mut meshopts = MeshOptions::new(self.positions);
+        meshopts.normals(self.normals);
+        meshopts.material(material);
+
+        for (index, face) in self.faces.into_iter().enumerate() {
+            let n = (index * 3) as u32;
+            meshopts.face(face, Some([n, n + 1, n + 2]), None);
+        }
+        meshopts
+    }
+
+    pub fn control_mesh(&self) -> ControlMesh {
+        ControlMesh::from_triangles(self.positions.clone(), &self.faces)
+    }
+}
+
+/// Loads a binary or ASCII STL file. Facets with no area are dropped, with
+/// a warning.
+pub fn from_stl(path: &Path, crease_angle: f64) -> Result<StlModel, ImportError> {
+    let bytes = read_file(path)?;
+    let facets = parse_stl(&bytes).map_err(|(line, message)| ImportError::parse(path, line, message))?;
+    let model = StlModel::from_facets(&facets, crease_angle);
+
+    if !model.degenerate.is_empty() {
+        eprintln!("{}: skipped {} degenerate facet(s), first at facet {}",
+                  path.display(), model.degenerate.len(), model.degenerate[0]);
+    }
+
+    Ok(model)
+}
+
+#[cfg(test)]
+fn folded_quad() -> Vec<u8> {
+    // Two facets folded 90 degrees along the shared edge x = 0, plus one
+    // with all three vertices coincident
+    let facets = [
+        [[0.0f32, 0.0, 1.0], [0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+        [[-1.0, 0.0, 0.0], [0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        [[0.0, 0.0, 0.0], [5.0, 5.0, 5.0], [5.0, 5.0, 5.0], [5.0, 5.0, 5.0]],
+    ];
+
+    // A header starting with "solid" must not be mistaken for ASCII
+    let mut bytes = b"solid exported as binary".to_vec();
+    bytes.resize(80, 0);
+    bytes.extend_from_slice(&(facets.len() as u32).to_le_bytes());
+    for facet in facets.iter() {
+        for value in facet.iter().flat_map(|v| v.iter()) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&[0, 0]);
+    }
+    bytes
+}
+
+#[test]
+fn it_welds_vertices_and_respects_the_crease_angle() {
+    let facets = parse_stl(&folded_quad()).unwrap();
+    assert_eq!(3, facets.len());
+
+    let sharp = StlModel::from_facets(&facets, 30.0);
+    assert_eq!(4, sharp.positions.len());
+    assert_eq!(2, sharp.faces.len());
+    assert_eq!(vec![2], sharp.degenerate);
+    assert_eq!(Vec3 { x: 0.0, y: 0.0, z: 1.0 }, sharp.normals[0]);
+    assert_eq!(Vec3 { x: -1.0, y: 0.0, z: 0.0 }, sharp.normals[3]);
+
+    // Shared corners are averaged once the fold is within the crease angle
+    let smooth = StlModel::from_facets(&facets, 100.0);
+    let n = smooth.normals[0];
+    assert!((n.x + 0.5f64.sqrt()).abs() < 1e-9 && (n.z - 0.5f64.sqrt()).abs() < 1e-9);
+    assert_eq!(Vec3 { x: 0.0, y: 0.0, z: 1.0 }, smooth.normals[1]);
+
+    assert_eq!(2, smooth.into_mesh(Box::new(crate::material::materials::FlatMaterial { color: Vec3::one() })).build().len());
+}
+
+#[test]
+fn it_parses_ascii_stls() {
+    let facets = parse_stl(b"solid part
+  facet normal 0 0 -1
+    outer loop
+      vertex 0 0 0
+      vertex 0 1 0
+      vertex 1 0 0
+    endloop
+  endfacet
+endsolid part
+").unwrap();
+    assert_eq!(1, facets.len());
+    assert_eq!(Vec3 { x: 0.0, y: 1.0, z: 0.0 }, facets[0][2]);
+
+    // A stored normal that disagrees with the winding flips it
+    let model = StlModel::from_facets(&[[Vec3 { x: 0.0, y: 0.0, z: 1.0 }, facets[0][1], facets[0][2], facets[0][3]]], 0.0);
+    assert_eq!([0, 2, 1], model.faces[0]);
+
+    let err = parse_stl(b"solid part\nfacet normal 0 0 1\nvertex 0 0 0\nvertex 0 1 zero\n");
+    assert_eq!(Some((4, "invalid vertex 'zero'".to_string())), err.err());
+}
@@ -0,0 +1,97 @@
+use std::path::Path;
+use crate::geometry::prims::{CurveBasis, CurveShape, CurvesOptions};
+use crate::vec3::Vec3;
+use super::{parse_token, parse_vec3, read_file, ImportError};
+
+/// One strand's control points and widths, as read from the file.
+pub struct Strand {
+    pub points: Vec<Vec3>,
+    pub widths: Vec<f64>
+}
+
+/// Parses a strand file: one control point per line as `x y z [width]`,
+/// with blank lines between strands and `#` starting a comment. Points
+/// without a width use `default_width`.
+fn parse_strands(text: &str, default_width: f64) -> Result<Vec<Strand>, (usize, String)> {
+    let mut strands = Vec::new();
+    let mut strand = Strand { points: Vec::new(), widths: Vec::new() };
+
+    for (line_index, line) in text.lines().enumerate() {
+        let error = |message: String| (line_index + 1, message);
+        // Only a truly blank line ends a strand, not a comment
+        if line.trim().is_empty() {
+            if !strand.points.is_empty() {
+                strands.push(strand);
+                strand = Strand { points: Vec::new(), widths: Vec::new() };
+            }
+            continue;
+        }
+
+        let mut tokens = line.split('#').next().unwrap_or("").split_whitespace().peekable();
+        if tokens.peek().is_none() {
+            continue;
+        }
+
+        strand.points.push(parse_vec3(&mut tokens, "point").map_err(error)?);
+        strand.widths.push(match tokens.next() {
+            Some(width) => parse_token(Some(width), "width").map_err(error)?,
+            None => default_width
+        });
+        if let Some(extra) = tokens.next() {
+            return Err(error(format!("unexpected '{}' after point", extra)));
+        }
+    }
+    if !strand.points.is_empty() {
+        strands.push(strand);
+    }
+
+    Ok(strands)
+}
+
+/// Loads hair, fur or grass from a strand file as tube curves. Strands with
+/// too few points for `basis` are skipped with a warning.
+#[allow(dead_code)]
+pub fn from_strands(path: &Path, basis: CurveBasis, default_width: f64) -> Result<CurvesOptions, ImportError> {
+    let bytes = read_file(path)?;
+    let text = String::from_utf8_lossy(&bytes);
+    let strands = parse_strands(&text, default_width).map_err(|(line, message)| ImportError::parse(path, line, message))?;
+
+    let mut options = CurvesOptions::new(basis, CurveShape::Tube);
+    let mut skipped = 0;
+    for strand in strands.iter() {
+        let n = strand.points.len();
+        let usable = match basis {
+            CurveBasis::Bezier => n >= 4 && (n - 1).is_multiple_of(3),
+            CurveBasis::BSpline => n >= 4
+        };
+        if usable {
+            options.strand(&strand.points, &strand.widths, None);
+        } else {
+            skipped += 1;
+        }
+    }
+
+    if skipped > 0 {
+        eprintln!("{}: skipped {} strand(s) with the wrong number of points for {:?} curves",
+                  path.display(), skipped, basis);
+    }
+
+    Ok(options)
+}
+
+#[test]
+fn it_parses_strands_between_blank_lines() {
+    let strands = parse_strands("# two strands
+0 0 0 0.2
+0 1 0
+0 2 0 0.1  # tip
+
+1 0 0
+1 1 0
+", 0.5).unwrap();
+    assert_eq!(2, strands.len());
+    assert_eq!(vec![0.2, 0.5, 0.1], strands[0].widths);
+    assert_eq!(Vec3 { x: 1.0, y: 1.0, z: 0.0 }, strands[1].points[1]);
+
+    assert_eq!(Err((2, "invalid point 'y'".to_string())), parse_strands("\n0 y 0\n", 1.0).map(|_| ()));
+}
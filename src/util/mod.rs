@@ -1,6 +1,8 @@
 use rand::{RngCore};
 
+pub mod cache;
 pub mod export;
+pub mod import;
 
 pub fn get_rng() -> Box<dyn RngCore> {
     Box::new(rand::thread_rng())
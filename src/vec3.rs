@@ -1,5 +1,3 @@
-use crate::prelude::*;
-use rand::{Rng};
 use core::cmp;
 use core::fmt;
 use core::ops::{Add, Div, Mul, Neg, Sub};
@@ -107,23 +105,6 @@ impl Vec3 {
             z: self.z.max(min).min(max),
         }
     }
-
-    /// Generates a random vector across a uniform distribution using the answer found in
-    /// http://stackoverflow.com/questions/5408276/python-uniform-spherical-distribution
-    pub fn random(rng : &mut Box<dyn rand::RngCore>) -> Vec3 {
-        let phi: f64 = rng.gen_range(0.0, 2.0 * ::core::f64::consts::PI);
-        let costheta: f64 = rng.gen_range(-1.0, 1.0);
-        let u: f64 = rng.gen_range(0.0, 1.0);
-
-        let theta = costheta.acos();
-        let r = u.powf(1.0 / 3.0);
-
-        Vec3 {
-            x: r * theta.sin() * phi.cos(),
-            y: r * theta.sin() * phi.sin(),
-            z: r * theta.cos(),
-        }
-    }
 }
 
 impl Add for Vec3 {